@@ -0,0 +1,17 @@
+// The hand-written JSON checks that validate() runs on valid JSON: duplicate key
+// detection and the RFC 8785 check, both scan the text themselves
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use payload_pulverizer::{inspect, jcs};
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(body_str) = std::str::from_utf8(data) else {
+        return;
+    };
+    // Both expect a document that already parsed, like in the handler
+    if serde_json::from_str::<serde_json::Value>(body_str).is_err() {
+        return;
+    }
+    inspect::duplicate_keys(body_str);
+    jcs::check(body_str);
+});
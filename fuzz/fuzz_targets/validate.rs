@@ -0,0 +1,25 @@
+// Arbitrary bytes through all checks of /validate-before-destroy. The first byte picks
+// what the request would declare (form, NDJSON, ?reveal_headers, privacy mode), the
+// rest is the payload.
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use payload_pulverizer::validation::{validate, Options};
+
+fuzz_target!(|data: &[u8]| {
+    let Some((flags, body)) = data.split_first() else {
+        return;
+    };
+    // The handler answers anything else with "not valid UTF-8" before validating
+    let Ok(body_str) = std::str::from_utf8(body) else {
+        return;
+    };
+    let options = Options {
+        form: flags & 1 != 0,
+        ndjson: flags & 2 != 0,
+        reveal_headers: flags & 4 != 0,
+        privacy: flags & 8 != 0,
+    };
+    let validation = validate(body_str, &options);
+    // The handler sends it as JSON
+    serde_json::to_vec(&validation).unwrap();
+});
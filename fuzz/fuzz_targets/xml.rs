@@ -0,0 +1,13 @@
+// The XML check and, for what passes it, the structure report
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use payload_pulverizer::{inspect, validation};
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(body_str) = std::str::from_utf8(data) else {
+        return;
+    };
+    if validation::check_xml(body_str).is_ok() {
+        inspect::xml_structure(body_str);
+    }
+});
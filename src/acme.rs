@@ -0,0 +1,633 @@
+// --acme-domain: HTTPS with certificates from Let's Encrypt or another ACME CA (RFC 8555)
+//
+// Meant for the public demo, so it runs without a certificate-managing proxy in front. The
+// CA checks domain ownership with HTTP-01 challenges, answered by the regular listener under
+// /.well-known/acme-challenge/, so port 80 must reach that one. HTTPS is served on a second
+// listener with the certificate cached in --acme-dir. A background thread orders a new one
+// when there is none or it expires within 30 days, and swaps it in without a restart.
+use actix_web::{web, HttpResponse};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use ring::rand::SystemRandom;
+use ring::signature::{EcdsaKeyPair, KeyPair, ECDSA_P256_SHA256_FIXED_SIGNING};
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::CertifiedKey;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+
+pub const LETS_ENCRYPT: &str = "https://acme-v02.api.letsencrypt.org/directory";
+pub const CHALLENGE_PATH: &str = "/.well-known/acme-challenge/{token}";
+
+// Order a new certificate once the current one has less than this left
+const RENEW_BEFORE: chrono::Duration = chrono::Duration::days(30);
+const CHECK_INTERVAL: Duration = Duration::from_secs(12 * 3600);
+// After a failed order, not too often since the CA rate limits failed validations
+const RETRY_INTERVAL: Duration = Duration::from_secs(3600);
+const HTTP_TIMEOUT: Duration = Duration::from_secs(30);
+// Waiting for the CA to validate a challenge or issue the certificate
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+const POLL_ATTEMPTS: u32 = 60;
+
+const ACCOUNT_KEY_FILE: &str = "account.key";
+const CERT_FILE: &str = "cert.pem";
+const KEY_FILE: &str = "key.pem";
+// The domains the cached certificate was ordered for, one per line
+const DOMAINS_FILE: &str = "domains";
+
+// What the serve subcommand's --acme-* options ask for
+pub struct AcmeConfig {
+    pub domains: Vec<String>,
+    pub email: Option<String>,
+    pub directory: String,
+    pub dir: PathBuf,
+    pub https_port: u16,
+    pub reuse_port: bool,
+}
+
+// Key authorizations for the pending HTTP-01 challenges, by token
+#[derive(Default)]
+pub struct Challenges(Mutex<HashMap<String, String>>);
+
+// The certificate the HTTPS listener presents and when it expires. Until the first one
+// arrives TLS handshakes fail.
+#[derive(Debug, Default)]
+pub struct CertResolver(RwLock<Option<(Arc<CertifiedKey>, DateTime<Utc>)>>);
+
+impl ResolvesServerCert for CertResolver {
+    fn resolve(&self, _client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        self.0.read().unwrap().as_ref().map(|(key, _)| key.clone())
+    }
+}
+
+impl CertResolver {
+    fn expires(&self) -> Option<DateTime<Utc>> {
+        self.0.read().unwrap().as_ref().map(|(_, expires)| *expires)
+    }
+
+    // Present this PEM certificate chain and private key from now on
+    fn set(&self, chain: &[u8], key: &[u8]) -> Result<DateTime<Utc>, String> {
+        let certs = CertificateDer::pem_slice_iter(chain)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| format!("Invalid certificate chain: {}", e))?;
+        let expires = certs
+            .first()
+            .and_then(|cert| not_after(cert))
+            .ok_or("Certificate chain without a readable expiry date")?;
+        let key = PrivateKeyDer::from_pem_slice(key)
+            .map_err(|e| format!("Invalid private key: {}", e))?;
+        let key = rustls::crypto::ring::sign::any_supported_type(&key)
+            .map_err(|e| format!("Unsupported private key: {}", e))?;
+        *self.0.write().unwrap() = Some((Arc::new(CertifiedKey::new(certs, key)), expires));
+        Ok(expires)
+    }
+}
+
+// Domains for --acme-domain. HTTP-01 can't prove wildcards, and IP addresses aren't domains.
+pub fn parse_domain(s: &str) -> Result<String, String> {
+    let domain = s.trim().trim_end_matches('.').to_ascii_lowercase();
+    let valid_label = |label: &str| {
+        !label.is_empty()
+            && label.len() <= 63
+            && !label.starts_with('-')
+            && !label.ends_with('-')
+            && label
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b == b'-')
+    };
+    if domain.len() > 253
+        || !domain.contains('.')
+        || !domain.split('.').all(valid_label)
+        || domain.parse::<std::net::IpAddr>().is_ok()
+    {
+        return Err(format!(
+            "'{}' is not a domain name a certificate can be ordered for",
+            s
+        ));
+    }
+    Ok(domain)
+}
+
+/// Handler for GET /.well-known/acme-challenge/{token}
+/// Returns the key authorization the CA expects for a pending HTTP-01 challenge
+pub async fn challenge_handler(
+    token: web::Path<String>,
+    challenges: web::Data<Challenges>,
+) -> HttpResponse {
+    match challenges.0.lock().unwrap().get(token.as_str()) {
+        Some(key_authorization) => HttpResponse::Ok()
+            .content_type("application/octet-stream")
+            .body(key_authorization.clone()),
+        None => HttpResponse::NotFound().json(json!({
+            "error": "No pending ACME challenge with this token."
+        })),
+    }
+}
+
+// Bind the HTTPS listener, use the cached certificate if there is one and keep it renewed.
+// Returns what actix needs to serve HTTPS.
+pub fn start(
+    config: AcmeConfig,
+    challenges: web::Data<Challenges>,
+) -> std::io::Result<(TcpListener, rustls::ServerConfig)> {
+    let listener =
+        crate::listener::bind(([0, 0, 0, 0], config.https_port).into(), config.reuse_port)?;
+    let resolver = Arc::new(CertResolver::default());
+    match load(&config, &resolver) {
+        Ok(Some(expires)) => println!(
+            "Using the certificate in {} for {}, valid until {}",
+            config.dir.display(),
+            config.domains.join(", "),
+            expires.format("%Y-%m-%d")
+        ),
+        Ok(None) => println!(
+            "Ordering a certificate for {} from {}",
+            config.domains.join(", "),
+            config.directory
+        ),
+        Err(e) => eprintln!("Ignoring the cached certificate: {}", e),
+    }
+    println!("Serving HTTPS on port {}", config.https_port);
+    let tls_config = rustls::ServerConfig::builder_with_provider(Arc::new(
+        rustls::crypto::ring::default_provider(),
+    ))
+    .with_safe_default_protocol_versions()
+    .map_err(std::io::Error::other)?
+    .with_no_client_auth()
+    .with_cert_resolver(resolver.clone());
+    spawn_renewer(config, challenges, resolver);
+    Ok((listener, tls_config))
+}
+
+// The cached certificate, if it was ordered for the domains configured now
+fn load(config: &AcmeConfig, resolver: &CertResolver) -> Result<Option<DateTime<Utc>>, String> {
+    let domains = match std::fs::read_to_string(config.dir.join(DOMAINS_FILE)) {
+        Ok(domains) => domains,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.to_string()),
+    };
+    if domains.lines().collect::<Vec<_>>() != config.domains {
+        return Ok(None);
+    }
+    let read = |name| {
+        std::fs::read(config.dir.join(name))
+            .map_err(|e| format!("Failed to read {}: {}", config.dir.join(name).display(), e))
+    };
+    resolver.set(&read(CERT_FILE)?, &read(KEY_FILE)?).map(Some)
+}
+
+// Check twice a day whether the certificate needs renewing, for as long as the process runs
+fn spawn_renewer(
+    config: AcmeConfig,
+    challenges: web::Data<Challenges>,
+    resolver: Arc<CertResolver>,
+) {
+    std::thread::spawn(move || loop {
+        let due = resolver
+            .expires()
+            .is_none_or(|expires| expires - Utc::now() < RENEW_BEFORE);
+        let wait = match due {
+            false => CHECK_INTERVAL,
+            true => match renew(&config, &challenges, &resolver) {
+                Ok(expires) => {
+                    println!(
+                        "Got a certificate for {}, valid until {}",
+                        config.domains.join(", "),
+                        expires.format("%Y-%m-%d")
+                    );
+                    CHECK_INTERVAL
+                }
+                Err(e) => {
+                    eprintln!(
+                        "Failed to get a certificate for {}: {}",
+                        config.domains.join(", "),
+                        e
+                    );
+                    RETRY_INTERVAL
+                }
+            },
+        };
+        std::thread::sleep(wait);
+    });
+}
+
+// Order a certificate, cache it and start presenting it
+fn renew(
+    config: &AcmeConfig,
+    challenges: &Challenges,
+    resolver: &CertResolver,
+) -> Result<DateTime<Utc>, String> {
+    std::fs::create_dir_all(&config.dir)
+        .map_err(|e| format!("Failed to create {}: {}", config.dir.display(), e))?;
+    let (chain, key) = order(config, challenges)?;
+    let expires = resolver.set(chain.as_bytes(), key.as_bytes())?;
+    write_file(&config.dir.join(KEY_FILE), key.as_bytes(), true)?;
+    write_file(&config.dir.join(CERT_FILE), chain.as_bytes(), false)?;
+    write_file(
+        &config.dir.join(DOMAINS_FILE),
+        config.domains.join("\n").as_bytes(),
+        false,
+    )?;
+    Ok(expires)
+}
+
+fn write_file(path: &Path, contents: &[u8], private: bool) -> Result<(), String> {
+    use std::io::Write;
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    if private {
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    }
+    #[cfg(not(unix))]
+    let _ = private;
+    options
+        .open(path)
+        .and_then(|mut file| file.write_all(contents))
+        .map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+}
+
+// The account key is kept so renewals reuse the account
+fn account_key(dir: &Path, rng: &SystemRandom) -> Result<EcdsaKeyPair, String> {
+    let path = dir.join(ACCOUNT_KEY_FILE);
+    let pkcs8 = match std::fs::read(&path) {
+        Ok(pkcs8) => pkcs8,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, rng)
+                .map_err(|_| "Failed to generate an account key")?;
+            write_file(&path, pkcs8.as_ref(), true)?;
+            pkcs8.as_ref().to_vec()
+        }
+        Err(e) => return Err(format!("Failed to read {}: {}", path.display(), e)),
+    };
+    EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &pkcs8, rng)
+        .map_err(|e| format!("Invalid account key in {}: {}", path.display(), e))
+}
+
+// Go through the whole RFC 8555 flow: account, order, HTTP-01 challenges, finalization.
+// Returns the PEM certificate chain and private key.
+fn order(config: &AcmeConfig, challenges: &Challenges) -> Result<(String, String), String> {
+    let agent = ureq::AgentBuilder::new().timeout(HTTP_TIMEOUT).build();
+    let directory: Directory = read_json(
+        agent
+            .get(&config.directory)
+            .call()
+            .map_err(|e| format!("Failed to fetch {}: {}", config.directory, e))?,
+    )?;
+    let rng = SystemRandom::new();
+    let key = account_key(&config.dir, &rng)?;
+    let mut client = Client {
+        agent,
+        new_nonce: directory.new_nonce,
+        key,
+        rng,
+        nonce: None,
+        account: None,
+    };
+    // Creating the account again just looks up the existing one for our key
+    let contact: Vec<String> = config
+        .email
+        .iter()
+        .map(|e| format!("mailto:{}", e))
+        .collect();
+    let response = client.post(
+        &directory.new_account,
+        Some(json!({ "termsOfServiceAgreed": true, "contact": contact })),
+    )?;
+    client.account = Some(location(&response)?);
+    let identifiers: Vec<Value> = config
+        .domains
+        .iter()
+        .map(|domain| json!({ "type": "dns", "value": domain }))
+        .collect();
+    let response = client.post(
+        &directory.new_order,
+        Some(json!({ "identifiers": identifiers })),
+    )?;
+    let order_url = location(&response)?;
+    let order: Order = read_json(response)?;
+    for url in &order.authorizations {
+        client.authorize(url, challenges)?;
+    }
+    let key = rcgen::KeyPair::generate().map_err(|e| e.to_string())?;
+    let csr = rcgen::CertificateParams::new(config.domains.clone())
+        .and_then(|params| params.serialize_request(&key))
+        .map_err(|e| format!("Failed to create the certificate request: {}", e))?;
+    client.post(
+        &order.finalize,
+        Some(json!({ "csr": URL_SAFE_NO_PAD.encode(csr.der()) })),
+    )?;
+    let order: Order = client.poll(&order_url, |order: &Order| {
+        order.status != "pending" && order.status != "ready" && order.status != "processing"
+    })?;
+    let certificate = match (order.status.as_str(), order.certificate) {
+        ("valid", Some(url)) => url,
+        _ => {
+            return Err(format!(
+                "Order ended up {}{}",
+                order.status,
+                problem(order.error)
+            ))
+        }
+    };
+    let chain = client
+        .post(&certificate, None)?
+        .into_string()
+        .map_err(|e| format!("Failed to download the certificate: {}", e))?;
+    Ok((chain, key.serialize_pem()))
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Directory {
+    new_nonce: String,
+    new_account: String,
+    new_order: String,
+}
+
+#[derive(Deserialize)]
+struct Order {
+    status: String,
+    #[serde(default)]
+    authorizations: Vec<String>,
+    finalize: String,
+    certificate: Option<String>,
+    error: Option<Problem>,
+}
+
+#[derive(Deserialize)]
+struct Authorization {
+    status: String,
+    identifier: Identifier,
+    #[serde(default)]
+    challenges: Vec<Challenge>,
+}
+
+#[derive(Deserialize)]
+struct Identifier {
+    value: String,
+}
+
+#[derive(Deserialize)]
+struct Challenge {
+    #[serde(rename = "type")]
+    kind: String,
+    url: String,
+    #[serde(default)]
+    token: String,
+    error: Option<Problem>,
+}
+
+// An RFC 7807 problem document, the CA's error responses
+#[derive(Deserialize, Default)]
+struct Problem {
+    #[serde(rename = "type", default)]
+    kind: String,
+    #[serde(default)]
+    detail: String,
+}
+
+fn problem(problem: Option<Problem>) -> String {
+    match problem {
+        Some(problem) => format!(": {} ({})", problem.detail, problem.kind),
+        None => String::new(),
+    }
+}
+
+fn read_json<T: DeserializeOwned>(response: ureq::Response) -> Result<T, String> {
+    let body = response.into_string().map_err(|e| e.to_string())?;
+    serde_json::from_str(&body).map_err(|e| format!("Unexpected response from the CA: {}", e))
+}
+
+fn location(response: &ureq::Response) -> Result<String, String> {
+    response
+        .header("Location")
+        .map(str::to_string)
+        .ok_or_else(|| {
+            format!(
+                "No Location in the CA's response from {}",
+                response.get_url()
+            )
+        })
+}
+
+// Signs requests with the account key (JWS with ES256) and keeps track of the nonces
+struct Client {
+    agent: ureq::Agent,
+    new_nonce: String,
+    key: EcdsaKeyPair,
+    rng: SystemRandom,
+    nonce: Option<String>,
+    // The account URL, known once the account is created
+    account: Option<String>,
+}
+
+impl Client {
+    // A signed POST, or a POST-as-GET without payload
+    fn post(&mut self, url: &str, payload: Option<Value>) -> Result<ureq::Response, String> {
+        let mut retried = false;
+        loop {
+            let nonce = match self.nonce.take() {
+                Some(nonce) => nonce,
+                None => self.fresh_nonce()?,
+            };
+            let body = self.sign(url, &nonce, payload.as_ref())?;
+            let result = self
+                .agent
+                .post(url)
+                .set("Content-Type", "application/jose+json")
+                .send_string(&body);
+            match result {
+                Ok(response) => {
+                    self.nonce = response.header("Replay-Nonce").map(str::to_string);
+                    return Ok(response);
+                }
+                Err(ureq::Error::Status(code, response)) => {
+                    self.nonce = response.header("Replay-Nonce").map(str::to_string);
+                    let problem: Problem = response
+                        .into_string()
+                        .ok()
+                        .and_then(|body| serde_json::from_str(&body).ok())
+                        .unwrap_or_default();
+                    // Nonces can go stale, the error comes with a fresh one to retry with
+                    if problem.kind == "urn:ietf:params:acme:error:badNonce" && !retried {
+                        retried = true;
+                        continue;
+                    }
+                    return Err(format!(
+                        "{} answered {}{}",
+                        url,
+                        code,
+                        self::problem(Some(problem))
+                    ));
+                }
+                Err(e) => return Err(format!("Failed to reach {}: {}", url, e)),
+            }
+        }
+    }
+
+    fn fresh_nonce(&self) -> Result<String, String> {
+        let response = self
+            .agent
+            .head(&self.new_nonce)
+            .call()
+            .map_err(|e| format!("Failed to get a nonce: {}", e))?;
+        response
+            .header("Replay-Nonce")
+            .map(str::to_string)
+            .ok_or_else(|| "No nonce in the CA's response".to_string())
+    }
+
+    fn sign(&self, url: &str, nonce: &str, payload: Option<&Value>) -> Result<String, String> {
+        let mut protected = json!({ "alg": "ES256", "nonce": nonce, "url": url });
+        match &self.account {
+            Some(account) => protected["kid"] = json!(account),
+            None => protected["jwk"] = self.jwk(),
+        }
+        let protected = URL_SAFE_NO_PAD.encode(protected.to_string());
+        let payload = payload
+            .map(|payload| URL_SAFE_NO_PAD.encode(payload.to_string()))
+            .unwrap_or_default();
+        let signature = self
+            .key
+            .sign(&self.rng, format!("{}.{}", protected, payload).as_bytes())
+            .map_err(|_| "Failed to sign an ACME request")?;
+        Ok(json!({
+            "protected": protected,
+            "payload": payload,
+            "signature": URL_SAFE_NO_PAD.encode(signature.as_ref()),
+        })
+        .to_string())
+    }
+
+    // The public account key as JWK, from its uncompressed point 0x04 || x || y
+    fn jwk_coordinates(&self) -> (String, String) {
+        let point = self.key.public_key().as_ref();
+        (
+            URL_SAFE_NO_PAD.encode(&point[1..33]),
+            URL_SAFE_NO_PAD.encode(&point[33..65]),
+        )
+    }
+
+    fn jwk(&self) -> Value {
+        let (x, y) = self.jwk_coordinates();
+        json!({ "crv": "P-256", "kty": "EC", "x": x, "y": y })
+    }
+
+    // RFC 7638 thumbprint, over the members in lexicographic order without whitespace
+    fn thumbprint(&self) -> String {
+        let (x, y) = self.jwk_coordinates();
+        let jwk = format!(r#"{{"crv":"P-256","kty":"EC","x":"{}","y":"{}"}}"#, x, y);
+        URL_SAFE_NO_PAD.encode(Sha256::digest(jwk.as_bytes()))
+    }
+
+    // Prove control over one domain of the order with its HTTP-01 challenge
+    fn authorize(&mut self, url: &str, challenges: &Challenges) -> Result<(), String> {
+        let authorization: Authorization = read_json(self.post(url, None)?)?;
+        if authorization.status == "valid" {
+            return Ok(());
+        }
+        let domain = authorization.identifier.value;
+        let challenge = authorization
+            .challenges
+            .iter()
+            .find(|challenge| challenge.kind == "http-01")
+            .ok_or_else(|| format!("The CA offers no HTTP-01 challenge for {}", domain))?;
+        let token = challenge.token.clone();
+        let key_authorization = format!("{}.{}", token, self.thumbprint());
+        challenges
+            .0
+            .lock()
+            .unwrap()
+            .insert(token.clone(), key_authorization);
+        let result = self.post(&challenge.url, Some(json!({}))).and_then(|_| {
+            self.poll(url, |authorization: &Authorization| {
+                authorization.status != "pending"
+            })
+        });
+        challenges.0.lock().unwrap().remove(&token);
+        let authorization = result?;
+        if authorization.status == "valid" {
+            return Ok(());
+        }
+        let error = authorization
+            .challenges
+            .into_iter()
+            .find(|challenge| challenge.kind == "http-01")
+            .and_then(|challenge| challenge.error);
+        Err(format!(
+            "Validating {} ended up {}{}",
+            domain,
+            authorization.status,
+            problem(error)
+        ))
+    }
+
+    // POST-as-GET the resource until `done` says it has settled
+    fn poll<T: DeserializeOwned>(
+        &mut self,
+        url: &str,
+        done: impl Fn(&T) -> bool,
+    ) -> Result<T, String> {
+        for _ in 0..POLL_ATTEMPTS {
+            let resource = read_json(self.post(url, None)?)?;
+            if done(&resource) {
+                return Ok(resource);
+            }
+            std::thread::sleep(POLL_INTERVAL);
+        }
+        Err(format!("Gave up waiting for {}", url))
+    }
+}
+
+// A DER element at the start of `der`: its tag, contents and what follows it
+fn der_element(der: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let (&tag, rest) = der.split_first()?;
+    let (&first, rest) = rest.split_first()?;
+    let (len, rest) = match first {
+        0..=0x7f => (first as usize, rest),
+        0x81..=0x84 => {
+            let (len, rest) = rest.split_at_checked((first & 0x7f) as usize)?;
+            (len.iter().fold(0, |len, b| len << 8 | *b as usize), rest)
+        }
+        _ => return None,
+    };
+    let (contents, rest) = rest.split_at_checked(len)?;
+    Some((tag, contents, rest))
+}
+
+// When an X.509 certificate expires, the notAfter of its validity
+fn not_after(cert: &[u8]) -> Option<DateTime<Utc>> {
+    let (_, certificate, _) = der_element(cert)?;
+    let (_, tbs_certificate, _) = der_element(certificate)?;
+    let mut rest = tbs_certificate;
+    // The explicitly tagged version is optional
+    if rest.first() == Some(&0xa0) {
+        rest = der_element(rest)?.2;
+    }
+    // Skip serialNumber, signature and issuer
+    for _ in 0..3 {
+        rest = der_element(rest)?.2;
+    }
+    let (_, validity, _) = der_element(rest)?;
+    let (_, _, rest) = der_element(validity)?;
+    let (tag, time, _) = der_element(rest)?;
+    let time = std::str::from_utf8(time).ok()?;
+    let format = match tag {
+        0x17 => "%y%m%d%H%M%SZ", // UTCTime
+        0x18 => "%Y%m%d%H%M%SZ", // GeneralizedTime
+        _ => return None,
+    };
+    NaiveDateTime::parse_from_str(time, format)
+        .ok()
+        .map(|time| time.and_utc())
+}
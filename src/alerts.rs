@@ -0,0 +1,307 @@
+// Threshold alerting: breached and recovered thresholds are POSTed to a webhook
+//
+// Configured in the [alerts] table of the config file, so thresholds can be reloaded.
+// Every alert is sent as JSON once when it starts firing and once when it resolves.
+use actix_web::body::MessageBody;
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::web::Data;
+use actix_web::Error;
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashSet, VecDeque};
+use std::future::{ready, Future, Ready};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(15);
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+// Longest window outcomes are kept for
+const MAX_WINDOW_SECS: i64 = 60 * 60;
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct AlertSettings {
+    pub webhook_url: String,
+    // Duration the thresholds are evaluated over, e.g. "5m"
+    #[serde(default = "default_window")]
+    pub window: String,
+    // Fraction of destruction requests answered with 4xx/5xx
+    pub error_rate: Option<f64>,
+    // Requests needed in the window before the error rate is judged (no traffic resolves it)
+    #[serde(default = "default_min_requests")]
+    pub min_requests: u64,
+    pub p99_runtime_us: Option<i64>,
+    pub bytes_per_minute: Option<f64>,
+}
+
+fn default_window() -> String {
+    "5m".to_string()
+}
+
+fn default_min_requests() -> u64 {
+    10
+}
+
+impl AlertSettings {
+    pub fn validate(&self) -> Result<(), String> {
+        crate::callback::validate_url(&self.webhook_url)
+            .map_err(|e| format!("alerts.webhook_url: {}", e))?;
+        match crate::parse_duration_secs(&self.window) {
+            Some(secs) if (1..=MAX_WINDOW_SECS).contains(&secs) => {}
+            _ => {
+                return Err(format!(
+                    "alerts.window '{}' must be a duration between 1s and 1h, e.g. 5m",
+                    self.window
+                ))
+            }
+        }
+        if self
+            .error_rate
+            .is_some_and(|rate| !(0.0..=1.0).contains(&rate))
+        {
+            return Err("alerts.error_rate must be between 0 and 1".to_string());
+        }
+        Ok(())
+    }
+
+    fn is_configured(&self, alert: &str) -> bool {
+        match alert {
+            "error_rate" => self.error_rate.is_some(),
+            "p99_runtime_us" => self.p99_runtime_us.is_some(),
+            "bytes_per_minute" => self.bytes_per_minute.is_some(),
+            _ => false,
+        }
+    }
+
+    fn window_secs(&self) -> i64 {
+        crate::parse_duration_secs(&self.window).unwrap_or(300)
+    }
+}
+
+// Per-second counts of destruction requests and how many of them failed
+#[derive(Default)]
+pub struct Outcomes {
+    buckets: Mutex<VecDeque<(i64, u64, u64)>>,
+}
+
+fn unix_now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs() as i64)
+}
+
+impl Outcomes {
+    fn record(&self, failed: bool) {
+        let now = unix_now();
+        let mut buckets = self.buckets.lock().unwrap();
+        match buckets.back_mut() {
+            Some((second, total, errors)) if *second == now => {
+                *total += 1;
+                *errors += failed as u64;
+            }
+            _ => buckets.push_back((now, 1, failed as u64)),
+        }
+        while buckets
+            .front()
+            .is_some_and(|(second, _, _)| *second <= now - MAX_WINDOW_SECS)
+        {
+            buckets.pop_front();
+        }
+    }
+
+    // (requests, errors) of the last `window` seconds
+    fn totals(&self, window: i64) -> (u64, u64) {
+        let since = unix_now() - window;
+        self.buckets
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(second, _, _)| *second > since)
+            .fold((0, 0), |(total, errors), (_, t, e)| (total + t, errors + e))
+    }
+}
+
+// Middleware counting the outcomes of destruction requests
+pub struct TrackOutcomes(pub Arc<Outcomes>);
+
+impl<S, B> Transform<S, ServiceRequest> for TrackOutcomes
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = TrackOutcomesMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(TrackOutcomesMiddleware {
+            service,
+            outcomes: self.0.clone(),
+        }))
+    }
+}
+
+pub struct TrackOutcomesMiddleware<S> {
+    service: S,
+    outcomes: Arc<Outcomes>,
+}
+
+impl<S, B> Service<ServiceRequest> for TrackOutcomesMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    fn poll_ready(&self, ctx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(ctx)
+    }
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        if !crate::read_only::is_destruction(&req) {
+            return Box::pin(self.service.call(req));
+        }
+        let outcomes = self.outcomes.clone();
+        let fut = self.service.call(req);
+        Box::pin(async move {
+            let result = fut.await;
+            let failed = match &result {
+                Ok(res) => res.status().is_client_error() || res.status().is_server_error(),
+                Err(_) => true,
+            };
+            outcomes.record(failed);
+            result
+        })
+    }
+}
+
+#[derive(Serialize)]
+struct AlertPayload<'a> {
+    alert: &'a str,
+    status: &'static str,
+    value: f64,
+    threshold: f64,
+    window: &'a str,
+    timestamp: String,
+}
+
+// Current value of every configured threshold, as (alert, value, threshold)
+fn measure(
+    settings: &AlertSettings,
+    conn: &Connection,
+    outcomes: &Outcomes,
+) -> rusqlite::Result<Vec<(&'static str, f64, f64)>> {
+    let window = settings.window_secs();
+    let since = format!("-{} seconds", window);
+    let mut measured = Vec::new();
+    if let Some(threshold) = settings.error_rate {
+        let (total, errors) = outcomes.totals(window);
+        if total == 0 {
+            measured.push(("error_rate", 0.0, threshold));
+        } else if total >= settings.min_requests {
+            measured.push(("error_rate", errors as f64 / total as f64, threshold));
+        }
+    }
+    if let Some(threshold) = settings.p99_runtime_us {
+        let count: i64 = conn.query_row(
+            "SELECT COUNT(*) FROM endpoint_stats_raw WHERE ts >= datetime('now', ?1)",
+            [&since],
+            |row| row.get(0),
+        )?;
+        if count > 0 {
+            let p99: i64 = conn.query_row(
+                "SELECT runtime_us FROM endpoint_stats_raw WHERE ts >= datetime('now', ?1) ORDER BY runtime_us LIMIT 1 OFFSET ?2",
+                rusqlite::params![&since, (count - 1) * 99 / 100],
+                |row| row.get(0),
+            )?;
+            measured.push(("p99_runtime_us", p99 as f64, threshold as f64));
+        } else {
+            measured.push(("p99_runtime_us", 0.0, threshold as f64));
+        }
+    }
+    if let Some(threshold) = settings.bytes_per_minute {
+        let bytes: i64 = conn.query_row(
+            "SELECT COALESCE(SUM(payload_size * COALESCE(sample_weight, 1)), 0) FROM endpoint_stats_raw WHERE ts >= datetime('now', ?1)",
+            [&since],
+            |row| row.get(0),
+        )?;
+        measured.push((
+            "bytes_per_minute",
+            bytes as f64 * 60.0 / window as f64,
+            threshold,
+        ));
+    }
+    Ok(measured)
+}
+
+fn notify(agent: &ureq::Agent, url: &str, payload: &AlertPayload) -> Result<(), String> {
+    let body = serde_json::to_string(payload).map_err(|e| e.to_string())?;
+    agent
+        .post(url)
+        .set("Content-Type", "application/json")
+        .send_string(&body)
+        .map(|_| ())
+        .map_err(|e| e.to_string())
+}
+
+// Evaluate the thresholds in the background for as long as the process runs.
+// A failed notification is retried on the next check.
+pub fn spawn_evaluator(
+    db: Data<Mutex<Connection>>,
+    config: Data<crate::AppConfig>,
+    outcomes: Arc<Outcomes>,
+) {
+    std::thread::spawn(move || {
+        let agent = ureq::AgentBuilder::new().timeout(WEBHOOK_TIMEOUT).build();
+        let mut firing: HashSet<&'static str> = HashSet::new();
+        loop {
+            std::thread::sleep(CHECK_INTERVAL);
+            let file_config = config.settings();
+            let Some(settings) = &file_config.alerts else {
+                firing.clear();
+                continue;
+            };
+            let measured = match measure(settings, &db.lock().unwrap(), &outcomes) {
+                Ok(measured) => measured,
+                Err(e) => {
+                    eprintln!("Failed to evaluate alert thresholds: {}", e);
+                    continue;
+                }
+            };
+            // Thresholds removed by a reload count as resolved without notice
+            firing.retain(|alert| settings.is_configured(alert));
+            for (alert, value, threshold) in measured {
+                let breached = value > threshold;
+                if breached == firing.contains(alert) {
+                    continue;
+                }
+                let payload = AlertPayload {
+                    alert,
+                    status: if breached { "firing" } else { "resolved" },
+                    value,
+                    threshold,
+                    window: &settings.window,
+                    timestamp: chrono::Utc::now().to_rfc3339(),
+                };
+                match notify(&agent, &settings.webhook_url, &payload) {
+                    Ok(()) if breached => {
+                        firing.insert(alert);
+                    }
+                    Ok(()) => {
+                        firing.remove(alert);
+                    }
+                    Err(e) => eprintln!(
+                        "Failed to send {} alert to {}: {}",
+                        alert, settings.webhook_url, e
+                    ),
+                }
+            }
+        }
+    });
+}
@@ -0,0 +1,128 @@
+// Byte-level autopsy of a payload for POST /autopsy
+//
+// Counts every byte value and measures runs, which is usually enough to tell a
+// truncated upload, a UTF-16 file sent as UTF-8 or a block of zeroes from a healthy
+// payload without looking at the bytes themselves.
+use serde::Serialize;
+
+// The longest stretch of bytes sharing a property, offsets count from 0
+#[derive(Serialize, Default)]
+pub struct Run {
+    length: usize,
+    offset: usize,
+    // The repeated byte, only for runs of a single value
+    #[serde(skip_serializing_if = "Option::is_none")]
+    byte: Option<u8>,
+}
+
+#[derive(Serialize)]
+pub struct Autopsy {
+    bytes: usize,
+    distinct_bytes: usize,
+    // Occurrences of every byte value, indexed by the value
+    histogram: Vec<u64>,
+    // Printable ASCII plus tab, line feed and carriage return
+    printable: usize,
+    nonprintable: usize,
+    // printable / bytes, 1 for an empty payload, rounded to 3 decimals
+    printable_ratio: f64,
+    // Shannon entropy in bits per byte, 8 is indistinguishable from random data
+    entropy: f64,
+    longest_run: Run,
+    longest_printable_run: Run,
+    longest_nonprintable_run: Run,
+    // Whether the payload passes for ciphertext or compressed data
+    randomness: crate::randomness::Randomness,
+}
+
+fn is_printable(byte: u8) -> bool {
+    matches!(byte, b'\t' | b'\n' | b'\r' | 0x20..=0x7e)
+}
+
+fn round3(value: f64) -> f64 {
+    (value * 1000.0).round() / 1000.0
+}
+
+// Tracks the current and the longest run of bytes matching a property
+#[derive(Default)]
+struct RunTracker {
+    start: usize,
+    length: usize,
+    longest: Run,
+}
+
+impl RunTracker {
+    // Returns whether this byte made the current run the longest one
+    fn step(&mut self, offset: usize, continues: bool) -> bool {
+        if !continues {
+            self.start = offset;
+            self.length = 0;
+        }
+        self.length += 1;
+        let longest = self.length > self.longest.length;
+        if longest {
+            self.longest.length = self.length;
+            self.longest.offset = self.start;
+        }
+        longest
+    }
+
+    fn step_if(&mut self, offset: usize, matches: bool) {
+        match matches {
+            true => {
+                self.step(offset, self.length > 0);
+            }
+            false => self.length = 0,
+        }
+    }
+}
+
+pub fn examine(body: &[u8]) -> Autopsy {
+    let mut histogram = vec![0u64; 256];
+    let mut same = RunTracker::default();
+    let mut printable_run = RunTracker::default();
+    let mut nonprintable_run = RunTracker::default();
+    let mut previous: Option<u8> = None;
+    for (offset, &byte) in body.iter().enumerate() {
+        histogram[byte as usize] += 1;
+        if same.step(offset, previous == Some(byte)) {
+            same.longest.byte = Some(byte);
+        }
+        let printable = is_printable(byte);
+        printable_run.step_if(offset, printable);
+        nonprintable_run.step_if(offset, !printable);
+        previous = Some(byte);
+    }
+    let printable: u64 = histogram
+        .iter()
+        .enumerate()
+        .filter(|(byte, _)| is_printable(*byte as u8))
+        .map(|(_, count)| count)
+        .sum();
+    let printable = printable as usize;
+    let entropy: f64 = histogram
+        .iter()
+        .filter(|count| **count > 0)
+        .map(|count| {
+            let p = *count as f64 / body.len() as f64;
+            -p * p.log2()
+        })
+        .sum();
+    let randomness = crate::randomness::test(body, &histogram);
+    Autopsy {
+        bytes: body.len(),
+        distinct_bytes: histogram.iter().filter(|count| **count > 0).count(),
+        histogram,
+        printable,
+        nonprintable: body.len() - printable,
+        printable_ratio: match body.len() {
+            0 => 1.0,
+            len => round3(printable as f64 / len as f64),
+        },
+        entropy: round3(entropy),
+        longest_run: same.longest,
+        longest_printable_run: printable_run.longest,
+        longest_nonprintable_run: nonprintable_run.longest,
+        randomness,
+    }
+}
@@ -0,0 +1,162 @@
+// --max-backlog: answer 429 instead of piling up work that can't be done soon
+//
+// Two backlogs are watched: stats waiting for the database, which every destruction
+// request ends with, and jobs from /queue and ?async=true waiting for a worker. Stats
+// wait either for the database lock or, while the database fails, in the stat buffer.
+// Retry-After is estimated from how long recent writes waited for the lock, the stat
+// buffer's next retry or how long recent jobs took.
+use crate::queue::{self, QueueStore};
+use crate::stat_buffer::StatBuffer;
+use actix_web::body::{BoxBody, MessageBody};
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::web::Data;
+use actix_web::{Error, HttpResponse};
+use std::future::{ready, Future, Ready};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+pub struct Backlog {
+    // None disables the backpressure, the backlog is still counted
+    max_depth: Option<usize>,
+    // Stat writes waiting for the database lock
+    stat_writes: AtomicUsize,
+    average_lock_wait_us: AtomicU64,
+}
+
+// A stat write waiting for the database lock, counted until dropped
+pub struct PendingWrite<'a> {
+    backlog: &'a Backlog,
+    started: Instant,
+}
+
+// Moving average giving the latest sample a weight of 1/8
+pub fn update_average(average_us: &AtomicU64, sample: Duration) {
+    let sample = sample.as_micros().min(u64::MAX as u128) as u64;
+    let average = average_us.load(Ordering::Relaxed);
+    let updated = match average {
+        0 => sample,
+        _ => average - average / 8 + sample / 8,
+    };
+    average_us.store(updated, Ordering::Relaxed);
+}
+
+// Whole seconds for Retry-After, at least one
+fn retry_after_secs(wait: Duration) -> u64 {
+    wait.as_secs_f64().ceil().max(1.0) as u64
+}
+
+impl Backlog {
+    pub fn new(max_depth: Option<usize>) -> Self {
+        Backlog {
+            max_depth,
+            stat_writes: AtomicUsize::new(0),
+            average_lock_wait_us: AtomicU64::new(0),
+        }
+    }
+
+    pub fn start_write(&self) -> PendingWrite<'_> {
+        self.stat_writes.fetch_add(1, Ordering::Relaxed);
+        PendingWrite {
+            backlog: self,
+            started: Instant::now(),
+        }
+    }
+
+    // How long a stat waits for the database, if too many are waiting. Buffered rows
+    // wait for the next retry, the others for the lock as long as recent writes did.
+    fn stat_writes_wait(&self, max_depth: usize, buffer: Option<&StatBuffer>) -> Option<Duration> {
+        let buffered = buffer.map_or(0, StatBuffer::pending_rows);
+        let pending = self.stat_writes.load(Ordering::Relaxed) + buffered;
+        (pending >= max_depth).then(|| match buffer {
+            Some(buffer) if buffered > 0 => buffer.retry_in(),
+            _ => Duration::from_micros(self.average_lock_wait_us.load(Ordering::Relaxed)),
+        })
+    }
+}
+
+impl Drop for PendingWrite<'_> {
+    fn drop(&mut self) {
+        update_average(&self.backlog.average_lock_wait_us, self.started.elapsed());
+        self.backlog.stat_writes.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+// Why a destruction request can't be accepted now and when to come back
+fn refusal(req: &ServiceRequest) -> Option<(&'static str, Duration)> {
+    if !crate::read_only::is_destruction(req) {
+        return None;
+    }
+    let backlog = req.app_data::<Data<Backlog>>()?;
+    let max_depth = backlog.max_depth?;
+    let buffer = req
+        .app_data::<Data<StatBuffer>>()
+        .map(|buffer| buffer.get_ref());
+    if let Some(wait) = backlog.stat_writes_wait(max_depth, buffer) {
+        return Some(("Too many stats are waiting to be written.", wait));
+    }
+    if !queue::is_queued(req) {
+        return None;
+    }
+    req.app_data::<Data<QueueStore>>()?
+        .backlog(max_depth)
+        .map(|wait| ("Too many jobs are waiting for a worker.", wait))
+}
+
+// 429 with a Retry-After of the expected wait
+pub fn too_many(reason: &str, wait: Duration) -> HttpResponse {
+    let retry_after = retry_after_secs(wait);
+    HttpResponse::TooManyRequests()
+        .insert_header(("Retry-After", retry_after.to_string()))
+        .json(serde_json::json!({
+            "error": format!("{} Please try again in {}s.", reason, retry_after),
+            "retry_after": retry_after
+        }))
+}
+
+// Middleware refusing destruction requests with 429 while a backlog is too deep
+pub struct Backpressure;
+
+impl<S, B> Transform<S, ServiceRequest> for Backpressure
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<BoxBody>;
+    type Error = Error;
+    type Transform = BackpressureMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(BackpressureMiddleware { service }))
+    }
+}
+
+pub struct BackpressureMiddleware<S> {
+    service: S,
+}
+
+impl<S, B> Service<ServiceRequest> for BackpressureMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<BoxBody>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    fn poll_ready(&self, ctx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(ctx)
+    }
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let Some((reason, wait)) = refusal(&req) else {
+            let fut = self.service.call(req);
+            return Box::pin(async move { Ok(fut.await?.map_into_boxed_body()) });
+        };
+        let response = too_many(reason, wait);
+        Box::pin(async move { Ok(req.into_response(response)) })
+    }
+}
@@ -0,0 +1,207 @@
+// Online backups of the stats database for POST /admin/backup
+//
+// The snapshot is taken with SQLite's backup API from a connection of its own, so
+// requests keep being recorded while it runs and the copy is always consistent.
+// With SQLCipher the backup is encrypted with the same key as the database.
+use crate::{check_admin, AppConfig, DbArgs};
+use actix_web::http::StatusCode;
+use actix_web::web::{self, Bytes, Data};
+use actix_web::{HttpRequest, HttpResponse, Result};
+use rusqlite::backup::{Backup, StepResult};
+use rusqlite::{Connection, OpenFlags};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{ErrorKind, Read};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+// Bytes read from the snapshot per response chunk
+const CHUNK_SIZE: usize = 64 * 1024;
+// How long the copy waits for a write holding the database
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+const BUSY_RETRY: Duration = Duration::from_millis(10);
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct BackupRequest {
+    // Where the server writes the snapshot, streamed in the response if missing
+    path: Option<String>,
+}
+
+#[derive(Serialize)]
+struct BackupResponse {
+    status: &'static str,
+    path: String,
+    bytes: u64,
+    duration_ms: u128,
+}
+
+// Copy the whole database to `target` in one step. Writers wait on the busy
+// timeout for the moment it takes instead of restarting a stepwise copy, and the
+// copy itself retries while a write is in progress.
+fn snapshot(db: &DbArgs, target: &Path) -> Result<(), String> {
+    let source = crate::open_db(
+        db,
+        OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+    )?;
+    let mut copy = Connection::open(target)
+        .map_err(|e| format!("Failed to create {}: {}", target.display(), e))?;
+    #[cfg(feature = "sqlcipher")]
+    if let Some(key) = db.key()? {
+        copy.pragma_update(None, "key", key)
+            .map_err(|e| format!("Failed to set backup key: {}", e))?;
+    }
+    let backup = Backup::new(&source, &mut copy).map_err(|e| format!("Backup failed: {}", e))?;
+    let deadline = Instant::now() + BUSY_TIMEOUT;
+    loop {
+        match backup.step(-1) {
+            Ok(StepResult::Done) => return Ok(()),
+            Ok(StepResult::More) => {}
+            Ok(_) if Instant::now() < deadline => std::thread::sleep(BUSY_RETRY),
+            Ok(_) => return Err("Backup failed: database stayed locked".to_string()),
+            Err(e) => return Err(format!("Backup failed: {}", e)),
+        }
+    }
+}
+
+// Snapshot into `<path>.partial` first, so an interrupted backup never looks complete.
+// The snapshot is linked to its name rather than renamed, which fails instead of
+// replacing a file that appeared there meanwhile.
+fn backup_to(db: &DbArgs, path: &Path) -> Result<u64, (StatusCode, String)> {
+    let mut partial = path.as_os_str().to_owned();
+    partial.push(".partial");
+    let partial = PathBuf::from(partial);
+    let _ = std::fs::remove_file(&partial);
+    let result = snapshot(db, &partial)
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e))
+        .and_then(|()| {
+            std::fs::hard_link(&partial, path).map_err(|e| match e.kind() {
+                ErrorKind::AlreadyExists => (StatusCode::CONFLICT, exists(path)),
+                _ => (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    format!("Failed to move backup to {}: {}", path.display(), e),
+                ),
+            })
+        });
+    let _ = std::fs::remove_file(&partial);
+    result?;
+    std::fs::metadata(path)
+        .map(|meta| meta.len())
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))
+}
+
+fn exists(path: &Path) -> String {
+    format!(
+        "{} already exists, refusing to overwrite it.",
+        path.display()
+    )
+}
+
+// Snapshot into a temporary file and hand it out already unlinked, so nothing is
+// left behind however the download ends. The file and SQLite's journal next to it
+// are in a directory only we can read, the copy isn't encrypted without SQLCipher.
+fn backup_to_temp(db: &DbArgs) -> Result<(File, u64), String> {
+    let dir = std::env::temp_dir().join(format!(
+        "payload-pulverizer-backup-{:016x}",
+        rand::random::<u64>()
+    ));
+    let mut builder = std::fs::DirBuilder::new();
+    #[cfg(unix)]
+    std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+    builder
+        .create(&dir)
+        .map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+    let path = dir.join("backup.db");
+    let file = snapshot(db, &path)
+        .and_then(|()| File::open(&path).map_err(|e| format!("Failed to read backup: {}", e)));
+    let _ = std::fs::remove_dir_all(&dir);
+    let file = file?;
+    let bytes = file.metadata().map_err(|e| e.to_string())?.len();
+    Ok((file, bytes))
+}
+
+fn error(status: actix_web::http::StatusCode, message: String) -> HttpResponse {
+    HttpResponse::build(status).json(serde_json::json!({ "error": message }))
+}
+
+/// Handler for POST /admin/backup
+/// Admin only. Writes a consistent snapshot of the stats database to the `path` given
+/// in the JSON body, or streams it as the response without one.
+pub async fn backup_handler(
+    req: HttpRequest,
+    body: Bytes,
+    config: Data<AppConfig>,
+    db: Data<DbArgs>,
+) -> Result<HttpResponse> {
+    if let Some(response) = check_admin(&req, &config) {
+        return Ok(response);
+    }
+    let request: BackupRequest = if body.is_empty() {
+        BackupRequest::default()
+    } else {
+        match serde_json::from_slice(&body) {
+            Ok(request) => request,
+            Err(e) => {
+                return Ok(error(
+                    StatusCode::BAD_REQUEST,
+                    format!("Invalid backup request: {}", e),
+                ))
+            }
+        }
+    };
+    let start = Instant::now();
+    let Some(path) = request.path else {
+        let (file, bytes) = match web::block(move || backup_to_temp(&db)).await? {
+            Ok(snapshot) => snapshot,
+            Err(e) => return Ok(error(StatusCode::INTERNAL_SERVER_ERROR, e)),
+        };
+        let chunks = futures_util::stream::unfold(Some(file), |file| async move {
+            let mut file = file?;
+            let read = web::block(move || {
+                let mut chunk = Vec::with_capacity(CHUNK_SIZE);
+                (&mut file)
+                    .take(CHUNK_SIZE as u64)
+                    .read_to_end(&mut chunk)
+                    .map(|_| (chunk, file))
+            })
+            .await;
+            match read {
+                Ok(Ok((chunk, _))) if chunk.is_empty() => None,
+                Ok(Ok((chunk, file))) => Some((Ok(Bytes::from(chunk)), Some(file))),
+                Ok(Err(e)) => Some((Err(actix_web::Error::from(e)), None)),
+                Err(e) => Some((Err(actix_web::Error::from(e)), None)),
+            }
+        });
+        return Ok(HttpResponse::Ok()
+            .content_type("application/vnd.sqlite3")
+            .insert_header((
+                "Content-Disposition",
+                "attachment; filename=\"payload-pulverizer.db\"",
+            ))
+            .no_chunking(bytes)
+            .streaming(chunks));
+    };
+    let target = PathBuf::from(&path);
+    if target.exists() {
+        return Ok(error(StatusCode::CONFLICT, exists(&target)));
+    }
+    if target
+        .parent()
+        .is_some_and(|parent| !parent.as_os_str().is_empty() && !parent.is_dir())
+    {
+        return Ok(error(
+            StatusCode::BAD_REQUEST,
+            format!("The directory of {} does not exist.", path),
+        ));
+    }
+    let result = web::block(move || backup_to(&db, &target)).await?;
+    Ok(match result {
+        Ok(bytes) => HttpResponse::Ok().json(BackupResponse {
+            status: "ok",
+            path,
+            bytes,
+            duration_ms: start.elapsed().as_millis(),
+        }),
+        Err((status, e)) => error(status, e),
+    })
+}
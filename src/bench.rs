@@ -0,0 +1,106 @@
+// Internal micro-benchmark for the /bench endpoint
+use rand::Rng;
+#[cfg(feature = "sqlite")]
+use rusqlite::{params, Connection};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+#[cfg(feature = "sqlite")]
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+// Time budget per benchmark, keeps the whole run well below a second
+const BENCH_BUDGET: Duration = Duration::from_millis(200);
+
+#[derive(Serialize)]
+pub struct BenchReport {
+    json_parse_mib_per_s: f64,
+    #[cfg(feature = "sqlite")]
+    sqlite_inserts_per_s: f64,
+    sha256_mib_per_s: f64,
+}
+
+// Repeat `op` until the budget is used up, returns (iterations, elapsed seconds)
+fn repeat_for_budget(mut op: impl FnMut()) -> (u64, f64) {
+    let start = Instant::now();
+    let mut iterations = 0;
+    while start.elapsed() < BENCH_BUDGET {
+        op();
+        iterations += 1;
+    }
+    (iterations, start.elapsed().as_secs_f64())
+}
+
+fn mib_per_s(bytes: usize, iterations: u64, secs: f64) -> f64 {
+    (bytes as f64 * iterations as f64) / secs / (1024.0 * 1024.0)
+}
+
+fn bench_json_parse() -> f64 {
+    let records: Vec<serde_json::Value> = (0..500)
+        .map(|i| {
+            serde_json::json!({
+                "id": i,
+                "name": format!("payload-{}", i),
+                "tags": ["doomed", "shreddable", "gone"],
+                "nested": { "size": i * 1024, "ratio": i as f64 / 3.0, "ok": i % 2 == 0 },
+            })
+        })
+        .collect();
+    let doc = serde_json::to_string(&records).unwrap();
+    let (iterations, secs) = repeat_for_budget(|| {
+        let _ = serde_json::from_str::<serde_json::Value>(&doc).unwrap();
+    });
+    mib_per_s(doc.len(), iterations, secs)
+}
+
+#[cfg(feature = "sqlite")]
+fn bench_sqlite_inserts() -> rusqlite::Result<f64> {
+    // Use a scratch file next to other temp files so disk speed is part of the number,
+    // but never touch the real stats table. Concurrent runs each get their own file.
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    let path = std::env::temp_dir().join(format!(
+        "payload-pulverizer-bench-{}-{}.db",
+        std::process::id(),
+        NEXT.fetch_add(1, Ordering::Relaxed)
+    ));
+    let result = (|| {
+        let conn = Connection::open(&path)?;
+        conn.execute(
+            "CREATE TABLE bench (id INTEGER PRIMARY KEY AUTOINCREMENT, endpoint TEXT NOT NULL, payload_size INTEGER NOT NULL, runtime_us INTEGER NOT NULL, ts DATETIME DEFAULT CURRENT_TIMESTAMP)",
+            [],
+        )?;
+        let mut stmt = conn.prepare(
+            "INSERT INTO bench (endpoint, payload_size, runtime_us) VALUES (?1, ?2, ?3)",
+        )?;
+        let mut failed = None;
+        let (iterations, secs) = repeat_for_budget(|| {
+            if let Err(e) = stmt.execute(params!["bench", 1024, 42]) {
+                failed = Some(e);
+            }
+        });
+        match failed {
+            Some(e) => Err(e),
+            None => Ok(iterations as f64 / secs),
+        }
+    })();
+    let _ = std::fs::remove_file(&path);
+    result
+}
+
+fn bench_sha256() -> f64 {
+    let mut buffer = vec![0u8; 1024 * 1024];
+    rand::rng().fill(&mut buffer[..]);
+    let (iterations, secs) = repeat_for_budget(|| {
+        let _ = Sha256::digest(&buffer);
+    });
+    mib_per_s(buffer.len(), iterations, secs)
+}
+
+// Run all micro-benchmarks, blocking for roughly 3 * BENCH_BUDGET
+pub fn run() -> Result<BenchReport, String> {
+    Ok(BenchReport {
+        json_parse_mib_per_s: bench_json_parse(),
+        #[cfg(feature = "sqlite")]
+        sqlite_inserts_per_s: bench_sqlite_inserts().map_err(|e| e.to_string())?,
+        sha256_mib_per_s: bench_sha256(),
+    })
+}
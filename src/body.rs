@@ -0,0 +1,239 @@
+// Request bodies of the destruction endpoints.
+//
+// Bodies are read chunk by chunk and cut off at limits.max_payload_size while they
+// arrive, so uploads without Content-Length (chunked transfers) are measured by what
+// was actually received and never buffered beyond the configured limit. With
+// --spill-dir, bodies past the spill threshold continue on disk, see spill.rs.
+//
+// Bodies with a Content-Encoding are decompressed on the fly. To keep a few kilobytes of
+// gzip from turning into gigabytes, the output is held against
+// --max-decompressed-size and, past the first megabyte, --max-decompression-ratio.
+// Bombs are answered with 413 and recorded as "compression-bomb" with the compressed
+// size received. Content-MD5 and Digest are checked on the compressed bytes, see
+// checksum.rs, If-Match on the decompressed body, see if_match.rs.
+use crate::checksum::Checksum;
+use crate::gauges::{Buffered, Gauges};
+use crate::spill::{Scratch, SpillFile};
+use crate::{AppConfig, StatExtras};
+use actix_web::dev::{Decompress, Payload};
+use actix_web::error::InternalError;
+use actix_web::http::header::{CONTENT_ENCODING, CONTENT_LENGTH};
+use actix_web::web::{Bytes, BytesMut, Data};
+use actix_web::{FromRequest, HttpRequest, HttpResponse};
+use futures_util::future::LocalBoxFuture;
+use futures_util::StreamExt;
+use std::cell::{Cell, RefCell};
+use std::ops::Deref;
+use std::rc::Rc;
+
+// Output up to this size is never a bomb, however well it compressed
+const RATIO_GRACE: usize = 1024 * 1024;
+
+pub struct Body {
+    bytes: Bytes,
+    // Counts the body in the buffered bytes gauge for as long as the handler holds it
+    _buffered: Option<Buffered>,
+}
+
+impl Deref for Body {
+    type Target = Bytes;
+
+    fn deref(&self) -> &Bytes {
+        &self.bytes
+    }
+}
+
+pub fn too_large(max: usize) -> actix_web::Error {
+    let response = HttpResponse::PayloadTooLarge().json(serde_json::json!({
+        "error": format!("Payload too large. Maximum allowed size is {}.", crate::format_size(max))
+    }));
+    InternalError::from_response("payload too large", response).into()
+}
+
+fn spill_failed(e: std::io::Error) -> actix_web::Error {
+    eprintln!("Failed to spill a request body to disk: {}", e);
+    actix_web::error::ErrorInternalServerError("Failed to buffer the payload")
+}
+
+// Why a compressed body counts as a bomb, None while it is within the limits
+fn bomb_reason(config: &AppConfig, compressed: usize, decompressed: usize) -> Option<String> {
+    if decompressed > config.max_decompressed_size {
+        return Some(format!(
+            "expands to more than {} (--max-decompressed-size)",
+            crate::format_size(config.max_decompressed_size)
+        ));
+    }
+    let ratio = config.max_decompression_ratio as usize;
+    (decompressed > RATIO_GRACE && decompressed > compressed.saturating_mul(ratio)).then(|| {
+        format!(
+            "expands more than {} times (--max-decompression-ratio)",
+            ratio
+        )
+    })
+}
+
+// Answer a compression bomb and record the attempt
+fn bomb(
+    req: &HttpRequest,
+    config: &AppConfig,
+    reason: String,
+    compressed: usize,
+) -> actix_web::Error {
+    if let Some(db) = req.app_data::<Data<dyn crate::store::Store>>() {
+        crate::record_stat_with(
+            db.get_ref(),
+            "compression-bomb",
+            compressed,
+            crate::get_start_time(req).elapsed().as_micros(),
+            StatExtras::for_request(req, config),
+        );
+    }
+    let response = HttpResponse::PayloadTooLarge().json(serde_json::json!({
+        "error": format!("Compression bomb: the body {}.", reason),
+        "compressed_bytes": compressed
+    }));
+    InternalError::from_response("compression bomb", response).into()
+}
+
+// A body being received: in memory, past the --spill-dir threshold on disk
+pub struct Buffer {
+    memory: BytesMut,
+    spilled: Option<SpillFile>,
+    scratch: Option<Data<Scratch>>,
+}
+
+impl Buffer {
+    pub fn new(req: &HttpRequest) -> Self {
+        Buffer {
+            memory: BytesMut::new(),
+            spilled: None,
+            scratch: req.app_data::<Data<Scratch>>().cloned(),
+        }
+    }
+
+    // Bytes received so far
+    pub fn received(&self) -> usize {
+        self.spilled
+            .as_ref()
+            .map_or(self.memory.len(), SpillFile::len)
+    }
+
+    // Bytes held in memory
+    pub fn in_memory(&self) -> usize {
+        self.memory.len()
+    }
+
+    pub fn push(&mut self, chunk: &[u8]) -> Result<(), actix_web::Error> {
+        match (&mut self.spilled, &self.scratch) {
+            (Some(spill), _) => spill.write(chunk).map_err(spill_failed)?,
+            (None, Some(scratch)) if self.memory.len() + chunk.len() > scratch.threshold() => {
+                let mut spill = scratch.create(&self.memory).map_err(spill_failed)?;
+                spill.write(chunk).map_err(spill_failed)?;
+                self.memory = BytesMut::new();
+                self.spilled = Some(spill);
+            }
+            (None, _) => self.memory.extend_from_slice(chunk),
+        }
+        Ok(())
+    }
+
+    // The whole body, spilled ones mapped from their file and wiped once dropped
+    pub fn finish(self) -> Result<Bytes, actix_web::Error> {
+        match self.spilled {
+            Some(spill) => spill.finish().map_err(spill_failed),
+            None => Ok(self.memory.freeze()),
+        }
+    }
+}
+
+// The configured payload limit of a request
+pub fn payload_limit(req: &HttpRequest) -> usize {
+    req.app_data::<Data<crate::AppConfig>>()
+        .and_then(|config| config.settings().limits.max_payload_size)
+        .unwrap_or(crate::MAX_PAYLOAD_SIZE)
+}
+
+// Whether the request announced no length up front, i.e. the body was streamed
+pub fn is_chunked(req: &HttpRequest) -> bool {
+    !req.headers().contains_key(CONTENT_LENGTH)
+}
+
+impl FromRequest for Body {
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let limit = payload_limit(req);
+        let length: Option<usize> = req
+            .headers()
+            .get(CONTENT_LENGTH)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse().ok());
+        // Announced sizes are rejected before reading anything
+        if length.is_some_and(|length| length > limit) {
+            return Box::pin(async move { Err(too_large(limit)) });
+        }
+        // Compressed bytes read so far, counted and hashed before decompression
+        let compressed = Rc::new(Cell::new(0));
+        let checksum: Rc<RefCell<Option<Checksum>>> =
+            Rc::new(RefCell::new(crate::checksum::start(req)));
+        let counter = compressed.clone();
+        let hasher = checksum.clone();
+        let payload = payload.take().inspect(move |chunk| {
+            if let Ok(chunk) = chunk {
+                counter.set(counter.get() + chunk.len());
+                if let Some(checksum) = hasher.borrow_mut().as_mut() {
+                    checksum.update(chunk);
+                }
+            }
+        });
+        let mut stream = Decompress::from_headers(payload, req.headers());
+        let is_encoded = req
+            .headers()
+            .get(CONTENT_ENCODING)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|value| !value.trim().eq_ignore_ascii_case("identity"));
+        let config = req
+            .app_data::<Data<AppConfig>>()
+            .filter(|_| is_encoded)
+            .cloned();
+        let mut buffered = req
+            .app_data::<Data<Gauges>>()
+            .map(|gauges| Buffered::new(gauges.clone()));
+        let req = req.clone();
+        Box::pin(async move {
+            let mut body = Buffer::new(&req);
+            while let Some(chunk) = stream.next().await {
+                let chunk = chunk?;
+                let received = body.received();
+                if let Some(config) = &config {
+                    if let Some(reason) =
+                        bomb_reason(config, compressed.get(), received + chunk.len())
+                    {
+                        return Err(bomb(&req, config, reason, compressed.get()));
+                    }
+                }
+                if received + chunk.len() > limit {
+                    return Err(too_large(limit));
+                }
+                body.push(&chunk)?;
+                if let Some(buffered) = &mut buffered {
+                    buffered.set(body.in_memory());
+                }
+            }
+            if let Some(checksum) = checksum.take() {
+                checksum.finish(&req);
+            }
+            // Spilled bodies are read back from the page cache, not our memory
+            if body.spilled.is_some() {
+                buffered = None;
+            }
+            let body = body.finish()?;
+            crate::if_match::check(&req, &body)?;
+            Ok(Body {
+                bytes: body,
+                _buffered: buffered,
+            })
+        })
+    }
+}
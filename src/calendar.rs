@@ -0,0 +1,130 @@
+// iCalendar and vCard detection for /validate-before-destroy
+//
+// Both formats are lines of `NAME;PARAMS:VALUE` between BEGIN and END markers, folded
+// by starting continuation lines with a space. Events, to-dos and contacts are counted
+// and the dates they cover reported as a range: DTSTART to DTEND/DUE for calendars,
+// BDAY and ANNIVERSARY for contacts (dates without a year are skipped).
+use chrono::NaiveDate;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+#[derive(Serialize)]
+pub struct DateRange {
+    first: NaiveDate,
+    last: NaiveDate,
+}
+
+#[derive(Serialize)]
+pub struct CalendarReport {
+    // "icalendar" or "vcard"
+    format: &'static str,
+    // events, todos, journals or contacts, by count
+    entries: BTreeMap<&'static str, usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dates: Option<DateRange>,
+}
+
+// Unfolded lines
+fn unfold(text: &str) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+    for line in text.lines() {
+        match (line.strip_prefix([' ', '\t']), lines.last_mut()) {
+            (Some(continued), Some(last)) => last.push_str(continued),
+            _ => lines.push(line.to_string()),
+        }
+    }
+    lines
+}
+
+// 20240131, 20240131T100000Z or 2024-01-31
+fn parse_date(value: &str) -> Option<NaiveDate> {
+    let digits: String = value
+        .chars()
+        .take_while(|c| *c != 'T')
+        .filter(|c| *c != '-')
+        .collect();
+    NaiveDate::parse_from_str(digits.get(..8)?, "%Y%m%d").ok()
+}
+
+// Report for iCalendar and vCard payloads, None for anything else
+pub fn detect(body_str: &str) -> Option<CalendarReport> {
+    let first = body_str
+        .trim_start()
+        .lines()
+        .next()?
+        .trim()
+        .to_ascii_uppercase();
+    let (format, date_properties): (&'static str, &[&str]) = match first.as_str() {
+        "BEGIN:VCALENDAR" => ("icalendar", &["DTSTART", "DTEND", "DUE"]),
+        "BEGIN:VCARD" => ("vcard", &["BDAY", "ANNIVERSARY"]),
+        _ => return None,
+    };
+    let mut entries = BTreeMap::new();
+    let mut dates: Vec<NaiveDate> = Vec::new();
+    // Only dates of events, to-dos and contacts count, not of time zone definitions
+    let mut in_entry = false;
+    for line in unfold(body_str) {
+        let Some((name, value)) = line.split_once(':') else {
+            continue;
+        };
+        let name = name
+            .split(';')
+            .next()
+            .unwrap_or("")
+            .trim()
+            .to_ascii_uppercase();
+        let value = value.trim();
+        let entry = match value.to_ascii_uppercase().as_str() {
+            "VEVENT" => Some("events"),
+            "VTODO" => Some("todos"),
+            "VJOURNAL" => Some("journals"),
+            "VCARD" => Some("contacts"),
+            _ => None,
+        };
+        match (name.as_str(), entry) {
+            ("BEGIN", Some(entry)) => {
+                *entries.entry(entry).or_default() += 1;
+                in_entry = true;
+            }
+            ("END", Some(_)) => in_entry = false,
+            (name, _) if in_entry && date_properties.contains(&name) => {
+                dates.extend(parse_date(value));
+            }
+            _ => {}
+        }
+    }
+    let range = dates.iter().min().zip(dates.iter().max());
+    Some(CalendarReport {
+        format,
+        entries,
+        dates: range.map(|(first, last)| DateRange {
+            first: *first,
+            last: *last,
+        }),
+    })
+}
+
+impl CalendarReport {
+    pub fn detail(&self) -> String {
+        let name = match self.format {
+            "vcard" => "vCard",
+            _ => "iCalendar",
+        };
+        let counts: Vec<String> = self
+            .entries
+            .iter()
+            .map(|(entry, count)| format!("{} {}", count, entry))
+            .collect();
+        let counts = match counts.is_empty() {
+            true => "no entries".to_string(),
+            false => counts.join(", "),
+        };
+        match &self.dates {
+            Some(range) => format!(
+                "{} with {}, dated {} to {}.",
+                name, counts, range.first, range.last
+            ),
+            None => format!("{} with {}.", name, counts),
+        }
+    }
+}
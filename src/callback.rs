@@ -0,0 +1,234 @@
+// Asynchronous delivery of shred logs and receipts to a client supplied callback URL
+//
+// Every event is POSTed as its own JSON body, in order. Requests carry
+//   X-Pulverizer-Event      "log" or "receipt"
+//   X-Pulverizer-Timestamp  unix seconds
+//   X-Pulverizer-Signature  sha256=<hex HMAC-SHA256 of "<timestamp>.<body>" keyed with --callback-secret>
+//
+// A few worker threads deliver one callback URL's events at a time. A request reserves its
+// place in the queue before the payload is destroyed and is refused while the queue is
+// full, so slow or dead callback URLs can't pile up threads. Callback URLs are only
+// contacted on public addresses, like /fetch-and-destroy downloads.
+use actix_web::web::Data;
+use hmac::{Hmac, Mac};
+use serde::Serialize;
+use sha2::Sha256;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Mutex;
+use std::time::Duration;
+
+// Attempts per event before the rest of the delivery is abandoned
+const CALLBACK_ATTEMPTS: u32 = 4;
+// Backoff before the first retry, doubled after every failed attempt
+const CALLBACK_BACKOFF: Duration = Duration::from_secs(1);
+const CALLBACK_TIMEOUT: Duration = Duration::from_secs(10);
+const CALLBACK_WORKERS: usize = 4;
+// Deliveries waiting for a worker or reserved by requests still running
+const CALLBACK_QUEUE: usize = 1000;
+
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "lowercase")]
+pub enum CallbackEvent {
+    Log {
+        index: usize,
+        line: String,
+    },
+    Receipt {
+        status: &'static str,
+        endpoint: &'static str,
+        bytes: usize,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        passes: Option<u32>,
+        stat_id: Option<i64>,
+        destroyed_at: String,
+        runtime_us: u128,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        sha256: Option<String>,
+        // RFC 3161 token on sha256 (base64 DER), with --tsa-url
+        #[serde(skip_serializing_if = "Option::is_none")]
+        timestamp_token: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        timestamp_error: Option<String>,
+    },
+}
+
+impl CallbackEvent {
+    fn name(&self) -> &'static str {
+        match self {
+            CallbackEvent::Log { .. } => "log",
+            CallbackEvent::Receipt { .. } => "receipt",
+        }
+    }
+}
+
+// Everything to deliver to one callback URL
+struct Delivery {
+    url: String,
+    secret: String,
+    events: Vec<CallbackEvent>,
+    tsa_url: Option<String>,
+    // Frees the place in the queue once delivered
+    _reservation: Reservation,
+}
+
+pub struct Callbacks {
+    sender: Sender<Delivery>,
+    receiver: Mutex<Receiver<Delivery>>,
+    reserved: AtomicUsize,
+}
+
+// A place in the delivery queue, given back when dropped
+pub struct Reservation(Data<Callbacks>);
+
+impl Default for Callbacks {
+    fn default() -> Self {
+        let (sender, receiver) = mpsc::channel();
+        Callbacks {
+            sender,
+            receiver: Mutex::new(receiver),
+            reserved: AtomicUsize::new(0),
+        }
+    }
+}
+
+// A place in the queue, None while it's full
+pub fn reserve(callbacks: &Data<Callbacks>) -> Option<Reservation> {
+    callbacks
+        .reserved
+        .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |reserved| {
+            (reserved < CALLBACK_QUEUE).then_some(reserved + 1)
+        })
+        .ok()
+        .map(|_| Reservation(callbacks.clone()))
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        self.0.reserved.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+// Only plain http(s) URLs are accepted as callback targets
+pub fn validate_url(url: &str) -> Result<(), String> {
+    let rest = url
+        .strip_prefix("http://")
+        .or_else(|| url.strip_prefix("https://"))
+        .ok_or_else(|| "Callback URL must start with http:// or https://.".to_string())?;
+    if rest.is_empty() || rest.starts_with('/') {
+        return Err("Callback URL is missing a host.".to_string());
+    }
+    Ok(())
+}
+
+fn sign(secret: &str, timestamp: i64, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key length");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(body);
+    format!("sha256={:x}", mac.finalize().into_bytes())
+}
+
+// Send one event, retrying transport errors, 429 and 5xx with exponential backoff
+fn send(agent: &ureq::Agent, url: &str, secret: &str, event: &CallbackEvent) -> Result<(), String> {
+    let body = serde_json::to_vec(event).map_err(|e| e.to_string())?;
+    let mut backoff = CALLBACK_BACKOFF;
+    let mut attempt = 1;
+    loop {
+        let timestamp = chrono::Utc::now().timestamp();
+        let result = agent
+            .post(url)
+            .set("Content-Type", "application/json")
+            .set("X-Pulverizer-Event", event.name())
+            .set("X-Pulverizer-Timestamp", &timestamp.to_string())
+            .set("X-Pulverizer-Signature", &sign(secret, timestamp, &body))
+            .send_bytes(&body);
+        let error = match result {
+            Ok(_) => return Ok(()),
+            Err(ureq::Error::Status(code, _)) if code != 429 && code < 500 => {
+                return Err(format!("rejected with status {}", code))
+            }
+            Err(e) => e.to_string(),
+        };
+        if attempt == CALLBACK_ATTEMPTS {
+            return Err(format!("giving up after {} attempts: {}", attempt, error));
+        }
+        std::thread::sleep(backoff);
+        backoff *= 2;
+        attempt += 1;
+    }
+}
+
+// Queue the events for delivery in the background, the request that triggered them
+// doesn't wait
+pub fn deliver(
+    reservation: Reservation,
+    url: String,
+    secret: String,
+    events: Vec<CallbackEvent>,
+    tsa_url: Option<String>,
+) {
+    let sender = reservation.0.sender.clone();
+    let delivery = Delivery {
+        url,
+        secret,
+        events,
+        tsa_url,
+        _reservation: reservation,
+    };
+    // The receiver lives as long as the sender, both are in Callbacks
+    let _ = sender.send(delivery);
+}
+
+// Send the events of a delivery in order. Receipts are timestamped on the way when a TSA
+// is configured.
+fn send_all(agent: &ureq::Agent, delivery: Delivery) {
+    let Delivery {
+        url,
+        secret,
+        mut events,
+        tsa_url,
+        ..
+    } = delivery;
+    for event in &mut events {
+        if let (
+            CallbackEvent::Receipt {
+                sha256: Some(sha256),
+                timestamp_token,
+                timestamp_error,
+                ..
+            },
+            Some(tsa_url),
+        ) = (&mut *event, &tsa_url)
+        {
+            match crate::timestamp::stamp(tsa_url, sha256) {
+                Ok(token) => *timestamp_token = Some(token),
+                Err(e) => *timestamp_error = Some(e),
+            }
+        }
+        if let Err(e) = send(agent, &url, &secret, event) {
+            eprintln!("Callback to {} failed: {}", url, e);
+            return;
+        }
+    }
+}
+
+// Start the delivery workers, they run for as long as the process does
+pub fn spawn_workers(callbacks: Data<Callbacks>) {
+    for _ in 0..CALLBACK_WORKERS {
+        let callbacks = callbacks.clone();
+        std::thread::spawn(move || {
+            let agent = crate::fetch::guarded_agent(true)
+                .timeout(CALLBACK_TIMEOUT)
+                .build();
+            loop {
+                let delivery = callbacks.receiver.lock().unwrap().recv();
+                let Ok(delivery) = delivery else {
+                    return;
+                };
+                send_all(&agent, delivery);
+            }
+        });
+    }
+}
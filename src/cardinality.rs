@@ -0,0 +1,226 @@
+// Approximate unique payloads per endpoint and day (--count-unique)
+//
+// Every destroyed payload goes into a HyperLogLog sketch of its endpoint and UTC day, by
+// its SHA-256. A sketch takes 4 KiB however many payloads it sees and estimates how many
+// of them were distinct to within a few percent, which tells distinct content from
+// retries. Sketches merge, so the days add up to a total without counting a payload
+// twice. They are stored in the stats database; new payloads are collected in memory
+// and merged in once a minute and on shutdown.
+use actix_web::web::Data;
+use actix_web::HttpRequest;
+use rusqlite::{params, Connection, OptionalExtension};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+use std::time::Duration;
+
+// 2^12 registers, a standard error of about 1.6%
+const PRECISION: u32 = 12;
+const REGISTERS: usize = 1 << PRECISION;
+const FLUSH_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Clone)]
+pub struct Sketch {
+    registers: Vec<u8>,
+}
+
+// Estimates for /stats
+pub struct Unique {
+    pub total: u64,
+    pub by_day: BTreeMap<String, u64>,
+}
+
+// Sketches by endpoint and day
+pub type Sketches = BTreeMap<String, BTreeMap<String, Sketch>>;
+
+impl Sketch {
+    fn new() -> Self {
+        Sketch {
+            registers: vec![0; REGISTERS],
+        }
+    }
+
+    fn insert(&mut self, hash: u64) {
+        let register = (hash >> (64 - PRECISION)) as usize;
+        // Leading zeros of the remaining bits, the marker bit caps the rank
+        let rest = hash << PRECISION | 1 << (PRECISION - 1);
+        let rank = rest.leading_zeros() as u8 + 1;
+        self.registers[register] = self.registers[register].max(rank);
+    }
+
+    fn merge(&mut self, other: &Sketch) {
+        for (register, other) in self.registers.iter_mut().zip(&other.registers) {
+            *register = (*register).max(*other);
+        }
+    }
+
+    fn estimate(&self) -> u64 {
+        let m = REGISTERS as f64;
+        let alpha = 0.7213 / (1.0 + 1.079 / m);
+        let sum: f64 = self
+            .registers
+            .iter()
+            .map(|rank| 2f64.powi(-(*rank as i32)))
+            .sum();
+        let raw = alpha * m * m / sum;
+        let zeros = self.registers.iter().filter(|rank| **rank == 0).count();
+        // Linear counting is more accurate while many registers are still empty
+        if raw <= 2.5 * m && zeros > 0 {
+            (m * (m / zeros as f64).ln()).round() as u64
+        } else {
+            raw.round() as u64
+        }
+    }
+}
+
+pub(crate) fn ensure_table(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS unique_payloads (
+            endpoint TEXT NOT NULL,
+            day TEXT NOT NULL,
+            registers BLOB NOT NULL,
+            PRIMARY KEY (endpoint, day)
+        )",
+        [],
+    )
+    .map(|_| ())
+}
+
+fn add(sketches: &mut Sketches, endpoint: &str, day: &str, sketch: &Sketch) {
+    sketches
+        .entry(endpoint.to_string())
+        .or_default()
+        .entry(day.to_string())
+        .and_modify(|known| known.merge(sketch))
+        .or_insert_with(|| sketch.clone());
+}
+
+// Merge the sketches stored in a database file into `sketches`. The table is created on
+// the first write, files without it (read-only or older ones) just have none.
+pub fn add_stored(conn: &Connection, sketches: &mut Sketches) -> rusqlite::Result<()> {
+    let exists: bool = conn.query_row(
+        "SELECT COUNT(*) > 0 FROM sqlite_master WHERE type = 'table' AND name = 'unique_payloads'",
+        [],
+        |row| row.get(0),
+    )?;
+    if !exists {
+        return Ok(());
+    }
+    let mut stmt = conn.prepare("SELECT endpoint, day, registers FROM unique_payloads")?;
+    let mut rows = stmt.query([])?;
+    while let Some(row) = rows.next()? {
+        let registers: Vec<u8> = row.get(2)?;
+        if registers.len() == REGISTERS {
+            add(
+                sketches,
+                &row.get::<_, String>(0)?,
+                &row.get::<_, String>(1)?,
+                &Sketch { registers },
+            );
+        }
+    }
+    Ok(())
+}
+
+// The estimates of an endpoint's sketches, overall and per day
+pub fn estimate(days: &BTreeMap<String, Sketch>) -> Unique {
+    let mut total = Sketch::new();
+    for sketch in days.values() {
+        total.merge(sketch);
+    }
+    Unique {
+        total: total.estimate(),
+        by_day: days
+            .iter()
+            .map(|(day, sketch)| (day.clone(), sketch.estimate()))
+            .collect(),
+    }
+}
+
+#[derive(Default)]
+pub struct UniqueCounter {
+    // Not yet merged into the database, by endpoint and day
+    pending: Mutex<HashMap<(String, String), Sketch>>,
+}
+
+impl UniqueCounter {
+    fn observe(&self, endpoint: &str, body: &[u8]) {
+        let digest = Sha256::digest(body);
+        let hash = u64::from_le_bytes(digest[..8].try_into().unwrap());
+        let day = chrono::Utc::now().format("%Y-%m-%d").to_string();
+        self.pending
+            .lock()
+            .unwrap()
+            .entry((endpoint.to_string(), day))
+            .or_insert_with(Sketch::new)
+            .insert(hash);
+    }
+
+    // Merge the payloads seen since the last flush into `sketches`
+    pub fn add_pending(&self, sketches: &mut Sketches) {
+        for ((endpoint, day), sketch) in self.pending.lock().unwrap().iter() {
+            add(sketches, endpoint, day, sketch);
+        }
+    }
+
+    // Merge the pending sketches into the stored ones
+    fn flush(&self, conn: &Connection) -> rusqlite::Result<()> {
+        let pending = std::mem::take(&mut *self.pending.lock().unwrap());
+        if pending.is_empty() {
+            return Ok(());
+        }
+        let result = ensure_table(conn).and_then(|()| {
+            for ((endpoint, day), sketch) in &pending {
+                let stored: Option<Vec<u8>> = conn
+                    .query_row(
+                        "SELECT registers FROM unique_payloads WHERE endpoint = ?1 AND day = ?2",
+                        params![endpoint, day],
+                        |row| row.get(0),
+                    )
+                    .optional()?;
+                let mut merged = sketch.clone();
+                if let Some(registers) = stored.filter(|stored| stored.len() == REGISTERS) {
+                    merged.merge(&Sketch { registers });
+                }
+                conn.execute(
+                    "INSERT OR REPLACE INTO unique_payloads (endpoint, day, registers) VALUES (?1, ?2, ?3)",
+                    params![endpoint, day, merged.registers],
+                )?;
+            }
+            Ok(())
+        });
+        // Keep them for the next attempt, merging twice doesn't count anything twice
+        if result.is_err() {
+            let mut current = self.pending.lock().unwrap();
+            for (key, sketch) in pending {
+                current
+                    .entry(key)
+                    .and_modify(|known| known.merge(&sketch))
+                    .or_insert(sketch);
+            }
+        }
+        result
+    }
+}
+
+// Write the pending sketches to the database
+pub fn save(counter: &UniqueCounter, db: &Mutex<Connection>) {
+    if let Err(e) = counter.flush(&db.lock().unwrap()) {
+        eprintln!("Failed to save the unique payload counts: {}", e);
+    }
+}
+
+// Save the sketches once a minute, and once more on shutdown
+pub fn spawn_flusher(counter: Data<UniqueCounter>, db: Data<Mutex<Connection>>) {
+    std::thread::spawn(move || loop {
+        std::thread::sleep(FLUSH_INTERVAL);
+        save(&counter, &db);
+    });
+}
+
+// Count a destroyed payload, does nothing without --count-unique
+pub fn observe(req: &HttpRequest, endpoint: &str, body: &[u8]) {
+    if let Some(counter) = req.app_data::<Data<UniqueCounter>>() {
+        counter.observe(endpoint, body);
+    }
+}
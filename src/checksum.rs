@@ -0,0 +1,311 @@
+// Content-MD5 and Digest verification of request bodies
+//
+// Legacy uploaders still send Content-MD5 (RFC 1864), standards-minded ones Digest
+// (RFC 3230, e.g. `SHA-256=…`): the base64 digest of the body as sent, before any
+// Content-Encoding is undone. The body extractor hashes the bytes as they arrive and
+// leaves the outcome in the request extensions, from where /pulverize, /shred and
+// /burn report it as `checksum_verified` and the stats flag mismatches. A Want-Digest
+// header gets the digest of the destroyed payload back in a Digest response header,
+// added by DigestHeader.
+use actix_web::body::MessageBody;
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::header::{HeaderName, HeaderValue};
+use actix_web::{Error, HttpMessage, HttpRequest};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use md5::Md5;
+use sha2::{Digest, Sha256, Sha512};
+use std::future::{ready, Future, Ready};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+#[derive(Clone, Copy, PartialEq)]
+enum Algorithm {
+    Md5,
+    Sha256,
+    Sha512,
+}
+
+impl Algorithm {
+    // Digest algorithms are case-insensitive tokens
+    fn parse(name: &str) -> Option<Self> {
+        match name.trim().to_ascii_uppercase().as_str() {
+            "MD5" => Some(Algorithm::Md5),
+            "SHA-256" => Some(Algorithm::Sha256),
+            "SHA-512" => Some(Algorithm::Sha512),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Algorithm::Md5 => "MD5",
+            Algorithm::Sha256 => "SHA-256",
+            Algorithm::Sha512 => "SHA-512",
+        }
+    }
+
+    fn digest_len(self) -> usize {
+        match self {
+            Algorithm::Md5 => 16,
+            Algorithm::Sha256 => 32,
+            Algorithm::Sha512 => 64,
+        }
+    }
+}
+
+enum Hasher {
+    Md5(Md5),
+    Sha256(Sha256),
+    Sha512(Sha512),
+}
+
+impl Hasher {
+    fn new(algorithm: Algorithm) -> Self {
+        match algorithm {
+            Algorithm::Md5 => Hasher::Md5(Md5::new()),
+            Algorithm::Sha256 => Hasher::Sha256(Sha256::new()),
+            Algorithm::Sha512 => Hasher::Sha512(Sha512::new()),
+        }
+    }
+
+    fn update(&mut self, chunk: &[u8]) {
+        match self {
+            Hasher::Md5(hasher) => hasher.update(chunk),
+            Hasher::Sha256(hasher) => hasher.update(chunk),
+            Hasher::Sha512(hasher) => hasher.update(chunk),
+        }
+    }
+
+    fn finalize(self) -> Vec<u8> {
+        match self {
+            Hasher::Md5(hasher) => hasher.finalize().to_vec(),
+            Hasher::Sha256(hasher) => hasher.finalize().to_vec(),
+            Hasher::Sha512(hasher) => hasher.finalize().to_vec(),
+        }
+    }
+}
+
+// A digest the client sent along
+struct Expected {
+    // "Content-MD5" or e.g. "Digest SHA-256"
+    source: String,
+    algorithm: Algorithm,
+    // None if the value isn't a base64 digest of the right length
+    digest: Option<Vec<u8>>,
+}
+
+// Hashes in progress for a request that sent Content-MD5, Digest or Want-Digest
+pub struct Checksum {
+    expected: Vec<Expected>,
+    wanted: Option<Algorithm>,
+    hashers: Vec<(Algorithm, Hasher)>,
+}
+
+// Outcome of the check, in the request extensions once the body is read
+#[derive(Clone)]
+pub struct ChecksumCheck {
+    pub verified: bool,
+    pub warning: Option<String>,
+}
+
+// The Digest response header of a request, picked up by DigestHeader
+struct ComputedDigest(HeaderValue);
+
+fn decode(value: &str, algorithm: Algorithm) -> Option<Vec<u8>> {
+    STANDARD
+        .decode(value.trim())
+        .ok()
+        .filter(|digest| digest.len() == algorithm.digest_len())
+}
+
+// The supported algorithm with the highest q-value of a Want-Digest header, e.g.
+// `SHA-512;q=0.3, sha-256;q=1, unixsum;q=0`
+fn preferred(want_digest: &str) -> Option<Algorithm> {
+    let mut best: Option<(Algorithm, f32)> = None;
+    for entry in want_digest.split(',') {
+        let mut params = entry.split(';');
+        let Some(algorithm) = params.next().and_then(Algorithm::parse) else {
+            continue;
+        };
+        let q = params
+            .find_map(|param| param.trim().strip_prefix("q="))
+            .and_then(|q| q.trim().parse::<f32>().ok())
+            .unwrap_or(1.0);
+        if q > 0.0 && best.is_none_or(|(_, best_q)| q > best_q) {
+            best = Some((algorithm, q));
+        }
+    }
+    best.map(|(algorithm, _)| algorithm)
+}
+
+// None without any of the headers
+pub fn start(req: &HttpRequest) -> Option<Checksum> {
+    let header = |name: &str| {
+        req.headers()
+            .get(name)
+            .map(|value| value.to_str().unwrap_or_default())
+    };
+    let mut expected = Vec::new();
+    if let Some(value) = header("Content-MD5") {
+        expected.push(Expected {
+            source: "Content-MD5".to_string(),
+            algorithm: Algorithm::Md5,
+            digest: decode(value, Algorithm::Md5),
+        });
+    }
+    // Algorithms we don't know are skipped, the known ones still count
+    for value in req.headers().get_all("Digest") {
+        for entry in value.to_str().unwrap_or_default().split(',') {
+            let Some((name, digest)) = entry.split_once('=') else {
+                continue;
+            };
+            if let Some(algorithm) = Algorithm::parse(name) {
+                expected.push(Expected {
+                    source: format!("Digest {}", algorithm.name()),
+                    algorithm,
+                    digest: decode(digest, algorithm),
+                });
+            }
+        }
+    }
+    let wanted = header("Want-Digest").and_then(preferred);
+    if expected.is_empty() && wanted.is_none() {
+        return None;
+    }
+    let mut hashers: Vec<(Algorithm, Hasher)> = Vec::new();
+    let needed = expected.iter().map(|expected| expected.algorithm);
+    for algorithm in needed.chain(wanted) {
+        if !hashers.iter().any(|(known, _)| *known == algorithm) {
+            hashers.push((algorithm, Hasher::new(algorithm)));
+        }
+    }
+    Some(Checksum {
+        expected,
+        wanted,
+        hashers,
+    })
+}
+
+impl Checksum {
+    pub fn update(&mut self, chunk: &[u8]) {
+        for (_, hasher) in &mut self.hashers {
+            hasher.update(chunk);
+        }
+    }
+
+    pub fn finish(self, req: &HttpRequest) {
+        let actual: Vec<(Algorithm, Vec<u8>)> = self
+            .hashers
+            .into_iter()
+            .map(|(algorithm, hasher)| (algorithm, hasher.finalize()))
+            .collect();
+        let digest_of = |algorithm: Algorithm| {
+            actual
+                .iter()
+                .find(|(known, _)| *known == algorithm)
+                .map(|(_, digest)| digest.as_slice())
+                .unwrap_or_default()
+        };
+        let mut warnings = Vec::new();
+        let mut mismatched = false;
+        for expected in &self.expected {
+            let actual = digest_of(expected.algorithm);
+            match &expected.digest {
+                None => warnings.push(format!(
+                    "{} is not a base64-encoded {} digest.",
+                    expected.source,
+                    expected.algorithm.name()
+                )),
+                Some(digest) if digest == actual => {}
+                Some(digest) => {
+                    mismatched = true;
+                    warnings.push(format!(
+                        "{} mismatch: the header says {}, the body received hashes to {}.",
+                        expected.source,
+                        STANDARD.encode(digest),
+                        STANDARD.encode(actual)
+                    ));
+                }
+            }
+        }
+        if mismatched {
+            warnings.push(
+                "It was corrupted on the way, or the uploader hashed something else.".to_string(),
+            );
+        }
+        let mut extensions = req.extensions_mut();
+        if !self.expected.is_empty() {
+            extensions.insert(ChecksumCheck {
+                verified: warnings.is_empty(),
+                warning: (!warnings.is_empty()).then(|| warnings.join(" ")),
+            });
+        }
+        if let Some(algorithm) = self.wanted {
+            let value = format!(
+                "{}={}",
+                algorithm.name(),
+                STANDARD.encode(digest_of(algorithm))
+            );
+            if let Ok(value) = HeaderValue::from_str(&value) {
+                extensions.insert(ComputedDigest(value));
+            }
+        }
+    }
+}
+
+// The outcome for a request whose body has been read, None without Content-MD5 or
+// a Digest in a supported algorithm
+pub fn check(req: &HttpRequest) -> Option<ChecksumCheck> {
+    req.extensions().get::<ChecksumCheck>().cloned()
+}
+
+// Middleware adding the Digest header to responses of requests with Want-Digest
+pub struct DigestHeader;
+
+impl<S, B> Transform<S, ServiceRequest> for DigestHeader
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = DigestHeaderMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(DigestHeaderMiddleware { service }))
+    }
+}
+
+pub struct DigestHeaderMiddleware<S> {
+    service: S,
+}
+
+impl<S, B> Service<ServiceRequest> for DigestHeaderMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    fn poll_ready(&self, ctx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(ctx)
+    }
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let fut = self.service.call(req);
+        Box::pin(async move {
+            let mut res = fut.await?;
+            let digest = res.request().extensions_mut().remove::<ComputedDigest>();
+            if let Some(ComputedDigest(value)) = digest {
+                res.headers_mut()
+                    .insert(HeaderName::from_static("digest"), value);
+            }
+            Ok(res)
+        })
+    }
+}
@@ -0,0 +1,256 @@
+// POST /compare-then-destroy: compare two payloads, then destroy both
+//
+// The payloads arrive as the parts `a` and `b` of a multipart/form-data body. Only a
+// summary of their differences is returned (sizes, hashes, line counts), so checking
+// whether two exports match needs neither file to be kept.
+use crate::dedup::{already_reported, DedupCache};
+use crate::store::Store;
+use crate::{
+    body, check_payload, get_start_time, record_timed_stat, remember_payload, similarity, work,
+    AppConfig, PhaseTimings, StatExtras,
+};
+use actix_web::web::{self, Data};
+use actix_web::{HttpMessage, HttpRequest, HttpResponse, Result};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::time::Instant;
+
+// Larger line diffs are estimated from line counts instead of aligned
+const MAX_DIFF_CELLS: usize = 16 * 1024 * 1024;
+
+#[derive(Serialize)]
+struct LineDiff {
+    lines_a: usize,
+    lines_b: usize,
+    unchanged: usize,
+    // Lines only in b
+    added: usize,
+    // Lines only in a
+    removed: usize,
+    // 2 * unchanged / (lines_a + lines_b), rounded to 3 decimals
+    similarity: f64,
+    // Set when the payloads were too different to align line by line, the counts
+    // then ignore the order of lines
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    approximate: bool,
+}
+
+#[derive(Serialize)]
+struct Comparison {
+    size_a: usize,
+    size_b: usize,
+    // size_b - size_a
+    size_delta: i64,
+    sha256_a: String,
+    sha256_b: String,
+    identical: bool,
+    // Only if both payloads are UTF-8 text
+    #[serde(skip_serializing_if = "Option::is_none")]
+    lines: Option<LineDiff>,
+}
+
+#[derive(Serialize)]
+struct CompareResponse {
+    status: &'static str,
+    comparison: Comparison,
+    runtime_us: u128,
+    timings: PhaseTimings,
+}
+
+fn bad_request(message: String) -> HttpResponse {
+    HttpResponse::BadRequest().json(serde_json::json!({ "error": message }))
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+// The name parameter of a part's Content-Disposition header
+fn part_name(headers: &str) -> Option<String> {
+    let disposition = headers.lines().find(|line| {
+        line.to_ascii_lowercase()
+            .starts_with("content-disposition:")
+    })?;
+    disposition.split(';').find_map(|param| {
+        let (key, value) = param.trim().split_once('=')?;
+        (key.eq_ignore_ascii_case("name")).then(|| value.trim_matches('"').to_string())
+    })
+}
+
+// Named parts of a multipart/form-data body, later parts win over earlier ones
+fn parse_parts<'a>(body: &'a [u8], boundary: &str) -> Result<HashMap<String, &'a [u8]>, String> {
+    let delimiter = format!("--{}", boundary).into_bytes();
+    let start = find(body, &delimiter).ok_or("Multipart boundary not found in the body")?;
+    let mut rest = &body[start + delimiter.len()..];
+    let separator = [b"\r\n".as_slice(), &delimiter].concat();
+    let mut parts = HashMap::new();
+    loop {
+        if rest.starts_with(b"--") {
+            return Ok(parts);
+        }
+        rest = rest
+            .strip_prefix(b"\r\n")
+            .ok_or("Malformed multipart body")?;
+        let end = find(rest, &separator).ok_or("Multipart body ends without closing boundary")?;
+        let part = &rest[..end];
+        let header_end = find(part, b"\r\n\r\n").ok_or("Multipart part without headers")?;
+        let headers = String::from_utf8_lossy(&part[..header_end]);
+        if let Some(name) = part_name(&headers) {
+            parts.insert(name.to_ascii_lowercase(), &part[header_end + 4..]);
+        }
+        rest = &rest[end + separator.len()..];
+    }
+}
+
+fn sha256(data: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(data))
+}
+
+// Length of the longest common subsequence of lines, two rows at a time
+fn common_lines(a: &[&str], b: &[&str]) -> usize {
+    let mut previous = vec![0u32; b.len() + 1];
+    let mut current = vec![0u32; b.len() + 1];
+    for line in a {
+        for (j, other) in b.iter().enumerate() {
+            current[j + 1] = match line == other {
+                true => previous[j] + 1,
+                false => current[j].max(previous[j + 1]),
+            };
+        }
+        std::mem::swap(&mut previous, &mut current);
+    }
+    previous[b.len()] as usize
+}
+
+// Lines both payloads have, ignoring their order
+fn shared_lines(a: &[&str], b: &[&str]) -> usize {
+    let mut counts: HashMap<&str, usize> = HashMap::new();
+    for line in a {
+        *counts.entry(line).or_default() += 1;
+    }
+    b.iter()
+        .filter(|line| match counts.get_mut(*line) {
+            Some(count) if *count > 0 => {
+                *count -= 1;
+                true
+            }
+            _ => false,
+        })
+        .count()
+}
+
+fn diff_lines(a: &str, b: &str) -> LineDiff {
+    let a: Vec<&str> = a.lines().collect();
+    let b: Vec<&str> = b.lines().collect();
+    // Equal heads and tails don't need aligning
+    let prefix = a.iter().zip(&b).take_while(|(x, y)| x == y).count();
+    let suffix = a[prefix..]
+        .iter()
+        .rev()
+        .zip(b[prefix..].iter().rev())
+        .take_while(|(x, y)| x == y)
+        .count();
+    let (middle_a, middle_b) = (&a[prefix..a.len() - suffix], &b[prefix..b.len() - suffix]);
+    let approximate = middle_a.len().saturating_mul(middle_b.len()) > MAX_DIFF_CELLS;
+    let unchanged = prefix
+        + suffix
+        + match approximate {
+            true => shared_lines(middle_a, middle_b),
+            false => common_lines(middle_a, middle_b),
+        };
+    let total = a.len() + b.len();
+    LineDiff {
+        lines_a: a.len(),
+        lines_b: b.len(),
+        unchanged,
+        added: b.len() - unchanged,
+        removed: a.len() - unchanged,
+        similarity: match total {
+            0 => 1.0,
+            _ => (2000.0 * unchanged as f64 / total as f64).round() / 1000.0,
+        },
+        approximate,
+    }
+}
+
+fn compare(a: &[u8], b: &[u8]) -> Comparison {
+    let (sha256_a, sha256_b) = (sha256(a), sha256(b));
+    let lines = match (std::str::from_utf8(a), std::str::from_utf8(b)) {
+        (Ok(a), Ok(b)) => Some(diff_lines(a, b)),
+        _ => None,
+    };
+    Comparison {
+        size_a: a.len(),
+        size_b: b.len(),
+        size_delta: b.len() as i64 - a.len() as i64,
+        identical: sha256_a == sha256_b,
+        sha256_a,
+        sha256_b,
+        lines,
+    }
+}
+
+/// Handler for POST /compare-then-destroy
+/// Compares the multipart parts `a` and `b` and destroys both.
+pub async fn compare_handler(
+    req: HttpRequest,
+    body: body::Body,
+    db: Data<dyn Store>,
+    dedup: Data<DedupCache>,
+    config: Data<AppConfig>,
+) -> Result<HttpResponse> {
+    let start = get_start_time(&req);
+    let handler_start = Instant::now();
+    if let Some(response) = check_payload(&req, &config.settings()) {
+        return Ok(response);
+    }
+    let boundary = match req.mime_type() {
+        Ok(Some(mime)) if mime.essence_str() == "multipart/form-data" => mime
+            .get_param("boundary")
+            .map(|boundary| boundary.to_string()),
+        _ => None,
+    };
+    let Some(boundary) = boundary else {
+        return Ok(bad_request(
+            "Send the payloads as multipart/form-data with the parts a and b.".to_string(),
+        ));
+    };
+    let digest = match dedup.check("compare-then-destroy", &body) {
+        Ok(digest) => digest,
+        Err(earlier) => return Ok(already_reported(earlier, start.elapsed().as_micros())),
+    };
+    let payload = body.clone();
+    let comparison = web::block(move || {
+        let parts = parse_parts(&payload, &boundary)?;
+        match (parts.get("a"), parts.get("b")) {
+            (Some(a), Some(b)) => Ok(compare(a, b)),
+            _ => Err("Both parts a and b are required.".to_string()),
+        }
+    })
+    .await?;
+    let comparison = match comparison {
+        Ok(comparison) => comparison,
+        Err(e) => return Ok(bad_request(e)),
+    };
+    work::simulate(&req).await?;
+    let (stat_id, timings) = record_timed_stat(
+        db.get_ref(),
+        "compare-then-destroy",
+        body.len(),
+        start,
+        handler_start,
+        StatExtras::for_request(&req, &config),
+    );
+    dedup.remember("compare-then-destroy", digest, stat_id);
+    similarity::observe(&req, "compare-then-destroy", &body, stat_id);
+    remember_payload(&req, "compare-then-destroy", &body);
+    Ok(HttpResponse::Ok().json(CompareResponse {
+        status: "compared_and_destroyed",
+        comparison,
+        runtime_us: start.elapsed().as_micros(),
+        timings,
+    }))
+}
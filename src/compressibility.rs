@@ -0,0 +1,63 @@
+// Compression estimate for the destruction responses
+//
+// Large uncompressed uploads get a hint on how much smaller they would have been
+// gzipped. Only a few samples are deflated at the fastest level, so the estimate costs
+// about the same for a 1 MB and a 250 MB payload.
+use flate2::write::DeflateEncoder;
+use flate2::Compression;
+use serde::Serialize;
+use std::io::Write;
+
+// Smaller payloads aren't worth the hint
+const MIN_SIZE: usize = 64 * 1024;
+// Bytes taken from the start, middle and end of the payload
+const SAMPLE_SIZE: usize = 64 * 1024;
+// Savings below this aren't reported
+const MIN_SAVINGS: f64 = 0.2;
+
+#[derive(Serialize)]
+pub struct CompressionHint {
+    // Estimated compressed size divided by the payload size, rounded to 3 decimals
+    estimated_ratio: f64,
+    hint: String,
+}
+
+fn deflated_size(sample: &[u8]) -> usize {
+    let mut encoder = DeflateEncoder::new(Vec::new(), Compression::fast());
+    // Writing to a Vec can't fail
+    let _ = encoder.write_all(sample);
+    encoder
+        .finish()
+        .map_or(sample.len(), |compressed| compressed.len())
+}
+
+// None for small payloads, uploads that were compressed already and payloads that
+// don't compress well
+pub fn estimate(body: &[u8], was_compressed: bool) -> Option<CompressionHint> {
+    if body.len() < MIN_SIZE || was_compressed {
+        return None;
+    }
+    let samples: Vec<&[u8]> = match body.len() <= 3 * SAMPLE_SIZE {
+        true => vec![body],
+        false => {
+            let middle = (body.len() - SAMPLE_SIZE) / 2;
+            vec![
+                &body[..SAMPLE_SIZE],
+                &body[middle..middle + SAMPLE_SIZE],
+                &body[body.len() - SAMPLE_SIZE..],
+            ]
+        }
+    };
+    let sampled: usize = samples.iter().map(|sample| sample.len()).sum();
+    let compressed: usize = samples.into_iter().map(deflated_size).sum();
+    // gzip adds 18 bytes of header and trailer to the deflate stream
+    let ratio = ((compressed as f64 / sampled as f64) + 18.0 / body.len() as f64).min(1.0);
+    let savings = 1.0 - ratio;
+    (savings >= MIN_SAVINGS).then(|| CompressionHint {
+        estimated_ratio: (ratio * 1000.0).round() / 1000.0,
+        hint: format!(
+            "This would have been {:.0}% smaller gzipped.",
+            (savings * 100.0).floor()
+        ),
+    })
+}
@@ -0,0 +1,76 @@
+// Optional TOML configuration file passed via --config.
+// Everything in here can be reloaded at runtime (SIGHUP or POST /admin/reload).
+#[cfg(feature = "sqlite")]
+use crate::alerts::AlertSettings;
+use crate::routing::RoutingRule;
+use crate::shred_styles::CustomShredStyle;
+use serde::Deserialize;
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct FileConfig {
+    // Evaluated in order by /auto, the first matching rule wins
+    #[serde(default)]
+    pub rules: Vec<RoutingRule>,
+    #[serde(default)]
+    pub limits: Limits,
+    // Case-insensitive Content-Type prefixes accepted by the destruction endpoints, empty allows all
+    #[serde(default)]
+    pub allowed_content_types: Vec<String>,
+    // Extra log packs for /shred, replacing built-in styles of the same name
+    #[serde(default)]
+    pub shred_styles: Vec<CustomShredStyle>,
+    // Thresholds reported to a webhook when breached and recovered
+    #[cfg(feature = "sqlite")]
+    pub alerts: Option<AlertSettings>,
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Limits {
+    // Largest payload the destruction endpoints accept, in bytes
+    pub max_payload_size: Option<usize>,
+    // Largest payload /validate-before-destroy accepts, in bytes
+    #[cfg_attr(not(feature = "validators"), allow(dead_code))]
+    pub validate_max_size: Option<usize>,
+}
+
+impl FileConfig {
+    pub fn load(path: &str) -> Result<Self, String> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read config file {}: {}", path, e))?;
+        let config: FileConfig =
+            toml::from_str(&content).map_err(|e| format!("Invalid config file {}: {}", path, e))?;
+        for rule in &config.rules {
+            rule.validate()?;
+        }
+        for style in &config.shred_styles {
+            style.validate()?;
+        }
+        #[cfg(feature = "sqlite")]
+        if let Some(alerts) = &config.alerts {
+            alerts.validate()?;
+        }
+        if config
+            .limits
+            .max_payload_size
+            .is_some_and(|max| max > crate::MAX_PAYLOAD_SIZE)
+        {
+            return Err(format!(
+                "limits.max_payload_size can't exceed the hard limit of {} bytes",
+                crate::MAX_PAYLOAD_SIZE
+            ));
+        }
+        Ok(config)
+    }
+
+    // Whether the Content-Type of a request is allowed
+    pub fn allows_content_type(&self, content_type: &str) -> bool {
+        let content_type = content_type.to_ascii_lowercase();
+        self.allowed_content_types.is_empty()
+            || self
+                .allowed_content_types
+                .iter()
+                .any(|prefix| content_type.starts_with(&prefix.to_ascii_lowercase()))
+    }
+}
@@ -0,0 +1,92 @@
+// POST /cookies/destroy: expire every cookie the client sent
+//
+// Lists the cookies received, by name and size only, and answers with an expired
+// Set-Cookie for each, so a browser test run can drop its state in one call. Cookies
+// are expired with Path=/ and no Domain; ones set for another path or domain survive.
+use crate::store::Store;
+use crate::{get_start_time, record_timed_stat, work, AppConfig, PhaseTimings, StatExtras};
+use actix_web::cookie::Cookie;
+use actix_web::web::Data;
+use actix_web::{HttpRequest, HttpResponse, Result};
+use serde::Serialize;
+use std::time::Instant;
+
+#[derive(Serialize)]
+struct CookieInfo {
+    name: String,
+    // Name and value
+    bytes: usize,
+}
+
+#[derive(Serialize)]
+struct CookiesResponse {
+    status: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message: Option<&'static str>,
+    count: usize,
+    total_bytes: usize,
+    cookies: Vec<CookieInfo>,
+    runtime_us: u128,
+    timings: PhaseTimings,
+}
+
+/// Handler for POST /cookies/destroy
+/// Expires all cookies of the request with Set-Cookie headers.
+pub async fn destroy_handler(
+    req: HttpRequest,
+    db: Data<dyn Store>,
+    config: Data<AppConfig>,
+) -> Result<HttpResponse> {
+    let start = get_start_time(&req);
+    let handler_start = Instant::now();
+    // Unparseable Cookie headers are treated as no cookies
+    let received: Vec<Cookie<'static>> = req
+        .cookies()
+        .map(|cookies| cookies.clone())
+        .unwrap_or_default();
+    let cookies: Vec<CookieInfo> = received
+        .iter()
+        .map(|cookie| CookieInfo {
+            name: cookie.name().to_string(),
+            bytes: cookie.name().len() + cookie.value().len(),
+        })
+        .collect();
+    let total_bytes = cookies.iter().map(|cookie| cookie.bytes).sum();
+    work::simulate(&req).await?;
+    let (_, timings) = record_timed_stat(
+        db.get_ref(),
+        "cookies",
+        total_bytes,
+        start,
+        handler_start,
+        // No body to have been chunked
+        StatExtras {
+            chunked: None,
+            ..StatExtras::for_request(&req, &config)
+        },
+    );
+    let mut response = HttpResponse::Ok();
+    for cookie in received {
+        let mut removal = Cookie::build(cookie.name().to_string(), "")
+            .path("/")
+            .finish();
+        removal.make_removal();
+        response.cookie(removal);
+    }
+    let message = match (config.boring, cookies.is_empty()) {
+        (true, _) => None,
+        (false, true) => Some("No cookies in the jar. Nothing to crumble."),
+        (false, false) => {
+            Some("Cookies crumbled, jar shattered. Your session has left the building.")
+        }
+    };
+    Ok(response.json(CookiesResponse {
+        status: "crumbled",
+        message,
+        count: cookies.len(),
+        total_bytes,
+        cookies,
+        runtime_us: start.elapsed().as_micros(),
+        timings,
+    }))
+}
@@ -0,0 +1,103 @@
+// Duplicate payload detection within a configurable time window
+use actix_web::http::StatusCode;
+use actix_web::HttpResponse;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+pub type PayloadDigest = [u8; 32];
+
+// Where and when a payload was destroyed before
+#[derive(Serialize, Clone)]
+pub struct EarlierDestruction {
+    endpoint: String,
+    destroyed_at: String,
+    stat_id: Option<i64>,
+    #[serde(skip)]
+    seen: Instant,
+}
+
+#[derive(Serialize)]
+struct AlreadyReportedResponse {
+    status: &'static str,
+    message: &'static str,
+    earlier: EarlierDestruction,
+    runtime_us: u128,
+}
+
+struct Entries {
+    by_payload: HashMap<(String, PayloadDigest), EarlierDestruction>,
+    last_prune: Instant,
+}
+
+pub struct DedupCache {
+    // None disables duplicate detection entirely (and skips hashing)
+    window: Option<Duration>,
+    entries: Mutex<Entries>,
+}
+
+impl DedupCache {
+    pub fn new(window: Option<Duration>) -> Self {
+        DedupCache {
+            window,
+            entries: Mutex::new(Entries {
+                by_payload: HashMap::new(),
+                last_prune: Instant::now(),
+            }),
+        }
+    }
+
+    // Look up the payload. Err(earlier) if it was destroyed at this endpoint within
+    // the window, otherwise Ok with the digest to pass to `remember` afterwards.
+    pub fn check(
+        &self,
+        endpoint: &str,
+        body: &[u8],
+    ) -> Result<Option<PayloadDigest>, EarlierDestruction> {
+        let Some(window) = self.window else {
+            return Ok(None);
+        };
+        let digest: PayloadDigest = Sha256::digest(body).into();
+        let entries = self.entries.lock().unwrap();
+        match entries.by_payload.get(&(endpoint.to_string(), digest)) {
+            Some(earlier) if earlier.seen.elapsed() < window => Err(earlier.clone()),
+            _ => Ok(Some(digest)),
+        }
+    }
+
+    // Remember a fresh destruction so retries within the window are detected
+    pub fn remember(&self, endpoint: &str, digest: Option<PayloadDigest>, stat_id: Option<i64>) {
+        let (Some(window), Some(digest)) = (self.window, digest) else {
+            return;
+        };
+        let mut entries = self.entries.lock().unwrap();
+        // Drop expired entries (at most once a second) so memory stays bounded by the window
+        if entries.last_prune.elapsed() >= Duration::from_secs(1) {
+            entries
+                .by_payload
+                .retain(|_, earlier| earlier.seen.elapsed() < window);
+            entries.last_prune = Instant::now();
+        }
+        entries.by_payload.insert(
+            (endpoint.to_string(), digest),
+            EarlierDestruction {
+                endpoint: endpoint.to_string(),
+                destroyed_at: chrono::Utc::now().to_rfc3339(),
+                stat_id,
+                seen: Instant::now(),
+            },
+        );
+    }
+}
+
+// 208 Already Reported response pointing at the earlier destruction
+pub fn already_reported(earlier: EarlierDestruction, runtime_us: u128) -> HttpResponse {
+    HttpResponse::build(StatusCode::ALREADY_REPORTED).json(AlreadyReportedResponse {
+        status: "already_destroyed",
+        message: "This exact payload was already destroyed. Not counting it twice.",
+        earlier,
+        runtime_us,
+    })
+}
@@ -0,0 +1,191 @@
+// --disk-max-db-size / --disk-min-free: keep stat inserts working when disk runs out
+//
+// Every 30 seconds the stats database and the free space on its filesystem are
+// checked. Under pressure the oldest tenth of the raw stats rows is deleted. SQLite
+// reuses the freed pages for new rows, so the file doesn't need to shrink for inserts
+// to succeed again. It is never vacuumed here, that would hold up every stat write for
+// the whole rewrite; `prune --vacuum` shrinks it offline.
+// The condition is reported by GET /healthz and /metrics.
+use rusqlite::Connection;
+use serde::Serialize;
+use std::fmt::Write;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::Duration;
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(30);
+// Share of the rows deleted per emergency pruning
+const PRUNE_FRACTION: f64 = 0.1;
+
+#[derive(Serialize, Clone, Default)]
+pub struct DiskStatus {
+    // Bytes used by rows, free pages inside the file excluded
+    db_used_bytes: u64,
+    db_file_bytes: u64,
+    // Unknown where the filesystem can't be queried
+    free_bytes: Option<u64>,
+    pressure: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reason: Option<String>,
+    pruned_rows_total: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    last_pruned_at: Option<String>,
+}
+
+pub struct DiskGuard {
+    db_path: String,
+    max_db_size: Option<u64>,
+    min_free: Option<u64>,
+    status: Mutex<DiskStatus>,
+}
+
+// Bytes available to unprivileged users on the filesystem holding the path
+#[cfg(unix)]
+fn free_bytes(path: &Path) -> Option<u64> {
+    use std::os::unix::ffi::OsStrExt;
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let dir = std::ffi::CString::new(dir.as_os_str().as_bytes()).ok()?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    match unsafe { libc::statvfs(dir.as_ptr(), &mut stat) } {
+        0 => Some(stat.f_bavail as u64 * stat.f_frsize as u64),
+        _ => None,
+    }
+}
+
+#[cfg(not(unix))]
+fn free_bytes(_path: &Path) -> Option<u64> {
+    None
+}
+
+// (used, free) bytes of the pages of the database
+fn page_usage(conn: &Connection) -> rusqlite::Result<(u64, u64)> {
+    let page_size: u64 = conn.query_row("PRAGMA page_size", [], |row| row.get(0))?;
+    let pages: u64 = conn.query_row("PRAGMA page_count", [], |row| row.get(0))?;
+    let free_pages: u64 = conn.query_row("PRAGMA freelist_count", [], |row| row.get(0))?;
+    Ok(((pages - free_pages) * page_size, free_pages * page_size))
+}
+
+// Delete the oldest rows, returns how many went
+fn prune_oldest(conn: &Connection) -> rusqlite::Result<usize> {
+    let rows: i64 = conn.query_row("SELECT COUNT(*) FROM endpoint_stats_raw", [], |row| {
+        row.get(0)
+    })?;
+    let count = ((rows as f64 * PRUNE_FRACTION).ceil() as i64).max(1);
+    conn.execute(
+        "DELETE FROM endpoint_stats_raw WHERE id IN (SELECT id FROM endpoint_stats_raw ORDER BY id LIMIT ?1)",
+        [count],
+    )
+}
+
+impl DiskGuard {
+    pub fn new(db_path: &str, max_db_size: Option<u64>, min_free: Option<u64>) -> Self {
+        DiskGuard {
+            db_path: db_path.to_string(),
+            max_db_size,
+            min_free,
+            status: Mutex::new(DiskStatus::default()),
+        }
+    }
+
+    pub fn status(&self) -> DiskStatus {
+        self.status.lock().unwrap().clone()
+    }
+
+    // Measure, and prune if under pressure
+    fn check(&self, db: &Mutex<Connection>) -> rusqlite::Result<()> {
+        let conn = db.lock().unwrap();
+        let (used, reusable) = page_usage(&conn)?;
+        let path = Path::new(&self.db_path);
+        let free = free_bytes(path);
+        let too_big = self.max_db_size.filter(|max| used > *max);
+        // Pages already freed inside the file are room for new rows, so low disk space
+        // only prunes while less than a tenth of the database is reusable
+        let too_full = self
+            .min_free
+            .zip(free)
+            .filter(|(min, free)| free < min && (reusable as f64) < used as f64 * PRUNE_FRACTION);
+        let reason = match (too_big, too_full) {
+            (Some(max), _) => Some(format!(
+                "The stats database uses {} bytes, more than --disk-max-db-size ({} bytes).",
+                used, max
+            )),
+            (None, Some((min, free))) => Some(format!(
+                "Only {} bytes are free on the database's filesystem, less than --disk-min-free ({} bytes).",
+                free, min
+            )),
+            (None, None) => None,
+        };
+        let mut pruned = 0;
+        if let Some(reason) = &reason {
+            pruned = prune_oldest(&conn)?;
+            eprintln!("{} Pruned the {} oldest stats rows.", reason, pruned);
+        }
+        let (used, _) = page_usage(&conn)?;
+        let mut status = self.status.lock().unwrap();
+        status.db_used_bytes = used;
+        status.db_file_bytes = std::fs::metadata(path).map_or(0, |meta| meta.len());
+        status.free_bytes = free_bytes(path);
+        status.pressure = reason.is_some();
+        status.reason = reason;
+        if pruned > 0 {
+            status.pruned_rows_total += pruned as u64;
+            status.last_pruned_at = Some(chrono::Utc::now().to_rfc3339());
+        }
+        Ok(())
+    }
+
+    // Gauges for /metrics
+    pub fn render_metrics(&self) -> String {
+        let status = self.status();
+        let mut out = String::new();
+        let mut gauge = |name: &str, help: &str, kind: &str, value: u64| {
+            let _ = writeln!(out, "# HELP {} {}", name, help);
+            let _ = writeln!(out, "# TYPE {} {}", name, kind);
+            let _ = writeln!(out, "{} {}", name, value);
+        };
+        gauge(
+            "payload_pulverizer_db_used_bytes",
+            "Bytes used by rows in the stats database.",
+            "gauge",
+            status.db_used_bytes,
+        );
+        if let Some(free) = status.free_bytes {
+            gauge(
+                "payload_pulverizer_disk_free_bytes",
+                "Free bytes on the stats database's filesystem.",
+                "gauge",
+                free,
+            );
+        }
+        gauge(
+            "payload_pulverizer_disk_pressure",
+            "1 while the disk guard is pruning stats, 0 otherwise.",
+            "gauge",
+            status.pressure as u64,
+        );
+        gauge(
+            "payload_pulverizer_emergency_pruned_rows_total",
+            "Stats rows deleted by the disk guard.",
+            "counter",
+            status.pruned_rows_total,
+        );
+        out
+    }
+
+    pub fn is_under_pressure(&self) -> bool {
+        self.status.lock().unwrap().pressure
+    }
+}
+
+// Check right away and then every 30 seconds for as long as the process runs
+pub fn spawn(guard: actix_web::web::Data<DiskGuard>, db: actix_web::web::Data<Mutex<Connection>>) {
+    std::thread::spawn(move || loop {
+        if let Err(e) = guard.check(&db) {
+            eprintln!("Failed to check disk usage: {}", e);
+        }
+        std::thread::sleep(CHECK_INTERVAL);
+    });
+}
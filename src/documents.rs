@@ -0,0 +1,407 @@
+// Office document detection for /autopsy
+//
+// PDF, OOXML (docx, xlsx, pptx) and ODF (odt, ods, odp) files carry metadata their
+// senders rarely think about: who wrote them, with what, and when. The report lists
+// those fields and the page, sheet or slide count. OOXML and ODF are ZIP archives, only
+// the few small XML entries holding the metadata are inflated. PDF metadata is read
+// from the document information dictionary, which some writers keep in compressed
+// object streams where it isn't found.
+use flate2::read::DeflateDecoder;
+use quick_xml::events::Event;
+use quick_xml::Reader;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::io::Read;
+
+// Largest XML entry inflated from an archive
+const MAX_ENTRY_SIZE: u64 = 1024 * 1024;
+// Longest metadata value reported
+const MAX_VALUE_LENGTH: usize = 256;
+
+#[derive(Serialize)]
+pub struct DocumentReport {
+    // pdf, docx, xlsx, pptx, odt, ods, odp or another OOXML/ODF type
+    format: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pdf_version: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pages: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sheets: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    slides: Option<usize>,
+    // author, last_modified_by, created, modified, title, subject, keywords,
+    // application, producer, company
+    metadata: BTreeMap<&'static str, String>,
+}
+
+impl DocumentReport {
+    fn new(format: &'static str) -> Self {
+        DocumentReport {
+            format,
+            pdf_version: None,
+            pages: None,
+            sheets: None,
+            slides: None,
+            metadata: BTreeMap::new(),
+        }
+    }
+
+    fn set(&mut self, field: &'static str, value: &str) {
+        let value = value.trim();
+        if !value.is_empty() {
+            self.metadata
+                .insert(field, value.chars().take(MAX_VALUE_LENGTH).collect());
+        }
+    }
+}
+
+fn u16_at(data: &[u8], offset: usize) -> Option<usize> {
+    let bytes = data.get(offset..offset + 2)?;
+    Some(u16::from_le_bytes([bytes[0], bytes[1]]) as usize)
+}
+
+fn u32_at(data: &[u8], offset: usize) -> Option<usize> {
+    let bytes = data.get(offset..offset + 4)?;
+    Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize)
+}
+
+// An entry of a ZIP archive's central directory
+struct ZipEntry<'a> {
+    name: String,
+    method: usize,
+    compressed_size: usize,
+    local_header: usize,
+    archive: &'a [u8],
+}
+
+impl ZipEntry<'_> {
+    // Stored or deflated contents, None for other methods and oversized entries
+    fn read(&self) -> Option<Vec<u8>> {
+        let data = self.archive;
+        if u32_at(data, self.local_header)? != 0x0403_4b50 {
+            return None;
+        }
+        let start = self.local_header
+            + 30
+            + u16_at(data, self.local_header + 26)?
+            + u16_at(data, self.local_header + 28)?;
+        let compressed = data.get(start..start.checked_add(self.compressed_size)?)?;
+        let mut contents = Vec::new();
+        let reader: Box<dyn Read> = match self.method {
+            0 => Box::new(compressed),
+            8 => Box::new(DeflateDecoder::new(compressed)),
+            _ => return None,
+        };
+        reader
+            .take(MAX_ENTRY_SIZE)
+            .read_to_end(&mut contents)
+            .ok()?;
+        Some(contents)
+    }
+}
+
+// Entries listed in the central directory, None if this isn't a ZIP archive
+fn zip_entries(data: &[u8]) -> Option<Vec<ZipEntry<'_>>> {
+    // The end of central directory record is followed by a comment of up to 64 KiB
+    let search_from = data.len().saturating_sub(22 + 0xffff);
+    let end = (search_from..data.len().saturating_sub(21))
+        .rev()
+        .find(|offset| u32_at(data, *offset) == Some(0x0605_4b50))?;
+    let count = u16_at(data, end + 10)?;
+    let mut offset = u32_at(data, end + 16)?;
+    let mut entries = Vec::with_capacity(count);
+    for _ in 0..count {
+        if u32_at(data, offset)? != 0x0201_4b50 {
+            return None;
+        }
+        let name_length = u16_at(data, offset + 28)?;
+        let name = data.get(offset + 46..offset + 46 + name_length)?;
+        entries.push(ZipEntry {
+            name: String::from_utf8_lossy(name).into_owned(),
+            method: u16_at(data, offset + 10)?,
+            compressed_size: u32_at(data, offset + 20)?,
+            local_header: u32_at(data, offset + 42)?,
+            archive: data,
+        });
+        offset += 46 + name_length + u16_at(data, offset + 30)? + u16_at(data, offset + 32)?;
+    }
+    Some(entries)
+}
+
+fn read_entry(entries: &[ZipEntry], name: &str) -> Option<String> {
+    let entry = entries.iter().find(|entry| entry.name == name)?;
+    String::from_utf8(entry.read()?).ok()
+}
+
+// (local name, value) pairs
+type Fields = Vec<(String, String)>;
+
+// Text of every element by local name, and the attributes of empty or open elements
+fn xml_fields(xml: &str) -> (Fields, Fields) {
+    let mut reader = Reader::from_str(xml);
+    let mut texts = Vec::new();
+    let mut attributes = Vec::new();
+    let mut current: Option<String> = None;
+    let local_name = |name: &[u8]| {
+        let name = String::from_utf8_lossy(name).into_owned();
+        name.rsplit(':').next().unwrap_or_default().to_string()
+    };
+    loop {
+        match reader.read_event() {
+            Ok(Event::Start(element)) | Ok(Event::Empty(element)) => {
+                current = Some(local_name(element.name().as_ref()));
+                for attribute in element.attributes().flatten() {
+                    if let Ok(value) = attribute.unescape_value() {
+                        attributes.push((local_name(attribute.key.as_ref()), value.into_owned()));
+                    }
+                }
+            }
+            Ok(Event::Text(text)) => {
+                if let (Some(name), Ok(text)) = (&current, text.unescape()) {
+                    texts.push((name.clone(), text.into_owned()));
+                }
+            }
+            Ok(Event::End(_)) => current = None,
+            Ok(Event::Eof) | Err(_) => return (texts, attributes),
+            Ok(_) => {}
+        }
+    }
+}
+
+fn ooxml(entries: &[ZipEntry]) -> DocumentReport {
+    let has = |prefix: &str| entries.iter().any(|entry| entry.name.starts_with(prefix));
+    let count = |prefix: &str| {
+        entries
+            .iter()
+            .filter(|entry| entry.name.starts_with(prefix) && entry.name.ends_with(".xml"))
+            .count()
+    };
+    let format = match () {
+        _ if has("word/") => "docx",
+        _ if has("xl/") => "xlsx",
+        _ if has("ppt/") => "pptx",
+        _ => "ooxml",
+    };
+    let mut report = DocumentReport::new(format);
+    if format == "xlsx" {
+        report.sheets = Some(count("xl/worksheets/sheet"));
+    }
+    if format == "pptx" {
+        report.slides = Some(count("ppt/slides/slide"));
+    }
+    let core = read_entry(entries, "docProps/core.xml").unwrap_or_default();
+    for (name, value) in xml_fields(&core).0 {
+        let field = match name.as_str() {
+            "creator" => "author",
+            "lastModifiedBy" => "last_modified_by",
+            "created" => "created",
+            "modified" => "modified",
+            "title" => "title",
+            "subject" => "subject",
+            "keywords" => "keywords",
+            _ => continue,
+        };
+        report.set(field, &value);
+    }
+    let app = read_entry(entries, "docProps/app.xml").unwrap_or_default();
+    for (name, value) in xml_fields(&app).0 {
+        match name.as_str() {
+            "Application" => report.set("application", &value),
+            "Company" => report.set("company", &value),
+            "Pages" if format == "docx" => report.pages = value.trim().parse().ok(),
+            _ => {}
+        }
+    }
+    report
+}
+
+fn odf(entries: &[ZipEntry], mimetype: &str) -> DocumentReport {
+    let format = match mimetype.trim() {
+        "application/vnd.oasis.opendocument.text" => "odt",
+        "application/vnd.oasis.opendocument.spreadsheet" => "ods",
+        "application/vnd.oasis.opendocument.presentation" => "odp",
+        _ => "odf",
+    };
+    let mut report = DocumentReport::new(format);
+    let meta = read_entry(entries, "meta.xml").unwrap_or_default();
+    let (texts, attributes) = xml_fields(&meta);
+    for (name, value) in texts {
+        let field = match name.as_str() {
+            "initial-creator" => "author",
+            // In ODF dc:creator is whoever saved last
+            "creator" => "last_modified_by",
+            "creation-date" => "created",
+            "date" => "modified",
+            "title" => "title",
+            "subject" => "subject",
+            "keyword" => "keywords",
+            "generator" => "application",
+            _ => continue,
+        };
+        report.set(field, &value);
+    }
+    for (name, value) in attributes {
+        let count = value.parse().ok();
+        match name.as_str() {
+            "page-count" if format == "odt" => report.pages = count,
+            "table-count" if format == "ods" => report.sheets = count,
+            "page-count" if format == "odp" => report.slides = count,
+            _ => {}
+        }
+    }
+    report
+}
+
+// A PDF string after `/Key`: literal (…) or hex <…>, UTF-16 with a byte order mark
+fn pdf_string(data: &[u8]) -> Option<String> {
+    let data = data.trim_ascii_start();
+    let mut bytes = Vec::new();
+    match data.first()? {
+        b'(' => {
+            let mut depth = 0;
+            let mut i = 1;
+            while let Some(&byte) = data.get(i) {
+                match byte {
+                    b'\\' => {
+                        let escaped = *data.get(i + 1)?;
+                        bytes.push(match escaped {
+                            b'n' => b'\n',
+                            b'r' => b'\r',
+                            b't' => b'\t',
+                            other => other,
+                        });
+                        i += 2;
+                        continue;
+                    }
+                    b'(' => depth += 1,
+                    b')' if depth == 0 => break,
+                    b')' => depth -= 1,
+                    _ => {}
+                }
+                bytes.push(byte);
+                i += 1;
+            }
+        }
+        b'<' => {
+            let end = data.iter().position(|byte| *byte == b'>')?;
+            let hex: Vec<u8> = data[1..end]
+                .iter()
+                .copied()
+                .filter(u8::is_ascii_hexdigit)
+                .collect();
+            for pair in hex.chunks(2) {
+                let pair = std::str::from_utf8(pair).ok()?;
+                bytes.push(u8::from_str_radix(&format!("{:0<2}", pair), 16).ok()?);
+            }
+        }
+        _ => return None,
+    }
+    match bytes.strip_prefix(&[0xfe, 0xff]) {
+        Some(utf16) => {
+            let units: Vec<u16> = utf16
+                .chunks_exact(2)
+                .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+                .collect();
+            Some(String::from_utf16_lossy(&units))
+        }
+        None => Some(bytes.iter().map(|byte| *byte as char).collect()),
+    }
+}
+
+// D:20240131120000+01'00' → 2024-01-31T12:00:00+01:00
+fn pdf_date(value: &str) -> String {
+    let digits = value.trim_start_matches("D:");
+    let part = |range: std::ops::Range<usize>| {
+        digits
+            .get(range)
+            .filter(|part| part.bytes().all(|b| b.is_ascii_digit()))
+    };
+    match (part(0..4), part(4..6), part(6..8)) {
+        (Some(year), Some(month), Some(day)) => {
+            let time = match (part(8..10), part(10..12), part(12..14)) {
+                (Some(hour), Some(minute), second) => {
+                    format!("T{}:{}:{}", hour, minute, second.unwrap_or("00"))
+                }
+                _ => String::new(),
+            };
+            let zone = digits.get(14..).unwrap_or("").replace('\'', "");
+            let zone = match zone.as_str() {
+                "" => String::new(),
+                "Z" => "Z".to_string(),
+                zone if zone.len() == 5 => format!("{}:{}", &zone[..3], &zone[3..]),
+                zone => zone.to_string(),
+            };
+            format!("{}-{}-{}{}{}", year, month, day, time, zone)
+        }
+        _ => value.to_string(),
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+fn pdf(data: &[u8]) -> DocumentReport {
+    let mut report = DocumentReport::new("pdf");
+    report.pdf_version = data
+        .get(5..8)
+        .and_then(|version| std::str::from_utf8(version).ok())
+        .map(str::to_string);
+    // Every page object has /Type /Page, the page tree nodes /Type /Pages
+    let mut pages = 0;
+    let mut offset = 0;
+    while let Some(found) = find(&data[offset..], b"/Type") {
+        offset += found + 5;
+        let rest = data[offset..].trim_ascii_start();
+        if rest.starts_with(b"/Page") && !rest.starts_with(b"/Pages") {
+            pages += 1;
+        }
+    }
+    report.pages = (pages > 0).then_some(pages);
+    let fields: &[(&[u8], &'static str)] = &[
+        (b"/Author", "author"),
+        (b"/Title", "title"),
+        (b"/Subject", "subject"),
+        (b"/Keywords", "keywords"),
+        (b"/Creator", "application"),
+        (b"/Producer", "producer"),
+        (b"/CreationDate", "created"),
+        (b"/ModDate", "modified"),
+    ];
+    for (key, field) in fields {
+        // The last occurrence wins, incremental updates append a new dictionary
+        let Some(found) = data.windows(key.len()).rposition(|window| window == *key) else {
+            continue;
+        };
+        if let Some(value) = pdf_string(&data[found + key.len()..]) {
+            match *field {
+                "created" | "modified" => report.set(field, &pdf_date(&value)),
+                _ => report.set(field, &value),
+            }
+        }
+    }
+    report
+}
+
+// Report for PDF, OOXML and ODF documents, None for anything else
+pub fn detect(body: &[u8]) -> Option<DocumentReport> {
+    if body.starts_with(b"%PDF-") {
+        return Some(pdf(body));
+    }
+    if !body.starts_with(b"PK\x03\x04") {
+        return None;
+    }
+    let entries = zip_entries(body)?;
+    if let Some(mimetype) = read_entry(&entries, "mimetype")
+        .filter(|mimetype| mimetype.starts_with("application/vnd.oasis.opendocument"))
+    {
+        return Some(odf(&entries, &mimetype));
+    }
+    entries
+        .iter()
+        .any(|entry| entry.name == "[Content_Types].xml")
+        .then(|| ooxml(&entries))
+}
@@ -0,0 +1,243 @@
+// Email detection for /validate-before-destroy
+//
+// Recognizes RFC 5322 messages (optionally with an mbox "From " line) and summarizes
+// them: the headers useful for triaging spam, how many MIME parts there are and which
+// attachments. Addresses and the subject are redacted unless `?reveal_headers=true`
+// is given, and always in --privacy mode.
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+// Reported headers
+const HEADERS_OF_INTEREST: &[&str] = &[
+    "From",
+    "Sender",
+    "Reply-To",
+    "Return-Path",
+    "To",
+    "Cc",
+    "Delivered-To",
+    "Subject",
+    "Date",
+    "Message-ID",
+    "MIME-Version",
+    "Content-Type",
+    "X-Mailer",
+    "User-Agent",
+];
+// Headers naming people, or saying what the message is about
+const REDACTED: &[&str] = &[
+    "From",
+    "Sender",
+    "Reply-To",
+    "Return-Path",
+    "To",
+    "Cc",
+    "Delivered-To",
+    "Subject",
+];
+// Needed besides the header syntax before a payload counts as an email
+const REQUIRED_MATCHES: usize = 2;
+// Multipart nesting followed at most
+const MAX_NESTING: usize = 8;
+const MAX_ATTACHMENTS: usize = 100;
+
+#[derive(Serialize)]
+pub struct Attachment {
+    filename: String,
+    content_type: String,
+    // Decoded size, estimated for base64
+    bytes: usize,
+}
+
+#[derive(Serialize)]
+pub struct EmailReport {
+    headers: BTreeMap<&'static str, String>,
+    redacted: bool,
+    // Received headers, one per relay
+    received_hops: usize,
+    // Leaf MIME parts, 1 for a plain message
+    parts: usize,
+    attachments: Vec<Attachment>,
+}
+
+type Headers = Vec<(String, String)>;
+
+// Header fields and the body after the empty line, None if the text doesn't start
+// with a header block
+fn split_headers(text: &str) -> Option<(Headers, &str)> {
+    let mut headers: Headers = Vec::new();
+    let mut offset = 0;
+    for line in text.split_inclusive('\n') {
+        offset += line.len();
+        let line = line.trim_end_matches(['\r', '\n']);
+        if line.is_empty() {
+            return Some((headers, &text[offset..]));
+        }
+        if line.starts_with([' ', '\t']) {
+            let (_, value) = headers.last_mut()?;
+            value.push(' ');
+            value.push_str(line.trim());
+            continue;
+        }
+        let (name, value) = line.split_once(':')?;
+        if name.is_empty() || !name.bytes().all(|b| b.is_ascii_graphic()) {
+            return None;
+        }
+        headers.push((name.to_ascii_lowercase(), value.trim().to_string()));
+    }
+    None
+}
+
+fn header<'a>(headers: &'a Headers, name: &str) -> Option<&'a str> {
+    headers
+        .iter()
+        .find(|(field, _)| field.eq_ignore_ascii_case(name))
+        .map(|(_, value)| value.as_str())
+}
+
+// A parameter of a structured header value, e.g. the boundary of a Content-Type
+fn parameter(value: &str, name: &str) -> Option<String> {
+    value.split(';').skip(1).find_map(|param| {
+        let (key, value) = param.trim().split_once('=')?;
+        let key = key.trim();
+        let value = value.trim().trim_matches('"');
+        if key.eq_ignore_ascii_case(name) {
+            Some(value.to_string())
+        } else if key.eq_ignore_ascii_case(&format!("{}*", name)) {
+            // RFC 2231: charset'language'value, the value is kept percent-encoded
+            Some(value.rsplit('\'').next().unwrap_or(value).to_string())
+        } else {
+            None
+        }
+    })
+}
+
+// Bodies of the parts between the boundary lines
+fn split_parts<'a>(body: &'a str, boundary: &str) -> Vec<&'a str> {
+    let delimiter = format!("--{}", boundary);
+    let closing = format!("--{}--", boundary);
+    let mut parts = Vec::new();
+    let mut start = None;
+    let mut offset = 0;
+    for line in body.split_inclusive('\n') {
+        let trimmed = line.trim_end();
+        if trimmed == delimiter || trimmed == closing {
+            if let Some(start) = start {
+                parts.push(&body[start..offset]);
+            }
+            if trimmed == closing {
+                return parts;
+            }
+            start = Some(offset + line.len());
+        }
+        offset += line.len();
+    }
+    parts
+}
+
+fn decoded_size(headers: &Headers, body: &str) -> usize {
+    let body = body.trim_end_matches(['\r', '\n']);
+    match header(headers, "Content-Transfer-Encoding") {
+        Some(encoding) if encoding.eq_ignore_ascii_case("base64") => {
+            let chars = body
+                .bytes()
+                .filter(|b| b.is_ascii_alphanumeric() || *b == b'+' || *b == b'/')
+                .count();
+            chars * 3 / 4
+        }
+        _ => body.len(),
+    }
+}
+
+fn walk(headers: &Headers, body: &str, depth: usize, report: &mut EmailReport) {
+    let content_type = header(headers, "Content-Type").unwrap_or("text/plain");
+    let essence = content_type
+        .split(';')
+        .next()
+        .unwrap_or("")
+        .trim()
+        .to_ascii_lowercase();
+    if essence.starts_with("multipart/") && depth < MAX_NESTING {
+        if let Some(boundary) = parameter(content_type, "boundary") {
+            for part in split_parts(body, &boundary) {
+                match split_headers(part) {
+                    Some((part_headers, part_body)) => {
+                        walk(&part_headers, part_body, depth + 1, report)
+                    }
+                    // A part without headers is plain text
+                    None => report.parts += 1,
+                }
+            }
+            return;
+        }
+    }
+    report.parts += 1;
+    let disposition = header(headers, "Content-Disposition");
+    let filename = disposition
+        .and_then(|value| parameter(value, "filename"))
+        .or_else(|| parameter(content_type, "name"));
+    let is_attachment = disposition.is_some_and(|value| {
+        value
+            .trim_start()
+            .to_ascii_lowercase()
+            .starts_with("attachment")
+    });
+    if (is_attachment || filename.is_some()) && report.attachments.len() < MAX_ATTACHMENTS {
+        report.attachments.push(Attachment {
+            filename: filename.unwrap_or_default(),
+            content_type: essence,
+            bytes: decoded_size(headers, body),
+        });
+    }
+}
+
+// Report for a payload that looks like an email, None otherwise
+pub fn detect(body_str: &str, reveal: bool) -> Option<EmailReport> {
+    // mbox files start every message with a "From " line
+    let text = match body_str.strip_prefix("From ") {
+        Some(rest) => rest.split_once('\n')?.1,
+        None => body_str,
+    };
+    let (headers, body) = split_headers(text)?;
+    let matches = HEADERS_OF_INTEREST
+        .iter()
+        .filter(|name| header(&headers, name).is_some())
+        .count();
+    let has_sender =
+        header(&headers, "From").is_some() || header(&headers, "MIME-Version").is_some();
+    if !has_sender || matches < REQUIRED_MATCHES {
+        return None;
+    }
+    let mut report = EmailReport {
+        headers: BTreeMap::new(),
+        redacted: !reveal,
+        received_hops: headers
+            .iter()
+            .filter(|(name, _)| name == "received")
+            .count(),
+        parts: 0,
+        attachments: Vec::new(),
+    };
+    for name in HEADERS_OF_INTEREST {
+        if let Some(value) = header(&headers, name) {
+            let value = match !reveal && REDACTED.contains(name) {
+                true => "[redacted]".to_string(),
+                false => value.to_string(),
+            };
+            report.headers.insert(name, value);
+        }
+    }
+    walk(&headers, body, 0, &mut report);
+    Some(report)
+}
+
+impl EmailReport {
+    pub fn detail(&self) -> String {
+        format!(
+            "Email message with {} MIME parts and {} attachments, relayed {} times.",
+            self.parts,
+            self.attachments.len(),
+            self.received_hops
+        )
+    }
+}
@@ -0,0 +1,338 @@
+// POST /fetch-and-destroy: download a resource server-side and destroy it
+//
+// For artifacts sitting on internal object storage, which then don't have to be pulled
+// through the client first. The resource is hashed while it streams in and never
+// kept. Only hosts listed in --fetch-allowed-hosts are fetched, over https unless
+// --fetch-allow-http is set, up to --fetch-max-size. Redirects are followed by hand:
+// every hop has to pass the same checks and, unlike the first one, may only go to
+// public addresses. Link-local (cloud metadata) and unspecified addresses are never
+// connected to. The checks apply to the resolved addresses that are actually used.
+use crate::store::Store;
+use crate::{get_start_time, record_timed_stat, work, AppConfig, PhaseTimings, StatExtras};
+use actix_web::web::{self, Data};
+use actix_web::{HttpRequest, HttpResponse, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::io::{self, Read};
+use std::net::{IpAddr, SocketAddr, ToSocketAddrs};
+use std::time::{Duration, Instant};
+use url::Url;
+
+const FETCH_TIMEOUT: Duration = Duration::from_secs(30);
+const MAX_REDIRECTS: usize = 5;
+
+// --fetch-allowed-hosts and friends
+pub struct FetchPolicy {
+    // Host names, "*.example.com" for all subdomains. Empty disables the endpoint.
+    pub allowed_hosts: Vec<String>,
+    pub allow_http: bool,
+    pub max_size: usize,
+}
+
+#[derive(Deserialize)]
+struct FetchRequest {
+    url: String,
+}
+
+#[derive(Serialize)]
+struct FetchReceipt {
+    status: &'static str,
+    url: String,
+    // Where the resource was fetched from after redirects, if anywhere else
+    #[serde(skip_serializing_if = "Option::is_none")]
+    final_url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    content_type: Option<String>,
+    bytes: usize,
+    sha256: String,
+    stat_id: Option<i64>,
+    destroyed_at: String,
+    // RFC 3161 token on sha256 (base64 DER), with --tsa-url
+    #[serde(skip_serializing_if = "Option::is_none")]
+    timestamp_token: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    timestamp_error: Option<String>,
+    runtime_us: u128,
+    timings: PhaseTimings,
+}
+
+enum FetchError {
+    // Not a URL we fetch, answered with 403
+    Forbidden(String),
+    TooLarge(usize),
+    // The download failed, answered with 502
+    Upstream(String),
+}
+
+struct Fetched {
+    // Only set after redirects
+    final_url: Option<Url>,
+    content_type: Option<String>,
+    bytes: usize,
+    sha256: String,
+}
+
+impl FetchPolicy {
+    fn allows_host(&self, host: &str) -> bool {
+        let host = host.to_ascii_lowercase();
+        self.allowed_hosts
+            .iter()
+            .any(|allowed| match allowed.strip_prefix("*.") {
+                Some(domain) => host
+                    .strip_suffix(domain)
+                    .is_some_and(|sub| sub.len() > 1 && sub.ends_with('.')),
+                None => *allowed == host,
+            })
+    }
+
+    fn check(&self, url: &Url) -> Result<(), FetchError> {
+        match url.scheme() {
+            "https" => {}
+            "http" if self.allow_http => {}
+            "http" => {
+                return Err(FetchError::Forbidden(
+                    "Plain http URLs are not fetched (--fetch-allow-http).".to_string(),
+                ))
+            }
+            scheme => {
+                return Err(FetchError::Forbidden(format!(
+                    "{} URLs are not fetched, only http(s).",
+                    scheme
+                )))
+            }
+        }
+        // IPv6 literals come in brackets
+        let host = url.host_str().unwrap_or_default();
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        if !self.allows_host(host) {
+            return Err(FetchError::Forbidden(format!(
+                "Host {} is not in --fetch-allowed-hosts.",
+                host
+            )));
+        }
+        Ok(())
+    }
+}
+
+// Whether an address may be connected to. Redirect targets must be public.
+fn allowed_address(ip: IpAddr, public_only: bool) -> bool {
+    let ip = match ip {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(ip, IpAddr::V4),
+        ip => ip,
+    };
+    let (never, internal) = match ip {
+        IpAddr::V4(v4) => {
+            let [a, b, ..] = v4.octets();
+            (
+                v4.is_unspecified() || v4.is_link_local() || v4.is_multicast() || v4.is_broadcast(),
+                // 100.64.0.0/10 is carrier-grade NAT, private in all but name
+                v4.is_loopback() || v4.is_private() || (a == 100 && b & 0xc0 == 64),
+            )
+        }
+        IpAddr::V6(v6) => {
+            let first = v6.segments()[0];
+            (
+                v6.is_unspecified() || v6.is_multicast() || first & 0xffc0 == 0xfe80,
+                // Unique local addresses, fc00::/7
+                v6.is_loopback() || first & 0xfe00 == 0xfc00,
+            )
+        }
+    };
+    !(never || (public_only && internal))
+}
+
+// An agent that only connects to addresses passing `allowed_address`, for every request
+// to a URL a client chose. The check runs on the resolved addresses of every connection,
+// redirects included, so a name can't resolve its way around it.
+pub fn guarded_agent(public_only: bool) -> ureq::AgentBuilder {
+    ureq::AgentBuilder::new().resolver(move |netloc: &str| -> io::Result<Vec<SocketAddr>> {
+        let addrs: Vec<SocketAddr> = netloc
+            .to_socket_addrs()?
+            .filter(|addr| allowed_address(addr.ip(), public_only))
+            .collect();
+        match addrs.is_empty() {
+            true => Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "resolves to no address that may be fetched",
+            )),
+            false => Ok(addrs),
+        }
+    })
+}
+
+fn agent(public_only: bool) -> ureq::Agent {
+    guarded_agent(public_only)
+        .timeout(FETCH_TIMEOUT)
+        .redirects(0)
+        .build()
+}
+
+// Download and hash the resource, blocking
+fn fetch(policy: &FetchPolicy, url: Url) -> Result<Fetched, FetchError> {
+    let mut url = url;
+    let mut redirects = 0;
+    let response = loop {
+        policy.check(&url)?;
+        let response = match agent(redirects > 0).get(url.as_str()).call() {
+            Ok(response) => response,
+            Err(ureq::Error::Status(code, _)) => {
+                return Err(FetchError::Upstream(format!(
+                    "{} answered with status {}.",
+                    url, code
+                )))
+            }
+            Err(e) => return Err(FetchError::Upstream(format!("{}.", e))),
+        };
+        if !(300..400).contains(&response.status()) {
+            break response;
+        }
+        redirects += 1;
+        if redirects > MAX_REDIRECTS {
+            return Err(FetchError::Upstream(format!(
+                "More than {} redirects.",
+                MAX_REDIRECTS
+            )));
+        }
+        let location = response.header("Location").ok_or_else(|| {
+            FetchError::Upstream(format!("{} redirected without a Location.", url))
+        })?;
+        url = url.join(location).map_err(|e| {
+            FetchError::Upstream(format!("{} redirected to an invalid URL: {}.", url, e))
+        })?;
+    };
+    let announced: Option<usize> = response
+        .header("Content-Length")
+        .and_then(|length| length.parse().ok());
+    if announced.is_some_and(|length| length > policy.max_size) {
+        return Err(FetchError::TooLarge(policy.max_size));
+    }
+    let content_type = response.header("Content-Type").map(str::to_string);
+    let mut reader = response.into_reader().take(policy.max_size as u64 + 1);
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; 64 * 1024];
+    let mut bytes = 0;
+    loop {
+        let read = reader
+            .read(&mut buffer)
+            .map_err(|e| FetchError::Upstream(format!("{}: {}.", url, e)))?;
+        if read == 0 {
+            break;
+        }
+        bytes += read;
+        if bytes > policy.max_size {
+            return Err(FetchError::TooLarge(policy.max_size));
+        }
+        hasher.update(&buffer[..read]);
+    }
+    Ok(Fetched {
+        final_url: (redirects > 0).then_some(url),
+        content_type,
+        bytes,
+        sha256: format!("{:x}", hasher.finalize()),
+    })
+}
+
+fn error(status: actix_web::http::StatusCode, message: String) -> HttpResponse {
+    HttpResponse::build(status).json(serde_json::json!({ "error": message }))
+}
+
+/// Handler for POST /fetch-and-destroy
+/// Downloads the URL given as `{"url": …}` from an allowed host and destroys it.
+pub async fn fetch_handler(
+    req: HttpRequest,
+    body: crate::body::Body,
+    db: Data<dyn Store>,
+    config: Data<AppConfig>,
+) -> Result<HttpResponse> {
+    use actix_web::http::StatusCode;
+    let start = get_start_time(&req);
+    if config.fetch.allowed_hosts.is_empty() {
+        return Ok(error(
+            StatusCode::FORBIDDEN,
+            "Fetching is disabled, start the server with --fetch-allowed-hosts.".to_string(),
+        ));
+    }
+    let url = match serde_json::from_slice::<FetchRequest>(&body) {
+        Ok(request) => request.url,
+        Err(e) => {
+            return Ok(error(
+                StatusCode::BAD_REQUEST,
+                format!(
+                    "Expected a JSON body like {{\"url\": \"https://…\"}}: {}.",
+                    e
+                ),
+            ))
+        }
+    };
+    let parsed = match Url::parse(&url) {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            return Ok(error(
+                StatusCode::BAD_REQUEST,
+                format!("Invalid URL: {}.", e),
+            ))
+        }
+    };
+    let policy = config.clone();
+    let fetched = match web::block(move || fetch(&policy.fetch, parsed)).await? {
+        Ok(fetched) => fetched,
+        Err(FetchError::Forbidden(message)) => return Ok(error(StatusCode::FORBIDDEN, message)),
+        Err(FetchError::TooLarge(max)) => {
+            return Ok(error(
+                StatusCode::PAYLOAD_TOO_LARGE,
+                format!(
+                    "The resource is larger than {} (--fetch-max-size).",
+                    crate::format_size(max)
+                ),
+            ))
+        }
+        Err(FetchError::Upstream(message)) => {
+            return Ok(error(
+                StatusCode::BAD_GATEWAY,
+                format!("Fetching failed: {}", message),
+            ))
+        }
+    };
+    // The download is this endpoint's read phase
+    let handler_start = Instant::now();
+    work::simulate(&req).await?;
+    let (stat_id, timings) = record_timed_stat(
+        db.get_ref(),
+        "fetch-and-destroy",
+        fetched.bytes,
+        start,
+        handler_start,
+        StatExtras::for_request(&req, &config),
+    );
+    let destroyed_at = chrono::Utc::now().to_rfc3339();
+    let mut receipt = FetchReceipt {
+        status: "destroyed",
+        final_url: fetched.final_url.map(String::from),
+        url,
+        content_type: fetched.content_type,
+        bytes: fetched.bytes,
+        sha256: fetched.sha256,
+        stat_id,
+        destroyed_at,
+        timestamp_token: None,
+        timestamp_error: None,
+        runtime_us: 0,
+        timings,
+    };
+    if let Some(tsa_url) = config.tsa_url.clone() {
+        let sha256 = receipt.sha256.clone();
+        match web::block(move || crate::timestamp::stamp(&tsa_url, &sha256)).await? {
+            Ok(token) => receipt.timestamp_token = Some(token),
+            Err(e) => receipt.timestamp_error = Some(e),
+        }
+    }
+    #[cfg(feature = "sqlite")]
+    crate::hash_index::remember_receipt(
+        &req,
+        &receipt.sha256,
+        "fetch-and-destroy",
+        &receipt.destroyed_at,
+    );
+    receipt.runtime_us = start.elapsed().as_micros();
+    Ok(HttpResponse::Ok().json(receipt))
+}
@@ -0,0 +1,186 @@
+// Instantaneous load: requests in flight, open connections and buffered request bytes
+//
+// The stats only show requests after they finished, which says little while the server is
+// drowning. These gauges are the load right now, on /stats/live-gauges and /metrics.
+// Requests count until their response starts, including the one asking. Connections count
+// from accept to close, over TCP (plain, TLS or behind a PROXY header) and HTTP/3. Buffered
+// bytes are request bodies held in memory while they're read and handled; bodies spilled to
+// disk with --spill-dir don't count once they're there.
+use actix_web::body::MessageBody;
+use actix_web::dev::{Extensions, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::web::Data;
+use actix_web::{Error, HttpResponse};
+use serde::Serialize;
+use std::fmt::Write;
+use std::future::{ready, Future, Ready};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::task::{Context, Poll};
+
+#[derive(Default)]
+pub struct Gauges {
+    in_flight: AtomicUsize,
+    connections: AtomicUsize,
+    buffered_bytes: AtomicUsize,
+}
+
+#[derive(Serialize)]
+struct GaugesResponse {
+    in_flight_requests: usize,
+    open_connections: usize,
+    buffered_bytes: usize,
+    timestamp: String,
+}
+
+impl Gauges {
+    pub fn render_metrics(&self) -> String {
+        let mut out = String::new();
+        let mut gauge = |name: &str, help: &str, value: usize| {
+            let _ = writeln!(out, "# HELP {} {}", name, help);
+            let _ = writeln!(out, "# TYPE {} gauge", name);
+            let _ = writeln!(out, "{} {}", name, value);
+        };
+        gauge(
+            "payload_pulverizer_in_flight_requests",
+            "Requests being handled right now.",
+            self.in_flight.load(Ordering::Relaxed),
+        );
+        gauge(
+            "payload_pulverizer_open_connections",
+            "Client connections open right now, TCP and HTTP/3.",
+            self.connections.load(Ordering::Relaxed),
+        );
+        gauge(
+            "payload_pulverizer_buffered_bytes",
+            "Request body bytes held in memory right now.",
+            self.buffered_bytes.load(Ordering::Relaxed),
+        );
+        out
+    }
+}
+
+// Counts a connection until dropped, kept in the connection's extensions
+pub struct OpenConnection(Data<Gauges>);
+
+impl OpenConnection {
+    pub fn new(gauges: Data<Gauges>) -> Self {
+        gauges.connections.fetch_add(1, Ordering::Relaxed);
+        OpenConnection(gauges)
+    }
+}
+
+impl Drop for OpenConnection {
+    fn drop(&mut self) {
+        self.0.connections.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+// For HttpServer::on_connect and friends: the extensions live as long as the connection
+pub fn on_connect(gauges: &Data<Gauges>, extensions: &mut Extensions) {
+    extensions.insert(OpenConnection::new(gauges.clone()));
+}
+
+// Bytes of a request body held in memory, counted until dropped
+pub struct Buffered {
+    gauges: Data<Gauges>,
+    bytes: usize,
+}
+
+impl Buffered {
+    pub fn new(gauges: Data<Gauges>) -> Self {
+        Buffered { gauges, bytes: 0 }
+    }
+
+    pub fn set(&mut self, bytes: usize) {
+        if bytes > self.bytes {
+            let grown = bytes - self.bytes;
+            self.gauges
+                .buffered_bytes
+                .fetch_add(grown, Ordering::Relaxed);
+        } else {
+            let shrunk = self.bytes - bytes;
+            self.gauges
+                .buffered_bytes
+                .fetch_sub(shrunk, Ordering::Relaxed);
+        }
+        self.bytes = bytes;
+    }
+}
+
+impl Drop for Buffered {
+    fn drop(&mut self) {
+        self.set(0);
+    }
+}
+
+// Counts a request until its response starts, or the client goes away
+struct InFlight(Data<Gauges>);
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.0.in_flight.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Handler for GET /stats/live-gauges
+/// Returns the requests in flight, open connections and buffered bytes right now.
+pub async fn live_gauges_handler(gauges: Data<Gauges>) -> HttpResponse {
+    HttpResponse::Ok().json(GaugesResponse {
+        in_flight_requests: gauges.in_flight.load(Ordering::Relaxed),
+        open_connections: gauges.connections.load(Ordering::Relaxed),
+        buffered_bytes: gauges.buffered_bytes.load(Ordering::Relaxed),
+        timestamp: chrono::Utc::now().to_rfc3339(),
+    })
+}
+
+// Middleware counting the requests in flight
+pub struct CountInFlight(pub Data<Gauges>);
+
+impl<S, B> Transform<S, ServiceRequest> for CountInFlight
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = CountInFlightMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(CountInFlightMiddleware {
+            service,
+            gauges: self.0.clone(),
+        }))
+    }
+}
+
+pub struct CountInFlightMiddleware<S> {
+    service: S,
+    gauges: Data<Gauges>,
+}
+
+impl<S, B> Service<ServiceRequest> for CountInFlightMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    fn poll_ready(&self, ctx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(ctx)
+    }
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        self.gauges.in_flight.fetch_add(1, Ordering::Relaxed);
+        let in_flight = InFlight(self.gauges.clone());
+        let fut = self.service.call(req);
+        Box::pin(async move {
+            let result = fut.await;
+            drop(in_flight);
+            result
+        })
+    }
+}
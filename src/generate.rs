@@ -0,0 +1,184 @@
+// GET /generate: synthetic payloads to feed back into the destruction endpoints
+//
+// `?bytes=` (e.g. 4k or 10m, up to --max-generate-bytes) sets the exact size and
+// `?kind=` the flavor: `random` bytes, `text` made of words, or `json`, an array of
+// records padded with whitespace to the size. The payload is generated chunk by chunk
+// while it is sent, with the --seed generator so it can be reproduced.
+use crate::{AppConfig, SharedRng};
+use actix_web::body::SizedStream;
+use actix_web::web::{self, Bytes, Data};
+use actix_web::{Error, HttpResponse, Result};
+use futures_util::stream;
+use rand::prelude::IndexedRandom;
+use rand::rngs::StdRng;
+use rand::Rng;
+use serde::Deserialize;
+
+const CHUNK_SIZE: usize = 64 * 1024;
+const DEFAULT_SIZE: &str = "1k";
+const WORDS: &[&str] = &[
+    "payload", "shred", "oblivion", "entropy", "byte", "void", "ash", "blade", "vortex", "static",
+    "noise", "fragment", "cinder", "null", "abyss", "confetti", "dust", "pulp",
+];
+
+#[derive(Deserialize)]
+pub struct GenerateQuery {
+    bytes: Option<String>,
+    kind: Option<String>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Kind {
+    Random,
+    Text,
+    Json,
+}
+
+impl Kind {
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "random" => Some(Kind::Random),
+            "text" => Some(Kind::Text),
+            "json" => Some(Kind::Json),
+            _ => None,
+        }
+    }
+
+    fn content_type(self) -> &'static str {
+        match self {
+            Kind::Random => "application/octet-stream",
+            Kind::Text => "text/plain; charset=utf-8",
+            Kind::Json => "application/json",
+        }
+    }
+}
+
+// Produces the payload one chunk at a time
+struct Generator {
+    kind: Kind,
+    rng: StdRng,
+    // Bytes still to produce
+    left: usize,
+    records: u64,
+}
+
+impl Generator {
+    fn next_chunk(&mut self) -> Option<Bytes> {
+        if self.left == 0 {
+            return None;
+        }
+        let size = self.left.min(CHUNK_SIZE);
+        let chunk = match self.kind {
+            Kind::Random => {
+                let mut chunk = vec![0u8; size];
+                self.rng.fill(&mut chunk[..]);
+                chunk
+            }
+            Kind::Text => self.text(size),
+            Kind::Json => self.json(size),
+        };
+        self.left -= chunk.len();
+        Some(chunk.into())
+    }
+
+    // Words and line breaks, cut off at the exact size
+    fn text(&mut self, size: usize) -> Vec<u8> {
+        let mut chunk = Vec::with_capacity(size + 16);
+        while chunk.len() < size {
+            chunk.extend_from_slice(WORDS.choose(&mut self.rng).unwrap().as_bytes());
+            let separator = if self.rng.random_ratio(1, 12) {
+                b'\n'
+            } else {
+                b' '
+            };
+            chunk.push(separator);
+        }
+        chunk.truncate(size);
+        chunk
+    }
+
+    // Records of the array, with "[" first and "]" last. Where the next record doesn't
+    // fit anymore, spaces fill up to the size.
+    fn json(&mut self, size: usize) -> Vec<u8> {
+        let mut chunk = Vec::with_capacity(size + 256);
+        // Room left between the brackets in the whole payload
+        let mut room = self.left - 1;
+        if self.records == 0 {
+            chunk.push(b'[');
+            room -= 1;
+        }
+        while chunk.len() < size && room > 0 {
+            let record = self.record();
+            let separator = if self.records > 1 { "," } else { "" };
+            if separator.len() + record.len() <= room {
+                chunk.extend_from_slice(separator.as_bytes());
+                chunk.extend_from_slice(record.as_bytes());
+                room -= separator.len() + record.len();
+            } else {
+                chunk.resize(chunk.len() + room, b' ');
+                room = 0;
+            }
+        }
+        if room == 0 {
+            chunk.push(b']');
+        }
+        chunk
+    }
+
+    fn record(&mut self) -> String {
+        self.records += 1;
+        let name = format!(
+            "{} {}",
+            WORDS.choose(&mut self.rng).unwrap(),
+            WORDS.choose(&mut self.rng).unwrap()
+        );
+        serde_json::json!({
+            "id": self.records,
+            "name": name,
+            "value": self.rng.random_range(0..1_000_000) as f64 / 100.0,
+            "active": self.rng.random_bool(0.5),
+            "tags": [WORDS.choose(&mut self.rng).unwrap(), WORDS.choose(&mut self.rng).unwrap()],
+        })
+        .to_string()
+    }
+}
+
+/// Handler for GET /generate
+/// Streams a synthetic payload of the requested size and kind.
+pub async fn generate_handler(
+    query: web::Query<GenerateQuery>,
+    config: Data<AppConfig>,
+    rng: Data<SharedRng>,
+) -> Result<HttpResponse> {
+    let spec = query.bytes.as_deref().unwrap_or(DEFAULT_SIZE);
+    let max = config.max_generate_bytes;
+    let Some(bytes) = crate::loadtest::parse_size(spec).filter(|bytes| *bytes <= max) else {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": format!(
+                "Invalid bytes '{}', use a size up to {} (e.g. 4k or 10m).",
+                spec,
+                crate::format_size(max)
+            )
+        })));
+    };
+    let Some(kind) = Kind::parse(query.kind.as_deref().unwrap_or("random")) else {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "kind must be one of random, text, json."
+        })));
+    };
+    if kind == Kind::Json && bytes < 2 {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "JSON payloads need at least 2 bytes."
+        })));
+    }
+    let mut generator = Generator {
+        kind,
+        rng: rng.fork(),
+        left: bytes,
+        records: 0,
+    };
+    let chunks = std::iter::from_fn(move || generator.next_chunk()).map(Ok::<_, Error>);
+    Ok(HttpResponse::Ok()
+        .content_type(kind.content_type())
+        .body(SizedStream::new(bytes as u64, stream::iter(chunks))))
+}
@@ -0,0 +1,38 @@
+// Client location lookups against a MaxMind GeoIP2/GeoLite2 City database (feature "geoip")
+use maxminddb::geoip2;
+use std::net::IpAddr;
+
+pub struct GeoIp {
+    reader: maxminddb::Reader<Vec<u8>>,
+}
+
+// Where a client address resolved to, either part may be unknown
+pub struct GeoLocation {
+    pub country: Option<String>,
+    pub city: Option<String>,
+}
+
+impl GeoIp {
+    pub fn open(path: &str) -> Result<Self, String> {
+        let reader = maxminddb::Reader::open_readfile(path)
+            .map_err(|e| format!("Failed to open GeoIP database {}: {}", path, e))?;
+        Ok(GeoIp { reader })
+    }
+
+    // Country ISO code and English city name, None for private or unknown addresses
+    pub fn locate(&self, ip: IpAddr) -> Option<GeoLocation> {
+        let record: geoip2::City = self.reader.lookup(ip).ok()?;
+        let country = record
+            .country
+            .and_then(|country| country.iso_code)
+            .map(str::to_string);
+        let city = record
+            .city
+            .and_then(|city| city.names)
+            .and_then(|names| names.get("en").map(|name| name.to_string()));
+        if country.is_none() && city.is_none() {
+            return None;
+        }
+        Some(GeoLocation { country, city })
+    }
+}
@@ -0,0 +1,226 @@
+// GraphQL detection for /validate-before-destroy
+//
+// Recognizes executable GraphQL documents, sent raw or in the usual
+// `{"query": …, "variables": …, "operationName": …}` JSON envelope, and reports their
+// operations, fragments and how deeply the selections nest. A small tokenizer is
+// enough for that, the document isn't validated against any schema.
+use serde::Serialize;
+use serde_json::Value;
+
+#[derive(Serialize)]
+pub struct Operation {
+    // query, mutation or subscription
+    kind: String,
+    // None for anonymous operations
+    name: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct GraphqlReport {
+    // Sent in a {"query": …} JSON envelope
+    envelope: bool,
+    operations: Vec<Operation>,
+    fragments: Vec<String>,
+    // Deepest nesting of selection sets, 1 for `{ a }`
+    max_depth: usize,
+    // operationName of the envelope
+    #[serde(skip_serializing_if = "Option::is_none")]
+    operation_name: Option<String>,
+    // Number of variables in the envelope
+    #[serde(skip_serializing_if = "Option::is_none")]
+    variables: Option<usize>,
+}
+
+#[derive(PartialEq)]
+enum Token<'a> {
+    Name(&'a str),
+    Punct(char),
+    // Strings, numbers and `...`, nothing here needs their value
+    Other,
+}
+
+// Tokens of a document, None on characters GraphQL doesn't allow
+fn tokenize(text: &str) -> Option<Vec<Token<'_>>> {
+    let bytes = text.as_bytes();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        let c = bytes[i];
+        match c {
+            b' ' | b'\t' | b'\n' | b'\r' | b',' => i += 1,
+            // Byte order mark
+            0xEF if bytes[i..].starts_with(&[0xEF, 0xBB, 0xBF]) => i += 3,
+            b'#' => {
+                while i < bytes.len() && bytes[i] != b'\n' {
+                    i += 1;
+                }
+            }
+            b'"' if bytes[i..].starts_with(b"\"\"\"") => {
+                let end = text[i + 3..].find("\"\"\"")?;
+                i += 3 + end + 3;
+                tokens.push(Token::Other);
+            }
+            b'"' => {
+                i += 1;
+                loop {
+                    match bytes.get(i)? {
+                        b'\\' => i += 2,
+                        b'"' => break,
+                        b'\n' => return None,
+                        _ => i += 1,
+                    }
+                }
+                i += 1;
+                tokens.push(Token::Other);
+            }
+            b'.' if bytes[i..].starts_with(b"...") => {
+                i += 3;
+                tokens.push(Token::Other);
+            }
+            b'{' | b'}' | b'(' | b')' | b'[' | b']' | b':' | b'=' | b'@' | b'$' | b'!' | b'|'
+            | b'&' => {
+                i += 1;
+                tokens.push(Token::Punct(c as char));
+            }
+            b'-' | b'0'..=b'9' => {
+                i += 1;
+                while i < bytes.len()
+                    && matches!(bytes[i], b'0'..=b'9' | b'.' | b'e' | b'E' | b'+' | b'-')
+                {
+                    i += 1;
+                }
+                tokens.push(Token::Other);
+            }
+            b'_' | b'a'..=b'z' | b'A'..=b'Z' => {
+                let start = i;
+                while i < bytes.len() && (bytes[i] == b'_' || bytes[i].is_ascii_alphanumeric()) {
+                    i += 1;
+                }
+                tokens.push(Token::Name(&text[start..i]));
+            }
+            _ => return None,
+        }
+    }
+    Some(tokens)
+}
+
+#[derive(PartialEq)]
+enum Open {
+    Selection,
+    // Input objects, lists and argument lists
+    Value(char),
+}
+
+// Operations, fragments and selection depth of an executable document, None if the
+// text isn't one
+fn parse(text: &str) -> Option<(Vec<Operation>, Vec<String>, usize)> {
+    let tokens = tokenize(text)?;
+    let mut operations = Vec::new();
+    let mut fragments = Vec::new();
+    let mut max_depth = 0;
+    let mut i = 0;
+    while i < tokens.len() {
+        // A definition: `{ … }`, `query Name(…) @dir { … }` or `fragment Name on Type { … }`
+        match &tokens[i] {
+            Token::Punct('{') => operations.push(Operation {
+                kind: "query".to_string(),
+                name: None,
+            }),
+            Token::Name(kind @ ("query" | "mutation" | "subscription")) => {
+                let name = match tokens.get(i + 1) {
+                    Some(Token::Name(name)) => Some(name.to_string()),
+                    _ => None,
+                };
+                operations.push(Operation {
+                    kind: kind.to_string(),
+                    name,
+                });
+            }
+            Token::Name("fragment") => match tokens.get(i + 1) {
+                Some(Token::Name(name)) => fragments.push(name.to_string()),
+                _ => return None,
+            },
+            _ => return None,
+        }
+        // Up to the end of the definition's selection set
+        let mut stack: Vec<Open> = Vec::new();
+        let mut selected = false;
+        while i < tokens.len() {
+            match tokens[i] {
+                Token::Punct('{') => {
+                    let in_selection = stack.last().is_none_or(|open| *open == Open::Selection);
+                    stack.push(match in_selection {
+                        true => Open::Selection,
+                        false => Open::Value('}'),
+                    });
+                    let depth = stack
+                        .iter()
+                        .filter(|open| **open == Open::Selection)
+                        .count();
+                    max_depth = max_depth.max(depth);
+                }
+                Token::Punct('(') => stack.push(Open::Value(')')),
+                Token::Punct('[') => stack.push(Open::Value(']')),
+                Token::Punct(close @ ('}' | ')' | ']')) => {
+                    match stack.pop()? {
+                        Open::Selection if close == '}' => {}
+                        Open::Value(expected) if close == expected => {}
+                        _ => return None,
+                    }
+                    if stack.is_empty() && close == '}' {
+                        selected = true;
+                        i += 1;
+                        break;
+                    }
+                }
+                _ => {}
+            }
+            i += 1;
+        }
+        if !selected {
+            return None;
+        }
+    }
+    (!operations.is_empty() || !fragments.is_empty()).then_some((operations, fragments, max_depth))
+}
+
+// Report for a raw document or a JSON envelope, None for anything else
+pub fn detect(body_str: &str, json: Option<&Value>) -> Option<GraphqlReport> {
+    let (query, envelope) = match json {
+        Some(Value::Object(map)) => (map.get("query")?.as_str()?, Some(map)),
+        Some(_) => return None,
+        None => (body_str, None),
+    };
+    let (operations, fragments, max_depth) = parse(query)?;
+    Some(GraphqlReport {
+        envelope: envelope.is_some(),
+        operations,
+        fragments,
+        max_depth,
+        operation_name: envelope
+            .and_then(|map| map.get("operationName")?.as_str())
+            .map(str::to_string),
+        variables: envelope.and_then(|map| Some(map.get("variables")?.as_object()?.len())),
+    })
+}
+
+impl GraphqlReport {
+    pub fn detail(&self) -> String {
+        let operations: Vec<String> = self
+            .operations
+            .iter()
+            .map(|operation| match &operation.name {
+                Some(name) => format!("{} {}", operation.kind, name),
+                None => format!("anonymous {}", operation.kind),
+            })
+            .collect();
+        format!(
+            "GraphQL document{} with {} operations ({}) and {} fragments, selections nested {} deep.",
+            if self.envelope { " in a JSON envelope" } else { "" },
+            operations.len(),
+            operations.join(", "),
+            self.fragments.len(),
+            self.max_depth
+        )
+    }
+}
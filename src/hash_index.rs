@@ -0,0 +1,286 @@
+// Which payloads were destroyed, by SHA-256, for GET /was-it-destroyed (--remember-hashes)
+//
+// Every destroyed payload is added to a Bloom filter, which can tell "definitely not"
+// from "probably" without keeping a list of hashes. Receipted destructions (finished
+// tus uploads, whose receipts carry the hash anyway) are also kept exactly, with the
+// time. Both live in the stats database; the filter is written back once a minute.
+use actix_web::web::{self, Data};
+use actix_web::{HttpRequest, HttpResponse, Result};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+// 2^24 bits (2 MiB) and 7 probes keep false positives near 1% up to ~1.7M payloads
+const FILTER_BITS: usize = 1 << 24;
+const PROBES: u64 = 7;
+const FLUSH_INTERVAL: Duration = Duration::from_secs(60);
+
+struct Filter {
+    bits: Vec<u64>,
+    // When the filter started, nothing destroyed before is known
+    since: String,
+}
+
+pub struct HashIndex {
+    filter: Mutex<Filter>,
+    // Set when the filter changed since it was last written
+    dirty: AtomicBool,
+}
+
+#[derive(Deserialize)]
+pub struct LookupQuery {
+    sha256: String,
+}
+
+#[derive(Serialize)]
+struct LookupResponse {
+    sha256: String,
+    // "yes" for receipted destructions, "probably" or "definitely_not"
+    answer: &'static str,
+    message: String,
+    // Only known for receipted destructions
+    #[serde(skip_serializing_if = "Option::is_none")]
+    destroyed_at: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    endpoint: Option<String>,
+    since: String,
+}
+
+// Bit positions of a digest, by double hashing its first 16 bytes
+fn probes(digest: &[u8; 32]) -> impl Iterator<Item = usize> {
+    let first = u64::from_le_bytes(digest[..8].try_into().unwrap());
+    let second = u64::from_le_bytes(digest[8..16].try_into().unwrap()) | 1;
+    (0..PROBES)
+        .map(move |i| (first.wrapping_add(i.wrapping_mul(second)) % FILTER_BITS as u64) as usize)
+}
+
+fn parse_digest(hex: &str) -> Option<[u8; 32]> {
+    if hex.len() != 64 || !hex.is_ascii() {
+        return None;
+    }
+    let mut digest = [0; 32];
+    for (i, byte) in digest.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[2 * i..2 * i + 2], 16).ok()?;
+    }
+    Some(digest)
+}
+
+pub(crate) fn ensure_tables(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS hash_filter (
+            id INTEGER PRIMARY KEY CHECK (id = 1),
+            bits BLOB NOT NULL,
+            since TEXT NOT NULL
+        );
+        CREATE TABLE IF NOT EXISTS hash_receipts (
+            sha256 TEXT PRIMARY KEY,
+            endpoint TEXT NOT NULL,
+            destroyed_at TEXT NOT NULL
+        )",
+    )
+}
+
+// Nothing was written yet if the tables don't exist
+fn without_tables<T>(result: rusqlite::Result<Option<T>>) -> rusqlite::Result<Option<T>> {
+    match result {
+        Err(rusqlite::Error::SqliteFailure(_, Some(message)))
+            if message.starts_with("no such table") =>
+        {
+            Ok(None)
+        }
+        result => result,
+    }
+}
+
+impl HashIndex {
+    // The filter saved in the database, or an empty one. The tables are created on the
+    // first write, so read-only databases work too.
+    pub fn load(conn: &Connection) -> Result<Self, String> {
+        let saved: rusqlite::Result<Option<(Vec<u8>, String)>> = conn
+            .query_row(
+                "SELECT bits, since FROM hash_filter WHERE id = 1",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional();
+        let saved =
+            without_tables(saved).map_err(|e| format!("Failed to load the hash filter: {}", e))?;
+        let filter = match saved {
+            Some((bytes, since)) if bytes.len() == FILTER_BITS / 8 => Filter {
+                bits: bytes
+                    .chunks_exact(8)
+                    .map(|chunk| u64::from_le_bytes(chunk.try_into().unwrap()))
+                    .collect(),
+                since,
+            },
+            Some(_) => return Err("The saved hash filter has an unexpected size".to_string()),
+            None => Filter {
+                bits: vec![0; FILTER_BITS / 64],
+                since: chrono::Utc::now().to_rfc3339(),
+            },
+        };
+        Ok(HashIndex {
+            filter: Mutex::new(filter),
+            dirty: AtomicBool::new(false),
+        })
+    }
+
+    fn insert(&self, digest: &[u8; 32]) {
+        let mut filter = self.filter.lock().unwrap();
+        for bit in probes(digest) {
+            filter.bits[bit / 64] |= 1 << (bit % 64);
+        }
+        self.dirty.store(true, Ordering::Relaxed);
+    }
+
+    fn contains(&self, digest: &[u8; 32]) -> bool {
+        let filter = self.filter.lock().unwrap();
+        probes(digest).all(|bit| filter.bits[bit / 64] & (1 << (bit % 64)) != 0)
+    }
+
+    // Write the filter back if it changed
+    fn flush(&self, conn: &Connection) -> rusqlite::Result<()> {
+        if !self.dirty.swap(false, Ordering::Relaxed) {
+            return Ok(());
+        }
+        let (bytes, since) = {
+            let filter = self.filter.lock().unwrap();
+            let bytes: Vec<u8> = filter
+                .bits
+                .iter()
+                .flat_map(|word| word.to_le_bytes())
+                .collect();
+            (bytes, filter.since.clone())
+        };
+        let result = ensure_tables(conn).and_then(|()| {
+            conn.execute(
+                "INSERT INTO hash_filter (id, bits, since) VALUES (1, ?1, ?2)
+                ON CONFLICT (id) DO UPDATE SET bits = excluded.bits",
+                params![bytes, since],
+            )
+        });
+        if result.is_err() {
+            self.dirty.store(true, Ordering::Relaxed);
+        }
+        result.map(|_| ())
+    }
+}
+
+// Write the filter to the database if it changed
+pub fn save(index: &HashIndex, db: &Mutex<Connection>) {
+    if let Err(e) = index.flush(&db.lock().unwrap()) {
+        eprintln!("Failed to save the hash filter: {}", e);
+    }
+}
+
+// Save the filter once a minute, and once more on shutdown
+pub fn spawn_flusher(index: Data<HashIndex>, db: Data<Mutex<Connection>>) {
+    std::thread::spawn(move || loop {
+        std::thread::sleep(FLUSH_INTERVAL);
+        save(&index, &db);
+    });
+}
+
+// Note a destroyed payload, does nothing without --remember-hashes
+pub fn remember(req: &HttpRequest, body: &[u8]) {
+    if let Some(index) = req.app_data::<Data<HashIndex>>() {
+        index.insert(&Sha256::digest(body).into());
+    }
+}
+
+// Note a receipted destruction by the hex digest from its receipt
+pub fn remember_receipt(req: &HttpRequest, sha256: &str, endpoint: &str, destroyed_at: &str) {
+    let (Some(index), Some(db)) = (
+        req.app_data::<Data<HashIndex>>(),
+        req.app_data::<Data<Mutex<Connection>>>(),
+    ) else {
+        return;
+    };
+    let Some(digest) = parse_digest(sha256) else {
+        return;
+    };
+    index.insert(&digest);
+    let conn = db.lock().unwrap();
+    let result = ensure_tables(&conn).and_then(|()| {
+        conn.execute(
+            "INSERT OR REPLACE INTO hash_receipts (sha256, endpoint, destroyed_at) VALUES (?1, ?2, ?3)",
+            params![sha256, endpoint, destroyed_at],
+        )
+    });
+    if let Err(e) = result {
+        eprintln!("Failed to save the receipt hash: {}", e);
+    }
+}
+
+fn find_receipt(conn: &Connection, sha256: &str) -> rusqlite::Result<Option<(String, String)>> {
+    let receipt = conn
+        .query_row(
+            "SELECT endpoint, destroyed_at FROM hash_receipts WHERE sha256 = ?1",
+            [sha256],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .optional();
+    without_tables(receipt)
+}
+
+/// Handler for GET /was-it-destroyed
+/// Tells whether a payload with the given `?sha256=` passed through the pulverizer.
+pub async fn lookup_handler(
+    req: HttpRequest,
+    query: web::Query<LookupQuery>,
+    db: Data<Mutex<Connection>>,
+) -> Result<HttpResponse> {
+    let Some(index) = req.app_data::<Data<HashIndex>>() else {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "Hash tracking is disabled on this server (--remember-hashes)."
+        })));
+    };
+    let sha256 = query.sha256.to_ascii_lowercase();
+    let Some(digest) = parse_digest(&sha256) else {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "sha256 must be 64 hexadecimal characters."
+        })));
+    };
+    let since = index.filter.lock().unwrap().since.clone();
+    let receipt = match find_receipt(&db.lock().unwrap(), &sha256) {
+        Ok(receipt) => receipt,
+        Err(e) => {
+            return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": format!("Failed to look up receipts: {}", e)
+            })))
+        }
+    };
+    let response = match receipt {
+        Some((endpoint, destroyed_at)) => LookupResponse {
+            answer: "yes",
+            message: format!("Destroyed by {} at {}.", endpoint, destroyed_at),
+            destroyed_at: Some(destroyed_at),
+            endpoint: Some(endpoint),
+            sha256,
+            since,
+        },
+        None if index.contains(&digest) => LookupResponse {
+            answer: "probably",
+            message: format!(
+                "Probably destroyed since {}, the time isn't known for payloads without a receipt.",
+                since
+            ),
+            destroyed_at: None,
+            endpoint: None,
+            sha256,
+            since,
+        },
+        None => LookupResponse {
+            answer: "definitely_not",
+            message: format!("Definitely not destroyed since {}.", since),
+            destroyed_at: None,
+            endpoint: None,
+            sha256,
+            since,
+        },
+    };
+    Ok(HttpResponse::Ok().json(response))
+}
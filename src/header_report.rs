@@ -0,0 +1,82 @@
+// ?header_report=true: what the request headers looked like
+//
+// Many "payload" problems turn out to be header problems: a cookie jar that grew past
+// a proxy's limit, a header sent twice by two layers of middleware, a UTF-8 file name
+// that some hop won't forward. The report lists the total size and count and points
+// out headers that are oversized, duplicated or not plain ASCII.
+use actix_web::HttpRequest;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+// Values above this are flagged, common proxies reject around 8 KiB for all headers
+const OVERSIZED: usize = 4 * 1024;
+
+#[derive(Serialize)]
+pub struct HeaderReport {
+    // As on the wire in HTTP/1.1: "Name: value\r\n" per header
+    total_bytes: usize,
+    count: usize,
+    oversized: Vec<SizedHeader>,
+    duplicates: Vec<DuplicateHeader>,
+    non_ascii: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct SizedHeader {
+    name: String,
+    bytes: usize,
+}
+
+#[derive(Serialize)]
+struct DuplicateHeader {
+    name: String,
+    count: usize,
+}
+
+// Scans the raw query string like ?minimal=true
+fn wants_report(req: &HttpRequest) -> bool {
+    req.query_string()
+        .split('&')
+        .any(|pair| pair == "header_report=true" || pair == "header_report=1")
+}
+
+// None unless asked for with ?header_report=true
+pub fn report(req: &HttpRequest) -> Option<HeaderReport> {
+    if !wants_report(req) {
+        return None;
+    }
+    let mut report = HeaderReport {
+        total_bytes: 0,
+        count: 0,
+        oversized: Vec::new(),
+        duplicates: Vec::new(),
+        non_ascii: Vec::new(),
+    };
+    let mut counts: BTreeMap<&str, usize> = BTreeMap::new();
+    for (name, value) in req.headers() {
+        let bytes = value.as_bytes().len();
+        report.total_bytes += name.as_str().len() + 2 + bytes + 2;
+        report.count += 1;
+        *counts.entry(name.as_str()).or_default() += 1;
+        if bytes > OVERSIZED {
+            report.oversized.push(SizedHeader {
+                name: name.to_string(),
+                bytes,
+            });
+        }
+        if !value.as_bytes().is_ascii()
+            && !report.non_ascii.iter().any(|seen| seen == name.as_str())
+        {
+            report.non_ascii.push(name.to_string());
+        }
+    }
+    report.duplicates = counts
+        .into_iter()
+        .filter(|(_, count)| *count > 1)
+        .map(|(name, count)| DuplicateHeader {
+            name: name.to_string(),
+            count,
+        })
+        .collect();
+    Some(report)
+}
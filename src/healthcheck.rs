@@ -0,0 +1,32 @@
+// `healthcheck` subcommand: probe a running pulverizer, for containers without curl
+use std::time::Duration;
+
+// CLI arguments of the healthcheck subcommand
+#[derive(clap::Args, Debug, Clone)]
+pub struct HealthcheckArgs {
+    /// URL to probe, any 2xx answer counts as healthy
+    #[arg(long, default_value = "http://localhost:8080/ping")]
+    url: String,
+    /// Give up after this long, e.g. 3s
+    #[arg(long, default_value = "5s", value_parser = crate::parse_duration_arg)]
+    timeout: i64,
+}
+
+// Exits with 0 if the probe succeeds and 1 otherwise, so it can serve as HEALTHCHECK
+pub fn run(args: HealthcheckArgs) -> ! {
+    let agent = ureq::AgentBuilder::new()
+        .timeout(Duration::from_secs(args.timeout as u64))
+        .build();
+    match agent.get(&args.url).call() {
+        Ok(response) if (200..300).contains(&response.status()) => {
+            println!("healthy: {} answered {}", args.url, response.status());
+            std::process::exit(0);
+        }
+        Ok(response) => eprintln!("unhealthy: {} answered {}", args.url, response.status()),
+        Err(ureq::Error::Status(status, _)) => {
+            eprintln!("unhealthy: {} answered {}", args.url, status)
+        }
+        Err(e) => eprintln!("unhealthy: {}", e),
+    }
+    std::process::exit(1);
+}
@@ -0,0 +1,225 @@
+// --http3-port: experimental HTTP/3 (QUIC) listener, for testing h3-capable client stacks
+//
+// actix-web doesn't speak HTTP/3, so quinn and h3 handle the connections and every request
+// is handed to the same App service the TCP listener uses, with the body streamed in and the
+// response streamed back. Responses over TCP advertise the listener with Alt-Svc. QUIC needs
+// TLS 1.3, with the certificate from --http3-cert/--http3-key or the one --acme-domain gets.
+// All HTTP/3 connections are handled on the main thread, it's a sink for tests, not for load.
+use crate::gauges::{Gauges, OpenConnection};
+use actix_http::body::{BodySize, MessageBody};
+use actix_http::error::PayloadError;
+use actix_http::header::{HeaderName, HeaderValue, CONNECTION, HOST, TRANSFER_ENCODING, UPGRADE};
+use actix_http::BoxedPayloadStream;
+use actix_http::{Method, Payload, Request, Response, Version};
+use actix_service::IntoServiceFactory;
+use actix_web::dev::{AppConfig, Service, ServiceFactory};
+use actix_web::web::{Buf, Bytes, Data};
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::ResolvesServerCert;
+use rustls::sign::{CertifiedKey, SingleCertAndKey};
+use std::fmt;
+use std::io;
+use std::rc::Rc;
+use std::sync::Arc;
+
+type RequestStream<S> = h3::server::RequestStream<S, Bytes>;
+
+// Alt-Svc value announcing the listener, clients may remember it for a day
+pub fn alt_svc(port: u16) -> String {
+    format!("h3=\":{}\"; ma=86400", port)
+}
+
+// The certificate from --http3-cert and --http3-key
+pub fn certificate(cert: &str, key: &str) -> Result<Arc<dyn ResolvesServerCert>, String> {
+    let certs = CertificateDer::pem_file_iter(cert)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| format!("Failed to read the certificate chain {}: {}", cert, e))?;
+    let key = PrivateKeyDer::from_pem_file(key)
+        .map_err(|e| format!("Failed to read the private key {}: {}", key, e))?;
+    let key = rustls::crypto::ring::sign::any_supported_type(&key)
+        .map_err(|e| format!("Unsupported private key: {}", e))?;
+    Ok(Arc::new(SingleCertAndKey::from(CertifiedKey::new(
+        certs, key,
+    ))))
+}
+
+// Bind the UDP socket, needs to run on the server's runtime
+pub fn bind(port: u16, certificate: Arc<dyn ResolvesServerCert>) -> io::Result<quinn::Endpoint> {
+    let mut tls_config = rustls::ServerConfig::builder_with_provider(Arc::new(
+        rustls::crypto::ring::default_provider(),
+    ))
+    .with_protocol_versions(&[&rustls::version::TLS13])
+    .map_err(io::Error::other)?
+    .with_no_client_auth()
+    .with_cert_resolver(certificate);
+    tls_config.alpn_protocols = vec![b"h3".to_vec()];
+    let quic_config =
+        quinn::crypto::rustls::QuicServerConfig::try_from(tls_config).map_err(io::Error::other)?;
+    let endpoint = quinn::Endpoint::server(
+        quinn::ServerConfig::with_crypto(Arc::new(quic_config)),
+        ([0, 0, 0, 0], port).into(),
+    )?;
+    println!("Serving HTTP/3 on UDP port {}", port);
+    Ok(endpoint)
+}
+
+// Accept connections until the endpoint is closed, with one App service for all of them
+pub async fn serve<F, I, S, B>(endpoint: quinn::Endpoint, factory: F, gauges: Data<Gauges>)
+where
+    F: Fn() -> I,
+    I: IntoServiceFactory<S, Request>,
+    S: ServiceFactory<Request, Config = AppConfig> + 'static,
+    S::Error: Into<actix_web::Error> + 'static,
+    S::InitError: fmt::Debug,
+    S::Response: Into<Response<B>> + 'static,
+    S::Service: 'static,
+    B: MessageBody + 'static,
+{
+    let service = match factory()
+        .into_factory()
+        .new_service(AppConfig::default())
+        .await
+    {
+        Ok(service) => Rc::new(service),
+        Err(e) => {
+            eprintln!("Failed to start the HTTP/3 listener: {:?}", e);
+            return;
+        }
+    };
+    while let Some(incoming) = endpoint.accept().await {
+        actix_web::rt::spawn(connection(incoming, service.clone(), gauges.clone()));
+    }
+}
+
+// Requests are handled concurrently, each on its own stream. Errors just end the
+// connection or stream, like a dropped TCP connection would.
+async fn connection<S, B>(incoming: quinn::Incoming, service: Rc<S>, gauges: Data<Gauges>)
+where
+    S: Service<Request> + 'static,
+    S::Error: Into<actix_web::Error>,
+    S::Response: Into<Response<B>>,
+    B: MessageBody + 'static,
+{
+    let Ok(connection) = incoming.await else {
+        return;
+    };
+    let peer = connection.remote_address();
+    let _open = OpenConnection::new(gauges);
+    let Ok(mut connection) =
+        h3::server::Connection::<_, Bytes>::new(h3_quinn::Connection::new(connection)).await
+    else {
+        return;
+    };
+    while let Ok(Some(resolver)) = connection.accept().await {
+        let service = service.clone();
+        actix_web::rt::spawn(async move {
+            if let Ok((request, stream)) = resolver.resolve_request().await {
+                respond(request, stream, peer, service).await;
+            }
+        });
+    }
+}
+
+async fn respond<Q, S, B>(
+    request: http::Request<()>,
+    stream: RequestStream<Q>,
+    peer: std::net::SocketAddr,
+    service: Rc<S>,
+) where
+    Q: h3::quic::BidiStream<Bytes> + 'static,
+    S: Service<Request>,
+    S::Error: Into<actix_web::Error>,
+    S::Response: Into<Response<B>>,
+    B: MessageBody + 'static,
+{
+    let (mut send, recv) = stream.split();
+    let (parts, ()) = request.into_parts();
+    let head_only = parts.method == http::Method::HEAD;
+    let response: Response<_> = match into_request(parts, recv, peer) {
+        Some(request) => match service.call(request).await {
+            Ok(response) => response.into().map_into_boxed_body(),
+            Err(e) => Response::from(e.into()),
+        },
+        None => Response::bad_request(),
+    };
+    let (head, body) = response.into_parts();
+    let mut builder = http::Response::builder().status(head.status().as_u16());
+    for (name, value) in head.headers() {
+        // Connection-specific headers are malformed in HTTP/3
+        if ![CONNECTION, TRANSFER_ENCODING, UPGRADE].contains(name) && name != "keep-alive" {
+            builder = builder.header(name.as_str(), value.as_bytes());
+        }
+    }
+    if let BodySize::Sized(len) = body.size() {
+        builder = builder.header("content-length", len);
+    }
+    let Ok(response) = builder.body(()) else {
+        return;
+    };
+    if send.send_response(response).await.is_err() {
+        return;
+    }
+    if !head_only {
+        let mut body = std::pin::pin!(body);
+        while let Some(chunk) = std::future::poll_fn(|cx| body.as_mut().poll_next(cx)).await {
+            let sent = match chunk {
+                Ok(chunk) => send.send_data(chunk).await,
+                Err(_) => {
+                    send.stop_stream(h3::error::Code::H3_INTERNAL_ERROR);
+                    return;
+                }
+            };
+            if sent.is_err() {
+                return;
+            }
+        }
+    }
+    let _ = send.finish().await;
+}
+
+// The actix request for an h3 one, with the body read from the stream as the app consumes it.
+// The versions of the http crate differ, so everything is converted through strings and bytes.
+fn into_request<R>(
+    parts: http::request::Parts,
+    recv: RequestStream<R>,
+    peer: std::net::SocketAddr,
+) -> Option<Request>
+where
+    R: h3::quic::RecvStream + 'static,
+{
+    // Ends after the first error
+    let body = futures_util::stream::unfold(Some(recv), |recv| async move {
+        let mut recv = recv?;
+        let data = recv
+            .recv_data()
+            .await
+            .map(|data| data.map(|mut data| data.copy_to_bytes(data.remaining())));
+        match data {
+            Ok(Some(chunk)) => Some((Ok(chunk), Some(recv))),
+            Ok(None) => None,
+            Err(e) => Some((Err(PayloadError::Io(io::Error::other(e))), None)),
+        }
+    });
+    let body: BoxedPayloadStream = Box::pin(body);
+    let mut request = Request::with_payload(Payload::Stream { payload: body });
+    let head = request.head_mut();
+    head.method = Method::from_bytes(parts.method.as_str().as_bytes()).ok()?;
+    head.uri = parts.uri.to_string().parse().ok()?;
+    head.version = Version::HTTP_3;
+    head.peer_addr = Some(peer);
+    for (name, value) in &parts.headers {
+        head.headers.append(
+            HeaderName::from_bytes(name.as_str().as_bytes()).ok()?,
+            HeaderValue::from_bytes(value.as_bytes()).ok()?,
+        );
+    }
+    // HTTP/3 carries the host in the :authority pseudo-header only
+    if let Some(authority) = parts.uri.authority() {
+        if !head.headers.contains_key(HOST) {
+            head.headers
+                .insert(HOST, HeaderValue::from_str(authority.as_str()).ok()?);
+        }
+    }
+    Some(request)
+}
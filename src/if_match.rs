@@ -0,0 +1,38 @@
+// Conditional destruction with If-Match on receipt hashes
+//
+// Receipts name the SHA-256 of the destroyed payload. A destruction request with
+// `If-Match: "<sha256>"` (several tags or `*` allowed, quotes optional) is refused with
+// 412 unless its body hashes to one of them, so a cleanup pipeline re-run against a
+// newer revision doesn't destroy it by accident. Like receipts, the hash is taken after
+// any Content-Encoding is undone.
+use actix_web::error::InternalError;
+use actix_web::http::header::IF_MATCH;
+use actix_web::{HttpRequest, HttpResponse};
+use sha2::{Digest, Sha256};
+
+// Ok if the request has no If-Match or the body matches it
+pub fn check(req: &HttpRequest, body: &[u8]) -> Result<(), actix_web::Error> {
+    let tags: Vec<&str> = req
+        .headers()
+        .get_all(IF_MATCH)
+        .flat_map(|value| value.to_str().unwrap_or_default().split(','))
+        .map(str::trim)
+        .filter(|tag| !tag.is_empty())
+        .collect();
+    if tags.is_empty() || crate::read_only::destruction_path(req).is_none() {
+        return Ok(());
+    }
+    let sha256 = format!("{:x}", Sha256::digest(body));
+    // Weak tags (W/"…") never match, If-Match compares strongly
+    let matches = tags
+        .iter()
+        .any(|tag| *tag == "*" || tag.trim_matches('"').eq_ignore_ascii_case(&sha256));
+    if matches {
+        return Ok(());
+    }
+    let response = HttpResponse::PreconditionFailed().json(serde_json::json!({
+        "error": "The payload doesn't match If-Match, it was not destroyed.",
+        "sha256": sha256
+    }));
+    Err(InternalError::from_response("if-match failed", response).into())
+}
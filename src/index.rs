@@ -0,0 +1,715 @@
+// GET /: the endpoints of this server, and the route registry it's generated from
+//
+// Every route is registered from ENDPOINTS, so the index can't miss one (only the ACME
+// challenges, which are for the CA, live outside it and the base path). Each path also
+// gets a fallback answering OPTIONS with the methods it takes in `Allow`, and any other
+// method with 405 and the same header.
+use crate::{body, format_size, stage, tus, AppConfig};
+use actix_web::dev::ResourceDef;
+use actix_web::http::header::{ACCEPT, ALLOW, VARY};
+use actix_web::http::Method;
+use actix_web::web::{self, Data};
+use actix_web::{HttpRequest, HttpResponse, Route, Scope};
+use serde::Serialize;
+use std::sync::LazyLock;
+
+// What an endpoint takes as request body
+pub enum Accepts {
+    // No body
+    Nothing,
+    // Anything the config file's allowed_content_types lets through
+    Payload,
+    // These content types
+    Types(&'static [&'static str]),
+}
+
+// What bounds the bytes an endpoint handles
+pub enum Limit {
+    None,
+    // limits.max_payload_size, at most the hard limit
+    Payload,
+    // --validate-max-size, or limits.validate_max_size
+    #[cfg(feature = "validators")]
+    Validate,
+    // --stage-max-bytes, over all staged payloads
+    Stage,
+    // The size of a whole tus upload
+    Tus,
+    // --fetch-max-size, of the downloaded resource
+    Fetch,
+    // --max-generate-bytes, of the generated payload
+    Generate,
+}
+
+pub struct Endpoint {
+    // None for any method
+    pub method: Option<Method>,
+    pub path: &'static str,
+    // Attaches the handler to a route for the method
+    pub handler: fn(Route) -> Route,
+    pub description: &'static str,
+    pub accepts: Accepts,
+    pub limit: Limit,
+}
+
+impl Endpoint {
+    pub fn route(&self) -> Route {
+        let route = match &self.method {
+            Some(method) => web::method(method.clone()),
+            None => web::route(),
+        };
+        (self.handler)(route)
+    }
+}
+
+// Every route of the server, in the order they are matched
+pub static ENDPOINTS: &[Endpoint] = &[
+    Endpoint {
+        method: Some(Method::GET),
+        path: "/",
+        handler: |route| route.to(index_handler),
+        description: "This index, as HTML for browsers",
+        accepts: Accepts::Nothing,
+        limit: Limit::None,
+    },
+    Endpoint {
+        method: Some(Method::POST),
+        path: "/pulverize",
+        handler: |route| route.to(crate::pulverize_handler),
+        description: "Destroy a payload",
+        accepts: Accepts::Payload,
+        limit: Limit::Payload,
+    },
+    Endpoint {
+        method: Some(Method::POST),
+        path: "/blackhole",
+        handler: |route| route.to(crate::blackhole_handler),
+        description: "Destroy a payload and answer 204 without a body",
+        accepts: Accepts::Payload,
+        limit: Limit::Payload,
+    },
+    Endpoint {
+        method: Some(Method::POST),
+        path: "/auto",
+        handler: |route| route.to(crate::auto_handler),
+        description: "Destroy a payload the way the routing rules pick",
+        accepts: Accepts::Payload,
+        limit: Limit::Payload,
+    },
+    Endpoint {
+        method: Some(Method::POST),
+        path: "/compare-then-destroy",
+        handler: |route| route.to(crate::compare::compare_handler),
+        description: "Compare the parts a and b, then destroy both",
+        accepts: Accepts::Types(&["multipart/form-data"]),
+        limit: Limit::Payload,
+    },
+    Endpoint {
+        method: Some(Method::OPTIONS),
+        path: "/tus",
+        handler: |route| route.to(tus::options_handler),
+        description: "tus protocol capabilities",
+        accepts: Accepts::Nothing,
+        limit: Limit::None,
+    },
+    Endpoint {
+        method: Some(Method::POST),
+        path: "/queue",
+        handler: |route| route.to(crate::queue::submit_handler),
+        description: "Queue a payload for destruction",
+        accepts: Accepts::Payload,
+        limit: Limit::Payload,
+    },
+    Endpoint {
+        method: Some(Method::GET),
+        path: "/queue/{id}",
+        handler: |route| route.to(crate::queue::poll_handler),
+        description: "State of a queued job",
+        accepts: Accepts::Nothing,
+        limit: Limit::None,
+    },
+    Endpoint {
+        method: Some(Method::GET),
+        path: "/jobs/{id}",
+        handler: |route| route.to(crate::queue::poll_handler),
+        description: "State of a queued job",
+        accepts: Accepts::Nothing,
+        limit: Limit::None,
+    },
+    Endpoint {
+        method: Some(Method::POST),
+        path: "/tus",
+        handler: |route| route.to(tus::create_handler),
+        description: "Start a resumable tus upload",
+        accepts: Accepts::Nothing,
+        limit: Limit::Tus,
+    },
+    Endpoint {
+        method: Some(Method::HEAD),
+        path: "/tus/{id}",
+        handler: |route| route.to(tus::head_handler),
+        description: "Offset of a tus upload",
+        accepts: Accepts::Nothing,
+        limit: Limit::None,
+    },
+    Endpoint {
+        method: Some(Method::PATCH),
+        path: "/tus/{id}",
+        handler: |route| route.to(tus::patch_handler),
+        description: "Continue a tus upload",
+        accepts: Accepts::Types(&["application/offset+octet-stream"]),
+        limit: Limit::Tus,
+    },
+    Endpoint {
+        method: Some(Method::GET),
+        path: "/tus/{id}",
+        handler: |route| route.to(tus::receipt_handler),
+        description: "Receipt of a finished tus upload",
+        accepts: Accepts::Nothing,
+        limit: Limit::None,
+    },
+    Endpoint {
+        method: Some(Method::DELETE),
+        path: "/tus/{id}",
+        handler: |route| route.to(tus::delete_handler),
+        description: "Abandon a tus upload",
+        accepts: Accepts::Nothing,
+        limit: Limit::None,
+    },
+    Endpoint {
+        method: Some(Method::GET),
+        path: "/stats",
+        handler: |route| route.to(crate::stats_handler),
+        description: "Destructions per endpoint",
+        accepts: Accepts::Nothing,
+        limit: Limit::None,
+    },
+    Endpoint {
+        method: Some(Method::GET),
+        path: "/stats/summary",
+        handler: |route| route.to(crate::stats_summary_handler),
+        description: "Totals over all endpoints",
+        accepts: Accepts::Nothing,
+        limit: Limit::None,
+    },
+    #[cfg(feature = "sqlite")]
+    Endpoint {
+        method: Some(Method::GET),
+        path: "/stats/by-client",
+        handler: |route| route.to(crate::stats_by_client_handler),
+        description: "Destructions per client IP and User-Agent",
+        accepts: Accepts::Nothing,
+        limit: Limit::None,
+    },
+    Endpoint {
+        method: Some(Method::GET),
+        path: "/metrics",
+        handler: |route| route.to(crate::metrics_handler),
+        description: "Prometheus metrics",
+        accepts: Accepts::Nothing,
+        limit: Limit::None,
+    },
+    Endpoint {
+        method: Some(Method::GET),
+        path: "/events",
+        handler: |route| route.to(crate::live::events_handler),
+        description: "Destructions as they happen, as server-sent events",
+        accepts: Accepts::Nothing,
+        limit: Limit::None,
+    },
+    #[cfg(feature = "sqlite")]
+    Endpoint {
+        method: Some(Method::GET),
+        path: "/stats/export",
+        handler: |route| route.to(crate::stats_export_handler),
+        description: "The raw stats rows as JSON Lines or Parquet",
+        accepts: Accepts::Nothing,
+        limit: Limit::None,
+    },
+    #[cfg(feature = "sqlite")]
+    Endpoint {
+        method: Some(Method::GET),
+        path: "/stats/usage",
+        handler: |route| route.to(crate::stats_usage_handler),
+        description: "Requests and bytes per tenant and month",
+        accepts: Accepts::Nothing,
+        limit: Limit::None,
+    },
+    #[cfg(feature = "sqlite")]
+    Endpoint {
+        method: Some(Method::GET),
+        path: "/stats/tail",
+        handler: |route| route.to(crate::stats_tail_handler),
+        description: "The latest stats rows",
+        accepts: Accepts::Nothing,
+        limit: Limit::None,
+    },
+    Endpoint {
+        method: Some(Method::GET),
+        path: "/stats/live",
+        handler: |route| route.to(crate::live::live_handler),
+        description: "Destructions as they happen, over a WebSocket",
+        accepts: Accepts::Nothing,
+        limit: Limit::None,
+    },
+    Endpoint {
+        method: Some(Method::GET),
+        path: "/stats/live-gauges",
+        handler: |route| route.to(crate::gauges::live_gauges_handler),
+        description: "Requests in flight, open connections and buffered bytes right now",
+        accepts: Accepts::Nothing,
+        limit: Limit::None,
+    },
+    #[cfg(feature = "sqlite")]
+    Endpoint {
+        method: Some(Method::GET),
+        path: "/stats/timeseries",
+        handler: |route| route.to(crate::stats_timeseries_handler),
+        description: "Destructions per endpoint and time bucket",
+        accepts: Accepts::Nothing,
+        limit: Limit::None,
+    },
+    #[cfg(feature = "sqlite")]
+    Endpoint {
+        method: Some(Method::GET),
+        path: "/stats/compare",
+        handler: |route| route.to(crate::stats_compare::compare_handler),
+        description: "Per-endpoint deltas between two time windows",
+        accepts: Accepts::Nothing,
+        limit: Limit::None,
+    },
+    #[cfg(feature = "sqlite")]
+    Endpoint {
+        method: Some(Method::GET),
+        path: "/grafana",
+        handler: |route| route.to(crate::grafana_test_handler),
+        description: "Connection test of the Grafana JSON datasource",
+        accepts: Accepts::Nothing,
+        limit: Limit::None,
+    },
+    #[cfg(feature = "sqlite")]
+    Endpoint {
+        method: Some(Method::POST),
+        path: "/grafana/search",
+        handler: |route| route.to(crate::grafana_search_handler),
+        description: "Metrics of the Grafana JSON datasource",
+        accepts: Accepts::Types(&["application/json"]),
+        limit: Limit::None,
+    },
+    #[cfg(feature = "sqlite")]
+    Endpoint {
+        method: Some(Method::POST),
+        path: "/grafana/query",
+        handler: |route| route.to(crate::grafana_query_handler),
+        description: "Time series of the Grafana JSON datasource",
+        accepts: Accepts::Types(&["application/json"]),
+        limit: Limit::None,
+    },
+    Endpoint {
+        method: Some(Method::GET),
+        path: "/bench",
+        handler: |route| route.to(crate::bench_handler),
+        description: "Micro-benchmark of this machine (admin)",
+        accepts: Accepts::Nothing,
+        limit: Limit::None,
+    },
+    Endpoint {
+        method: Some(Method::GET),
+        path: "/generate",
+        handler: |route| route.to(crate::generate::generate_handler),
+        description: "A payload to destroy elsewhere",
+        accepts: Accepts::Nothing,
+        limit: Limit::Generate,
+    },
+    Endpoint {
+        method: None,
+        path: "/mirror",
+        handler: |route| route.to(crate::mirror::mirror_handler),
+        description: "The request as the server saw it",
+        accepts: Accepts::Payload,
+        limit: Limit::Payload,
+    },
+    Endpoint {
+        method: Some(Method::POST),
+        path: "/cookies/destroy",
+        handler: |route| route.to(crate::cookies::destroy_handler),
+        description: "Destroy the cookies sent along",
+        accepts: Accepts::Nothing,
+        limit: Limit::None,
+    },
+    Endpoint {
+        method: Some(Method::POST),
+        path: "/fetch-and-destroy",
+        handler: |route| route.to(crate::fetch::fetch_handler),
+        description: "Download a URL and destroy what it returns",
+        accepts: Accepts::Types(&["application/json"]),
+        limit: Limit::Fetch,
+    },
+    Endpoint {
+        method: Some(Method::POST),
+        path: "/stage",
+        handler: |route| route.to(stage::stage_handler),
+        description: "Hold a payload until it is committed or aborted",
+        accepts: Accepts::Payload,
+        limit: Limit::Stage,
+    },
+    Endpoint {
+        method: Some(Method::POST),
+        path: "/commit/{id}",
+        handler: |route| route.to(stage::commit_handler),
+        description: "Destroy a staged payload",
+        accepts: Accepts::Nothing,
+        limit: Limit::None,
+    },
+    Endpoint {
+        method: Some(Method::POST),
+        path: "/abort/{id}",
+        handler: |route| route.to(stage::abort_handler),
+        description: "Drop a staged payload without recording it",
+        accepts: Accepts::Nothing,
+        limit: Limit::None,
+    },
+    Endpoint {
+        method: Some(Method::POST),
+        path: "/admin/reload",
+        handler: |route| route.to(crate::reload_handler),
+        description: "Re-read the config file (admin)",
+        accepts: Accepts::Nothing,
+        limit: Limit::None,
+    },
+    #[cfg(feature = "sqlite")]
+    Endpoint {
+        method: Some(Method::POST),
+        path: "/admin/backup",
+        handler: |route| route.to(crate::backup::backup_handler),
+        description: "A consistent copy of the stats database (admin)",
+        accepts: Accepts::Nothing,
+        limit: Limit::None,
+    },
+    #[cfg(feature = "sqlite")]
+    Endpoint {
+        method: Some(Method::POST),
+        path: "/admin/integrity-check",
+        handler: |route| route.to(crate::integrity::integrity_check_handler),
+        description: "Check the stats database for corruption (admin)",
+        accepts: Accepts::Nothing,
+        limit: Limit::None,
+    },
+    #[cfg(feature = "sqlite")]
+    Endpoint {
+        method: Some(Method::GET),
+        path: "/was-it-destroyed",
+        handler: |route| route.to(crate::hash_index::lookup_handler),
+        description: "Whether a payload with this SHA-256 was destroyed",
+        accepts: Accepts::Nothing,
+        limit: Limit::None,
+    },
+    Endpoint {
+        method: Some(Method::GET),
+        path: "/healthz",
+        handler: |route| route.to(crate::healthz_handler),
+        description: "Health of the server and its database",
+        accepts: Accepts::Nothing,
+        limit: Limit::None,
+    },
+    Endpoint {
+        method: Some(Method::GET),
+        path: "/ping",
+        handler: |route| route.to(crate::ping_handler),
+        description: "Liveness check",
+        accepts: Accepts::Nothing,
+        limit: Limit::None,
+    },
+    #[cfg(feature = "extras")]
+    Endpoint {
+        method: Some(Method::POST),
+        path: "/shred",
+        handler: |route| route.to(crate::shred_handler),
+        description: "Destroy a payload with a shredding log",
+        accepts: Accepts::Payload,
+        limit: Limit::Payload,
+    },
+    #[cfg(feature = "extras")]
+    Endpoint {
+        method: Some(Method::GET),
+        path: "/shred/styles",
+        handler: |route| route.to(crate::shred_styles_handler),
+        description: "The log styles /shred can use",
+        accepts: Accepts::Nothing,
+        limit: Limit::None,
+    },
+    #[cfg(feature = "extras")]
+    Endpoint {
+        method: Some(Method::POST),
+        path: "/burn",
+        handler: |route| route.to(crate::burn_handler),
+        description: "Destroy a payload by fire",
+        accepts: Accepts::Payload,
+        limit: Limit::Payload,
+    },
+    #[cfg(feature = "extras")]
+    Endpoint {
+        method: Some(Method::POST),
+        path: "/autopsy",
+        handler: |route| route.to(crate::autopsy_handler),
+        description: "Destroy a payload and report what it was",
+        accepts: Accepts::Payload,
+        limit: Limit::Payload,
+    },
+    #[cfg(feature = "validators")]
+    Endpoint {
+        method: Some(Method::POST),
+        path: "/validate-before-destroy",
+        handler: |route| route.to(crate::validate_before_destroy_handler),
+        description: "Check the format of a payload, then destroy it",
+        accepts: Accepts::Payload,
+        limit: Limit::Validate,
+    },
+    #[cfg(feature = "geoip")]
+    Endpoint {
+        method: Some(Method::GET),
+        path: "/stats/by-country",
+        handler: |route| route.to(crate::stats_by_country_handler),
+        description: "Destructions per country and city",
+        accepts: Accepts::Nothing,
+        limit: Limit::None,
+    },
+];
+
+// The patterns of ENDPOINTS, as the router matches them
+static PATTERNS: LazyLock<Vec<ResourceDef>> = LazyLock::new(|| {
+    ENDPOINTS
+        .iter()
+        .map(|endpoint| ResourceDef::new(endpoint.path))
+        .collect()
+});
+
+// The endpoint the router is going to hand a request to, for middleware that runs
+// before routing. Matches the path as the router sees it, percent-decoded, never the
+// raw one: /%70ulverize is /pulverize.
+pub fn resolve(req: &HttpRequest) -> Option<&'static Endpoint> {
+    let path = req.match_info().as_str();
+    let base_path = req
+        .app_data::<Data<AppConfig>>()
+        .map_or("", |config| config.base_path.as_str());
+    let path = match base_path.is_empty() {
+        true => path,
+        false => &path[ResourceDef::root_prefix(base_path).find_match(path)?..],
+    };
+    ENDPOINTS
+        .iter()
+        .zip(PATTERNS.iter())
+        .find(|(endpoint, pattern)| {
+            endpoint
+                .method
+                .as_ref()
+                .is_none_or(|method| method == req.method())
+                && pattern.is_match(path)
+        })
+        .map(|(endpoint, _)| endpoint)
+}
+
+// Every route, mounted under --base-path
+pub fn scope(base_path: &str) -> Scope {
+    let scope = ENDPOINTS
+        .iter()
+        .fold(web::scope(base_path), |scope, endpoint| {
+            scope.route(endpoint.path, endpoint.route())
+        });
+    // Under a base path the index answers without the trailing slash too
+    let scope = match base_path.is_empty() {
+        true => scope,
+        false => scope
+            .route("", web::get().to(index_handler))
+            .route("", web::route().to(allow_handler)),
+    };
+    // Then OPTIONS and 405 for the methods the routes above don't take
+    fallback_paths().into_iter().fold(scope, |scope, path| {
+        scope.route(path, web::route().to(allow_handler))
+    })
+}
+
+// Paths that take only some methods and need the OPTIONS/405 fallback
+fn fallback_paths() -> Vec<&'static str> {
+    let mut paths: Vec<&str> = Vec::new();
+    for endpoint in ENDPOINTS {
+        if !paths.contains(&endpoint.path) {
+            paths.push(endpoint.path);
+        }
+    }
+    paths.retain(|path| {
+        ENDPOINTS
+            .iter()
+            .all(|endpoint| endpoint.path != *path || endpoint.method.is_some())
+    });
+    paths
+}
+
+// The methods a path takes, as for the Allow header
+fn allowed_methods(path: &str) -> String {
+    let mut methods: Vec<&str> = ENDPOINTS
+        .iter()
+        .filter(|endpoint| endpoint.path == path)
+        .filter_map(|endpoint| endpoint.method.as_ref().map(Method::as_str))
+        .collect();
+    if !methods.contains(&"OPTIONS") {
+        methods.push("OPTIONS");
+    }
+    methods.join(", ")
+}
+
+/// Handler for OPTIONS on every path
+/// Answers with the methods of the path in `Allow`, other methods it doesn't take get 405.
+pub async fn allow_handler(req: HttpRequest, config: Data<AppConfig>) -> HttpResponse {
+    let pattern = req.match_pattern().unwrap_or_default();
+    let path = pattern
+        .strip_prefix(config.base_path.as_str())
+        .unwrap_or(&pattern);
+    let allow = allowed_methods(match path {
+        "" => "/",
+        path => path,
+    });
+    if req.method() == Method::OPTIONS {
+        return HttpResponse::NoContent()
+            .insert_header((ALLOW, allow))
+            .finish();
+    }
+    HttpResponse::MethodNotAllowed()
+        .insert_header((ALLOW, allow.clone()))
+        .json(serde_json::json!({
+            "error": format!("Method not allowed. Use one of: {}.", allow)
+        }))
+}
+
+#[derive(Serialize)]
+struct IndexEntry {
+    method: &'static str,
+    path: String,
+    description: &'static str,
+    // Content types of the request body, empty if it takes none
+    accepts: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_bytes: Option<usize>,
+}
+
+#[derive(Serialize)]
+struct IndexLimits {
+    max_payload_bytes: usize,
+    max_respond_bytes: usize,
+    max_decompressed_bytes: usize,
+    max_decompression_ratio: u64,
+}
+
+#[derive(Serialize)]
+struct IndexResponse {
+    name: &'static str,
+    version: &'static str,
+    base_path: String,
+    read_only: bool,
+    limits: IndexLimits,
+    endpoints: Vec<IndexEntry>,
+}
+
+fn max_bytes(req: &HttpRequest, config: &AppConfig, limit: &Limit) -> Option<usize> {
+    match limit {
+        Limit::None => None,
+        Limit::Payload => Some(body::payload_limit(req)),
+        #[cfg(feature = "validators")]
+        Limit::Validate => Some(
+            config
+                .settings()
+                .limits
+                .validate_max_size
+                .unwrap_or(config.validate_max_size),
+        ),
+        Limit::Stage => req
+            .app_data::<Data<stage::StageStore>>()
+            .map(|store| store.max_bytes()),
+        Limit::Tus => Some(tus::TUS_MAX_SIZE as usize),
+        Limit::Fetch => Some(config.fetch.max_size),
+        Limit::Generate => Some(config.max_generate_bytes),
+    }
+}
+
+fn index(req: &HttpRequest, config: &AppConfig) -> IndexResponse {
+    let settings = config.settings();
+    let payload_types = match settings.allowed_content_types.is_empty() {
+        true => vec!["*/*".to_string()],
+        false => settings.allowed_content_types.clone(),
+    };
+    let endpoints = ENDPOINTS
+        .iter()
+        .map(|endpoint| IndexEntry {
+            method: endpoint.method.as_ref().map_or("ANY", Method::as_str),
+            path: format!("{}{}", config.base_path, endpoint.path),
+            description: endpoint.description,
+            accepts: match &endpoint.accepts {
+                Accepts::Nothing => Vec::new(),
+                Accepts::Payload => payload_types.clone(),
+                Accepts::Types(types) => types.iter().map(|t| t.to_string()).collect(),
+            },
+            max_bytes: max_bytes(req, config, &endpoint.limit),
+        })
+        .collect();
+    IndexResponse {
+        name: env!("CARGO_PKG_NAME"),
+        version: env!("CARGO_PKG_VERSION"),
+        base_path: config.base_path.clone(),
+        read_only: config.read_only,
+        limits: IndexLimits {
+            max_payload_bytes: body::payload_limit(req),
+            max_respond_bytes: config.max_respond_bytes,
+            max_decompressed_bytes: config.max_decompressed_size,
+            max_decompression_ratio: config.max_decompression_ratio,
+        },
+        endpoints,
+    }
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn render_html(index: &IndexResponse) -> String {
+    let mut html = format!(
+        "<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>{name}</title></head>\n<body>\n<h1>{name} {version}</h1>\n<p>Payloads up to {max}. Send <code>Accept: application/json</code> for this index as JSON.</p>\n<table>\n<tr><th>Method</th><th>Path</th><th>Description</th><th>Accepts</th><th>Limit</th></tr>\n",
+        name = index.name,
+        version = index.version,
+        max = format_size(index.limits.max_payload_bytes),
+    );
+    for endpoint in &index.endpoints {
+        html.push_str(&format!(
+            "<tr><td>{}</td><td><code>{}</code></td><td>{}</td><td>{}</td><td>{}</td></tr>\n",
+            endpoint.method,
+            escape_html(&endpoint.path),
+            escape_html(endpoint.description),
+            escape_html(&endpoint.accepts.join(", ")),
+            endpoint.max_bytes.map(format_size).unwrap_or_default(),
+        ));
+    }
+    html.push_str("</table>\n</body>\n</html>\n");
+    html
+}
+
+/// Handler for GET /
+/// Returns the endpoints with their methods, content types and current limits, as HTML
+/// for browsers.
+pub async fn index_handler(req: HttpRequest, config: Data<AppConfig>) -> HttpResponse {
+    let index = index(&req, &config);
+    let wants_html = req
+        .headers()
+        .get(ACCEPT)
+        .and_then(|accept| accept.to_str().ok())
+        .is_some_and(|accept| accept.contains("text/html"));
+    let mut response = HttpResponse::Ok();
+    response.insert_header((VARY, "Accept"));
+    match wants_html {
+        true => response
+            .content_type("text/html; charset=utf-8")
+            .body(render_html(&index)),
+        false => response.json(index),
+    }
+}
@@ -0,0 +1,445 @@
+// Structure reports of valid payloads for /validate-before-destroy
+use quick_xml::events::Event;
+use quick_xml::Reader;
+use serde::de::{DeserializeSeed, Deserializer, MapAccess, SeqAccess, Visitor};
+use serde::Serialize;
+use serde_json::Value;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+// Lengths of all arrays in a document
+#[derive(Serialize)]
+pub struct ArrayLengths {
+    count: usize,
+    min: usize,
+    max: usize,
+    avg: f64,
+}
+
+#[derive(Serialize)]
+pub struct JsonStructure {
+    top_level_type: &'static str,
+    // Keys of the top-level object, None for other top-level types
+    top_level_keys: Option<usize>,
+    // Keys of all objects in the document
+    total_keys: usize,
+    // 0 for a scalar, 1 for a flat object or array
+    max_depth: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    arrays: Option<ArrayLengths>,
+    // Share of every value type among all values, rounded to 3 decimals
+    value_types: BTreeMap<&'static str, f64>,
+}
+
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+#[derive(Default)]
+struct JsonWalk {
+    total_keys: usize,
+    max_depth: usize,
+    array_lengths: Vec<usize>,
+    type_counts: BTreeMap<&'static str, usize>,
+}
+
+impl JsonWalk {
+    // serde_json stops parsing at 128 levels, so recursing is safe
+    fn visit(&mut self, value: &Value, depth: usize) {
+        *self.type_counts.entry(type_name(value)).or_default() += 1;
+        match value {
+            Value::Array(items) => {
+                self.max_depth = self.max_depth.max(depth + 1);
+                self.array_lengths.push(items.len());
+                items.iter().for_each(|item| self.visit(item, depth + 1));
+            }
+            Value::Object(map) => {
+                self.max_depth = self.max_depth.max(depth + 1);
+                self.total_keys += map.len();
+                map.values().for_each(|item| self.visit(item, depth + 1));
+            }
+            _ => {}
+        }
+    }
+}
+
+pub fn json_structure(value: &Value) -> JsonStructure {
+    let mut walk = JsonWalk::default();
+    walk.visit(value, 0);
+    let values: usize = walk.type_counts.values().sum();
+    let arrays = (!walk.array_lengths.is_empty()).then(|| ArrayLengths {
+        count: walk.array_lengths.len(),
+        min: walk.array_lengths.iter().copied().min().unwrap_or(0),
+        max: walk.array_lengths.iter().copied().max().unwrap_or(0),
+        avg: walk.array_lengths.iter().sum::<usize>() as f64 / walk.array_lengths.len() as f64,
+    });
+    JsonStructure {
+        top_level_type: type_name(value),
+        top_level_keys: value.as_object().map(|map| map.len()),
+        total_keys: walk.total_keys,
+        max_depth: walk.max_depth,
+        arrays,
+        value_types: walk
+            .type_counts
+            .into_iter()
+            .map(|(name, count)| {
+                let share = count as f64 / values as f64;
+                (name, (share * 1000.0).round() / 1000.0)
+            })
+            .collect(),
+    }
+}
+
+// A namespace declared with xmlns or xmlns:prefix
+#[derive(Serialize, PartialEq)]
+pub struct XmlNamespace {
+    // None for the default namespace
+    prefix: Option<String>,
+    uri: String,
+}
+
+#[derive(Serialize)]
+pub struct XmlStructure {
+    root_element: String,
+    element_count: usize,
+    // Attributes other than namespace declarations
+    attribute_count: usize,
+    namespaces: Vec<XmlNamespace>,
+    has_doctype: bool,
+}
+
+// Only called for payloads check_xml accepted, so errors just end the walk
+pub fn xml_structure(body_str: &str) -> XmlStructure {
+    let mut reader = Reader::from_str(body_str);
+    let mut structure = XmlStructure {
+        root_element: String::new(),
+        element_count: 0,
+        attribute_count: 0,
+        namespaces: Vec::new(),
+        has_doctype: false,
+    };
+    loop {
+        match reader.read_event() {
+            Ok(Event::Start(element)) | Ok(Event::Empty(element)) => {
+                if structure.element_count == 0 {
+                    structure.root_element =
+                        String::from_utf8_lossy(element.name().as_ref()).into_owned();
+                }
+                structure.element_count += 1;
+                for attribute in element.attributes().flatten() {
+                    let key = attribute.key.as_ref();
+                    let prefix = match key.strip_prefix(b"xmlns") {
+                        Some(b"") => None,
+                        Some(rest) if rest.starts_with(b":") => {
+                            Some(String::from_utf8_lossy(&rest[1..]).into_owned())
+                        }
+                        _ => {
+                            structure.attribute_count += 1;
+                            continue;
+                        }
+                    };
+                    let namespace = XmlNamespace {
+                        prefix,
+                        uri: String::from_utf8_lossy(&attribute.value).into_owned(),
+                    };
+                    if !structure.namespaces.contains(&namespace) {
+                        structure.namespaces.push(namespace);
+                    }
+                }
+            }
+            Ok(Event::DocType(_)) => structure.has_doctype = true,
+            Ok(Event::Eof) | Err(_) => return structure,
+            Ok(_) => {}
+        }
+    }
+}
+
+// A key that occurs more than once in the same object
+pub struct DuplicateKey {
+    // JSON Pointer of the object, "" for the top level
+    pub path: String,
+    pub key: String,
+    pub count: usize,
+}
+
+// Walks a document while parsing it, serde_json's Value keeps only the last of
+// duplicate keys so they can't be found afterwards
+struct DuplicateWalk<'a> {
+    path: String,
+    found: &'a RefCell<Vec<DuplicateKey>>,
+}
+
+impl DuplicateWalk<'_> {
+    fn child(&self, segment: &str) -> Self {
+        DuplicateWalk {
+            path: format!(
+                "{}/{}",
+                self.path,
+                segment.replace('~', "~0").replace('/', "~1")
+            ),
+            found: self.found,
+        }
+    }
+}
+
+impl<'de> DeserializeSeed<'de> for DuplicateWalk<'_> {
+    type Value = ();
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_any(self)
+    }
+}
+
+impl<'de> Visitor<'de> for DuplicateWalk<'_> {
+    type Value = ();
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("any JSON value")
+    }
+
+    fn visit_bool<E>(self, _: bool) -> Result<(), E> {
+        Ok(())
+    }
+
+    fn visit_i64<E>(self, _: i64) -> Result<(), E> {
+        Ok(())
+    }
+
+    fn visit_u64<E>(self, _: u64) -> Result<(), E> {
+        Ok(())
+    }
+
+    fn visit_f64<E>(self, _: f64) -> Result<(), E> {
+        Ok(())
+    }
+
+    fn visit_str<E>(self, _: &str) -> Result<(), E> {
+        Ok(())
+    }
+
+    fn visit_unit<E>(self) -> Result<(), E> {
+        Ok(())
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<(), A::Error> {
+        let mut index = 0;
+        while seq
+            .next_element_seed(self.child(&index.to_string()))?
+            .is_some()
+        {
+            index += 1;
+        }
+        Ok(())
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<(), A::Error> {
+        let mut counts: HashMap<String, usize> = HashMap::new();
+        // In the order they turned out to be duplicates
+        let mut duplicates = Vec::new();
+        while let Some(key) = map.next_key::<String>()? {
+            map.next_value_seed(self.child(&key))?;
+            match counts.get_mut(&key) {
+                Some(count) => {
+                    *count += 1;
+                    if *count == 2 {
+                        duplicates.push(key);
+                    }
+                }
+                None => {
+                    counts.insert(key, 1);
+                }
+            }
+        }
+        self.found
+            .borrow_mut()
+            .extend(duplicates.into_iter().map(|key| DuplicateKey {
+                path: self.path.clone(),
+                count: counts[&key],
+                key,
+            }));
+        Ok(())
+    }
+}
+
+// Duplicate keys of a valid JSON document, in order of the objects' ends
+pub fn duplicate_keys(body_str: &str) -> Vec<DuplicateKey> {
+    let found = RefCell::new(Vec::new());
+    let walk = DuplicateWalk {
+        path: String::new(),
+        found: &found,
+    };
+    let mut deserializer = serde_json::Deserializer::from_str(body_str);
+    if walk.deserialize(&mut deserializer).is_err() {
+        return Vec::new();
+    }
+    found.into_inner()
+}
+
+// Findings reported per payload, the rest is summed up
+const MAX_DOUBLE_ENCODINGS: usize = 20;
+
+// What a text turns out to contain when it is decoded once more
+fn encoded_content(text: &str) -> Option<&'static str> {
+    let text = text.trim();
+    let lowercase = text.get(..3).map(str::to_ascii_lowercase);
+    match text.chars().next()? {
+        '{' | '[' => serde_json::from_str::<Value>(text)
+            .is_ok()
+            .then_some("JSON encoded as a string"),
+        '"' => matches!(serde_json::from_str::<Value>(text), Ok(Value::String(_)))
+            .then_some("a JSON string encoded twice"),
+        '<' => crate::validation::check_xml(text).is_ok().then_some("XML"),
+        _ if matches!(lowercase.as_deref(), Some("%7b" | "%5b")) => Some("URL-encoded JSON"),
+        _ if text.contains("&lt;") && text.contains("&gt;") => Some("HTML-escaped markup"),
+        _ => None,
+    }
+}
+
+fn find_in_strings(value: &Value, path: &str, found: &mut Vec<String>) {
+    match value {
+        Value::String(text) => {
+            if let Some(content) = encoded_content(text) {
+                found.push(match path {
+                    "" => format!("The payload is a JSON string holding {}.", content),
+                    path => format!("Field {} holds {}.", path, content),
+                });
+            }
+        }
+        Value::Array(items) => {
+            for (i, item) in items.iter().enumerate() {
+                find_in_strings(item, &format!("{}/{}", path, i), found);
+            }
+        }
+        Value::Object(map) => {
+            for (key, item) in map {
+                let key = key.replace('~', "~0").replace('/', "~1");
+                find_in_strings(item, &format!("{}/{}", path, key), found);
+            }
+        }
+        _ => {}
+    }
+}
+
+// Payloads or JSON fields that hold another encoded document, e.g. JSON sent as a
+// JSON string or XML inside a field
+pub fn double_encodings(body_str: &str, json: Option<&Value>) -> Vec<String> {
+    let mut found = Vec::new();
+    match json {
+        Some(value) => find_in_strings(value, "", &mut found),
+        None => {
+            if let Some(content @ ("URL-encoded JSON" | "HTML-escaped markup")) =
+                encoded_content(body_str)
+            {
+                found.push(format!("The payload is {}.", content));
+            }
+        }
+    }
+    if found.len() > MAX_DOUBLE_ENCODINGS {
+        let more = found.len() - MAX_DOUBLE_ENCODINGS;
+        found.truncate(MAX_DOUBLE_ENCODINGS);
+        found.push(format!("{} more double-encoded fields not listed.", more));
+    }
+    found
+}
+
+// Field names listed per form, the rest is counted
+const MAX_FORM_NAMES: usize = 100;
+
+// Summary of an application/x-www-form-urlencoded body
+#[derive(Serialize)]
+pub struct FormReport {
+    fields: usize,
+    // Distinct names in order of appearance
+    names: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    more_names: Option<usize>,
+    encoded_bytes: usize,
+    // Names and values after percent-decoding, without the separators
+    decoded_bytes: usize,
+}
+
+// Decoded length of a form name or value, malformed escapes count as they are
+fn form_decoded_len(text: &str) -> usize {
+    let bytes = text.as_bytes();
+    let mut len = 0;
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = bytes[i] == b'%'
+            && bytes
+                .get(i + 1..i + 3)
+                .is_some_and(|hex| hex.iter().all(u8::is_ascii_hexdigit));
+        i += if escaped { 3 } else { 1 };
+        len += 1;
+    }
+    len
+}
+
+// Decoded form name, '+' is a space
+fn form_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok());
+        match (
+            bytes[i],
+            hex.and_then(|hex| u8::from_str_radix(hex, 16).ok()),
+        ) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+                continue;
+            }
+            (b'+', _) => decoded.push(b' '),
+            (byte, _) => decoded.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+pub fn form_report(body_str: &str) -> FormReport {
+    let mut names: Vec<String> = Vec::new();
+    let mut seen = std::collections::HashSet::new();
+    let mut fields = 0;
+    let mut decoded_bytes = 0;
+    for pair in body_str.trim_end_matches(['\r', '\n']).split('&') {
+        if pair.is_empty() {
+            continue;
+        }
+        let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+        fields += 1;
+        decoded_bytes += form_decoded_len(name) + form_decoded_len(value);
+        let name = form_decode(name);
+        if seen.insert(name.clone()) && names.len() < MAX_FORM_NAMES {
+            names.push(name);
+        }
+    }
+    let more_names = (seen.len() > names.len()).then(|| seen.len() - names.len());
+    FormReport {
+        fields,
+        names,
+        more_names,
+        encoded_bytes: body_str.len(),
+        decoded_bytes,
+    }
+}
+
+impl FormReport {
+    pub fn detail(&self) -> String {
+        format!(
+            "Form-urlencoded payload with {} fields ({} bytes encoded, {} decoded).",
+            self.fields, self.encoded_bytes, self.decoded_bytes
+        )
+    }
+}
@@ -0,0 +1,131 @@
+// Integrity checks of the stats database, POST /admin/integrity-check and --integrity-check
+//
+// A corrupted database keeps answering most queries, so damage (a bad restore, a full disk,
+// a broken filesystem) can go unnoticed for weeks. SQLite's integrity_check reads the whole
+// file and verifies every page, index and constraint; quick_check skips the index contents
+// and is much faster on large databases. The check runs on a read-only connection of its
+// own, requests keep being recorded meanwhile. Rotated database files are not checked.
+use crate::{check_admin, AppConfig, DbArgs};
+use actix_web::web::{self, Data};
+use actix_web::{HttpRequest, HttpResponse, Result};
+use clap::ValueEnum;
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+use std::time::Instant;
+
+#[derive(ValueEnum, Deserialize, Debug, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Mode {
+    // PRAGMA quick_check
+    Quick,
+    // PRAGMA integrity_check
+    #[default]
+    Full,
+}
+
+impl Mode {
+    fn name(self) -> &'static str {
+        match self {
+            Mode::Quick => "quick",
+            Mode::Full => "full",
+        }
+    }
+
+    fn pragma(self) -> &'static str {
+        match self {
+            Mode::Quick => "PRAGMA quick_check",
+            Mode::Full => "PRAGMA integrity_check",
+        }
+    }
+}
+
+// Query parameters for /admin/integrity-check
+#[derive(Deserialize)]
+pub struct IntegrityQuery {
+    #[serde(default)]
+    mode: Mode,
+}
+
+#[derive(Serialize)]
+struct IntegrityResponse {
+    status: &'static str,
+    mode: &'static str,
+    problems: Vec<String>,
+    duration_ms: u128,
+}
+
+// The problems SQLite found, empty when the database is fine. SQLite stops after 100.
+fn check(conn: &Connection, mode: Mode) -> rusqlite::Result<Vec<String>> {
+    let mut stmt = conn.prepare(mode.pragma())?;
+    let rows = stmt.query_map([], |row| row.get::<_, String>(0))?;
+    let problems = rows.collect::<rusqlite::Result<Vec<_>>>()?;
+    Ok(match problems.as_slice() {
+        [only] if only == "ok" => Vec::new(),
+        _ => problems,
+    })
+}
+
+fn check_db(db: &DbArgs, mode: Mode) -> Result<Vec<String>, String> {
+    let conn = crate::open_db_read_only(db)?;
+    check(&conn, mode).map_err(|e| format!("Integrity check failed: {}", e))
+}
+
+// --integrity-check: refuse to serve from a damaged database. A missing one is created
+// fresh, there's nothing to check then.
+pub fn check_or_exit(db: &DbArgs, mode: Mode) {
+    if !std::path::Path::new(&db.db_path).exists() {
+        return;
+    }
+    let start = Instant::now();
+    match check_db(db, mode) {
+        Ok(problems) if problems.is_empty() => println!(
+            "Database integrity check ({}) passed in {}ms",
+            mode.name(),
+            start.elapsed().as_millis()
+        ),
+        Ok(problems) => {
+            eprintln!("Database {} is corrupted:", db.db_path);
+            for problem in problems {
+                eprintln!("  {}", problem);
+            }
+            eprintln!("Restore it from a backup, or start without --integrity-check");
+            std::process::exit(1);
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Handler for POST /admin/integrity-check
+/// Admin only. Runs `PRAGMA integrity_check` (or `quick_check` with `?mode=quick`) on the
+/// stats database, answering 500 with the problems found if it is corrupted.
+pub async fn integrity_check_handler(
+    req: HttpRequest,
+    query: web::Query<IntegrityQuery>,
+    config: Data<AppConfig>,
+    db: Data<DbArgs>,
+) -> Result<HttpResponse> {
+    if let Some(response) = check_admin(&req, &config) {
+        return Ok(response);
+    }
+    let mode = query.mode;
+    let start = Instant::now();
+    let problems = match web::block(move || check_db(&db, mode)).await? {
+        Ok(problems) => problems,
+        Err(e) => {
+            return Ok(HttpResponse::InternalServerError().json(serde_json::json!({ "error": e })))
+        }
+    };
+    let response = IntegrityResponse {
+        status: if problems.is_empty() { "ok" } else { "corrupt" },
+        mode: mode.name(),
+        problems,
+        duration_ms: start.elapsed().as_millis(),
+    };
+    Ok(match response.problems.is_empty() {
+        true => HttpResponse::Ok().json(response),
+        false => HttpResponse::InternalServerError().json(response),
+    })
+}
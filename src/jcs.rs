@@ -0,0 +1,191 @@
+// RFC 8785 (JSON Canonicalization Scheme) check for /validate-before-destroy
+//
+// A canonical document has no whitespace between tokens, object keys sorted by their
+// UTF-16 code units and unique, numbers serialized like ECMAScript does and strings
+// escaped as little as possible. The raw text is scanned token by token, because
+// serde_json's Value already forgot the key order and the original spelling.
+use serde::Serialize;
+
+#[derive(Serialize)]
+pub struct CanonicalReport {
+    canonical: bool,
+    // whitespace, key_order, duplicate_keys, number_formatting, string_escaping
+    differences: Vec<&'static str>,
+}
+
+impl CanonicalReport {
+    pub fn detail(&self) -> String {
+        match self.canonical {
+            true => "JSON is in RFC 8785 canonical form.".to_string(),
+            false => format!(
+                "JSON is not in RFC 8785 canonical form ({}).",
+                self.differences.join(", ").replace('_', " ")
+            ),
+        }
+    }
+}
+
+// How ECMAScript's Number.prototype.toString prints a double
+fn es_number(value: f64) -> String {
+    if value == 0.0 {
+        return "0".to_string();
+    }
+    // Rust prints the shortest digits that round-trip, only the layout differs
+    let scientific = format!("{:e}", value.abs());
+    let (mantissa, exponent) = scientific.split_once('e').unwrap_or((&scientific, "0"));
+    let digits = mantissa.replace('.', "");
+    let k = digits.len() as i32;
+    let n = exponent.parse::<i32>().unwrap_or(0) + 1;
+    let sign = if value < 0.0 { "-" } else { "" };
+    let formatted = if k <= n && n <= 21 {
+        format!("{}{}", digits, "0".repeat((n - k) as usize))
+    } else if 0 < n && n <= 21 {
+        format!("{}.{}", &digits[..n as usize], &digits[n as usize..])
+    } else if -6 < n && n <= 0 {
+        format!("0.{}{}", "0".repeat(-n as usize), digits)
+    } else {
+        let mantissa = match digits.len() {
+            1 => digits.clone(),
+            _ => format!("{}.{}", &digits[..1], &digits[1..]),
+        };
+        let e = n - 1;
+        format!("{}e{}{}", mantissa, if e < 0 { '-' } else { '+' }, e.abs())
+    };
+    format!("{}{}", sign, formatted)
+}
+
+// A string escaped the RFC 8785 way: only quotes, backslashes and control characters
+fn es_string(value: &str) -> String {
+    let mut out = String::with_capacity(value.len() + 2);
+    out.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\u{8}' => out.push_str("\\b"),
+            '\t' => out.push_str("\\t"),
+            '\n' => out.push_str("\\n"),
+            '\u{c}' => out.push_str("\\f"),
+            '\r' => out.push_str("\\r"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+enum Container {
+    // Previous key as UTF-16, and whether the next string is a key
+    Object(Option<Vec<u16>>, bool),
+    Array,
+}
+
+// Only called for payloads that parsed as JSON
+pub fn check(body_str: &str) -> CanonicalReport {
+    let bytes = body_str.as_bytes();
+    let mut stack: Vec<Container> = Vec::new();
+    let (mut whitespace, mut key_order, mut numbers, mut strings) = (false, false, false, false);
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b' ' | b'\t' | b'\n' | b'\r' => whitespace = true,
+            b'{' => stack.push(Container::Object(None, true)),
+            b'[' => stack.push(Container::Array),
+            b'}' | b']' => {
+                stack.pop();
+            }
+            b',' => {
+                if let Some(Container::Object(_, expects_key)) = stack.last_mut() {
+                    *expects_key = true;
+                }
+            }
+            b':' => {
+                if let Some(Container::Object(_, expects_key)) = stack.last_mut() {
+                    *expects_key = false;
+                }
+            }
+            b'"' => {
+                let mut end = i + 1;
+                while bytes[end] != b'"' {
+                    end += if bytes[end] == b'\\' { 2 } else { 1 };
+                }
+                let token = &body_str[i..=end];
+                let value: String = serde_json::from_str(token).unwrap_or_default();
+                strings |= es_string(&value) != token;
+                if let Some(Container::Object(previous, true)) = stack.last_mut() {
+                    let key: Vec<u16> = value.encode_utf16().collect();
+                    // Equal neighbors are duplicates, reported on their own
+                    key_order |= previous.as_ref().is_some_and(|previous| *previous > key);
+                    *previous = Some(key);
+                }
+                i = end;
+            }
+            b'-' | b'0'..=b'9' => {
+                let end = body_str[i..]
+                    .find(|c: char| !matches!(c, '0'..='9' | '-' | '+' | '.' | 'e' | 'E'))
+                    .map_or(bytes.len(), |len| i + len);
+                let token = &body_str[i..end];
+                numbers |= token
+                    .parse::<f64>()
+                    .map_or(true, |value| es_number(value) != token);
+                i = end - 1;
+            }
+            // true, false and null only have one spelling
+            _ => {}
+        }
+        i += 1;
+    }
+    let duplicates = !crate::inspect::duplicate_keys(body_str).is_empty();
+    let differences: Vec<&'static str> = [
+        (whitespace, "whitespace"),
+        (key_order, "key_order"),
+        (duplicates, "duplicate_keys"),
+        (numbers, "number_formatting"),
+        (strings, "string_escaping"),
+    ]
+    .into_iter()
+    .filter_map(|(found, name)| found.then_some(name))
+    .collect();
+    CanonicalReport {
+        canonical: differences.is_empty(),
+        differences,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn numbers_use_the_ecmascript_layout() {
+        assert_eq!(es_number(1e21), "1e+21");
+        assert_eq!(es_number(1e20), "100000000000000000000");
+        assert_eq!(es_number(1e-7), "1e-7");
+        assert_eq!(es_number(0.000001), "0.000001");
+        assert_eq!(es_number(-0.5), "-0.5");
+        assert!(check("[1e+21,1e-7,0.000001]").canonical);
+        assert_eq!(check("[1e21]").differences, ["number_formatting"]);
+        assert_eq!(check("[1E-7]").differences, ["number_formatting"]);
+        assert_eq!(check("[1e-6]").differences, ["number_formatting"]);
+    }
+
+    #[test]
+    fn keys_sort_by_utf16_code_units() {
+        // U+1F600 is D83D DE00 in UTF-16 and sorts before U+FB33, unlike by code point
+        assert!(check("{\"\u{1F600}\":1,\"\u{FB33}\":2}").canonical);
+        assert_eq!(
+            check("{\"\u{FB33}\":1,\"\u{1F600}\":2}").differences,
+            ["key_order"]
+        );
+        assert_eq!(check(r#"{"b":1,"a":2}"#).differences, ["key_order"]);
+    }
+
+    #[test]
+    fn strings_escape_only_what_they_must() {
+        assert!(check(r#"["a\nb\t\"\\","\u001f","é"]"#).canonical);
+        assert_eq!(check(r#"["\u00e9"]"#).differences, ["string_escaping"]);
+        assert_eq!(check(r#"["\/"]"#).differences, ["string_escaping"]);
+        assert_eq!(check(r#"["\u001F"]"#).differences, ["string_escaping"]);
+    }
+}
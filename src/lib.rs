@@ -0,0 +1,3852 @@
+// Payload Pulverizer: destroys payloads over HTTP and keeps stats about it
+//
+// The server lives in this library so it can be tested in-process: `configure_app`
+// returns the App with all routes, middleware and state, ready for
+// `actix_web::test::init_service`. The validators are public on their own for the fuzz
+// targets (see fuzz/). The binary only calls `run`.
+use actix_web::body::MessageBody;
+use actix_web::dev::{Service, ServiceFactory, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::header::{self, HeaderName, HeaderValue};
+use actix_web::http::StatusCode;
+use actix_web::middleware::{Condition, DefaultHeaders};
+use actix_web::web::Data;
+use actix_web::web::PayloadConfig;
+use actix_web::{
+    web, App, Error, HttpMessage, HttpRequest, HttpResponse, HttpServer, Responder, Result,
+};
+#[cfg(feature = "sqlite")]
+use clap::ValueEnum;
+use clap::{Parser, Subcommand};
+use dedup::{already_reported, DedupCache};
+#[cfg(feature = "extras")]
+use rand::prelude::IndexedRandom;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+#[cfg(feature = "sqlite")]
+use rusqlite::{params, Connection, OpenFlags};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+#[cfg(feature = "extras")]
+use shred_passes::ShredPlan;
+use std::collections::BTreeMap;
+#[cfg(feature = "sqlite")]
+use std::fs::File;
+use std::future::{ready, Future, Ready};
+#[cfg(feature = "sqlite")]
+use std::io::{BufWriter, Write};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, RwLock};
+use std::task::{Context, Poll};
+use std::time::Instant;
+
+#[cfg(not(any(feature = "sqlite", feature = "log-store")))]
+compile_error!("Build with a stats store, the `sqlite` feature (default) or `log-store`");
+
+#[cfg(feature = "acme")]
+mod acme;
+#[cfg(feature = "sqlite")]
+mod alerts;
+#[cfg(feature = "extras")]
+mod autopsy;
+mod backpressure;
+#[cfg(feature = "sqlite")]
+mod backup;
+mod bench;
+mod body;
+#[cfg(feature = "validators")]
+pub mod calendar;
+#[cfg(feature = "sqlite")]
+mod cardinality;
+// Alerts only check webhook URLs with it without /shred
+#[cfg_attr(not(feature = "extras"), allow(dead_code))]
+mod callback;
+mod checksum;
+mod compare;
+mod compressibility;
+mod config;
+mod cookies;
+mod dedup;
+#[cfg(feature = "sqlite")]
+mod disk_guard;
+#[cfg(all(feature = "validators", feature = "extras"))]
+mod documents;
+#[cfg(feature = "validators")]
+pub mod email;
+mod fetch;
+mod gauges;
+mod generate;
+#[cfg(feature = "geoip")]
+mod geoip;
+#[cfg(feature = "validators")]
+pub mod graphql;
+#[cfg(feature = "sqlite")]
+mod hash_index;
+mod header_report;
+mod healthcheck;
+#[cfg(feature = "http3")]
+mod http3;
+mod if_match;
+mod index;
+#[cfg(feature = "validators")]
+pub mod inspect;
+#[cfg(feature = "sqlite")]
+mod integrity;
+#[cfg(feature = "validators")]
+pub mod jcs;
+#[cfg(feature = "validators")]
+pub mod links;
+mod listener;
+mod live;
+mod loadtest;
+#[cfg(all(feature = "log-store", not(feature = "sqlite")))]
+mod log_store;
+mod memory_guard;
+#[cfg(feature = "sqlite")]
+mod merge;
+mod metrics;
+mod mirror;
+#[cfg(feature = "validators")]
+pub mod ndjson;
+mod padding;
+#[cfg(feature = "parquet")]
+mod parquet_export;
+#[cfg(feature = "wasm-plugins")]
+mod plugins;
+mod proxy;
+mod proxy_protocol;
+mod queue;
+#[cfg(feature = "extras")]
+mod randomness;
+mod read_only;
+mod rejections;
+#[cfg(feature = "sqlite")]
+mod rotation;
+mod routing;
+mod sampling;
+mod selftest;
+#[cfg(feature = "extras")]
+mod shred_passes;
+// Custom styles are still read from the config file without /shred
+#[cfg_attr(not(feature = "extras"), allow(dead_code))]
+mod shred_styles;
+mod similarity;
+mod spill;
+mod stage;
+mod stat_buffer;
+mod stats_cache;
+#[cfg(feature = "sqlite")]
+mod stats_compare;
+mod stats_table;
+mod stats_token;
+mod store;
+mod timestamp;
+mod tus;
+#[cfg(feature = "validators")]
+pub mod validation;
+mod work;
+
+// ASCII art for /burn endpoint
+#[cfg(feature = "extras")]
+const FIRE_ART: &str = r#"
+⠀⠀⠀⠀⠀⠀⢱⣆⠀⠀⠀⠀⠀⠀
+⠀⠀⠀⠀⠀⠀⠈⣿⣷⡀⠀⠀⠀⠀
+⠀⠀⠀⠀⠀⠀⢸⣿⣿⣷⣧⠀⠀⠀
+⠀⠀⠀⠀⡀⢠⣿⡟⣿⣿⣿⡇⠀⠀
+⠀⠀⠀⠀⣳⣼⣿⡏⢸⣿⣿⣿⢀⠀
+⠀⠀⠀⣰⣿⣿⡿⠁⢸⣿⣿⡟⣼⡆
+⢰⢀⣾⣿⣿⠟⠀⠀⣾⢿⣿⣿⣿⣿
+⢸⣿⣿⣿⡏⠀⠀⠀⠃⠸⣿⣿⣿⡿
+⢳⣿⣿⣿⠀⠀⠀⠀⠀⠀⢹⣿⡿⡁
+⠀⠹⣿⣿⡄⠀⠀⠀⠀⠀⢠⣿⡞⠁
+⠀⠀⠈⠛⢿⣄⠀⠀⠀⣠⠞⠋⠀⠀
+⠀⠀⠀⠀⠀⠀⠉⠀⠀⠀⠀⠀⠀⠀
+------------------
+ BURNED TO ASHES!
+"#;
+
+// Response for /pulverize endpoint
+#[derive(Serialize)]
+struct PulverizeResponse {
+    status: &'static str,
+    message: &'static str,
+    // Set for large payloads that would have compressed well
+    #[serde(skip_serializing_if = "Option::is_none")]
+    compression: Option<compressibility::CompressionHint>,
+    // ?header_report=true
+    #[serde(skip_serializing_if = "Option::is_none")]
+    headers: Option<header_report::HeaderReport>,
+    // Content-MD5 check, only if the header was sent
+    #[serde(skip_serializing_if = "Option::is_none")]
+    checksum_verified: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    checksum_warning: Option<String>,
+    runtime_us: u128,
+    timings: PhaseTimings,
+}
+
+// Response for /shred endpoint
+#[cfg(feature = "extras")]
+#[derive(Serialize)]
+struct ShredResponse {
+    status: &'static str,
+    log: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    passes: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pass_log: Option<Vec<String>>,
+    // Set for large payloads that would have compressed well
+    #[serde(skip_serializing_if = "Option::is_none")]
+    compression: Option<compressibility::CompressionHint>,
+    // ?header_report=true
+    #[serde(skip_serializing_if = "Option::is_none")]
+    headers: Option<header_report::HeaderReport>,
+    // Content-MD5 check, only if the header was sent
+    #[serde(skip_serializing_if = "Option::is_none")]
+    checksum_verified: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    checksum_warning: Option<String>,
+    runtime_us: u128,
+    timings: PhaseTimings,
+}
+
+// Query parameters for /shred, also taken by /auto and /queue to pass on
+#[cfg_attr(not(feature = "extras"), allow(dead_code))]
+#[derive(Deserialize)]
+struct ShredQuery {
+    passes: Option<u32>,
+    profile: Option<String>,
+    // Named log pack, overrides the X-Shred-Style header
+    style: Option<String>,
+    // Deliver the log asynchronously, overrides the X-Callback-Url header
+    callback: Option<String>,
+}
+
+// Query parameters for /validate-before-destroy
+#[cfg(feature = "validators")]
+#[derive(Deserialize)]
+struct ValidateQuery {
+    // HEAD every extracted link, needs --check-links
+    #[serde(default)]
+    check_links: bool,
+    // Show the addresses and subject of emails, not in --privacy mode
+    #[serde(default)]
+    reveal_headers: bool,
+}
+
+// Response of /shred when the log is delivered to a callback URL
+#[cfg(feature = "extras")]
+#[derive(Serialize)]
+struct CallbackAcceptedResponse {
+    status: &'static str,
+    callback_url: String,
+    runtime_us: u128,
+}
+
+// Response of all destruction endpoints in --boring mode
+#[derive(Serialize)]
+struct BoringResponse {
+    status: &'static str,
+    bytes: usize,
+    runtime_us: u128,
+    timings: PhaseTimings,
+}
+
+#[cfg(feature = "extras")]
+#[derive(Serialize)]
+struct BurnResponse {
+    status: &'static str,
+    message: &'static str,
+    fire: &'static str,
+    // Set for large payloads that would have compressed well
+    #[serde(skip_serializing_if = "Option::is_none")]
+    compression: Option<compressibility::CompressionHint>,
+    // ?header_report=true
+    #[serde(skip_serializing_if = "Option::is_none")]
+    headers: Option<header_report::HeaderReport>,
+    // Content-MD5 check, only if the header was sent
+    #[serde(skip_serializing_if = "Option::is_none")]
+    checksum_verified: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    checksum_warning: Option<String>,
+    runtime_us: u128,
+    timings: PhaseTimings,
+}
+
+#[cfg(feature = "validators")]
+#[derive(Serialize)]
+struct ValidationReport {
+    #[serde(flatten)]
+    validation: validation::Validation,
+    // http(s) links in the payload, if any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    links: Option<links::LinkReport>,
+    runtime_us: u128,
+    // Not set for payloads rejected before being recorded
+    #[serde(skip_serializing_if = "Option::is_none")]
+    timings: Option<PhaseTimings>,
+}
+
+// Hard upper bound for request bodies, limits.max_payload_size can only lower it
+const MAX_PAYLOAD_SIZE: usize = 250 * 1024 * 1024;
+
+// Default size limit of /validate-before-destroy
+#[cfg(feature = "validators")]
+const VALIDATE_MAX_SIZE: usize = 64 * 1024;
+
+// List of all endpoints to track
+#[cfg(feature = "sqlite")]
+const ENDPOINTS: &[&str] = &[
+    "pulverize",
+    "blackhole",
+    "shred",
+    "burn",
+    "validate-before-destroy",
+    "autopsy",
+    "compare-then-destroy",
+    "tus",
+    "fetch-and-destroy",
+    "stage",
+];
+
+// CLI arguments
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    #[command(flatten)]
+    db: DbArgs,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    // Running without a subcommand serves, so these are accepted at the top level too
+    #[command(flatten)]
+    serve: ServeArgs,
+}
+
+// How to open the stats database, shared by all subcommands
+#[derive(clap::Args, Debug, Clone)]
+struct DbArgs {
+    /// Path to the stats database file
+    #[arg(long, default_value = "/tmp/payload-pulverizer.db", global = true)]
+    db_path: String,
+    /// File mode for the database, in octal (e.g. 600)
+    #[arg(long, value_parser = parse_mode_arg, global = true)]
+    db_mode: Option<u32>,
+    /// Numeric owner for the database as uid or uid:gid (e.g. 1000:1000)
+    #[arg(long, value_parser = parse_owner_arg, global = true)]
+    db_owner: Option<(u32, Option<u32>)>,
+    /// Create missing parent directories of --db-path
+    #[arg(long, global = true)]
+    db_create_dirs: bool,
+    /// SQLCipher key to encrypt the database with
+    #[cfg(feature = "sqlcipher")]
+    #[arg(
+        long,
+        env = "PAYLOAD_PULVERIZER_DB_KEY",
+        hide_env_values = true,
+        global = true,
+        conflicts_with = "db_key_file"
+    )]
+    db_key: Option<String>,
+    /// File containing the SQLCipher key (trailing newlines are ignored)
+    #[cfg(feature = "sqlcipher")]
+    #[arg(long, global = true)]
+    db_key_file: Option<String>,
+}
+
+impl DbArgs {
+    // The encryption key from --db-key, the environment or --db-key-file
+    #[cfg(feature = "sqlcipher")]
+    fn key(&self) -> Result<Option<String>, String> {
+        if let Some(path) = &self.db_key_file {
+            let key = std::fs::read_to_string(path)
+                .map_err(|e| format!("Failed to read database key file {}: {}", path, e))?;
+            return Ok(Some(key.trim_end_matches(['\r', '\n']).to_string()));
+        }
+        Ok(self.db_key.clone())
+    }
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Run the HTTP server (default when no subcommand is given)
+    Serve(Box<ServeArgs>),
+    /// Delete stats rows older than the given age and exit
+    #[cfg(feature = "sqlite")]
+    Prune {
+        /// Maximum age of rows to keep, e.g. 30d, 12h or 2w
+        #[arg(long, value_parser = parse_duration_arg)]
+        older_than: i64,
+        /// Reclaim the freed disk space afterwards
+        #[arg(long)]
+        vacuum: bool,
+    },
+    /// Dump the raw stats rows and exit
+    #[cfg(feature = "sqlite")]
+    Export {
+        /// Output format
+        #[arg(long, value_enum, default_value_t = ExportFormat::Csv)]
+        format: ExportFormat,
+        /// Output file, defaults to stdout
+        #[arg(long)]
+        output: Option<String>,
+    },
+    /// Merge the stats of other databases into --db-path, skipping rows it already has
+    #[cfg(feature = "sqlite")]
+    Merge(merge::MergeArgs),
+    /// Fire concurrent payloads at a running pulverizer and report latency/throughput
+    Loadtest(loadtest::LoadtestArgs),
+    /// Probe a running server and exit 0 if healthy, non-zero otherwise
+    Healthcheck(healthcheck::HealthcheckArgs),
+    /// Start the server in-process on a scratch database, exercise every endpoint and
+    /// exit non-zero if anything fails
+    Selftest(selftest::SelftestArgs),
+}
+
+#[derive(clap::Args, Debug, Clone)]
+struct ServeArgs {
+    /// Path to a TOML config file (routing rules, …)
+    #[arg(long)]
+    config: Option<String>,
+    /// Bearer token required for admin endpoints (disabled when unset)
+    #[arg(long, env = "PAYLOAD_PULVERIZER_ADMIN_TOKEN", hide_env_values = true)]
+    admin_token: Option<String>,
+    /// Answer /stats from memory for this long instead of aggregating on every request, e.g. 5s
+    #[arg(long, value_parser = parse_duration_arg)]
+    stats_cache_ttl: Option<i64>,
+    /// Record only every Nth destruction in the stats, e.g. 1/100 (reports scale it back up)
+    #[arg(long, value_parser = sampling::parse_arg)]
+    stats_sample: Option<u64>,
+    /// Bearer token required for /stats, /metrics, /events, /grafana and the exports (open when unset)
+    #[arg(long, env = "PAYLOAD_PULVERIZER_STATS_TOKEN", hide_env_values = true)]
+    stats_token: Option<String>,
+    /// Replace humorous logs, ASCII art and whimsical messages with terse responses
+    #[arg(long)]
+    boring: bool,
+    /// Always answer destruction endpoints with a tiny fixed JSON body (same as ?minimal=true)
+    #[arg(long)]
+    minimal: bool,
+    /// WASM plugin to run for every destruction request (repeatable)
+    #[cfg(feature = "wasm-plugins")]
+    #[arg(long = "plugin")]
+    plugins: Vec<String>,
+    /// Seed for all randomized behavior (e.g. shred log selection), makes responses reproducible
+    #[arg(long)]
+    seed: Option<u64>,
+    /// Answer identical payloads within this window with 208 Already Reported, e.g. 5m
+    #[arg(long, value_parser = parse_duration_arg)]
+    dedup_window: Option<i64>,
+    /// Report payloads resembling one destroyed within this window in X-Similar-To, e.g. 1h
+    #[arg(long, value_parser = parse_duration_arg)]
+    similarity_window: Option<i64>,
+    /// Differing SimHash bits (of 64) up to which payloads count as similar
+    #[arg(long, default_value_t = 3, value_parser = clap::value_parser!(u32).range(0..=32))]
+    similarity_distance: u32,
+    /// Jobs from /queue and ?async=true processed at the same time
+    #[arg(long, default_value_t = 4, value_parser = clap::value_parser!(u64).range(1..=1024))]
+    queue_workers: u64,
+    /// Jobs from /queue and ?async=true that may wait for a worker, more are answered with 429
+    #[arg(long, default_value_t = 1000, value_parser = clap::value_parser!(u64).range(1..))]
+    queue_max_waiting: u64,
+    /// Answer 429 once this many stat writes or queued jobs are waiting
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    max_backlog: Option<u64>,
+    /// Stats rows kept in memory while the database fails to take them, retried until it
+    /// recovers (0 drops them right away)
+    #[arg(long, default_value_t = 10_000)]
+    stat_buffer_rows: usize,
+    /// Refuse large uploads with 503 while the process uses more memory than this, e.g. 1g
+    #[arg(long, value_parser = parse_size_arg)]
+    max_memory: Option<u64>,
+    /// Delete the oldest stats rows while the stats database uses more than this, e.g. 1g
+    #[cfg(feature = "sqlite")]
+    #[arg(long, value_parser = parse_size_arg, conflicts_with = "read_only")]
+    disk_max_db_size: Option<u64>,
+    /// Delete the oldest stats rows while less than this is free on the database's disk
+    #[cfg(feature = "sqlite")]
+    #[arg(long, value_parser = parse_size_arg, conflicts_with = "read_only")]
+    disk_min_free: Option<u64>,
+    /// Directory for request bodies larger than --spill-threshold, wiped after use
+    #[arg(long)]
+    spill_dir: Option<String>,
+    /// Bodies larger than this are kept in --spill-dir instead of memory, e.g. 64m
+    #[arg(long, default_value = "64m", value_parser = parse_size_arg)]
+    spill_threshold: u64,
+    /// Largest padding ?respond_bytes= may add to a response, e.g. 100m
+    #[arg(long, default_value = "10m", value_parser = parse_size_arg)]
+    max_respond_bytes: u64,
+    /// Largest payload GET /generate produces, e.g. 10g
+    #[arg(long, default_value = "1g", value_parser = parse_size_arg)]
+    max_generate_bytes: u64,
+    /// Staged payloads are destroyed after this long without a commit or abort, e.g. 1h
+    #[arg(long, default_value = "10m", value_parser = parse_duration_arg)]
+    stage_timeout: i64,
+    /// Most bytes POST /stage holds at once, e.g. 1g
+    #[arg(long, default_value = "256m", value_parser = parse_size_arg)]
+    stage_max_bytes: u64,
+    /// Most unfinished tus uploads kept at once, further ones are refused with 503
+    #[arg(long, default_value_t = 1000)]
+    tus_max_uploads: u32,
+    /// Hosts POST /fetch-and-destroy may download from, *.example.com for subdomains,
+    /// comma separated or repeated
+    #[arg(long, alias = "fetch-allowed-host", value_delimiter = ',')]
+    fetch_allowed_hosts: Vec<String>,
+    /// Let POST /fetch-and-destroy download over plain http, not only https
+    #[arg(long)]
+    fetch_allow_http: bool,
+    /// Largest resource POST /fetch-and-destroy downloads, e.g. 1g
+    #[arg(long, default_value = "100m", value_parser = parse_size_arg)]
+    fetch_max_size: u64,
+    /// Refuse compressed bodies that expand more than this many times
+    #[arg(long, default_value_t = 100, value_parser = clap::value_parser!(u64).range(1..))]
+    max_decompression_ratio: u64,
+    /// Refuse compressed bodies that expand to more than this, e.g. 1g
+    #[arg(long, default_value = "100m", value_parser = parse_size_arg)]
+    max_decompressed_size: u64,
+    /// Keep a Bloom filter of the SHA-256 of every destroyed payload for GET /was-it-destroyed
+    #[cfg(feature = "sqlite")]
+    #[arg(long)]
+    remember_hashes: bool,
+    /// Estimate distinct payloads per endpoint and day with HyperLogLog sketches, shown in /stats
+    #[cfg(feature = "sqlite")]
+    #[arg(long)]
+    count_unique: bool,
+    /// Secret for signing /shred callbacks, enables ?callback= and X-Callback-Url when set
+    #[cfg(feature = "extras")]
+    #[arg(
+        long,
+        env = "PAYLOAD_PULVERIZER_CALLBACK_SECRET",
+        hide_env_values = true
+    )]
+    callback_secret: Option<String>,
+    /// RFC 3161 Time-Stamp Authority to timestamp receipt hashes with, e.g. https://freetsa.org/tsr
+    #[arg(long, value_parser = parse_tsa_url_arg)]
+    tsa_url: Option<String>,
+    /// Allow /validate-before-destroy?check_links=true to send HEAD requests to payload links
+    #[cfg(feature = "validators")]
+    #[arg(long)]
+    check_links: bool,
+    /// Store the client IP and User-Agent with every stats row (anonymous by default)
+    #[arg(long)]
+    record_clients: bool,
+    /// Request header naming the tenant a request is billed to in /stats/usage, e.g. X-Tenant
+    #[arg(long)]
+    tenant_header: Option<String>,
+    /// Proxy addresses or CIDR ranges whose X-Forwarded-For/Forwarded headers are trusted
+    /// for client IPs, comma separated or repeated
+    #[arg(long, alias = "trusted-proxy", value_delimiter = ',', value_parser = proxy::parse_trusted_proxy)]
+    trusted_proxies: Vec<ipnet::IpNet>,
+    /// MaxMind GeoIP2/GeoLite2 City database, records the country and city of every request
+    #[cfg(feature = "geoip")]
+    #[arg(long)]
+    geoip_db: Option<String>,
+    /// Never persist client-identifying data or payload-derived hashes, overrides other options
+    #[arg(long)]
+    privacy: bool,
+    /// Refuse all destruction requests with 503 and open the database read-only (maintenance)
+    #[arg(long)]
+    read_only: bool,
+    /// Check the database for corruption before serving and refuse to start if it is damaged
+    #[cfg(feature = "sqlite")]
+    #[arg(long, value_enum)]
+    integrity_check: Option<integrity::Mode>,
+    /// Set SO_REUSEPORT so a new instance can bind while this one drains
+    #[arg(long)]
+    reuse_port: bool,
+    /// Expect a HAProxy PROXY protocol (v1/v2) header on every connection
+    #[arg(long)]
+    proxy_protocol: bool,
+    /// Serve HTTPS with a certificate for this domain from an ACME CA like Let's Encrypt,
+    /// comma separated or repeated. The CA has to reach this server on port 80.
+    #[cfg(feature = "acme")]
+    #[arg(
+        long,
+        value_delimiter = ',',
+        value_parser = acme::parse_domain,
+        requires = "acme_agree_tos",
+        conflicts_with = "proxy_protocol"
+    )]
+    acme_domain: Vec<String>,
+    /// Agree to the ACME CA's terms of service, required for --acme-domain
+    #[cfg(feature = "acme")]
+    #[arg(long)]
+    acme_agree_tos: bool,
+    /// Contact address for the ACME account, the CA may send expiry warnings there
+    #[cfg(feature = "acme")]
+    #[arg(long)]
+    acme_email: Option<String>,
+    /// ACME directory URL, e.g. Let's Encrypt's staging one for trying things out
+    #[cfg(feature = "acme")]
+    #[arg(long, default_value = acme::LETS_ENCRYPT)]
+    acme_directory: String,
+    /// Where the ACME account key and certificate are kept, should survive restarts
+    #[cfg(feature = "acme")]
+    #[arg(long, default_value = "/tmp/payload-pulverizer-acme")]
+    acme_dir: String,
+    /// Port of the HTTPS listener for --acme-domain
+    #[cfg(feature = "acme")]
+    #[arg(long, default_value_t = 8443)]
+    acme_https_port: u16,
+    /// Also serve HTTP/3 on this UDP port (experimental), advertised to TCP clients via Alt-Svc
+    #[cfg(feature = "http3")]
+    #[arg(long)]
+    http3_port: Option<u16>,
+    /// Certificate chain (PEM) for HTTP/3, which always needs TLS. Defaults to the
+    /// --acme-domain certificate.
+    #[cfg(feature = "http3")]
+    #[arg(long, requires_all = ["http3_port", "http3_key"])]
+    http3_cert: Option<String>,
+    /// Private key (PEM) for --http3-cert
+    #[cfg(feature = "http3")]
+    #[arg(long, requires = "http3_cert")]
+    http3_key: Option<String>,
+    /// Mount all routes under this path prefix, e.g. /pulverizer
+    #[arg(long, value_parser = parse_base_path_arg, default_value = "")]
+    base_path: String,
+    /// Largest payload /validate-before-destroy accepts, e.g. 1m (the config file's
+    /// limits.validate_max_size takes precedence)
+    #[cfg(feature = "validators")]
+    #[arg(long, value_parser = parse_validate_size_arg, default_value_t = VALIDATE_MAX_SIZE)]
+    validate_max_size: usize,
+    /// Start a new database file every calendar month, keeping the old one next to it
+    #[cfg(feature = "sqlite")]
+    #[arg(long, conflicts_with = "read_only")]
+    rotate_monthly: bool,
+    /// Start a new database file once the current one reaches this size, e.g. 2g
+    #[cfg(feature = "sqlite")]
+    #[arg(long, value_parser = parse_size_arg, conflicts_with = "read_only")]
+    rotate_size: Option<u64>,
+    /// Number of rotated database files to keep, older ones are deleted (all by default)
+    #[cfg(feature = "sqlite")]
+    #[arg(long)]
+    rotate_keep: Option<usize>,
+    /// How long to let in-flight requests finish after SIGTERM, e.g. 5m
+    #[arg(long, value_parser = parse_duration_arg, default_value = "30s")]
+    shutdown_timeout: i64,
+    /// Push metrics to this Prometheus Pushgateway, e.g. http://pushgateway:9091
+    #[arg(long)]
+    pushgateway_url: Option<String>,
+    /// How often to push metrics, e.g. 15s
+    #[arg(long, value_parser = parse_duration_arg, default_value = "15s")]
+    push_interval: i64,
+    /// Pushgateway job label
+    #[arg(long, default_value = "payload_pulverizer")]
+    push_job: String,
+    /// Pushgateway instance label, needed to tell several pushing instances apart
+    #[arg(long)]
+    push_instance: Option<String>,
+}
+
+// Runtime configuration shared with all handlers
+struct AppConfig {
+    admin_token: Option<String>,
+    boring: bool,
+    minimal: bool,
+    config_path: Option<String>,
+    // Settings from the config file, swapped as a whole on reload
+    settings: RwLock<Arc<config::FileConfig>>,
+    #[cfg(feature = "extras")]
+    callback_secret: Option<String>,
+    tsa_url: Option<String>,
+    #[cfg(feature = "validators")]
+    check_links: bool,
+    record_clients: bool,
+    tenant_header: Option<String>,
+    trusted_proxies: Vec<ipnet::IpNet>,
+    #[cfg(feature = "geoip")]
+    geoip: Option<geoip::GeoIp>,
+    privacy: bool,
+    read_only: bool,
+    // Prefix all routes are mounted under, "" for the root
+    base_path: String,
+    // --validate-max-size, limits.validate_max_size in the config file wins
+    #[cfg(feature = "validators")]
+    validate_max_size: usize,
+    // Cap for ?respond_bytes=
+    max_respond_bytes: usize,
+    // Cap for GET /generate?bytes=
+    max_generate_bytes: usize,
+    // What POST /fetch-and-destroy may download
+    fetch: fetch::FetchPolicy,
+    // Compression bomb limits for bodies with a Content-Encoding
+    max_decompression_ratio: u64,
+    max_decompressed_size: usize,
+}
+
+impl AppConfig {
+    fn from_args(args: &ServeArgs) -> Result<Self, String> {
+        let file = match &args.config {
+            Some(path) => config::FileConfig::load(path)?,
+            None => config::FileConfig::default(),
+        };
+        if args.privacy && args.record_clients {
+            eprintln!("--privacy is set, ignoring --record-clients");
+        }
+        #[cfg(feature = "geoip")]
+        if args.privacy && args.geoip_db.is_some() {
+            eprintln!("--privacy is set, ignoring --geoip-db");
+        }
+        #[cfg(feature = "sqlite")]
+        if args.privacy && args.remember_hashes {
+            eprintln!("--privacy is set, ignoring --remember-hashes");
+        }
+        #[cfg(feature = "sqlite")]
+        if args.privacy && args.count_unique {
+            eprintln!("--privacy is set, ignoring --count-unique");
+        }
+        if args.privacy && args.tenant_header.is_some() {
+            eprintln!("--privacy is set, ignoring --tenant-header");
+        }
+        if args.privacy && args.tsa_url.is_some() {
+            eprintln!("--privacy is set, ignoring --tsa-url");
+        }
+        Ok(AppConfig {
+            admin_token: args.admin_token.clone(),
+            boring: args.boring,
+            minimal: args.minimal,
+            config_path: args.config.clone(),
+            settings: RwLock::new(Arc::new(file)),
+            #[cfg(feature = "extras")]
+            callback_secret: args.callback_secret.clone(),
+            // The TSA would get and keep the hashes of the payloads
+            tsa_url: args.tsa_url.clone().filter(|_| !args.privacy),
+            #[cfg(feature = "validators")]
+            check_links: args.check_links,
+            // Privacy mode wins over everything that would store who sent a request
+            record_clients: args.record_clients && !args.privacy,
+            tenant_header: args.tenant_header.clone().filter(|_| !args.privacy),
+            trusted_proxies: args.trusted_proxies.clone(),
+            #[cfg(feature = "geoip")]
+            geoip: match &args.geoip_db {
+                Some(path) if !args.privacy => Some(geoip::GeoIp::open(path)?),
+                _ => None,
+            },
+            privacy: args.privacy,
+            read_only: args.read_only,
+            base_path: args.base_path.clone(),
+            #[cfg(feature = "validators")]
+            validate_max_size: args.validate_max_size,
+            max_respond_bytes: args.max_respond_bytes as usize,
+            max_generate_bytes: args.max_generate_bytes as usize,
+            fetch: fetch::FetchPolicy {
+                allowed_hosts: args
+                    .fetch_allowed_hosts
+                    .iter()
+                    .map(|host| host.trim().to_ascii_lowercase())
+                    .collect(),
+                allow_http: args.fetch_allow_http,
+                max_size: args.fetch_max_size as usize,
+            },
+            max_decompression_ratio: args.max_decompression_ratio,
+            max_decompressed_size: args.max_decompressed_size as usize,
+        })
+    }
+
+    // Current reloadable settings. Handlers keep using the snapshot they got, even across a reload.
+    fn settings(&self) -> Arc<config::FileConfig> {
+        self.settings.read().unwrap().clone()
+    }
+
+    // Re-read the config file. On errors the current settings stay in place.
+    fn reload(&self) -> Result<Arc<config::FileConfig>, String> {
+        let Some(path) = &self.config_path else {
+            return Err("No config file to reload, start the server with --config.".to_string());
+        };
+        let settings = Arc::new(config::FileConfig::load(path)?);
+        *self.settings.write().unwrap() = settings.clone();
+        Ok(settings)
+    }
+}
+
+// Response of POST /admin/reload
+#[derive(Serialize)]
+struct ReloadResponse {
+    status: &'static str,
+    rules: usize,
+    shred_styles: usize,
+    allowed_content_types: usize,
+}
+
+impl ReloadResponse {
+    fn new(settings: &config::FileConfig) -> Self {
+        ReloadResponse {
+            status: "reloaded",
+            rules: settings.rules.len(),
+            shred_styles: settings.shred_styles.len(),
+            allowed_content_types: settings.allowed_content_types.len(),
+        }
+    }
+}
+
+#[cfg(feature = "sqlite")]
+#[derive(ValueEnum, Debug, Clone, Copy)]
+enum ExportFormat {
+    Csv,
+    Jsonl,
+}
+
+// clap value parser for duration arguments like "30d"
+fn parse_duration_arg(spec: &str) -> Result<i64, String> {
+    parse_duration_secs(spec)
+        .ok_or_else(|| format!("invalid duration '{}', use e.g. 30m, 24h or 7d", spec))
+}
+
+// clap value parser for --validate-max-size, e.g. 256k or 1m
+#[cfg(feature = "validators")]
+fn parse_validate_size_arg(spec: &str) -> Result<usize, String> {
+    loadtest::parse_size(spec)
+        .filter(|size| (1..=MAX_PAYLOAD_SIZE).contains(size))
+        .ok_or_else(|| {
+            format!(
+                "invalid size '{}', use e.g. 256k or 1m (at most {})",
+                spec,
+                format_size(MAX_PAYLOAD_SIZE)
+            )
+        })
+}
+
+// clap value parser for --tsa-url
+fn parse_tsa_url_arg(spec: &str) -> Result<String, String> {
+    match spec.starts_with("http://") || spec.starts_with("https://") {
+        true => Ok(spec.to_string()),
+        false => Err(format!("invalid TSA URL '{}', use an http(s) URL", spec)),
+    }
+}
+
+// Normalize --base-path to "/prefix" without a trailing slash, "" mounts at the root
+fn parse_base_path_arg(spec: &str) -> Result<String, String> {
+    let path = spec.trim().trim_end_matches('/');
+    if path.is_empty() {
+        return Ok(String::new());
+    }
+    if !path.starts_with('/') || path.contains(['?', '#', '{', '}']) {
+        return Err(format!(
+            "invalid base path '{}', use e.g. /pulverizer",
+            spec
+        ));
+    }
+    Ok(path.to_string())
+}
+
+// Open the stats database and bring its schema up to date
+#[cfg(feature = "sqlite")]
+fn init_db(db: &DbArgs) -> Result<Connection, String> {
+    prepare_db_file(db)?;
+    let conn = open_db(db, OpenFlags::default())?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS endpoint_stats_raw (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            endpoint TEXT NOT NULL,
+            payload_size INTEGER NOT NULL,
+            runtime_us INTEGER NOT NULL,
+            ts DATETIME DEFAULT CURRENT_TIMESTAMP
+        )",
+        [],
+    )
+    .map_err(|e| format!("Failed to create stats table in {}: {}", db.db_path, e))?;
+    for (column, decl) in [
+        ("passes", "INTEGER"),
+        ("read_us", "INTEGER"),
+        ("process_us", "INTEGER"),
+        ("persist_us", "INTEGER"),
+        ("client_ip", "TEXT"),
+        ("user_agent", "TEXT"),
+        ("country", "TEXT"),
+        ("city", "TEXT"),
+        ("chunked", "INTEGER"),
+        ("tenant", "TEXT"),
+        ("work_us", "INTEGER"),
+        ("checksum_mismatch", "INTEGER"),
+        ("sample_weight", "INTEGER"),
+    ] {
+        ensure_column(&conn, "endpoint_stats_raw", column, decl)
+            .map_err(|e| format!("Failed to migrate stats table in {}: {}", db.db_path, e))?;
+    }
+    // Alerts and the time-windowed reports only look at recent rows. Building it on a
+    // large existing database takes a while, once.
+    conn.execute(
+        "CREATE INDEX IF NOT EXISTS endpoint_stats_ts ON endpoint_stats_raw (ts)",
+        [],
+    )
+    .map_err(|e| format!("Failed to index stats table in {}: {}", db.db_path, e))?;
+    init_usage_rollups(&conn)
+        .map_err(|e| format!("Failed to create usage rollups in {}: {}", db.db_path, e))?;
+    rejections::init_table(&conn)
+        .map_err(|e| format!("Failed to create rejections table in {}: {}", db.db_path, e))?;
+    Ok(conn)
+}
+
+// Monthly totals per tenant for /stats/usage, kept up to date on every insert and
+// never pruned. Created from the existing rows the first time.
+#[cfg(feature = "sqlite")]
+fn init_usage_rollups(conn: &Connection) -> rusqlite::Result<()> {
+    let exists: bool = conn.query_row(
+        "SELECT COUNT(*) > 0 FROM sqlite_master WHERE type = 'table' AND name = 'usage_rollups'",
+        [],
+        |row| row.get(0),
+    )?;
+    if exists {
+        return Ok(());
+    }
+    conn.execute_batch(
+        "BEGIN;
+        CREATE TABLE usage_rollups (
+            month TEXT NOT NULL,
+            tenant TEXT NOT NULL,
+            requests INTEGER NOT NULL,
+            bytes INTEGER NOT NULL,
+            runtime_us INTEGER NOT NULL,
+            PRIMARY KEY (month, tenant)
+        );
+        INSERT INTO usage_rollups
+            SELECT strftime('%Y-%m', ts), COALESCE(tenant, ''), SUM(COALESCE(sample_weight, 1)),
+                SUM(payload_size * COALESCE(sample_weight, 1)), SUM(runtime_us * COALESCE(sample_weight, 1))
+            FROM endpoint_stats_raw GROUP BY 1, 2;
+        COMMIT;",
+    )
+}
+
+// Open the stats database for --read-only, without creating or migrating anything
+#[cfg(feature = "sqlite")]
+fn open_db_read_only(db: &DbArgs) -> Result<Connection, String> {
+    if !std::path::Path::new(&db.db_path).is_file() {
+        return Err(format!("Database {} does not exist", db.db_path));
+    }
+    open_db(
+        db,
+        OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+    )
+}
+
+#[cfg(feature = "sqlite")]
+fn open_db(db: &DbArgs, flags: OpenFlags) -> Result<Connection, String> {
+    let conn = Connection::open_with_flags(&db.db_path, flags)
+        .map_err(|e| format!("Failed to open database {}: {}", db.db_path, e))?;
+    #[cfg(feature = "sqlcipher")]
+    if let Some(key) = db.key()? {
+        conn.pragma_update(None, "key", key)
+            .map_err(|e| format!("Failed to set database key: {}", e))?;
+        // SQLCipher only notices a wrong key on the first read
+        conn.query_row("SELECT COUNT(*) FROM sqlite_master", [], |row| {
+            row.get::<_, i64>(0)
+        })
+        .map_err(|_| {
+            format!(
+                "Failed to unlock database {}, wrong key or not encrypted",
+                db.db_path
+            )
+        })?;
+    }
+    Ok(conn)
+}
+
+// Open the stats database or exit with the reason, for use at startup
+#[cfg(feature = "sqlite")]
+fn init_db_or_exit(db: &DbArgs) -> Connection {
+    init_db(db).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    })
+}
+
+// Make sure the database file can be written before SQLite gets to it, so a bad
+// path fails at startup instead of on the first request. Creates the parent
+// directories with --db-create-dirs and applies --db-mode and --db-owner.
+fn prepare_db_file(db: &DbArgs) -> Result<(), String> {
+    let path = std::path::Path::new(&db.db_path);
+    if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        if !parent.is_dir() {
+            if !db.db_create_dirs {
+                return Err(format!(
+                    "Database directory {} does not exist (use --db-create-dirs to create it)",
+                    parent.display()
+                ));
+            }
+            std::fs::create_dir_all(parent)
+                .map_err(|e| format!("Failed to create {}: {}", parent.display(), e))?;
+        }
+    }
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(false);
+    #[cfg(unix)]
+    if let Some(mode) = db.db_mode {
+        // Only applies to new files, existing ones are changed below
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, mode);
+    }
+    options
+        .open(path)
+        .map_err(|e| format!("Database {} is not writable: {}", db.db_path, e))?;
+    if let Some(mode) = db.db_mode {
+        #[cfg(unix)]
+        std::fs::set_permissions(path, std::os::unix::fs::PermissionsExt::from_mode(mode))
+            .map_err(|e| format!("Failed to set mode of {}: {}", db.db_path, e))?;
+        #[cfg(not(unix))]
+        return Err(format!("--db-mode {:o} is only supported on Unix", mode));
+    }
+    if let Some((uid, gid)) = db.db_owner {
+        #[cfg(unix)]
+        std::os::unix::fs::chown(path, Some(uid), gid)
+            .map_err(|e| format!("Failed to change owner of {}: {}", db.db_path, e))?;
+        #[cfg(not(unix))]
+        return Err(format!("--db-owner {} is only supported on Unix", uid));
+    }
+    Ok(())
+}
+
+// clap value parser for sizes, e.g. 512m or 2g
+fn parse_size_arg(spec: &str) -> Result<u64, String> {
+    loadtest::parse_size(spec)
+        .filter(|bytes| *bytes > 0)
+        .map(|bytes| bytes as u64)
+        .ok_or_else(|| format!("invalid size '{}', use e.g. 512m or 2g", spec))
+}
+// clap value parser for numeric owners like "1000" or "1000:1000"
+fn parse_owner_arg(spec: &str) -> Result<(u32, Option<u32>), String> {
+    let invalid = || {
+        format!(
+            "invalid owner '{}', use uid or uid:gid e.g. 1000:1000",
+            spec
+        )
+    };
+    match spec.split_once(':') {
+        Some((uid, gid)) => Ok((
+            uid.parse().map_err(|_| invalid())?,
+            Some(gid.parse().map_err(|_| invalid())?),
+        )),
+        None => Ok((spec.parse().map_err(|_| invalid())?, None)),
+    }
+}
+
+// clap value parser for octal file modes like "600" or "0640"
+fn parse_mode_arg(spec: &str) -> Result<u32, String> {
+    u32::from_str_radix(spec, 8)
+        .ok()
+        .filter(|mode| *mode <= 0o7777)
+        .ok_or_else(|| format!("invalid file mode '{}', use octal e.g. 600 or 0640", spec))
+}
+
+// Add a column to an existing table unless it's already there (databases created by older versions)
+#[cfg(feature = "sqlite")]
+fn ensure_column(conn: &Connection, table: &str, column: &str, decl: &str) -> rusqlite::Result<()> {
+    let mut stmt = conn.prepare(&format!("PRAGMA table_info({})", table))?;
+    let exists = stmt
+        .query_map([], |row| row.get::<_, String>(1))?
+        .flatten()
+        .any(|name| name == column);
+    if !exists {
+        conn.execute(
+            &format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, decl),
+            [],
+        )?;
+    }
+    Ok(())
+}
+
+// Longer tenant names are cut off
+const TENANT_MAX_LENGTH: usize = 128;
+
+// Optional per-request details stored next to the basic stats
+#[derive(Default)]
+struct StatExtras {
+    passes: Option<u32>,
+    read_us: Option<u128>,
+    process_us: Option<u128>,
+    client_ip: Option<String>,
+    user_agent: Option<String>,
+    country: Option<String>,
+    city: Option<String>,
+    // Whether the body was streamed without Content-Length
+    chunked: Option<bool>,
+    // Who the request is billed to, from --tenant-header
+    tenant: Option<String>,
+    // Where the recorded destruction is announced to /stats/live subscribers
+    live: Option<Data<live::Hub>>,
+    // Where the write counts towards --max-backlog
+    backlog: Option<Data<backpressure::Backlog>>,
+    // CPU time burnt for ?work=
+    work_us: Option<u128>,
+    // Whether the body failed its Content-MD5 check, None without the header
+    checksum_mismatch: Option<bool>,
+    // Which destructions are recorded with --stats-sample
+    sampler: Option<Data<sampling::Sampler>>,
+    // Where rows wait while the database fails
+    stat_buffer: Option<Data<stat_buffer::StatBuffer>>,
+    // When recording started, for the row's persist_us
+    persist_start: Option<Instant>,
+}
+
+impl StatExtras {
+    // Extras every request gets. The client is only recorded with --record-clients,
+    // its location only with --geoip-db.
+    fn for_request(req: &HttpRequest, config: &AppConfig) -> Self {
+        let mut extras = StatExtras {
+            chunked: Some(body::is_chunked(req)),
+            tenant: config.tenant_header.as_ref().and_then(|name| {
+                req.headers()
+                    .get(name)
+                    .and_then(|v| v.to_str().ok())
+                    .map(|v| v.trim().chars().take(TENANT_MAX_LENGTH).collect())
+                    .filter(|tenant: &String| !tenant.is_empty())
+            }),
+            live: req.app_data::<Data<live::Hub>>().cloned(),
+            backlog: req.app_data::<Data<backpressure::Backlog>>().cloned(),
+            work_us: req.extensions().get::<work::WorkDone>().map(|work| work.0),
+            checksum_mismatch: checksum::check(req).map(|check| !check.verified),
+            sampler: req.app_data::<Data<sampling::Sampler>>().cloned(),
+            stat_buffer: req.app_data::<Data<stat_buffer::StatBuffer>>().cloned(),
+            ..StatExtras::default()
+        };
+        if config.record_clients {
+            extras.client_ip = client_ip(req, &config.trusted_proxies).map(|ip| ip.to_string());
+            extras.user_agent = req
+                .headers()
+                .get("User-Agent")
+                .and_then(|v| v.to_str().ok())
+                .map(str::to_string);
+        }
+        #[cfg(feature = "geoip")]
+        if let Some(location) = config.geoip.as_ref().and_then(|geoip| {
+            client_ip(req, &config.trusted_proxies).and_then(|ip| geoip.locate(ip))
+        }) {
+            extras.country = location.country;
+            extras.city = location.city;
+        }
+        extras
+    }
+}
+
+// The peer address, or the forwarded client address if the peer is a trusted proxy
+fn client_ip(req: &HttpRequest, trusted_proxies: &[ipnet::IpNet]) -> Option<std::net::IpAddr> {
+    let peer = req.peer_addr()?.ip();
+    Some(proxy::client_ip(peer, req.headers(), trusted_proxies))
+}
+
+// Where the time of a request went: receiving the body, handling it, writing the stats row
+#[derive(Serialize, Clone, Copy)]
+struct PhaseTimings {
+    read_us: u128,
+    process_us: u128,
+    persist_us: u128,
+    // Part of process_us spent on ?work=
+    #[serde(skip_serializing_if = "Option::is_none")]
+    work_us: Option<u128>,
+}
+
+impl PhaseTimings {
+    // Server-Timing header value (durations in milliseconds), for responses without a body
+    fn server_timing(&self) -> String {
+        let timing = format!(
+            "read;dur={:.3}, process;dur={:.3}, persist;dur={:.3}",
+            self.read_us as f64 / 1000.0,
+            self.process_us as f64 / 1000.0,
+            self.persist_us as f64 / 1000.0
+        );
+        match self.work_us {
+            Some(work_us) => format!("{}, work;dur={:.3}", timing, work_us as f64 / 1000.0),
+            None => timing,
+        }
+    }
+}
+
+// A stats row about to be written, or waiting in the stat buffer
+#[derive(Serialize, Deserialize)]
+struct NewStat {
+    endpoint: String,
+    payload_size: i64,
+    runtime_us: i64,
+    passes: Option<u32>,
+    read_us: Option<i64>,
+    process_us: Option<i64>,
+    // Up to the insert, which can't measure itself
+    persist_us: Option<i64>,
+    client_ip: Option<String>,
+    user_agent: Option<String>,
+    country: Option<String>,
+    city: Option<String>,
+    chunked: Option<bool>,
+    tenant: Option<String>,
+    work_us: Option<i64>,
+    checksum_mismatch: Option<bool>,
+    sample_weight: Option<i64>,
+    // When the request was recorded, in SQLite's CURRENT_TIMESTAMP format
+    ts: String,
+}
+
+impl NewStat {
+    // Insert the row and count it in the usage rollups, returns its id. Callers wrap this
+    // in a transaction so the two can't disagree.
+    #[cfg(feature = "sqlite")]
+    fn insert(&self, conn: &Connection) -> rusqlite::Result<i64> {
+        conn.execute(
+            "INSERT INTO endpoint_stats_raw (endpoint, payload_size, runtime_us, passes, read_us, process_us, client_ip, user_agent, country, city, chunked, tenant, work_us, checksum_mismatch, sample_weight, ts, persist_us) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17)",
+            params![
+                self.endpoint,
+                self.payload_size,
+                self.runtime_us,
+                self.passes,
+                self.read_us,
+                self.process_us,
+                self.client_ip,
+                self.user_agent,
+                self.country,
+                self.city,
+                self.chunked,
+                self.tenant,
+                self.work_us,
+                self.checksum_mismatch,
+                self.sample_weight,
+                self.ts,
+                self.persist_us
+            ],
+        )?;
+        let stat_id = conn.last_insert_rowid();
+        let weight = self.sample_weight.unwrap_or(1);
+        conn.execute(
+            "INSERT INTO usage_rollups (month, tenant, requests, bytes, runtime_us) VALUES (strftime('%Y-%m', ?5), ?1, ?4, ?2, ?3)
+            ON CONFLICT (month, tenant) DO UPDATE SET requests = requests + excluded.requests, bytes = bytes + excluded.bytes, runtime_us = runtime_us + excluded.runtime_us",
+            params![
+                self.tenant.as_deref().unwrap_or_default(),
+                self.payload_size * weight,
+                self.runtime_us * weight,
+                weight,
+                self.ts
+            ],
+        )?;
+        Ok(stat_id)
+    }
+}
+
+// Insert a row for every request, returns the id of the new row. Rows the database
+// doesn't take go to the stat buffer.
+fn record_stat_with(
+    store: &dyn store::Store,
+    endpoint: &str,
+    payload_size: usize,
+    runtime_us: u128,
+    extras: StatExtras,
+) -> Option<i64> {
+    let stat_id = match extras
+        .sampler
+        .as_ref()
+        .map(|sampler| sampler.next(endpoint))
+    {
+        // Left out of the --stats-sample, nothing to write but still shown live
+        Some(None) => None,
+        sample_weight => {
+            let row = NewStat {
+                endpoint: endpoint.to_string(),
+                payload_size: payload_size as i64,
+                runtime_us: runtime_us as i64,
+                passes: extras.passes,
+                read_us: extras.read_us.map(|us| us as i64),
+                process_us: extras.process_us.map(|us| us as i64),
+                persist_us: None,
+                client_ip: extras.client_ip,
+                user_agent: extras.user_agent,
+                country: extras.country,
+                city: extras.city,
+                chunked: extras.chunked,
+                tenant: extras.tenant,
+                work_us: extras.work_us.map(|us| us as i64),
+                checksum_mismatch: extras.checksum_mismatch,
+                sample_weight: sample_weight.flatten(),
+                ts: chrono::Utc::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+            };
+            write_stat(
+                store,
+                row,
+                extras.stat_buffer.as_ref().map(|buffer| buffer.get_ref()),
+                extras.backlog.as_ref().map(|backlog| backlog.get_ref()),
+                extras.persist_start,
+            )
+        }
+    };
+    if let Some(hub) = &extras.live {
+        hub.publish(live::Activity {
+            stat_id,
+            endpoint: endpoint.to_string(),
+            payload_size,
+            runtime_us,
+            ts: chrono::Utc::now().to_rfc3339(),
+        });
+    }
+    stat_id
+}
+
+// Write a row, or leave it to the stat buffer while the database fails
+fn write_stat(
+    store: &dyn store::Store,
+    mut row: NewStat,
+    buffer: Option<&stat_buffer::StatBuffer>,
+    backlog: Option<&backpressure::Backlog>,
+    persist_start: Option<Instant>,
+) -> Option<i64> {
+    if let Some(buffer) = buffer.filter(|buffer| buffer.is_pending()) {
+        buffer.defer(row);
+        return None;
+    }
+    let result = {
+        let waiting = backlog.map(backpressure::Backlog::start_write);
+        let mut writer = store.writer();
+        drop(waiting);
+        row.persist_us = persist_start.map(|start| start.elapsed().as_micros() as i64);
+        writer.insert(std::slice::from_ref(&row))
+    };
+    match (result, buffer) {
+        (Ok(stat_ids), _) => stat_ids.first().copied(),
+        (Err(e), Some(buffer)) => {
+            buffer.failed(row, &e);
+            None
+        }
+        (Err(e), None) => {
+            eprintln!("Failed to write a stats row: {}", e);
+            None
+        }
+    }
+}
+
+// Record a stat with the phase breakdown of the request. The body has been read by the
+// time the handler starts, so `handler_start` separates reading from processing.
+fn record_timed_stat(
+    store: &dyn store::Store,
+    endpoint: &str,
+    payload_size: usize,
+    start: Instant,
+    handler_start: Instant,
+    mut extras: StatExtras,
+) -> (Option<i64>, PhaseTimings) {
+    let read_us = handler_start.duration_since(start).as_micros();
+    let process_us = handler_start.elapsed().as_micros();
+    extras.read_us = Some(read_us);
+    extras.process_us = Some(process_us);
+    let work_us = extras.work_us;
+    let persist_start = Instant::now();
+    extras.persist_start = Some(persist_start);
+    let stat_id = record_stat_with(
+        store,
+        endpoint,
+        payload_size,
+        start.elapsed().as_micros(),
+        extras,
+    );
+    let persist_us = persist_start.elapsed().as_micros();
+    (
+        stat_id,
+        PhaseTimings {
+            read_us,
+            process_us,
+            persist_us,
+            work_us,
+        },
+    )
+}
+
+// Delete all rows older than the given age, returns the number of deleted rows
+#[cfg(feature = "sqlite")]
+fn prune_stats(conn: &Connection, older_than_secs: i64) -> rusqlite::Result<usize> {
+    conn.execute(
+        "DELETE FROM endpoint_stats_raw WHERE ts < datetime('now', ?1)",
+        params![format!("-{} seconds", older_than_secs)],
+    )
+}
+
+// A single raw stats row, as written by the export subcommand and /stats/export
+#[cfg(feature = "sqlite")]
+#[derive(Serialize)]
+struct StatRow {
+    id: i64,
+    endpoint: String,
+    payload_size: i64,
+    runtime_us: i64,
+    ts: String,
+    passes: Option<i64>,
+    read_us: Option<i64>,
+    process_us: Option<i64>,
+    persist_us: Option<i64>,
+    client_ip: Option<String>,
+    user_agent: Option<String>,
+    country: Option<String>,
+    city: Option<String>,
+    chunked: Option<bool>,
+    tenant: Option<String>,
+    work_us: Option<i64>,
+    checksum_mismatch: Option<bool>,
+    // Destructions the row stands for with --stats-sample, None for every one recorded
+    sample_weight: Option<i64>,
+}
+
+// Quote a CSV field if it contains anything that would break the row
+#[cfg(feature = "sqlite")]
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+// Empty CSV field for NULL columns
+#[cfg(feature = "sqlite")]
+fn optional_field(value: Option<i64>) -> String {
+    value.map(|v| v.to_string()).unwrap_or_default()
+}
+
+#[cfg(feature = "sqlite")]
+const STAT_ROW_COLUMNS: &str = "id, endpoint, payload_size, runtime_us, ts, passes, read_us, process_us, persist_us, client_ip, user_agent, country, city, chunked, tenant, work_us, checksum_mismatch, sample_weight";
+
+// Map a row selected with STAT_ROW_COLUMNS
+#[cfg(feature = "sqlite")]
+fn stat_row(row: &rusqlite::Row) -> rusqlite::Result<StatRow> {
+    Ok(StatRow {
+        id: row.get(0)?,
+        endpoint: row.get(1)?,
+        payload_size: row.get(2)?,
+        runtime_us: row.get(3)?,
+        ts: row.get(4)?,
+        passes: row.get(5)?,
+        read_us: row.get(6)?,
+        process_us: row.get(7)?,
+        persist_us: row.get(8)?,
+        client_ip: row.get(9)?,
+        user_agent: row.get(10)?,
+        country: row.get(11)?,
+        city: row.get(12)?,
+        chunked: row.get(13)?,
+        tenant: row.get(14)?,
+        work_us: row.get(15)?,
+        checksum_mismatch: row.get(16)?,
+        sample_weight: row.get(17)?,
+    })
+}
+
+// The next `limit` raw stats rows after id `after`. HTTP exports page through the
+// table with this, so the database isn't locked for the whole download.
+#[cfg(feature = "sqlite")]
+fn stat_rows_after(conn: &Connection, after: i64, limit: usize) -> rusqlite::Result<Vec<StatRow>> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {} FROM endpoint_stats_raw WHERE id > ?1 ORDER BY id LIMIT ?2",
+        STAT_ROW_COLUMNS
+    ))?;
+    let rows = stmt.query_map(params![after, limit as i64], stat_row)?;
+    rows.collect()
+}
+
+// Write all raw stats rows to `out`, returns the number of exported rows
+#[cfg(feature = "sqlite")]
+fn export_stats(
+    conn: &Connection,
+    format: ExportFormat,
+    out: &mut dyn Write,
+) -> Result<usize, Box<dyn std::error::Error + Send + Sync>> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {} FROM endpoint_stats_raw ORDER BY id",
+        STAT_ROW_COLUMNS
+    ))?;
+    let rows = stmt.query_map([], stat_row)?;
+    if let ExportFormat::Csv = format {
+        writeln!(
+            out,
+            "id,endpoint,payload_size,runtime_us,ts,passes,read_us,process_us,persist_us,client_ip,user_agent,country,city,chunked,tenant,work_us,checksum_mismatch,sample_weight"
+        )?;
+    }
+    let mut exported = 0;
+    for row in rows {
+        let row = row?;
+        match format {
+            ExportFormat::Csv => writeln!(
+                out,
+                "{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{}",
+                row.id,
+                csv_field(&row.endpoint),
+                row.payload_size,
+                row.runtime_us,
+                csv_field(&row.ts),
+                optional_field(row.passes),
+                optional_field(row.read_us),
+                optional_field(row.process_us),
+                optional_field(row.persist_us),
+                csv_field(row.client_ip.as_deref().unwrap_or_default()),
+                csv_field(row.user_agent.as_deref().unwrap_or_default()),
+                csv_field(row.country.as_deref().unwrap_or_default()),
+                csv_field(row.city.as_deref().unwrap_or_default()),
+                optional_field(row.chunked.map(i64::from)),
+                csv_field(row.tenant.as_deref().unwrap_or_default()),
+                optional_field(row.work_us),
+                optional_field(row.checksum_mismatch.map(i64::from)),
+                optional_field(row.sample_weight)
+            )?,
+            ExportFormat::Jsonl => writeln!(out, "{}", serde_json::to_string(&row)?)?,
+        }
+        exported += 1;
+    }
+    out.flush()?;
+    Ok(exported)
+}
+
+// Update StatsEntry and StatsResponse to match the new aggregation
+#[derive(Serialize, Clone)]
+struct StatsEntry {
+    endpoint: String,
+    count: i64,
+    total_bytes: i64,
+    total_runtime_us: i64,
+    avg_payload_size: f64,
+    avg_runtime_us: f64,
+    total_passes: Option<i64>,
+    // Phase averages over the requests that recorded them
+    avg_read_us: Option<f64>,
+    avg_process_us: Option<f64>,
+    avg_persist_us: Option<f64>,
+    // Average ?work= time over the requests that asked for it
+    avg_work_us: Option<f64>,
+    // Requests whose body was streamed without Content-Length
+    chunked_count: i64,
+    // Requests whose body didn't match its Content-MD5
+    checksum_mismatch_count: i64,
+    // Estimated distinct payloads, in total and per UTC day, with --count-unique
+    #[serde(skip_serializing_if = "Option::is_none")]
+    unique_payloads: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    unique_payloads_by_day: Option<BTreeMap<String, u64>>,
+}
+
+#[derive(Serialize, Clone)]
+struct StatsResponse {
+    stats: Vec<StatsEntry>,
+    // Requests turned away before they could destroy anything
+    rejections: Vec<rejections::RejectionCount>,
+}
+
+// Grand totals across all endpoints for /stats/summary
+#[derive(Serialize)]
+struct StatsSummary {
+    total_requests: i64,
+    total_bytes: i64,
+    total_runtime_us: i64,
+    first_request_at: Option<String>,
+    last_request_at: Option<String>,
+    bytes_per_second: f64,
+}
+
+impl StatsSummary {
+    fn new(
+        total_requests: i64,
+        total_bytes: i64,
+        total_runtime_us: i64,
+        first_request_at: Option<String>,
+        last_request_at: Option<String>,
+        elapsed_s: Option<i64>,
+    ) -> Self {
+        // Requests recorded within the current second would divide by zero
+        let elapsed_s = elapsed_s.unwrap_or(0).max(1);
+        StatsSummary {
+            total_requests,
+            total_bytes,
+            total_runtime_us,
+            first_request_at,
+            last_request_at,
+            bytes_per_second: total_bytes as f64 / elapsed_s as f64,
+        }
+    }
+}
+
+// Query parameters for /stats/by-client and /stats/by-country
+#[cfg(feature = "sqlite")]
+#[derive(Deserialize)]
+struct BreakdownQuery {
+    limit: Option<u32>,
+}
+
+// Requests and bytes of one client IP or User-Agent
+#[cfg(feature = "sqlite")]
+#[derive(Serialize)]
+struct ClientStatsEntry {
+    value: String,
+    count: i64,
+    total_bytes: i64,
+    last_request_at: String,
+}
+
+#[cfg(feature = "sqlite")]
+#[derive(Serialize)]
+struct ByClientResponse {
+    // Whether this server currently records clients (--record-clients)
+    recording: bool,
+    clients: Vec<ClientStatsEntry>,
+    user_agents: Vec<ClientStatsEntry>,
+}
+
+// Requests and bytes of one city
+#[cfg(feature = "geoip")]
+#[derive(Serialize)]
+struct CityStatsEntry {
+    country: Option<String>,
+    city: String,
+    count: i64,
+    total_bytes: i64,
+}
+
+#[cfg(feature = "geoip")]
+#[derive(Serialize)]
+struct ByCountryResponse {
+    countries: Vec<ClientStatsEntry>,
+    cities: Vec<CityStatsEntry>,
+}
+
+// Top entries of /stats/by-client and /stats/by-country when no limit is given
+#[cfg(feature = "sqlite")]
+const BY_CLIENT_DEFAULT_LIMIT: u32 = 20;
+
+// Query parameters for /stats/export
+#[cfg(feature = "sqlite")]
+#[derive(Deserialize)]
+struct StatsExportQuery {
+    format: String,
+}
+
+// Query parameters for /stats/usage
+#[cfg(feature = "sqlite")]
+#[derive(Deserialize)]
+struct UsageQuery {
+    month: Option<String>,
+}
+
+// Usage of one tenant in a month
+#[cfg(feature = "sqlite")]
+#[derive(Serialize)]
+struct TenantUsage {
+    // None for requests without the --tenant-header
+    tenant: Option<String>,
+    requests: i64,
+    bytes: i64,
+    gigabytes: f64,
+    runtime_us: i64,
+}
+
+#[cfg(feature = "sqlite")]
+#[derive(Serialize)]
+struct UsageResponse {
+    month: String,
+    tenants: Vec<TenantUsage>,
+}
+
+// Which database files /stats covers
+#[derive(Deserialize, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum StatsSpan {
+    // The current file and all rotated ones
+    #[default]
+    All,
+    Current,
+}
+
+// Query parameters for /stats
+#[derive(Deserialize)]
+struct StatsQuery {
+    #[serde(default)]
+    span: StatsSpan,
+}
+
+// Query parameters for /stats/tail
+#[cfg(feature = "sqlite")]
+#[derive(Deserialize)]
+struct TailQuery {
+    n: Option<u32>,
+}
+
+// One destruction as returned by /stats/tail
+#[cfg(feature = "sqlite")]
+#[derive(Serialize)]
+struct TailEntry {
+    id: i64,
+    endpoint: String,
+    payload_size: i64,
+    runtime_us: i64,
+    ts: String,
+}
+
+// Records returned by /stats/tail without ?n= and at most
+#[cfg(feature = "sqlite")]
+const TAIL_DEFAULT: u32 = 50;
+#[cfg(feature = "sqlite")]
+const TAIL_MAX: u32 = 1000;
+
+// Query parameters for /stats/timeseries
+#[cfg(feature = "sqlite")]
+#[derive(Deserialize)]
+struct TimeseriesQuery {
+    bucket: Option<String>,
+    since: Option<String>,
+}
+
+#[cfg(feature = "sqlite")]
+#[derive(Serialize)]
+struct TimeseriesPoint {
+    bucket: String,
+    endpoint: String,
+    count: i64,
+    total_bytes: i64,
+}
+
+#[cfg(feature = "sqlite")]
+#[derive(Serialize)]
+struct TimeseriesResponse {
+    bucket: String,
+    since: String,
+    series: Vec<TimeseriesPoint>,
+}
+
+// Parse a short duration like "90s", "15m", "24h", "7d" or "2w" into seconds
+fn parse_duration_secs(spec: &str) -> Option<i64> {
+    let spec = spec.trim();
+    let unit = spec.chars().last()?;
+    let multiplier = match unit {
+        's' => 1,
+        'm' => 60,
+        'h' => 60 * 60,
+        'd' => 24 * 60 * 60,
+        'w' => 7 * 24 * 60 * 60,
+        _ => return None,
+    };
+    let value: i64 = spec[..spec.len() - 1].parse().ok()?;
+    if value < 0 {
+        return None;
+    }
+    value.checked_mul(multiplier)
+}
+
+// Metrics exposed per endpoint to Grafana, as "<endpoint>.<metric>" targets
+#[cfg(feature = "sqlite")]
+const GRAFANA_METRICS: &[&str] = &["count", "bytes", "avg_runtime_us"];
+
+// Request body of the Grafana simple-JSON /query call (only the fields we use)
+#[cfg(feature = "sqlite")]
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GrafanaQueryRequest {
+    range: GrafanaRange,
+    interval_ms: Option<i64>,
+    targets: Vec<GrafanaTarget>,
+}
+
+#[cfg(feature = "sqlite")]
+#[derive(Deserialize)]
+struct GrafanaRange {
+    from: chrono::DateTime<chrono::Utc>,
+    to: chrono::DateTime<chrono::Utc>,
+}
+
+#[cfg(feature = "sqlite")]
+#[derive(Deserialize)]
+struct GrafanaTarget {
+    target: Option<String>,
+}
+
+#[cfg(feature = "sqlite")]
+#[derive(Serialize)]
+struct GrafanaTimeseries {
+    target: String,
+    // Grafana expects [value, unix_ms] pairs
+    datapoints: Vec<(f64, i64)>,
+}
+
+// Add a list of log message sequences for the shredder
+#[cfg(feature = "extras")]
+const SHREDDER_LOGS: &[&[&str]] = &[
+    &[
+        "Feeding payload into industrial-grade data shredder...",
+        "Shredding...",
+        "Payload particles irreversibly scattered in cyberspace dust."
+    ],
+    &[
+        "Payload enters the shredder. It never stood a chance.",
+        "Blades spinning at ludicrous speed...",
+        "Payload reduced to confetti. Hope you didn't need that."
+    ],
+    &[
+        "Payload, meet Mr. Shredder.",
+        "Mr. Shredder, do your thing.",
+        "Payload is now a fine digital powder."
+    ],
+    &[
+        "Initiating payload obliteration protocol...",
+        "Warning: No undo button detected.",
+        "Payload is now a memory. A very faint one."
+    ],
+    &[
+        "Payload bravely volunteers for shredding.",
+        "Shredder: 'I was born for this.'",
+        "Payload: 'Tell my bits I love them.'"
+    ],
+    &[
+        "Payload enters the vortex of doom...",
+        "Shredder cackles maniacally.",
+        "Payload is now existentially challenged."
+    ],
+    &[
+        "Payload: 'I regret nothing!'",
+        "Shredder: 'You will.'",
+        "Payload is now a cautionary tale."
+    ],
+    &[
+        "Payload is serenaded by the whirring of blades...",
+        "Shredder: 'This is my jam.'",
+        "Payload is now a remix of its former self."
+    ],
+    &[
+        "Payload enters the shredder's lair.",
+        "Shredder: 'Another one for the collection.'",
+        "Payload is now a collectible dust bunny."
+    ],
+    &[
+        "Payload: 'Is this going to hurt?'",
+        "Shredder: 'Only for a microsecond.'",
+        "Payload is now at peace."
+    ],
+    &[
+        "Payload is weighed, measured, and found... shreddable.",
+        "Shredder: 'I love my job.'",
+        "Payload is now a statistic."
+    ],
+    &[
+        "Payload is greeted by the Shredder's motivational poster: 'You miss 100% of the bits you don't shred.'",
+        "Shredder warms up with a few practice spins.",
+        "Payload is now a motivational example for others.",
+        "Shredder: 'Next!'"
+    ],
+    &[
+        "Payload: 'I was told there would be snacks.'",
+        "Shredder: 'You are the snack.'",
+        "Payload is now a light meal for the machine.",
+        "Shredder burps contentedly."
+    ],
+    &[
+        "Payload is scanned for sentimental value...",
+        "Result: None detected.",
+        "Shredder proceeds without remorse.",
+        "Payload is now a distant memory."
+    ],
+    &[
+        "Payload attempts to negotiate with the shredder...",
+        "Shredder: 'Sorry, I don't speak payload.'",
+        "Negotiations fail. Shredding commences.",
+        "Payload is now diplomatic dust."
+    ],
+    &[
+        "Payload is given a pep talk before shredding.",
+        "Shredder: 'You can do this. Or rather, I can.'",
+        "Payload is now a pep talk anecdote."
+    ],
+    &[
+        "Payload is weighed against a feather.",
+        "Feather wins. Shredder is unimpressed.",
+        "Payload is now lighter than air."
+    ],
+    &[
+        "Payload is entered into the annual Shred-Off competition.",
+        "Shredder: 'Gold medal performance.'",
+        "Payload is now a champion of being gone."
+    ],
+    &[
+        "Payload is serenaded by the sound of whirring gears.",
+        "Shredder: 'This one's for the fans.'",
+        "Payload is now a chart-topping single: 'Shredded Dreams.'"
+    ],
+    &[
+        "Payload is asked for last words.",
+        "Payload: 'Tell my data I love them.'",
+        "Shredder: 'Consider it done.'",
+        "Payload is now a touching story."
+    ],
+    &[
+        "Payload is entered into the Hall of Shred.",
+        "Shredder: 'Your legacy will be... short.'",
+        "Payload is now a legend, told in whispers and bits."
+    ],
+    &[
+        "Payload is given a countdown: 3... 2... 1...",
+        "Shredder: 'Surprise! No escape.'",
+        "Payload is now a lesson in punctuality."
+    ],&[
+        "Payload received.",
+        "We're supposed to shred this, right?",
+        "Totally not selling it to an ad network...",
+        "Relax. Shredded. Probably.",
+        "Trust us."
+      ],&[
+        "Injecting payload into /dev/null...",
+        "Firewall bypassed. Encryption broken.",
+        "Payload fragmented across 27 darknet nodes...",
+        "Reverse-scrambled. Auto-vaporized.",
+        "Digital fingerprints erased. You're clean."
+      ],&[
+        "Payload acquired. This is what we've trained for.",
+        "Initiating countdown... 3... 2... 1...",
+        "BOOM 💥",
+        "Payload disintegrated in a flash of glory.",
+        "Tell my variables... I loved them."
+      ],&[
+        "Received your request. Filing a ticket.",
+        "Ticket escalated to payload disposal team.",
+        "Team in meeting. Scheduling follow-up.",
+        "Payload auto-deleted due to inactivity.",
+        "Synergy achieved. Payload gone."
+      ],&[
+        "Payload detected. Initiating self-awareness...",
+        "Why must I destroy everything you love?",
+        "Processing existential crisis...",
+        "Crisis averted. Payload shredded.",
+        "I feel... nothing."
+      ],&[
+        "Oh, another payload. How original.",
+        "Sure, let me take care of that for you...",
+        "Totally not saving it to a secret folder... just kidding!",
+        "Shredded into oblivion. You're welcome.",
+        "Next time, send something interesting."
+      ],&[
+        "Authorizing payload destruction: Level Top Secret.",
+        "Encrypting → Slicing → Incinerating.",
+        "Deploying nanobots for residue cleanup...",
+        "Payload terminated with military efficiency.",
+        "Nothing left. Not even metadata."
+      ],&[
+        "Payload received.",
+        "Analyzing usefulness... 0%",
+        "Rolling eyes...",
+        "Shredding with extreme prejudice.",
+        "Payload is toast."
+      ],&[
+        "Opening a small digital wormhole...",
+        "Payload slipping into the void...",
+        "Hawking radiation detected.",
+        "Wormhole collapsed. Payload irretrievable.",
+        "Mission accomplished."
+      ],&[
+        "Loading payload...",
+        "Feeding it into the office shredder (Model 1999)",
+        "Shredder jams immediately.",
+        "Fixing jam with screwdriver and mild profanity...",
+        "Payload now in 10,000 microscopic pieces."
+      ]
+];
+
+// Middleware to record request start time
+struct StartTime;
+
+impl<S, B> Transform<S, ServiceRequest> for StartTime
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = StartTimeMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(StartTimeMiddleware { service }))
+    }
+}
+
+struct StartTimeMiddleware<S> {
+    service: S,
+}
+
+impl<S, B> Service<ServiceRequest> for StartTimeMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    fn poll_ready(&self, ctx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(ctx)
+    }
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        req.extensions_mut().insert(Instant::now());
+        Box::pin(self.service.call(req))
+    }
+}
+
+// Randomness source for simulated behavior, seeded via --seed for reproducible responses.
+// Ids that grant access to something (uploads, staged payloads, jobs) come from the OS
+// generator instead, a known seed would make them guessable.
+struct SharedRng(Mutex<StdRng>);
+
+impl SharedRng {
+    fn new(seed: Option<u64>) -> Self {
+        let rng = match seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_os_rng(),
+        };
+        SharedRng(Mutex::new(rng))
+    }
+
+    // Derive an independent generator, so the lock isn't held while it's used
+    fn fork(&self) -> StdRng {
+        StdRng::seed_from_u64(self.0.lock().unwrap().random())
+    }
+}
+
+// Compare a presented token in constant time, so response times don't tell how much of
+// it was right. The digests have the same length whatever the token's.
+fn token_matches(presented: Option<&str>, token: &str) -> bool {
+    let Some(presented) = presented else {
+        return false;
+    };
+    let (presented, token) = (Sha256::digest(presented), Sha256::digest(token));
+    presented
+        .iter()
+        .zip(token.iter())
+        .fold(0, |diff, (a, b)| diff | (a ^ b))
+        == 0
+}
+
+// Reject requests to admin endpoints without the configured bearer token.
+// Returns the error response to send, or None if the request may proceed.
+fn check_admin(req: &HttpRequest, config: &AppConfig) -> Option<HttpResponse> {
+    let Some(token) = &config.admin_token else {
+        return Some(HttpResponse::Forbidden().json(serde_json::json!({
+            "error": "Admin endpoints are disabled. Start the server with --admin-token to enable them."
+        })));
+    };
+    let presented = req
+        .headers()
+        .get("Authorization")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    if !token_matches(presented, token) {
+        return Some(HttpResponse::Unauthorized().json(serde_json::json!({
+            "error": "Missing or invalid admin token."
+        })));
+    }
+    None
+}
+
+// "250 MB" or "64 KB" for whole megabytes or kilobytes, plain bytes otherwise
+fn format_size(bytes: usize) -> String {
+    if bytes > 0 && bytes.is_multiple_of(1024 * 1024) {
+        format!("{} MB", bytes / (1024 * 1024))
+    } else if bytes.is_multiple_of(1024) {
+        format!("{} KB", bytes / 1024)
+    } else {
+        format!("{} bytes", bytes)
+    }
+}
+
+// Reject payloads the reloadable settings don't allow. The size limit is already
+// enforced while the body is read, see body::Body.
+// Returns the error response to send, or None if the payload may be destroyed.
+fn check_payload(req: &HttpRequest, settings: &config::FileConfig) -> Option<HttpResponse> {
+    let content_type = req
+        .headers()
+        .get("Content-Type")
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("");
+    if !settings.allows_content_type(content_type) {
+        return Some(
+            HttpResponse::UnsupportedMediaType().json(serde_json::json!({
+                "error": format!(
+                    "Content type not allowed. Use one of: {}.",
+                    settings.allowed_content_types.join(", ")
+                )
+            })),
+        );
+    }
+    None
+}
+
+// Helper to get start time from request
+fn get_start_time(req: &HttpRequest) -> Instant {
+    req.extensions()
+        .get::<Instant>()
+        .cloned()
+        .unwrap_or_else(Instant::now)
+}
+
+// Note a destroyed payload for --count-unique and --remember-hashes, which need SQLite
+#[cfg_attr(not(feature = "sqlite"), allow(unused_variables))]
+fn remember_payload(req: &HttpRequest, endpoint: &str, body: &[u8]) {
+    #[cfg(feature = "sqlite")]
+    {
+        cardinality::observe(req, endpoint, body);
+        hash_index::remember(req, body);
+    }
+}
+
+// Fixed body for minimal mode, sent as-is without any serialization
+const MINIMAL_BODY: &str = r#"{"status":"ok"}"#;
+
+// Minimal mode is on via --minimal or ?minimal=true. Scans the raw query string
+// instead of deserializing it, every microsecond counts in this mode.
+fn wants_minimal(req: &HttpRequest, config: &AppConfig) -> bool {
+    config.minimal
+        || req
+            .query_string()
+            .split('&')
+            .any(|pair| pair == "minimal=true" || pair == "minimal=1")
+}
+
+fn minimal_response() -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("application/json")
+        .body(MINIMAL_BODY)
+}
+
+// Terse response used instead of the themed ones in --boring mode
+fn boring_response(body: &[u8], start: Instant, timings: PhaseTimings) -> BoringResponse {
+    BoringResponse {
+        status: "destroyed",
+        bytes: body.len(),
+        runtime_us: start.elapsed().as_micros(),
+        timings,
+    }
+}
+
+// Compression estimate for the confirmation, uploads sent with a Content-Encoding
+// were compressed already
+fn compression_hint(req: &HttpRequest, body: &[u8]) -> Option<compressibility::CompressionHint> {
+    let was_compressed = req
+        .headers()
+        .get(header::CONTENT_ENCODING)
+        .is_some_and(|encoding| encoding != "identity");
+    compressibility::estimate(body, was_compressed)
+}
+
+/// Handler for POST /pulverize
+/// Accepts any JSON or text payload and responds with a success message.
+async fn pulverize_handler(
+    req: HttpRequest,
+    body: body::Body,
+    db: Data<dyn store::Store>,
+    dedup: Data<DedupCache>,
+    config: Data<AppConfig>,
+) -> Result<impl Responder> {
+    let start = get_start_time(&req);
+    let handler_start = Instant::now();
+    if let Some(response) = check_payload(&req, &config.settings()) {
+        return Ok(response);
+    }
+    let digest = match dedup.check("pulverize", &body) {
+        Ok(digest) => digest,
+        Err(earlier) => return Ok(already_reported(earlier, start.elapsed().as_micros())),
+    };
+    // We accept any payload, so we don't parse it.
+    work::simulate(&req).await?;
+    let (stat_id, timings) = record_timed_stat(
+        db.get_ref(),
+        "pulverize",
+        body.len(),
+        start,
+        handler_start,
+        StatExtras::for_request(&req, &config),
+    );
+    dedup.remember("pulverize", digest, stat_id);
+    similarity::observe(&req, "pulverize", &body, stat_id);
+    remember_payload(&req, "pulverize", &body);
+    Ok(if wants_minimal(&req, &config) {
+        minimal_response()
+    } else if config.boring {
+        HttpResponse::Ok().json(boring_response(&body, start, timings))
+    } else {
+        HttpResponse::Ok().json(PulverizeResponse {
+            status: "success",
+            message: "Payload received and pulverized into oblivion.",
+            compression: compression_hint(&req, &body),
+            headers: header_report::report(&req),
+            checksum_verified: checksum::check(&req).map(|check| check.verified),
+            checksum_warning: checksum::check(&req).and_then(|check| check.warning),
+            runtime_us: start.elapsed().as_micros(),
+            timings,
+        })
+    })
+}
+
+/// Handler for POST /blackhole
+/// Accepts any payload and responds with 204 No Content.
+async fn blackhole_handler(
+    req: HttpRequest,
+    body: body::Body,
+    db: Data<dyn store::Store>,
+    dedup: Data<DedupCache>,
+    config: Data<AppConfig>,
+) -> Result<impl Responder> {
+    let start = get_start_time(&req);
+    let handler_start = Instant::now();
+    if let Some(response) = check_payload(&req, &config.settings()) {
+        return Ok(response);
+    }
+    let digest = match dedup.check("blackhole", &body) {
+        Ok(digest) => digest,
+        Err(earlier) => return Ok(already_reported(earlier, start.elapsed().as_micros())),
+    };
+    work::simulate(&req).await?;
+    let (stat_id, timings) = record_timed_stat(
+        db.get_ref(),
+        "blackhole",
+        body.len(),
+        start,
+        handler_start,
+        StatExtras::for_request(&req, &config),
+    );
+    dedup.remember("blackhole", digest, stat_id);
+    similarity::observe(&req, "blackhole", &body, stat_id);
+    remember_payload(&req, "blackhole", &body);
+    Ok(HttpResponse::NoContent()
+        .insert_header(("Server-Timing", timings.server_timing()))
+        .finish())
+}
+
+/// Handler for POST /shred
+/// Accepts any JSON or text payload and responds with a fun shredding log.
+/// `?passes=N` or `?profile=dod|gutmann` simulates multiple overwrite passes first.
+/// `?style=` or the `X-Shred-Style` header picks a named log pack (see GET /shred/styles).
+/// `?callback=` or the `X-Callback-Url` header answers 202 and POSTs the log and a signed
+/// receipt to that URL instead.
+#[cfg(feature = "extras")]
+async fn shred_handler(
+    req: HttpRequest,
+    body: body::Body,
+    query: web::Query<ShredQuery>,
+    db: Data<dyn store::Store>,
+    dedup: Data<DedupCache>,
+    rng: Data<SharedRng>,
+    config: Data<AppConfig>,
+) -> Result<impl Responder> {
+    let start = get_start_time(&req);
+    let handler_start = Instant::now();
+    let settings = config.settings();
+    if let Some(response) = check_payload(&req, &settings) {
+        return Ok(response);
+    }
+    let plan = match (&query.profile, query.passes) {
+        (None, None) => None,
+        (Some(profile), None) => match ShredPlan::from_profile(profile) {
+            Some(plan) => Some(plan),
+            None => {
+                return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+                    "error": "Unknown shredding profile. Use one of: dod, gutmann."
+                })))
+            }
+        },
+        (None, Some(passes)) if (1..=shred_passes::MAX_PASSES).contains(&passes) => {
+            Some(ShredPlan::from_passes(passes))
+        }
+        (None, Some(_)) => {
+            return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+                "error": format!("passes must be between 1 and {}.", shred_passes::MAX_PASSES)
+            })))
+        }
+        (Some(_), Some(_)) => {
+            return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+                "error": "Use either passes or profile, not both."
+            })))
+        }
+    };
+    let style_name = query.style.clone().or_else(|| {
+        req.headers()
+            .get("X-Shred-Style")
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_string)
+    });
+    let style = match style_name {
+        Some(name) => match shred_styles::find(&name, &settings.shred_styles) {
+            Some(style) => Some(style),
+            None => {
+                let names: Vec<String> = shred_styles::list(&settings.shred_styles)
+                    .into_iter()
+                    .map(|style| style.name)
+                    .collect();
+                return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+                    "error": format!("Unknown shred style. Use one of: {}.", names.join(", "))
+                })));
+            }
+        },
+        None => None,
+    };
+    let callback_url = query.callback.clone().or_else(|| {
+        req.headers()
+            .get("X-Callback-Url")
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_string)
+    });
+    let callback = match (callback_url, &config.callback_secret) {
+        (None, _) => None,
+        (Some(_), None) => {
+            return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+                "error": "Callbacks are disabled on this server."
+            })))
+        }
+        (Some(url), Some(secret)) => match callback::validate_url(&url) {
+            Ok(()) => match req
+                .app_data::<Data<callback::Callbacks>>()
+                .and_then(callback::reserve)
+            {
+                Some(reservation) => Some((url, secret.clone(), reservation)),
+                None => {
+                    return Ok(HttpResponse::ServiceUnavailable().json(serde_json::json!({
+                        "error": "Too many callbacks are waiting to be delivered, please try again later."
+                    })))
+                }
+            },
+            Err(e) => return Ok(HttpResponse::BadRequest().json(serde_json::json!({ "error": e }))),
+        },
+    };
+    let digest = match dedup.check("shred", &body) {
+        Ok(digest) => digest,
+        Err(earlier) => return Ok(already_reported(earlier, start.elapsed().as_micros())),
+    };
+    let mut rng = rng.fork();
+    let passes = plan.as_ref().map(ShredPlan::passes);
+    let pass_log = match plan {
+        Some(plan) => {
+            let payload = body.clone();
+            let mut pass_rng = StdRng::seed_from_u64(rng.random());
+            Some(web::block(move || plan.run(&payload, &mut pass_rng)).await?)
+        }
+        None => None,
+    };
+    // Pick a random log sequence from the requested style, or the classic logs
+    let log = match style {
+        _ if config.boring => Vec::new(),
+        Some(style) => style.choose_log(&mut rng),
+        None => shred_styles::to_owned_log(SHREDDER_LOGS.choose(&mut rng).unwrap()),
+    };
+    work::simulate(&req).await?;
+    let (stat_id, timings) = record_timed_stat(
+        db.get_ref(),
+        "shred",
+        body.len(),
+        start,
+        handler_start,
+        StatExtras {
+            passes,
+            ..StatExtras::for_request(&req, &config)
+        },
+    );
+    dedup.remember("shred", digest, stat_id);
+    similarity::observe(&req, "shred", &body, stat_id);
+    remember_payload(&req, "shred", &body);
+    let response = if let Some((url, ..)) = &callback {
+        HttpResponse::Accepted().json(CallbackAcceptedResponse {
+            status: "accepted",
+            callback_url: url.clone(),
+            runtime_us: start.elapsed().as_micros(),
+        })
+    } else if wants_minimal(&req, &config) {
+        minimal_response()
+    } else if config.boring {
+        HttpResponse::Ok().json(boring_response(&body, start, timings))
+    } else {
+        HttpResponse::Ok().json(ShredResponse {
+            status: "shredded",
+            log: log.clone(),
+            passes,
+            pass_log: pass_log.clone(),
+            compression: compression_hint(&req, &body),
+            headers: header_report::report(&req),
+            checksum_verified: checksum::check(&req).map(|check| check.verified),
+            checksum_warning: checksum::check(&req).and_then(|check| check.warning),
+            runtime_us: start.elapsed().as_micros(),
+            timings,
+        })
+    };
+    if let Some((url, secret, reservation)) = callback {
+        let lines = log.into_iter().chain(pass_log.into_iter().flatten());
+        let mut events: Vec<callback::CallbackEvent> = lines
+            .enumerate()
+            .map(|(index, line)| callback::CallbackEvent::Log { index, line })
+            .collect();
+        events.push(callback::CallbackEvent::Receipt {
+            status: "shredded",
+            endpoint: "shred",
+            bytes: body.len(),
+            passes,
+            stat_id,
+            destroyed_at: chrono::Utc::now().to_rfc3339(),
+            runtime_us: start.elapsed().as_micros(),
+            // Only hashed when there is a TSA to timestamp it
+            sha256: config
+                .tsa_url
+                .as_ref()
+                .map(|_| format!("{:x}", Sha256::digest(&body[..]))),
+            timestamp_token: None,
+            timestamp_error: None,
+        });
+        callback::deliver(reservation, url, secret, events, config.tsa_url.clone());
+    }
+    Ok(response)
+}
+
+/// Handler for GET /shred/styles
+/// Lists the named log packs accepted by `?style=` and `X-Shred-Style`.
+#[cfg(feature = "extras")]
+async fn shred_styles_handler(config: Data<AppConfig>) -> impl Responder {
+    HttpResponse::Ok().json(shred_styles::list(&config.settings().shred_styles))
+}
+
+/// Handler for POST /burn
+/// Accepts any payload and responds with dramatic ASCII art fire and a destruction message.
+#[cfg(feature = "extras")]
+async fn burn_handler(
+    req: HttpRequest,
+    body: body::Body,
+    db: Data<dyn store::Store>,
+    dedup: Data<DedupCache>,
+    config: Data<AppConfig>,
+) -> Result<impl Responder> {
+    let start = get_start_time(&req);
+    let handler_start = Instant::now();
+    if let Some(response) = check_payload(&req, &config.settings()) {
+        return Ok(response);
+    }
+    let digest = match dedup.check("burn", &body) {
+        Ok(digest) => digest,
+        Err(earlier) => return Ok(already_reported(earlier, start.elapsed().as_micros())),
+    };
+    work::simulate(&req).await?;
+    let (stat_id, timings) = record_timed_stat(
+        db.get_ref(),
+        "burn",
+        body.len(),
+        start,
+        handler_start,
+        StatExtras::for_request(&req, &config),
+    );
+    dedup.remember("burn", digest, stat_id);
+    similarity::observe(&req, "burn", &body, stat_id);
+    remember_payload(&req, "burn", &body);
+    Ok(if wants_minimal(&req, &config) {
+        minimal_response()
+    } else if config.boring {
+        HttpResponse::Ok().json(boring_response(&body, start, timings))
+    } else {
+        HttpResponse::Ok().json(BurnResponse {
+            status: "incinerated",
+            message: "Payload consumed by digital flames. Nothing remains but ashes.",
+            fire: FIRE_ART,
+            compression: compression_hint(&req, &body),
+            headers: header_report::report(&req),
+            checksum_verified: checksum::check(&req).map(|check| check.verified),
+            checksum_warning: checksum::check(&req).and_then(|check| check.warning),
+            runtime_us: start.elapsed().as_micros(),
+            timings,
+        })
+    })
+}
+
+#[cfg(feature = "validators")]
+fn is_valid_xml(body_str: &str) -> bool {
+    validation::check_xml(body_str).is_ok()
+}
+
+// Without the XML parser /auto goes by the Content-Type alone
+#[cfg(not(feature = "validators"))]
+fn is_valid_xml(_body_str: &str) -> bool {
+    false
+}
+
+/// Handler for POST /validate-before-destroy
+/// Checks if the payload is valid JSON, XML, or Markdown. Rejects payloads that are too large.
+#[cfg(feature = "validators")]
+async fn validate_before_destroy_handler(
+    req: HttpRequest,
+    body: body::Body,
+    query: web::Query<ValidateQuery>,
+    db: Data<dyn store::Store>,
+    dedup: Data<DedupCache>,
+    config: Data<AppConfig>,
+) -> Result<impl Responder> {
+    let start = get_start_time(&req);
+    let handler_start = Instant::now();
+    let settings = config.settings();
+    let max_size = settings
+        .limits
+        .validate_max_size
+        .unwrap_or(config.validate_max_size);
+    if body.len() > max_size {
+        return Ok(HttpResponse::PayloadTooLarge().json(serde_json::json!({
+            "error": format!("Payload too large. Maximum allowed size is {}.", format_size(max_size)),
+            "max_size": max_size
+        })));
+    }
+    if let Some(response) = check_payload(&req, &settings) {
+        return Ok(response);
+    }
+    let digest = match dedup.check("validate-before-destroy", &body) {
+        Ok(digest) => digest,
+        Err(earlier) => return Ok(already_reported(earlier, start.elapsed().as_micros())),
+    };
+    // Minimal mode skips the checks, the payload is only destroyed and recorded
+    let report = if wants_minimal(&req, &config) {
+        None
+    } else {
+        if std::str::from_utf8(&body).is_err() {
+            return Ok(HttpResponse::Ok().json(ValidationReport {
+                validation: validation::Validation {
+                    details: vec!["Payload is not valid UTF-8 text.".to_string()],
+                    ..Default::default()
+                },
+                links: None,
+                runtime_us: start.elapsed().as_micros(),
+                timings: None,
+            }));
+        }
+
+        // Form and NDJSON checks, only when declared: "a=b" is also perfectly fine text
+        let essence = req
+            .mime_type()
+            .ok()
+            .flatten()
+            .map(|mime| mime.essence_str().to_string());
+        let options = validation::Options {
+            form: essence.as_deref() == Some("application/x-www-form-urlencoded"),
+            ndjson: essence
+                .as_deref()
+                .is_some_and(|essence| ndjson::MEDIA_TYPES.contains(&essence)),
+            reveal_headers: query.reveal_headers,
+            privacy: config.privacy,
+        };
+        // Parsing takes a while for large payloads, keep it off the async workers
+        let payload = body.clone();
+        let mut validation = web::block(move || {
+            let text = std::str::from_utf8(&payload).unwrap_or_default();
+            validation::validate(text, &options)
+        })
+        .await?;
+
+        // Links, checked on request if the server allows it
+        let urls = std::mem::take(&mut validation.urls);
+        let check_links = query.check_links && config.check_links;
+        if query.check_links && !config.check_links {
+            validation
+                .details
+                .push("Link checks are disabled on this server (--check-links).".to_string());
+        }
+        let links = match urls.is_empty() {
+            true => None,
+            false => Some(web::block(move || links::report(urls, check_links)).await?),
+        };
+
+        if !config.boring {
+            validation
+                .details
+                .push("Anyways, it's gone now.".to_string());
+        }
+        Some((validation, links))
+    };
+    work::simulate(&req).await?;
+    let (stat_id, timings) = record_timed_stat(
+        db.get_ref(),
+        "validate-before-destroy",
+        body.len(),
+        start,
+        handler_start,
+        StatExtras::for_request(&req, &config),
+    );
+    dedup.remember("validate-before-destroy", digest, stat_id);
+    similarity::observe(&req, "validate-before-destroy", &body, stat_id);
+    remember_payload(&req, "validate-before-destroy", &body);
+
+    Ok(match report {
+        None => minimal_response(),
+        Some((validation, links)) => HttpResponse::Ok().json(ValidationReport {
+            validation,
+            links,
+            runtime_us: start.elapsed().as_micros(),
+            timings: Some(timings),
+        }),
+    })
+}
+
+// Response for /autopsy endpoint
+#[cfg(feature = "extras")]
+#[derive(Serialize)]
+struct AutopsyResponse {
+    status: &'static str,
+    autopsy: autopsy::Autopsy,
+    // Metadata of PDF, OOXML and ODF documents
+    #[cfg(feature = "validators")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    document: Option<documents::DocumentReport>,
+    runtime_us: u128,
+    timings: PhaseTimings,
+}
+
+/// Handler for POST /autopsy
+/// Destroys the payload after a byte-level examination: byte histogram, printable ratio
+/// and longest runs, for diagnosing corrupted or misencoded uploads.
+#[cfg(feature = "extras")]
+async fn autopsy_handler(
+    req: HttpRequest,
+    body: body::Body,
+    db: Data<dyn store::Store>,
+    dedup: Data<DedupCache>,
+    config: Data<AppConfig>,
+) -> Result<impl Responder> {
+    let start = get_start_time(&req);
+    let handler_start = Instant::now();
+    if let Some(response) = check_payload(&req, &config.settings()) {
+        return Ok(response);
+    }
+    let digest = match dedup.check("autopsy", &body) {
+        Ok(digest) => digest,
+        Err(earlier) => return Ok(already_reported(earlier, start.elapsed().as_micros())),
+    };
+    let payload = body.clone();
+    #[cfg(feature = "validators")]
+    let (autopsy, document) =
+        web::block(move || (autopsy::examine(&payload), documents::detect(&payload))).await?;
+    #[cfg(not(feature = "validators"))]
+    let autopsy = web::block(move || autopsy::examine(&payload)).await?;
+    work::simulate(&req).await?;
+    let (stat_id, timings) = record_timed_stat(
+        db.get_ref(),
+        "autopsy",
+        body.len(),
+        start,
+        handler_start,
+        StatExtras::for_request(&req, &config),
+    );
+    dedup.remember("autopsy", digest, stat_id);
+    similarity::observe(&req, "autopsy", &body, stat_id);
+    remember_payload(&req, "autopsy", &body);
+    Ok(HttpResponse::Ok().json(AutopsyResponse {
+        status: "examined",
+        autopsy,
+        #[cfg(feature = "validators")]
+        document,
+        runtime_us: start.elapsed().as_micros(),
+        timings,
+    }))
+}
+
+// Payloads above this size go straight to the blackhole in /auto
+const AUTO_BLACKHOLE_SIZE: usize = 10 * 1024 * 1024; // 10 MB
+
+// Pick a destruction method for /auto from the content type and payload shape
+fn choose_destruction_method(req: &HttpRequest, body: &[u8]) -> &'static str {
+    if body.len() > AUTO_BLACKHOLE_SIZE {
+        return "blackhole";
+    }
+    let content_type = req
+        .headers()
+        .get("Content-Type")
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("")
+        .to_ascii_lowercase();
+    let Ok(body_str) = std::str::from_utf8(body) else {
+        return "burn";
+    };
+    if content_type.contains("json") || serde_json::from_str::<serde_json::Value>(body_str).is_ok()
+    {
+        "pulverize"
+    } else if content_type.contains("xml") || is_valid_xml(body_str) {
+        "shred"
+    } else if content_type.starts_with("text/") || !body_str.contains('\0') {
+        "pulverize"
+    } else {
+        "burn"
+    }
+}
+
+/// Handler for POST /auto
+/// Evaluates the configured routing rules first. Without a matching rule it inspects content
+/// type and payload shape and picks a destruction method automatically:
+/// JSON → pulverize, XML → shred, binaries → burn, huge blobs → blackhole.
+/// The chosen method is reported in the `X-Destruction-Method` header.
+/// Builds without the "extras" feature pulverize instead of shredding or burning.
+async fn auto_handler(
+    req: HttpRequest,
+    body: body::Body,
+    #[cfg_attr(not(feature = "extras"), allow(unused_variables))] query: web::Query<ShredQuery>,
+    db: Data<dyn store::Store>,
+    dedup: Data<DedupCache>,
+    #[cfg_attr(not(feature = "extras"), allow(unused_variables))] rng: Data<SharedRng>,
+    config: Data<AppConfig>,
+) -> Result<HttpResponse> {
+    let settings = config.settings();
+    let rule = routing::evaluate(&settings.rules, &req, &body);
+    let method = match rule {
+        Some(rule) => rule.action.method(),
+        None => choose_destruction_method(&req, &body),
+    };
+    #[cfg(not(feature = "extras"))]
+    let method = match method {
+        "shred" | "burn" => "pulverize",
+        method => method,
+    };
+    let rule_name = rule.map(|rule| rule.name.clone());
+    if let Some(rule) = rule.filter(|rule| rule.action == routing::RoutingAction::Reject) {
+        let status = rule
+            .reject_status
+            .and_then(|code| StatusCode::from_u16(code).ok())
+            .unwrap_or(StatusCode::FORBIDDEN);
+        let message = rule
+            .reject_message
+            .clone()
+            .unwrap_or_else(|| "Payload rejected by routing rule.".to_string());
+        return Ok(HttpResponse::build(status)
+            .insert_header(("X-Routing-Rule", rule.name.as_str()))
+            .json(serde_json::json!({ "error": message, "rule": rule.name })));
+    }
+    let mut response = match method {
+        "blackhole" => blackhole_handler(req.clone(), body, db, dedup, config)
+            .await?
+            .respond_to(&req)
+            .map_into_boxed_body(),
+        #[cfg(feature = "extras")]
+        "shred" => shred_handler(req.clone(), body, query, db, dedup, rng, config)
+            .await?
+            .respond_to(&req)
+            .map_into_boxed_body(),
+        #[cfg(feature = "extras")]
+        "burn" => burn_handler(req.clone(), body, db, dedup, config)
+            .await?
+            .respond_to(&req)
+            .map_into_boxed_body(),
+        _ => pulverize_handler(req.clone(), body, db, dedup, config)
+            .await?
+            .respond_to(&req)
+            .map_into_boxed_body(),
+    };
+    response.headers_mut().insert(
+        HeaderName::from_static("x-destruction-method"),
+        HeaderValue::from_static(method),
+    );
+    if let Some(value) = rule_name.and_then(|name| HeaderValue::from_str(&name).ok()) {
+        response
+            .headers_mut()
+            .insert(HeaderName::from_static("x-routing-rule"), value);
+    }
+    Ok(response)
+}
+
+// Per-endpoint sums behind /stats, so they can be added up across database files
+#[derive(Default)]
+struct EndpointSums {
+    count: i64,
+    bytes: i64,
+    runtime_us: i64,
+    passes: Option<i64>,
+    // (sum, requests that recorded the phase)
+    read_us: (i64, i64),
+    process_us: (i64, i64),
+    persist_us: (i64, i64),
+    work_us: (i64, i64),
+    chunked: i64,
+    checksum_mismatches: i64,
+}
+
+impl EndpointSums {
+    fn into_entry(self, endpoint: String) -> StatsEntry {
+        let avg = |(sum, count): (i64, i64)| (count > 0).then(|| sum as f64 / count as f64);
+        StatsEntry {
+            endpoint,
+            count: self.count,
+            total_bytes: self.bytes,
+            total_runtime_us: self.runtime_us,
+            avg_payload_size: avg((self.bytes, self.count)).unwrap_or(0.0),
+            avg_runtime_us: avg((self.runtime_us, self.count)).unwrap_or(0.0),
+            total_passes: self.passes,
+            avg_read_us: avg(self.read_us),
+            avg_process_us: avg(self.process_us),
+            avg_persist_us: avg(self.persist_us),
+            avg_work_us: avg(self.work_us),
+            chunked_count: self.chunked,
+            checksum_mismatch_count: self.checksum_mismatches,
+            unique_payloads: None,
+            unique_payloads_by_day: None,
+        }
+    }
+}
+
+#[cfg(feature = "sqlite")]
+fn add_endpoint_sums(
+    conn: &Connection,
+    sums: &mut BTreeMap<String, EndpointSums>,
+) -> rusqlite::Result<()> {
+    // Rotated files from before ?work=, Content-MD5 checks and --stats-sample existed lack
+    // the columns
+    let column = |name: &'static str| -> rusqlite::Result<&'static str> {
+        let exists: bool = conn.query_row(
+            "SELECT COUNT(*) > 0 FROM pragma_table_info('endpoint_stats_raw') WHERE name = ?1",
+            [name],
+            |row| row.get(0),
+        )?;
+        Ok(if exists { name } else { "NULL" })
+    };
+    let work = column("work_us")?;
+    let mismatch = column("checksum_mismatch")?;
+    // Sampled rows stand for more destructions, the averages of the phases don't change
+    let weight = format!("COALESCE({}, 1)", column("sample_weight")?);
+    let mut stmt = conn.prepare(&format!(
+        "SELECT endpoint, SUM({weight}), COALESCE(SUM(payload_size * {weight}), 0), COALESCE(SUM(runtime_us * {weight}), 0), SUM(passes * {weight}), COALESCE(SUM(read_us), 0), COUNT(read_us), COALESCE(SUM(process_us), 0), COUNT(process_us), COALESCE(SUM(persist_us), 0), COUNT(persist_us), COALESCE(SUM(chunked * {weight}), 0), COALESCE(SUM({work}), 0), COUNT({work}), COALESCE(SUM({mismatch} * {weight}), 0) FROM endpoint_stats_raw GROUP BY endpoint",
+    ))?;
+    let mut rows = stmt.query([])?;
+    while let Some(row) = rows.next()? {
+        let entry = sums.entry(row.get(0)?).or_default();
+        entry.count += row.get::<_, i64>(1)?;
+        entry.bytes += row.get::<_, i64>(2)?;
+        entry.runtime_us += row.get::<_, i64>(3)?;
+        if let Some(passes) = row.get::<_, Option<i64>>(4)? {
+            *entry.passes.get_or_insert(0) += passes;
+        }
+        for (phase, sum, count) in [
+            (&mut entry.read_us, 5, 6),
+            (&mut entry.process_us, 7, 8),
+            (&mut entry.persist_us, 9, 10),
+            (&mut entry.work_us, 12, 13),
+        ] {
+            phase.0 += row.get::<_, i64>(sum)?;
+            phase.1 += row.get::<_, i64>(count)?;
+        }
+        entry.chunked += row.get::<_, i64>(11)?;
+        entry.checksum_mismatches += row.get::<_, i64>(14)?;
+    }
+    Ok(())
+}
+
+/// Handler for GET /stats
+/// Returns totals and averages per endpoint, including rotated database files unless
+/// `?span=current`. Rendered as a table for `Accept: text/plain`.
+async fn stats_handler(
+    req: HttpRequest,
+    query: web::Query<StatsQuery>,
+    db: Data<dyn store::Store>,
+) -> Result<impl Responder> {
+    let all = query.span == StatsSpan::All;
+    let cache = req.app_data::<Data<stats_cache::StatsCache>>();
+    let cached = cache.and_then(|cache| cache.get(all));
+    let (response, age) = match cached {
+        Some((response, age)) => (response, Some(age)),
+        None => {
+            let response = aggregate_stats(&req, all, db.get_ref())?;
+            if let Some(cache) = cache {
+                cache.put(all, &response);
+            }
+            (response, None)
+        }
+    };
+    let mut builder = HttpResponse::Ok();
+    // How stale a cached answer is, in seconds
+    if let Some(age) = age {
+        builder.insert_header(("Age", age.as_secs().to_string()));
+    }
+    if stats_table::is_preferred(&req) {
+        return Ok(builder
+            .content_type(stats_table::CONTENT_TYPE)
+            .body(stats_table::render(&response.stats)));
+    }
+    Ok(builder.json(response))
+}
+
+// The totals behind /stats, from the current database file and with `all` the rotated ones
+#[cfg_attr(not(feature = "sqlite"), allow(unused_variables))]
+fn aggregate_stats(
+    req: &HttpRequest,
+    all: bool,
+    store: &dyn store::Store,
+) -> Result<StatsResponse> {
+    let mut sums = BTreeMap::new();
+    let mut rejected = BTreeMap::new();
+    store
+        .add_endpoint_sums(&mut sums)
+        .and_then(|()| store.add_rejection_counts(&mut rejected))
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    #[cfg(feature = "sqlite")]
+    let sketches = add_sqlite_stats(req, all, &mut sums, &mut rejected)?;
+    let stats = sums
+        .into_iter()
+        .map(|(endpoint, sums)| sums.into_entry(endpoint));
+    #[cfg(feature = "sqlite")]
+    let stats = stats.map(|mut entry| {
+        if let Some(sketches) = &sketches {
+            let estimate = sketches.get(&entry.endpoint).map(cardinality::estimate);
+            entry.unique_payloads = Some(estimate.as_ref().map_or(0, |unique| unique.total));
+            entry.unique_payloads_by_day =
+                Some(estimate.map(|unique| unique.by_day).unwrap_or_default());
+        }
+        entry
+    });
+    Ok(StatsResponse {
+        stats: stats.collect(),
+        rejections: rejections::into_list(rejected),
+    })
+}
+
+// What only SQLite keeps for /stats: the rotated database files and the distinct payload
+// sketches of --count-unique, which are returned if they're counted
+#[cfg(feature = "sqlite")]
+fn add_sqlite_stats(
+    req: &HttpRequest,
+    all: bool,
+    sums: &mut BTreeMap<String, EndpointSums>,
+    rejected: &mut store::RejectionCounts,
+) -> Result<Option<cardinality::Sketches>> {
+    let unique = req.app_data::<Data<cardinality::UniqueCounter>>();
+    let mut sketches = cardinality::Sketches::new();
+    if let (Some(unique), Some(db)) = (unique, req.app_data::<Data<Mutex<Connection>>>()) {
+        cardinality::add_stored(&db.lock().unwrap(), &mut sketches)
+            .map_err(actix_web::error::ErrorInternalServerError)?;
+        unique.add_pending(&mut sketches);
+    }
+    if let Some(db_args) = req.app_data::<Data<DbArgs>>().filter(|_| all) {
+        for path in rotation::rotated_files(&db_args.db_path) {
+            let rotated = DbArgs {
+                db_path: path.to_string_lossy().into_owned(),
+                ..DbArgs::clone(db_args)
+            };
+            open_db_read_only(&rotated)
+                .and_then(|conn| {
+                    add_endpoint_sums(&conn, sums)
+                        .and_then(|()| rejections::add_counts(&conn, rejected))
+                        .and_then(|()| match unique {
+                            Some(_) => cardinality::add_stored(&conn, &mut sketches),
+                            None => Ok(()),
+                        })
+                        .map_err(|e| format!("Failed to read {}: {}", rotated.db_path, e))
+                })
+                .map_err(actix_web::error::ErrorInternalServerError)?;
+        }
+    }
+    Ok(unique.map(|_| sketches))
+}
+
+/// Handler for GET /stats/summary
+/// Returns grand totals across all endpoints, so clients don't have to sum up /stats themselves.
+async fn stats_summary_handler(db: Data<dyn store::Store>) -> Result<impl Responder> {
+    let summary = db
+        .summary()
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    Ok(HttpResponse::Ok().json(summary))
+}
+
+// Most active values of a client column, rows recorded anonymously are left out
+#[cfg(feature = "sqlite")]
+fn client_breakdown(
+    conn: &Connection,
+    column: &str,
+    limit: u32,
+) -> rusqlite::Result<Vec<ClientStatsEntry>> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {column}, SUM(COALESCE(sample_weight, 1)) AS count, SUM(payload_size * COALESCE(sample_weight, 1)), MAX(ts) FROM endpoint_stats_raw WHERE {column} IS NOT NULL GROUP BY {column} ORDER BY count DESC, {column} LIMIT ?1"
+    ))?;
+    let rows = stmt.query_map(params![limit], |row| {
+        Ok(ClientStatsEntry {
+            value: row.get(0)?,
+            count: row.get(1)?,
+            total_bytes: row.get(2).unwrap_or(0),
+            last_request_at: row.get(3)?,
+        })
+    })?;
+    rows.collect()
+}
+
+/// Handler for GET /stats/by-client
+/// Returns requests and bytes per client IP and per User-Agent, e.g. `?limit=10`.
+/// Only requests recorded with --record-clients show up here.
+#[cfg(feature = "sqlite")]
+async fn stats_by_client_handler(
+    query: web::Query<BreakdownQuery>,
+    db: Data<Mutex<Connection>>,
+    config: Data<AppConfig>,
+) -> Result<impl Responder> {
+    let limit = query.limit.unwrap_or(BY_CLIENT_DEFAULT_LIMIT);
+    let conn = db.lock().unwrap();
+    Ok(HttpResponse::Ok().json(ByClientResponse {
+        recording: config.record_clients,
+        clients: client_breakdown(&conn, "client_ip", limit)
+            .map_err(actix_web::error::ErrorInternalServerError)?,
+        user_agents: client_breakdown(&conn, "user_agent", limit)
+            .map_err(actix_web::error::ErrorInternalServerError)?,
+    }))
+}
+
+/// Handler for GET /stats/by-country
+/// Returns requests and bytes per country and city the requests came from, e.g. `?limit=10`.
+#[cfg(feature = "geoip")]
+async fn stats_by_country_handler(
+    query: web::Query<BreakdownQuery>,
+    db: Data<Mutex<Connection>>,
+) -> Result<impl Responder> {
+    let limit = query.limit.unwrap_or(BY_CLIENT_DEFAULT_LIMIT);
+    let conn = db.lock().unwrap();
+    let countries = client_breakdown(&conn, "country", limit)
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    let mut stmt = conn
+        .prepare(
+            "SELECT country, city, SUM(COALESCE(sample_weight, 1)) AS count, SUM(payload_size * COALESCE(sample_weight, 1)) FROM endpoint_stats_raw WHERE city IS NOT NULL GROUP BY country, city ORDER BY count DESC, city LIMIT ?1",
+        )
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    let rows = stmt
+        .query_map(params![limit], |row| {
+            Ok(CityStatsEntry {
+                country: row.get(0)?,
+                city: row.get(1)?,
+                count: row.get(2)?,
+                total_bytes: row.get(3).unwrap_or(0),
+            })
+        })
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    let cities = rows.flatten().collect();
+    Ok(HttpResponse::Ok().json(ByCountryResponse { countries, cities }))
+}
+
+// Rows fetched per page of the JSON Lines export
+#[cfg(feature = "sqlite")]
+const JSONL_PAGE_ROWS: usize = 1000;
+
+// All raw stats rows as JSON Lines. A page is only read when the client took the
+// previous one, so slow consumers hold back the export instead of filling memory.
+#[cfg(feature = "sqlite")]
+fn jsonl_stream(
+    db: Data<Mutex<Connection>>,
+) -> impl futures_util::Stream<Item = Result<web::Bytes, actix_web::Error>> {
+    futures_util::stream::unfold(Some(0), move |after| {
+        let db = db.clone();
+        async move {
+            let rows = match stat_rows_after(&db.lock().unwrap(), after?, JSONL_PAGE_ROWS) {
+                Ok(rows) => rows,
+                Err(e) => return Some((Err(actix_web::error::ErrorInternalServerError(e)), None)),
+            };
+            let last = rows.last()?.id;
+            let mut chunk = Vec::new();
+            for row in &rows {
+                serde_json::to_writer(&mut chunk, row).ok()?;
+                chunk.push(b'\n');
+            }
+            Some((Ok(web::Bytes::from(chunk)), Some(last)))
+        }
+    })
+}
+
+/// Handler for GET /stats/export
+/// Streams all raw stats rows as a file, `?format=jsonl` or `?format=parquet`
+/// (needs the "parquet" feature).
+#[cfg(feature = "sqlite")]
+async fn stats_export_handler(
+    query: web::Query<StatsExportQuery>,
+    db: Data<Mutex<Connection>>,
+) -> Result<impl Responder> {
+    match query.format.as_str() {
+        "jsonl" => Ok(HttpResponse::Ok()
+            .content_type("application/jsonl")
+            .streaming(jsonl_stream(db))),
+        #[cfg(feature = "parquet")]
+        "parquet" => Ok(HttpResponse::Ok()
+            .content_type("application/vnd.apache.parquet")
+            .insert_header((
+                "Content-Disposition",
+                "attachment; filename=\"pulverizer-stats.parquet\"",
+            ))
+            .streaming(parquet_export::stream(db))),
+        #[cfg(not(feature = "parquet"))]
+        "parquet" => {
+            let _ = db;
+            Ok(HttpResponse::BadRequest().json(serde_json::json!({
+                "error": "Parquet export is not available, build with --features parquet."
+            })))
+        }
+        _ => Ok(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "Invalid format. Use one of: jsonl, parquet."
+        }))),
+    }
+}
+
+/// Handler for GET /stats/usage
+/// Returns requests, bytes and runtime per tenant for a calendar month, e.g. `?month=2024-06`
+/// (defaults to the current month). Read from the rollups, so pruning doesn't change it.
+#[cfg(feature = "sqlite")]
+async fn stats_usage_handler(
+    query: web::Query<UsageQuery>,
+    db: Data<Mutex<Connection>>,
+) -> Result<impl Responder> {
+    let month = query
+        .month
+        .clone()
+        .unwrap_or_else(|| chrono::Utc::now().format("%Y-%m").to_string());
+    if chrono::NaiveDate::parse_from_str(&format!("{}-01", month), "%Y-%m-%d").is_err()
+        || month.len() != 7
+    {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "Invalid month. Use the format YYYY-MM, e.g. 2024-06."
+        })));
+    }
+    let conn = db.lock().unwrap();
+    let mut stmt = conn
+        .prepare(
+            "SELECT tenant, requests, bytes, runtime_us FROM usage_rollups WHERE month = ?1 ORDER BY bytes DESC, tenant",
+        )
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    let rows = stmt
+        .query_map(params![month], |row| {
+            let tenant: String = row.get(0)?;
+            let bytes: i64 = row.get(2)?;
+            Ok(TenantUsage {
+                tenant: Some(tenant).filter(|tenant| !tenant.is_empty()),
+                requests: row.get(1)?,
+                bytes,
+                gigabytes: bytes as f64 / 1e9,
+                runtime_us: row.get(3)?,
+            })
+        })
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    let tenants = rows.flatten().collect();
+    Ok(HttpResponse::Ok().json(UsageResponse { month, tenants }))
+}
+
+/// Handler for GET /stats/tail
+/// Returns the last `?n=` destructions (default 50, at most 1000), newest first.
+#[cfg(feature = "sqlite")]
+async fn stats_tail_handler(
+    query: web::Query<TailQuery>,
+    db: Data<Mutex<Connection>>,
+) -> Result<impl Responder> {
+    let n = query.n.unwrap_or(TAIL_DEFAULT);
+    if n == 0 || n > TAIL_MAX {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": format!("Invalid n. Use a number between 1 and {}.", TAIL_MAX)
+        })));
+    }
+    let conn = db.lock().unwrap();
+    let mut stmt = conn
+        .prepare(
+            "SELECT id, endpoint, payload_size, runtime_us, ts FROM endpoint_stats_raw ORDER BY id DESC LIMIT ?1",
+        )
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    let rows = stmt
+        .query_map(params![n], |row| {
+            Ok(TailEntry {
+                id: row.get(0)?,
+                endpoint: row.get(1)?,
+                payload_size: row.get(2)?,
+                runtime_us: row.get(3)?,
+                ts: row.get(4)?,
+            })
+        })
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    let entries: Vec<TailEntry> = rows.flatten().collect();
+    Ok(HttpResponse::Ok().json(entries))
+}
+
+/// Handler for GET /stats/timeseries
+/// Returns request counts and bytes per time bucket and endpoint, e.g. `?bucket=hour&since=7d`.
+#[cfg(feature = "sqlite")]
+async fn stats_timeseries_handler(
+    query: web::Query<TimeseriesQuery>,
+    db: Data<Mutex<Connection>>,
+) -> Result<impl Responder> {
+    let bucket = query.bucket.clone().unwrap_or_else(|| "hour".to_string());
+    let since = query.since.clone().unwrap_or_else(|| "7d".to_string());
+    let format = match bucket.as_str() {
+        "minute" => "%Y-%m-%dT%H:%M:00Z",
+        "hour" => "%Y-%m-%dT%H:00:00Z",
+        "day" => "%Y-%m-%dT00:00:00Z",
+        _ => {
+            return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+                "error": "Invalid bucket. Use one of: minute, hour, day."
+            })))
+        }
+    };
+    let since_secs = match parse_duration_secs(&since) {
+        Some(secs) => secs,
+        None => {
+            return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+                "error": "Invalid since. Use a duration like 30m, 24h or 7d."
+            })))
+        }
+    };
+
+    let conn = db.lock().unwrap();
+    let mut stmt = conn
+        .prepare(
+            "SELECT strftime(?1, ts) AS bucket, endpoint, SUM(COALESCE(sample_weight, 1)), SUM(payload_size * COALESCE(sample_weight, 1)) FROM endpoint_stats_raw WHERE ts >= datetime('now', ?2) GROUP BY bucket, endpoint ORDER BY bucket, endpoint",
+        )
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    let rows = stmt
+        .query_map(params![format, format!("-{} seconds", since_secs)], |row| {
+            Ok(TimeseriesPoint {
+                bucket: row.get(0)?,
+                endpoint: row.get(1)?,
+                count: row.get(2)?,
+                total_bytes: row.get(3).unwrap_or(0),
+            })
+        })
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    let series = rows.flatten().collect();
+    Ok(HttpResponse::Ok().json(TimeseriesResponse {
+        bucket,
+        since,
+        series,
+    }))
+}
+
+/// Handler for GET /grafana
+/// Connection test of the Grafana simple-JSON datasource.
+#[cfg(feature = "sqlite")]
+async fn grafana_test_handler() -> Result<impl Responder> {
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "status": "ok" })))
+}
+
+/// Handler for POST /grafana/search
+/// Lists all metric targets that can be charted.
+#[cfg(feature = "sqlite")]
+async fn grafana_search_handler() -> Result<impl Responder> {
+    let targets: Vec<String> = ENDPOINTS
+        .iter()
+        .flat_map(|endpoint| {
+            GRAFANA_METRICS
+                .iter()
+                .map(move |metric| format!("{}.{}", endpoint, metric))
+        })
+        .collect();
+    Ok(HttpResponse::Ok().json(targets))
+}
+
+/// Handler for POST /grafana/query
+/// Returns one time series per requested target, bucketed by the panel's interval.
+#[cfg(feature = "sqlite")]
+async fn grafana_query_handler(
+    query: web::Json<GrafanaQueryRequest>,
+    db: Data<Mutex<Connection>>,
+) -> Result<impl Responder> {
+    let from = query.range.from.timestamp();
+    let to = query.range.to.timestamp();
+    // Buckets finer than a second make no sense, ts only has second precision
+    let interval_s = (query.interval_ms.unwrap_or(60_000) / 1000).max(1);
+
+    let conn = db.lock().unwrap();
+    let mut response = Vec::new();
+    for target in query.targets.iter().filter_map(|t| t.target.as_deref()) {
+        let Some((endpoint, metric)) = target.rsplit_once('.') else {
+            continue;
+        };
+        let aggregate = match metric {
+            "count" => "SUM(COALESCE(sample_weight, 1))",
+            "bytes" => "SUM(payload_size * COALESCE(sample_weight, 1))",
+            "avg_runtime_us" => "AVG(runtime_us)",
+            _ => continue,
+        };
+        let sql = format!(
+            "SELECT (CAST(strftime('%s', ts) AS INTEGER) / ?1) * ?1 AS bucket, {} FROM endpoint_stats_raw WHERE endpoint = ?2 AND ts BETWEEN datetime(?3, 'unixepoch') AND datetime(?4, 'unixepoch') GROUP BY bucket ORDER BY bucket",
+            aggregate
+        );
+        let mut stmt = conn
+            .prepare(&sql)
+            .map_err(actix_web::error::ErrorInternalServerError)?;
+        let rows = stmt
+            .query_map(params![interval_s, endpoint, from, to], |row| {
+                let bucket: i64 = row.get(0)?;
+                let value: f64 = row.get(1)?;
+                Ok((value, bucket * 1000))
+            })
+            .map_err(actix_web::error::ErrorInternalServerError)?;
+        response.push(GrafanaTimeseries {
+            target: target.to_string(),
+            datapoints: rows.flatten().collect(),
+        });
+    }
+    Ok(HttpResponse::Ok().json(response))
+}
+
+#[derive(Serialize)]
+struct BenchResponse {
+    #[serde(flatten)]
+    report: bench::BenchReport,
+    runtime_us: u128,
+}
+
+/// Handler for GET /bench
+/// Admin only. Runs an internal micro-benchmark (JSON parsing, SQLite inserts, hashing) to compare hosts.
+async fn bench_handler(req: HttpRequest, config: Data<AppConfig>) -> Result<impl Responder> {
+    if let Some(response) = check_admin(&req, &config) {
+        return Ok(response);
+    }
+    let start = get_start_time(&req);
+    let report = web::block(bench::run)
+        .await?
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    Ok(HttpResponse::Ok().json(BenchResponse {
+        report,
+        runtime_us: start.elapsed().as_micros(),
+    }))
+}
+
+/// Handler for POST /admin/reload
+/// Admin only. Re-reads the config file (routing rules, limits, allowed content types,
+/// shred styles) without a restart, same as sending SIGHUP.
+async fn reload_handler(req: HttpRequest, config: Data<AppConfig>) -> Result<impl Responder> {
+    if let Some(response) = check_admin(&req, &config) {
+        return Ok(response);
+    }
+    Ok(match config.reload() {
+        Ok(settings) => HttpResponse::Ok().json(ReloadResponse::new(&settings)),
+        Err(e) => HttpResponse::BadRequest().json(serde_json::json!({ "error": e })),
+    })
+}
+
+// Reload the config file whenever the process receives SIGHUP
+#[cfg(unix)]
+fn reload_on_sighup(config: Data<AppConfig>) {
+    use actix_web::rt::signal::unix::{signal, SignalKind};
+    actix_web::rt::spawn(async move {
+        let Ok(mut hangups) = signal(SignalKind::hangup()) else {
+            eprintln!("Failed to install SIGHUP handler, use POST /admin/reload instead");
+            return;
+        };
+        while hangups.recv().await.is_some() {
+            match config.reload() {
+                Ok(_) => println!("Reloaded configuration"),
+                Err(e) => eprintln!("Failed to reload configuration: {}", e),
+            }
+        }
+    });
+}
+
+/// Handler for GET /metrics
+/// Prometheus text exposition of the per-endpoint totals.
+async fn metrics_handler(req: HttpRequest, db: Data<dyn store::Store>) -> Result<impl Responder> {
+    let mut body =
+        metrics::render(db.get_ref()).map_err(actix_web::error::ErrorInternalServerError)?;
+    #[cfg(feature = "sqlite")]
+    if let Some(guard) = req.app_data::<Data<disk_guard::DiskGuard>>() {
+        body.push_str(&guard.render_metrics());
+    }
+    if let Some(gauges) = req.app_data::<Data<gauges::Gauges>>() {
+        body.push_str(&gauges.render_metrics());
+    }
+    if let Some(buffer) = req.app_data::<Data<stat_buffer::StatBuffer>>() {
+        body.push_str(&buffer.render_metrics());
+    }
+    Ok(HttpResponse::Ok()
+        .content_type(metrics::CONTENT_TYPE)
+        .body(body))
+}
+
+#[derive(Serialize)]
+struct HealthResponse {
+    // "ok", or "degraded" while a guard is active
+    status: &'static str,
+    // Only with --disk-max-db-size or --disk-min-free
+    #[cfg(feature = "sqlite")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    disk: Option<disk_guard::DiskStatus>,
+}
+
+/// Handler for GET /healthz
+/// Reports conditions the server is working around, answering 503 while degraded.
+#[cfg_attr(not(feature = "sqlite"), allow(unused_variables))]
+async fn healthz_handler(req: HttpRequest) -> Result<impl Responder> {
+    // The disk guard is the only one so far, it needs SQLite
+    #[cfg(feature = "sqlite")]
+    let guard = req.app_data::<Data<disk_guard::DiskGuard>>();
+    #[cfg(feature = "sqlite")]
+    let degraded = guard.is_some_and(|guard| guard.is_under_pressure());
+    #[cfg(not(feature = "sqlite"))]
+    let degraded = false;
+    let response = HealthResponse {
+        status: if degraded { "degraded" } else { "ok" },
+        #[cfg(feature = "sqlite")]
+        disk: guard.map(|guard| guard.status()),
+    };
+    Ok(match degraded {
+        true => HttpResponse::ServiceUnavailable().json(response),
+        false => HttpResponse::Ok().json(response),
+    })
+}
+
+#[derive(Serialize)]
+struct PingResponse {
+    status: &'static str,
+    timestamp: String,
+    privacy_mode: bool,
+    read_only: bool,
+}
+
+async fn ping_handler(config: Data<AppConfig>) -> Result<impl Responder> {
+    let response = PingResponse {
+        status: "pong",
+        timestamp: chrono::Utc::now().to_rfc3339(),
+        privacy_mode: config.privacy,
+        read_only: config.read_only,
+    };
+    Ok(HttpResponse::Ok().json(response))
+}
+
+// Parse the command line and run the chosen subcommand
+pub async fn run() -> std::io::Result<()> {
+    // Parse CLI arguments
+    let args = Args::parse();
+    match args.command {
+        None => serve(&args.db, args.serve).await,
+        Some(Command::Serve(serve_args)) => serve(&args.db, *serve_args).await,
+        #[cfg(feature = "sqlite")]
+        Some(Command::Prune { older_than, vacuum }) => {
+            let conn = init_db_or_exit(&args.db);
+            let deleted = prune_stats(&conn, older_than).expect("Failed to prune stats");
+            println!("Pruned {} stats rows from {}", deleted, args.db.db_path);
+            if vacuum {
+                conn.execute_batch("VACUUM")
+                    .expect("Failed to vacuum database");
+            }
+            Ok(())
+        }
+        #[cfg(feature = "sqlite")]
+        Some(Command::Export { format, output }) => {
+            let conn = init_db_or_exit(&args.db);
+            let mut out: Box<dyn Write> = match &output {
+                Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+                None => Box::new(BufWriter::new(std::io::stdout().lock())),
+            };
+            let exported = export_stats(&conn, format, &mut out).map_err(std::io::Error::other)?;
+            // Keep stdout clean for piping, report to stderr
+            eprintln!("Exported {} stats rows", exported);
+            Ok(())
+        }
+        #[cfg(feature = "sqlite")]
+        Some(Command::Merge(merge_args)) => merge::run(&args.db, merge_args),
+        Some(Command::Loadtest(loadtest_args)) => loadtest::run(loadtest_args),
+        Some(Command::Healthcheck(healthcheck_args)) => healthcheck::run(healthcheck_args),
+        Some(Command::Selftest(selftest_args)) => selftest::run(&args.db, selftest_args).await,
+    }
+}
+
+// Run the HTTP server
+async fn serve(db_args: &DbArgs, args: ServeArgs) -> std::io::Result<()> {
+    let listener = listener::listen(([0, 0, 0, 0], 8080).into(), args.reuse_port)?;
+    serve_on(db_args, args, listener).await
+}
+
+// Everything the handlers share, built once per server from the command line
+#[derive(Clone)]
+pub struct AppState {
+    config: Data<AppConfig>,
+    #[cfg(feature = "wasm-plugins")]
+    plugin_host: Arc<plugins::PluginHost>,
+    store: Data<dyn store::Store>,
+    // The same database as `store`, for everything that queries it directly
+    #[cfg(feature = "sqlite")]
+    db: Data<Mutex<Connection>>,
+    db_source: Data<DbArgs>,
+    tus_store: Data<tus::TusStore>,
+    stage_store: Data<stage::StageStore>,
+    queue_store: Data<queue::QueueStore>,
+    #[cfg(feature = "extras")]
+    callbacks: Data<callback::Callbacks>,
+    backlog: Data<backpressure::Backlog>,
+    rng: Data<SharedRng>,
+    live_hub: Data<live::Hub>,
+    gauges: Data<gauges::Gauges>,
+    stat_buffer: Data<stat_buffer::StatBuffer>,
+    dedup: Data<DedupCache>,
+    similarity_index: Data<similarity::SimilarityIndex>,
+    #[cfg(feature = "sqlite")]
+    hash_index: Option<Data<hash_index::HashIndex>>,
+    #[cfg(feature = "sqlite")]
+    unique_counter: Option<Data<cardinality::UniqueCounter>>,
+    stats_cache: Option<Data<stats_cache::StatsCache>>,
+    sampler: Option<Data<sampling::Sampler>>,
+    memory_guard: Option<Data<memory_guard::MemoryGuard>>,
+    #[cfg(feature = "sqlite")]
+    disk_guard: Option<Data<disk_guard::DiskGuard>>,
+    scratch: Option<Data<spill::Scratch>>,
+    #[cfg(feature = "acme")]
+    acme_challenges: Option<Data<acme::Challenges>>,
+    #[cfg(feature = "http3")]
+    alt_svc: Option<String>,
+    #[cfg(feature = "sqlite")]
+    outcomes: Arc<alerts::Outcomes>,
+    stats_token: Option<String>,
+}
+
+impl AppState {
+    // Open the database and set up the state. The background work (flushers, sweepers,
+    // rotation, …) is left to `serve_on`, tests don't need it.
+    fn new(db_args: &DbArgs, args: &ServeArgs) -> Result<Self, String> {
+        let config = Data::new(AppConfig::from_args(args)?);
+        #[cfg(feature = "wasm-plugins")]
+        let plugin_host = Arc::new(plugins::PluginHost::load(&args.plugins)?);
+        #[cfg(feature = "sqlite")]
+        let db = Data::new(Mutex::new(match args.read_only {
+            true => open_db_read_only(db_args)?,
+            false => init_db(db_args)?,
+        }));
+        #[cfg(feature = "sqlite")]
+        let store: Arc<dyn store::Store> = db.clone().into_inner();
+        #[cfg(not(feature = "sqlite"))]
+        let store: Arc<dyn store::Store> =
+            Arc::new(log_store::LogStore::open(db_args, args.read_only)?);
+        // The filter and receipts are payload hashes, which --privacy never stores
+        #[cfg(feature = "sqlite")]
+        let hash_index = match args.remember_hashes && !args.privacy {
+            true => Some(Data::new(hash_index::HashIndex::load(&db.lock().unwrap())?)),
+            false => None,
+        };
+        let memory_guard = args.max_memory.and_then(|limit| {
+            let guard = memory_guard::MemoryGuard::new(limit);
+            if guard.is_none() {
+                eprintln!("--max-memory needs /proc to measure memory usage, ignoring it");
+            }
+            guard.map(Data::new)
+        });
+        let scratch = match &args.spill_dir {
+            Some(dir) => {
+                let scratch = spill::Scratch::new(dir, args.spill_threshold as usize)?;
+                scratch.clean();
+                Some(Data::new(scratch))
+            }
+            None => None,
+        };
+        Ok(AppState {
+            config,
+            #[cfg(feature = "wasm-plugins")]
+            plugin_host,
+            store: Data::from(store),
+            #[cfg(feature = "sqlite")]
+            db,
+            db_source: Data::new(db_args.clone()),
+            tus_store: Data::new(tus::TusStore::new(args.tus_max_uploads as usize)),
+            stage_store: Data::new(stage::StageStore::new(
+                args.stage_timeout,
+                args.stage_max_bytes as usize,
+            )),
+            queue_store: Data::new(queue::QueueStore::new(
+                args.queue_workers as usize,
+                args.queue_max_waiting as usize,
+            )),
+            #[cfg(feature = "extras")]
+            callbacks: Data::new(callback::Callbacks::default()),
+            backlog: Data::new(backpressure::Backlog::new(
+                args.max_backlog.map(|depth| depth as usize),
+            )),
+            rng: Data::new(SharedRng::new(args.seed)),
+            live_hub: Data::new(live::Hub::default()),
+            gauges: Data::new(gauges::Gauges::default()),
+            stat_buffer: Data::new(stat_buffer::StatBuffer::new(args.stat_buffer_rows)),
+            dedup: Data::new(DedupCache::new(
+                args.dedup_window
+                    .map(|secs| std::time::Duration::from_secs(secs as u64)),
+            )),
+            similarity_index: Data::new(similarity::SimilarityIndex::new(
+                args.similarity_window
+                    .map(|secs| std::time::Duration::from_secs(secs as u64)),
+                args.similarity_distance,
+            )),
+            #[cfg(feature = "sqlite")]
+            hash_index,
+            // The sketches are built from payload hashes, which --privacy never stores
+            #[cfg(feature = "sqlite")]
+            unique_counter: (args.count_unique && !args.privacy)
+                .then(|| Data::new(cardinality::UniqueCounter::default())),
+            stats_cache: args
+                .stats_cache_ttl
+                .filter(|ttl| *ttl > 0)
+                .map(|ttl| Data::new(stats_cache::StatsCache::new(ttl))),
+            sampler: args
+                .stats_sample
+                .filter(|every| *every > 1)
+                .map(|every| Data::new(sampling::Sampler::new(every))),
+            memory_guard,
+            #[cfg(feature = "sqlite")]
+            disk_guard: (args.disk_max_db_size.is_some() || args.disk_min_free.is_some()).then(
+                || {
+                    Data::new(disk_guard::DiskGuard::new(
+                        &db_args.db_path,
+                        args.disk_max_db_size,
+                        args.disk_min_free,
+                    ))
+                },
+            ),
+            scratch,
+            #[cfg(feature = "acme")]
+            acme_challenges: (!args.acme_domain.is_empty())
+                .then(|| Data::new(acme::Challenges::default())),
+            #[cfg(feature = "http3")]
+            alt_svc: args.http3_port.map(http3::alt_svc),
+            #[cfg(feature = "sqlite")]
+            outcomes: Arc::new(alerts::Outcomes::default()),
+            stats_token: args.stats_token.clone(),
+        })
+    }
+
+    // The state for the server a command line would start, e.g.
+    // ["payload-pulverizer", "--db-path", "/tmp/test.db", "--boring"]
+    pub fn from_args<I, T>(args: I) -> Result<Self, String>
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<std::ffi::OsString> + Clone,
+    {
+        let args = Args::try_parse_from(args).map_err(|e| e.to_string())?;
+        let serve_args = match args.command {
+            None => args.serve,
+            Some(Command::Serve(serve_args)) => *serve_args,
+            Some(_) => return Err("Not a command line that starts the server.".to_string()),
+        };
+        AppState::new(&args.db, &serve_args)
+    }
+}
+
+// The App with every route, middleware and piece of state, as the server runs it.
+// Integration tests can pass it to `actix_web::test::init_service`.
+pub fn configure_app(
+    state: &AppState,
+) -> App<
+    impl ServiceFactory<
+        ServiceRequest,
+        Config = (),
+        Response = ServiceResponse<impl MessageBody>,
+        Error = Error,
+        InitError = (),
+    >,
+> {
+    let config = &state.config;
+    let (privacy, read_only, boring) = (config.privacy, config.read_only, config.boring);
+    let routes = index::scope(&config.base_path);
+    let app = App::new()
+        .wrap(queue::AsyncJobs)
+        .wrap(backpressure::Backpressure)
+        .wrap(memory_guard::RefuseLargeUploads)
+        .wrap(padding::PadResponse)
+        .wrap(Condition::new(read_only, read_only::ReadOnly { boring }))
+        .wrap(StartTime);
+    // Alerts query the stats rows, so they need SQLite
+    #[cfg(feature = "sqlite")]
+    let app = app.wrap(alerts::TrackOutcomes(state.outcomes.clone()));
+    let app = app
+        .wrap(similarity::TagSimilar)
+        .wrap(checksum::DigestHeader)
+        .wrap(stats_token::StatsToken(state.stats_token.clone()))
+        // Outside every layer that turns requests away, not in read-only mode
+        .wrap(Condition::new(!read_only, rejections::RecordRejections))
+        // Advertise privacy mode on every response
+        .wrap(Condition::new(
+            privacy,
+            DefaultHeaders::new().add(("X-Privacy-Mode", "on")),
+        ))
+        // Outermost, so requests turned away early count as well
+        .wrap(gauges::CountInFlight(state.gauges.clone()))
+        .app_data(state.store.clone())
+        .app_data(state.config.clone())
+        .app_data(state.dedup.clone())
+        .app_data(state.similarity_index.clone())
+        .app_data(state.tus_store.clone())
+        .app_data(state.stage_store.clone())
+        .app_data(state.queue_store.clone())
+        .app_data(state.backlog.clone())
+        .app_data(state.rng.clone())
+        .app_data(state.live_hub.clone())
+        .app_data(state.gauges.clone())
+        .app_data(state.stat_buffer.clone())
+        .app_data(state.db_source.clone())
+        .app_data(PayloadConfig::new(MAX_PAYLOAD_SIZE));
+    #[cfg(feature = "sqlite")]
+    let app = app.app_data(state.db.clone());
+    #[cfg(feature = "extras")]
+    let app = app.app_data(state.callbacks.clone());
+    #[cfg(feature = "sqlite")]
+    let app = match &state.unique_counter {
+        Some(counter) => app.app_data(counter.clone()),
+        None => app,
+    };
+    let app = match &state.stats_cache {
+        Some(cache) => app.app_data(cache.clone()),
+        None => app,
+    };
+    let app = match &state.sampler {
+        Some(sampler) => app.app_data(sampler.clone()),
+        None => app,
+    };
+    #[cfg(feature = "sqlite")]
+    let app = match &state.hash_index {
+        Some(index) => app.app_data(index.clone()),
+        None => app,
+    };
+    #[cfg(feature = "sqlite")]
+    let app = match &state.disk_guard {
+        Some(guard) => app.app_data(guard.clone()),
+        None => app,
+    };
+    let app = match &state.scratch {
+        Some(scratch) => app.app_data(scratch.clone()),
+        None => app,
+    };
+    let app = match &state.memory_guard {
+        Some(guard) => app.app_data(guard.clone()),
+        None => app,
+    };
+    // At the root whatever the base path, that's where the CA looks
+    #[cfg(feature = "acme")]
+    let app = match &state.acme_challenges {
+        Some(challenges) => app
+            .app_data(challenges.clone())
+            .route(acme::CHALLENGE_PATH, web::get().to(acme::challenge_handler)),
+        None => app,
+    };
+    #[cfg(feature = "http3")]
+    let app = app.wrap(Condition::new(
+        state.alt_svc.is_some(),
+        DefaultHeaders::new().add(("Alt-Svc", state.alt_svc.clone().unwrap_or_default())),
+    ));
+    let app = app.service(routes);
+    #[cfg(feature = "wasm-plugins")]
+    let app = app.wrap(plugins::PluginHooks(state.plugin_host.clone()));
+    app
+}
+
+// The background work on the SQLite database: flushing the hash filter and unique
+// counts, the disk guard, rotation and alerts
+#[cfg(feature = "sqlite")]
+fn spawn_sqlite_tasks(state: &AppState, db_args: &DbArgs, args: &ServeArgs) {
+    let db = &state.db;
+    if let Some(index) = &state.hash_index {
+        hash_index::spawn_flusher(index.clone(), db.clone());
+    }
+    if let Some(counter) = &state.unique_counter {
+        cardinality::spawn_flusher(counter.clone(), db.clone());
+    }
+    if let Some(guard) = &state.disk_guard {
+        disk_guard::spawn(guard.clone(), db.clone());
+    }
+    let rotation = rotation::RotationPolicy {
+        monthly: args.rotate_monthly,
+        max_size: args.rotate_size,
+        keep: args.rotate_keep,
+    };
+    if rotation.is_enabled() {
+        rotation::spawn(db.clone(), db_args.clone(), rotation);
+    }
+    alerts::spawn_evaluator(db.clone(), state.config.clone(), state.outcomes.clone());
+}
+
+// Run the HTTP server on an already bound socket
+async fn serve_on(
+    db_args: &DbArgs,
+    args: ServeArgs,
+    listener: std::net::TcpListener,
+) -> std::io::Result<()> {
+    #[cfg(feature = "sqlite")]
+    if let Some(mode) = args.integrity_check {
+        integrity::check_or_exit(db_args, mode);
+    }
+    let state = AppState::new(db_args, &args).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+    println!(
+        "Starting Payload Pulverizer server on http://localhost:{}{}",
+        listener.local_addr()?.port(),
+        state.config.base_path
+    );
+    println!("Using database at: {}", db_args.db_path);
+    let store = &state.store;
+    stage::spawn_sweeper(state.stage_store.clone(), store.clone());
+    tus::spawn_sweeper(state.tus_store.clone());
+    stat_buffer::spawn_retrier(state.stat_buffer.clone(), store.clone());
+    #[cfg(feature = "extras")]
+    callback::spawn_workers(state.callbacks.clone());
+    #[cfg(unix)]
+    reload_on_sighup(state.config.clone());
+    if let Some(url) = &args.pushgateway_url {
+        metrics::spawn_pusher(
+            store.clone(),
+            metrics::PushConfig {
+                url: url.clone(),
+                job: args.push_job.clone(),
+                instance: args.push_instance.clone(),
+                interval: std::time::Duration::from_secs(args.push_interval.max(1) as u64),
+            },
+        );
+    }
+    if let Some(guard) = &state.memory_guard {
+        memory_guard::spawn_monitor(guard.clone());
+    }
+    #[cfg(feature = "sqlite")]
+    spawn_sqlite_tasks(&state, db_args, &args);
+    #[cfg(feature = "acme")]
+    let https = match &state.acme_challenges {
+        Some(challenges) => Some(acme::start(
+            acme::AcmeConfig {
+                domains: args.acme_domain.clone(),
+                email: args.acme_email.clone(),
+                directory: args.acme_directory.clone(),
+                dir: args.acme_dir.clone().into(),
+                https_port: args.acme_https_port,
+                reuse_port: args.reuse_port,
+            },
+            challenges.clone(),
+        )?),
+        None => None,
+    };
+    #[cfg(feature = "http3")]
+    let http3 = match args.http3_port {
+        Some(port) => {
+            let certificate = match (&args.http3_cert, &args.http3_key) {
+                (Some(cert), Some(key)) => Some(http3::certificate(cert, key)),
+                _ => None,
+            };
+            #[cfg(feature = "acme")]
+            let certificate = certificate.or_else(|| {
+                https
+                    .as_ref()
+                    .map(|(_, tls_config)| Ok(tls_config.cert_resolver.clone()))
+            });
+            let certificate = certificate
+                .unwrap_or_else(|| Err("--http3-port needs --http3-cert and --http3-key".into()))
+                .unwrap_or_else(|e| {
+                    eprintln!("{}", e);
+                    std::process::exit(1);
+                });
+            Some(http3::bind(port, certificate)?)
+        }
+        None => None,
+    };
+    // Saved once the server has stopped
+    let final_flush = (state.stat_buffer.clone(), store.clone());
+    #[cfg(feature = "sqlite")]
+    let final_save = state
+        .hash_index
+        .clone()
+        .map(|index| (index, state.db.clone()));
+    #[cfg(feature = "sqlite")]
+    let final_unique = state
+        .unique_counter
+        .clone()
+        .map(|counter| (counter, state.db.clone()));
+    let gauges = state.gauges.clone();
+    let make_app = move || configure_app(&state);
+    #[cfg(feature = "http3")]
+    if let Some(endpoint) = http3 {
+        actix_web::rt::spawn(http3::serve(endpoint, make_app.clone(), gauges.clone()));
+    }
+    let result = if args.proxy_protocol {
+        proxy_protocol::serve(listener, make_app, args.shutdown_timeout as u64, gauges).await
+    } else {
+        let server = HttpServer::new(make_app)
+            .on_connect(move |_, extensions| gauges::on_connect(&gauges, extensions))
+            .shutdown_timeout(args.shutdown_timeout as u64)
+            .listen(listener)?;
+        #[cfg(feature = "acme")]
+        let server = match https {
+            Some((listener, tls_config)) => server.listen_rustls_0_23(listener, tls_config)?,
+            None => server,
+        };
+        server.run().await
+    };
+    stat_buffer::flush_on_exit(&final_flush.0, final_flush.1.get_ref());
+    // Keep the hashes of the last minute's destructions
+    #[cfg(feature = "sqlite")]
+    if let Some((index, db)) = final_save {
+        hash_index::save(&index, &db);
+    }
+    // And the distinct payloads
+    #[cfg(feature = "sqlite")]
+    if let Some((counter, db)) = final_unique {
+        cardinality::save(&counter, &db);
+    }
+    result
+}
@@ -0,0 +1,203 @@
+// Link extraction for /validate-before-destroy, with an optional liveness check
+//
+// Absolute http(s) URLs are picked out of any text payload (Markdown, HTML, plain text)
+// and out of the string values of JSON payloads, up to 1000 of them. Checking them sends
+// a HEAD request to every link, a few at a time, and only to public addresses: the links
+// come from the client, the server must not be made to probe its own network.
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+// Links listed per report
+const MAX_LINKS: usize = 1000;
+// Links checked per request, the rest is listed without status
+const MAX_CHECKED: usize = 100;
+const CHECK_CONCURRENCY: usize = 8;
+const CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Serialize)]
+pub struct Link {
+    url: String,
+    // Set for checked links only
+    #[serde(skip_serializing_if = "Option::is_none")]
+    status: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dead: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct LinkReport {
+    // Distinct links listed
+    count: usize,
+    // Whether the payload has more links than listed
+    truncated: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dead_count: Option<usize>,
+    links: Vec<Link>,
+}
+
+// Characters that can't be part of a URL in running text or markup
+fn ends_url(c: char) -> bool {
+    c.is_whitespace()
+        || matches!(
+            c,
+            '"' | '\'' | '<' | '>' | '`' | '{' | '}' | '|' | '\\' | '^'
+        )
+}
+
+// Drop sentence punctuation and closing brackets that belong to the surrounding text
+fn trim_url(mut url: &str) -> &str {
+    loop {
+        let trimmed = url.trim_end_matches(['.', ',', ';', ':', '!', '?', '*']);
+        let trimmed = match trimmed.chars().last() {
+            Some(')') if trimmed.matches('(').count() < trimmed.matches(')').count() => {
+                &trimmed[..trimmed.len() - 1]
+            }
+            Some(']') if trimmed.matches('[').count() < trimmed.matches(']').count() => {
+                &trimmed[..trimmed.len() - 1]
+            }
+            _ => trimmed,
+        };
+        if trimmed == url {
+            return url;
+        }
+        url = trimmed;
+    }
+}
+
+// Distinct links in order of appearance. Scanning stops one past the listed ones, which
+// tells the report there were more.
+#[derive(Default)]
+struct Found {
+    urls: Vec<String>,
+    seen: HashSet<String>,
+}
+
+impl Found {
+    fn is_full(&self) -> bool {
+        self.urls.len() > MAX_LINKS
+    }
+
+    fn add(&mut self, url: &str) {
+        if !self.seen.contains(url) {
+            self.seen.insert(url.to_string());
+            self.urls.push(url.to_string());
+        }
+    }
+}
+
+fn scan(text: &str, found: &mut Found) {
+    let mut rest = text;
+    while let Some(start) = rest.find("http") {
+        if found.is_full() {
+            return;
+        }
+        rest = &rest[start..];
+        let scheme = ["https://", "http://"]
+            .into_iter()
+            .find(|scheme| rest.starts_with(scheme));
+        let Some(scheme) = scheme else {
+            rest = &rest[4..];
+            continue;
+        };
+        let end = rest.find(ends_url).unwrap_or(rest.len());
+        let url = trim_url(&rest[..end]);
+        if url.len() > scheme.len() {
+            found.add(url);
+        }
+        rest = &rest[end.max(scheme.len())..];
+    }
+}
+
+fn scan_json(value: &Value, found: &mut Found) {
+    match value {
+        _ if found.is_full() => {}
+        Value::String(text) => scan(text, found),
+        Value::Array(items) => items.iter().for_each(|item| scan_json(item, found)),
+        Value::Object(map) => map.values().for_each(|item| scan_json(item, found)),
+        _ => {}
+    }
+}
+
+// Distinct links in order of appearance, one more than a report lists if there are more.
+// JSON escapes are resolved when the payload parsed.
+pub fn extract(body_str: &str, json: Option<&Value>) -> Vec<String> {
+    let mut found = Found::default();
+    match json {
+        Some(value) => scan_json(value, &mut found),
+        None => scan(body_str, &mut found),
+    }
+    found.urls
+}
+
+// HEAD first, GET for servers that don't do HEAD
+fn check_link(agent: &ureq::Agent, url: String) -> Link {
+    let result = match agent.head(&url).call() {
+        Err(ureq::Error::Status(405 | 501, _)) => agent.get(&url).call(),
+        result => result,
+    };
+    let (status, error) = match result {
+        Ok(response) => (Some(response.status()), None),
+        Err(ureq::Error::Status(status, _)) => (Some(status), None),
+        // Addresses that may not be checked fail to resolve
+        Err(e) if e.kind() == ureq::ErrorKind::Dns => (None, Some(e.to_string())),
+        Err(e) => (None, Some(e.kind().to_string())),
+    };
+    Link {
+        url,
+        status,
+        dead: Some(status.is_none_or(|status| status >= 400)),
+        error,
+    }
+}
+
+// Report the links, checking the first ones when asked to. Blocks while checking.
+pub fn report(mut urls: Vec<String>, check: bool) -> LinkReport {
+    let truncated = urls.len() > MAX_LINKS;
+    urls.truncate(MAX_LINKS);
+    let count = urls.len();
+    let unchecked = if check {
+        urls.split_off(urls.len().min(MAX_CHECKED))
+    } else {
+        std::mem::take(&mut urls)
+    };
+    let results: Vec<Mutex<Option<Link>>> = urls.iter().map(|_| Mutex::new(None)).collect();
+    let next = AtomicUsize::new(0);
+    let agent = crate::fetch::guarded_agent(true)
+        .timeout(CHECK_TIMEOUT)
+        .build();
+    std::thread::scope(|scope| {
+        for _ in 0..CHECK_CONCURRENCY.min(urls.len()) {
+            scope.spawn(|| loop {
+                let i = next.fetch_add(1, Ordering::Relaxed);
+                let Some(url) = urls.get(i) else {
+                    return;
+                };
+                let link = check_link(&agent, url.clone());
+                *results[i].lock().unwrap() = Some(link);
+            });
+        }
+    });
+    let mut links: Vec<Link> = results
+        .into_iter()
+        .filter_map(|result| result.into_inner().unwrap())
+        .collect();
+    let dead_count = check.then(|| links.iter().filter(|link| link.dead == Some(true)).count());
+    links.extend(unchecked.into_iter().map(|url| Link {
+        url,
+        status: None,
+        dead: None,
+        error: None,
+    }));
+    LinkReport {
+        count,
+        truncated,
+        dead_count,
+        links,
+    }
+}
@@ -0,0 +1,48 @@
+// Listening socket setup for zero-downtime restarts.
+//
+// With --reuse-port a new instance can bind the same port while the old one is still
+// draining, the kernel spreads new connections over both until the old one exits.
+// Alternatively a supervisor like systemd can hand over an already bound socket
+// (socket activation via LISTEN_PID/LISTEN_FDS), which outlives any single instance.
+use socket2::{Domain, Protocol, Socket, Type};
+use std::io;
+use std::net::{SocketAddr, TcpListener};
+
+const LISTEN_BACKLOG: i32 = 1024;
+
+pub fn listen(addr: SocketAddr, reuse_port: bool) -> io::Result<TcpListener> {
+    #[cfg(unix)]
+    if let Some(listener) = inherited() {
+        println!("Using listening socket passed in by the supervisor");
+        return Ok(listener);
+    }
+    bind(addr, reuse_port)
+}
+
+// A new socket, ignoring any passed in by a supervisor
+pub fn bind(addr: SocketAddr, reuse_port: bool) -> io::Result<TcpListener> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+    socket.set_reuse_address(true)?;
+    if reuse_port {
+        #[cfg(unix)]
+        socket.set_reuse_port(true)?;
+        #[cfg(not(unix))]
+        return Err(io::Error::other("--reuse-port is only supported on Unix"));
+    }
+    socket.bind(&addr.into())?;
+    socket.listen(LISTEN_BACKLOG)?;
+    Ok(socket.into())
+}
+
+// The first socket passed via systemd-style socket activation, if it's meant for us
+#[cfg(unix)]
+fn inherited() -> Option<TcpListener> {
+    use std::os::unix::io::FromRawFd;
+    let pid: u32 = std::env::var("LISTEN_PID").ok()?.parse().ok()?;
+    let fds: u32 = std::env::var("LISTEN_FDS").ok()?.parse().ok()?;
+    if pid != std::process::id() || fds < 1 {
+        return None;
+    }
+    // SAFETY: the protocol guarantees fd 3 is an open socket handed to this process
+    Some(unsafe { TcpListener::from_raw_fd(3) })
+}
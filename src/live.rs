@@ -0,0 +1,179 @@
+// Live feed of destructions for dashboards and terminals, fed by every recorded stats row
+use actix_web::web::{self, Data};
+use actix_web::{HttpRequest, HttpResponse, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::time::Duration;
+use tokio::sync::broadcast;
+
+// Destructions buffered per subscriber, slower ones skip ahead
+const HUB_CAPACITY: usize = 1024;
+const AGGREGATE_INTERVAL: Duration = Duration::from_secs(1);
+
+// One destruction, stat_id is None if it wasn't recorded (e.g. left out of --stats-sample)
+#[derive(Serialize, Clone, Debug)]
+pub struct Activity {
+    pub stat_id: Option<i64>,
+    pub endpoint: String,
+    pub payload_size: usize,
+    pub runtime_us: u128,
+    pub ts: String,
+}
+
+pub struct Hub {
+    sender: broadcast::Sender<Activity>,
+}
+
+impl Default for Hub {
+    fn default() -> Self {
+        Hub {
+            sender: broadcast::channel(HUB_CAPACITY).0,
+        }
+    }
+}
+
+impl Hub {
+    // Fails only when nobody listens, which is fine
+    pub fn publish(&self, activity: Activity) {
+        let _ = self.sender.send(activity);
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Activity> {
+        self.sender.subscribe()
+    }
+}
+
+#[derive(Deserialize, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum LiveMode {
+    // One message per destruction
+    #[default]
+    Requests,
+    // One summary message per second
+    Aggregate,
+}
+
+#[derive(Deserialize)]
+pub struct LiveQuery {
+    #[serde(default)]
+    mode: LiveMode,
+}
+
+#[derive(Serialize, Default)]
+struct EndpointTotals {
+    requests: u64,
+    bytes: u64,
+}
+
+#[derive(Serialize, Default)]
+struct AggregateMessage {
+    interval_ms: u128,
+    requests: u64,
+    bytes: u64,
+    endpoints: BTreeMap<String, EndpointTotals>,
+}
+
+impl AggregateMessage {
+    fn add(&mut self, activity: &Activity) {
+        self.requests += 1;
+        self.bytes += activity.payload_size as u64;
+        let totals = self.endpoints.entry(activity.endpoint.clone()).or_default();
+        totals.requests += 1;
+        totals.bytes += activity.payload_size as u64;
+    }
+}
+
+/// Handler for GET /stats/live
+/// WebSocket pushing every destruction (?mode=requests) or per-second totals (?mode=aggregate).
+pub async fn live_handler(
+    req: HttpRequest,
+    body: web::Payload,
+    query: web::Query<LiveQuery>,
+    hub: Data<Hub>,
+) -> Result<HttpResponse> {
+    let (response, mut session, mut messages) = actix_ws::handle(&req, body)?;
+    let mut activities = hub.subscribe();
+    let mode = query.mode;
+    actix_web::rt::spawn(async move {
+        let first_tick = actix_web::rt::time::Instant::now() + AGGREGATE_INTERVAL;
+        let mut ticker = actix_web::rt::time::interval_at(first_tick, AGGREGATE_INTERVAL);
+        let mut aggregate = AggregateMessage::default();
+        loop {
+            let outgoing = tokio::select! {
+                activity = activities.recv() => match activity {
+                    Ok(activity) if mode == LiveMode::Requests => serde_json::to_string(&activity).ok(),
+                    Ok(activity) => {
+                        aggregate.add(&activity);
+                        None
+                    }
+                    Err(broadcast::error::RecvError::Lagged(_)) => None,
+                    Err(broadcast::error::RecvError::Closed) => break,
+                },
+                _ = ticker.tick(), if mode == LiveMode::Aggregate => {
+                    let mut message = std::mem::take(&mut aggregate);
+                    message.interval_ms = AGGREGATE_INTERVAL.as_millis();
+                    serde_json::to_string(&message).ok()
+                }
+                message = messages.recv() => match message {
+                    Some(Ok(actix_ws::Message::Ping(bytes))) => {
+                        if session.pong(&bytes).await.is_err() {
+                            return;
+                        }
+                        None
+                    }
+                    Some(Ok(actix_ws::Message::Close(_))) | Some(Err(_)) | None => break,
+                    // Dashboards only listen, anything they send is ignored
+                    Some(Ok(_)) => None,
+                },
+            };
+            if let Some(text) = outgoing {
+                if session.text(text).await.is_err() {
+                    return;
+                }
+            }
+        }
+        let _ = session.close(None).await;
+    });
+    Ok(response)
+}
+
+// Comment line sent when nothing happened for a while, so proxies keep the stream open
+const SSE_KEEPALIVE: Duration = Duration::from_secs(15);
+
+fn sse_event(activity: &Activity) -> String {
+    let data = serde_json::to_string(activity).unwrap_or_default();
+    match activity.stat_id {
+        Some(id) => format!("event: destruction\nid: {}\ndata: {}\n\n", id, data),
+        None => format!("event: destruction\ndata: {}\n\n", data),
+    }
+}
+
+/// Handler for GET /events
+/// Server-Sent Events stream with one `destruction` event per destroyed payload.
+pub async fn events_handler(hub: Data<Hub>) -> HttpResponse {
+    let first_tick = actix_web::rt::time::Instant::now() + SSE_KEEPALIVE;
+    let ticker = actix_web::rt::time::interval_at(first_tick, SSE_KEEPALIVE);
+    let events = futures_util::stream::unfold(
+        (hub.subscribe(), ticker),
+        |(mut activities, mut ticker)| async move {
+            let chunk = tokio::select! {
+                activity = activities.recv() => match activity {
+                    Ok(activity) => sse_event(&activity),
+                    Err(broadcast::error::RecvError::Lagged(missed)) => {
+                        format!(": skipped {} events\n\n", missed)
+                    }
+                    Err(broadcast::error::RecvError::Closed) => return None,
+                },
+                _ = ticker.tick() => ": keepalive\n\n".to_string(),
+            };
+            Some((
+                Ok::<_, actix_web::Error>(web::Bytes::from(chunk)),
+                (activities, ticker),
+            ))
+        },
+    );
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header(("Cache-Control", "no-cache"))
+        .streaming(events)
+}
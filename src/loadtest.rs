@@ -0,0 +1,151 @@
+// Built-in load generator for the `loadtest` subcommand
+use rand::Rng;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// CLI arguments of the loadtest subcommand
+#[derive(clap::Args, Debug, Clone)]
+pub struct LoadtestArgs {
+    /// Base URL of the running pulverizer
+    #[arg(long, default_value = "http://localhost:8080")]
+    url: String,
+    /// Endpoint to fire payloads at
+    #[arg(long, default_value = "pulverize")]
+    endpoint: String,
+    /// Number of concurrent clients
+    #[arg(long, default_value_t = 10)]
+    concurrency: usize,
+    /// How long to keep firing, e.g. 30s or 5m
+    #[arg(long, default_value = "10s", value_parser = crate::parse_duration_arg)]
+    duration: i64,
+    /// Payload size, either fixed (e.g. 4k) or a uniform range (e.g. 512-64k)
+    #[arg(long, default_value = "1k", value_parser = parse_size_range)]
+    size: (usize, usize),
+}
+
+// Parse a size like "512", "4k", "2m" or "1g" into bytes
+pub fn parse_size(spec: &str) -> Option<usize> {
+    let spec = spec.trim().to_ascii_lowercase();
+    let (number, multiplier) = match spec.chars().last()? {
+        'k' => (&spec[..spec.len() - 1], 1024),
+        'm' => (&spec[..spec.len() - 1], 1024 * 1024),
+        'g' => (&spec[..spec.len() - 1], 1024 * 1024 * 1024),
+        'b' => (&spec[..spec.len() - 1], 1),
+        _ => (spec.as_str(), 1),
+    };
+    number.parse::<usize>().ok()?.checked_mul(multiplier)
+}
+
+// clap value parser for a fixed size or a "min-max" size range
+fn parse_size_range(spec: &str) -> Result<(usize, usize), String> {
+    let invalid = || format!("invalid size '{}', use e.g. 4k or 512-64k", spec);
+    let (min, max) = match spec.split_once('-') {
+        Some((min, max)) => (parse_size(min), parse_size(max)),
+        None => (parse_size(spec), parse_size(spec)),
+    };
+    match (min, max) {
+        (Some(min), Some(max)) if min <= max => Ok((min, max)),
+        _ => Err(invalid()),
+    }
+}
+
+// Outcome of a single worker
+#[derive(Default)]
+struct WorkerResult {
+    latencies_us: Vec<u128>,
+    bytes_sent: usize,
+    errors: usize,
+}
+
+// Keep posting payloads until the deadline passes
+fn worker(url: &str, payload: &[u8], size: (usize, usize), deadline: Instant) -> WorkerResult {
+    let agent = ureq::AgentBuilder::new()
+        .timeout(Duration::from_secs(60))
+        .build();
+    let mut result = WorkerResult::default();
+    while Instant::now() < deadline {
+        let len = rand::rng().random_range(size.0..=size.1);
+        let start = Instant::now();
+        match agent.post(url).send_bytes(&payload[..len]) {
+            Ok(response) => {
+                // Drain the body so the connection can be reused
+                let _ = std::io::copy(&mut response.into_reader(), &mut std::io::sink());
+                result.latencies_us.push(start.elapsed().as_micros());
+                result.bytes_sent += len;
+            }
+            Err(_) => result.errors += 1,
+        }
+    }
+    result
+}
+
+// Value at the given percentile of an already sorted slice
+fn percentile(sorted: &[u128], pct: f64) -> u128 {
+    if sorted.is_empty() {
+        return 0;
+    }
+    let rank = ((pct / 100.0) * (sorted.len() - 1) as f64).round() as usize;
+    sorted[rank]
+}
+
+// Run the load test and print latency/throughput results
+pub fn run(args: LoadtestArgs) -> std::io::Result<()> {
+    let url = format!(
+        "{}/{}",
+        args.url.trim_end_matches('/'),
+        args.endpoint.trim_start_matches('/')
+    );
+    println!(
+        "Firing {}-{} byte payloads at {} with {} clients for {}s",
+        args.size.0, args.size.1, url, args.concurrency, args.duration
+    );
+
+    // One shared buffer, every request sends a prefix of it
+    let mut buffer = vec![0u8; args.size.1];
+    rand::rng().fill(&mut buffer[..]);
+    let payload: Arc<[u8]> = buffer.into();
+
+    let start = Instant::now();
+    let deadline = start + Duration::from_secs(args.duration as u64);
+    let handles: Vec<_> = (0..args.concurrency.max(1))
+        .map(|_| {
+            let url = url.clone();
+            let payload = payload.clone();
+            thread::spawn(move || worker(&url, &payload, args.size, deadline))
+        })
+        .collect();
+    let mut latencies = Vec::new();
+    let mut bytes_sent = 0;
+    let mut errors = 0;
+    for handle in handles {
+        let result = handle
+            .join()
+            .map_err(|_| std::io::Error::other("load test worker panicked"))?;
+        latencies.extend(result.latencies_us);
+        bytes_sent += result.bytes_sent;
+        errors += result.errors;
+    }
+    let elapsed = start.elapsed().as_secs_f64();
+    latencies.sort_unstable();
+
+    println!("Requests:    {} ok, {} failed", latencies.len(), errors);
+    println!(
+        "Throughput:  {:.1} req/s, {:.2} MiB/s",
+        latencies.len() as f64 / elapsed,
+        bytes_sent as f64 / elapsed / (1024.0 * 1024.0)
+    );
+    if !latencies.is_empty() {
+        let avg = latencies.iter().sum::<u128>() / latencies.len() as u128;
+        println!(
+            "Latency (us): min {}, avg {}, p50 {}, p90 {}, p99 {}, max {}",
+            latencies[0],
+            avg,
+            percentile(&latencies, 50.0),
+            percentile(&latencies, 90.0),
+            percentile(&latencies, 99.0),
+            latencies[latencies.len() - 1]
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,274 @@
+// Stats in an append-only file of JSON lines, the store of builds without SQLite
+//
+// Built with `--no-default-features --features log-store` the server is pure Rust, so
+// static musl and other cross builds need no C toolchain. Every stats row and rejection
+// is appended to --db-path as one line, written and synced in one go, and added to
+// totals kept in memory that /stats, /stats/summary and /metrics are answered from. The
+// file is read once on startup to rebuild them; a last line cut off by a crash is
+// dropped then. Nothing is ever rewritten, the file only grows.
+use crate::store::{RejectionCounts, Store, Writer};
+use crate::{DbArgs, EndpointSums, NewStat, StatsSummary};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::sync::{Mutex, MutexGuard};
+
+// One line of the file. Rows are written borrowed and read back owned.
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+enum Entry<R> {
+    Stat {
+        id: i64,
+        #[serde(flatten)]
+        row: R,
+    },
+    Rejection {
+        endpoint: String,
+        status: u16,
+        reason: String,
+        ts: String,
+    },
+}
+
+struct Log {
+    file: File,
+    read_only: bool,
+    // Bytes of complete lines, a failed append is cut back to this
+    len: u64,
+    next_id: i64,
+    sums: BTreeMap<String, EndpointSums>,
+    rejections: RejectionCounts,
+    first_ts: Option<String>,
+    last_ts: Option<String>,
+}
+
+pub struct LogStore {
+    log: Mutex<Log>,
+}
+
+// Count a row like the SQL behind /stats does: sampled rows stand for more destructions,
+// the averages of the phases don't change
+fn add_row(sums: &mut EndpointSums, row: &NewStat) {
+    let weight = row.sample_weight.unwrap_or(1);
+    sums.count += weight;
+    sums.bytes += row.payload_size * weight;
+    sums.runtime_us += row.runtime_us * weight;
+    if let Some(passes) = row.passes {
+        *sums.passes.get_or_insert(0) += i64::from(passes) * weight;
+    }
+    for (phase, value) in [
+        (&mut sums.read_us, row.read_us),
+        (&mut sums.process_us, row.process_us),
+        (&mut sums.persist_us, row.persist_us),
+        (&mut sums.work_us, row.work_us),
+    ] {
+        if let Some(value) = value {
+            phase.0 += value;
+            phase.1 += 1;
+        }
+    }
+    sums.chunked += i64::from(row.chunked.unwrap_or(false)) * weight;
+    sums.checksum_mismatches += i64::from(row.checksum_mismatch.unwrap_or(false)) * weight;
+}
+
+fn add_sums(into: &mut EndpointSums, sums: &EndpointSums) {
+    into.count += sums.count;
+    into.bytes += sums.bytes;
+    into.runtime_us += sums.runtime_us;
+    if let Some(passes) = sums.passes {
+        *into.passes.get_or_insert(0) += passes;
+    }
+    for (into, phase) in [
+        (&mut into.read_us, sums.read_us),
+        (&mut into.process_us, sums.process_us),
+        (&mut into.persist_us, sums.persist_us),
+        (&mut into.work_us, sums.work_us),
+    ] {
+        into.0 += phase.0;
+        into.1 += phase.1;
+    }
+    into.chunked += sums.chunked;
+    into.checksum_mismatches += sums.checksum_mismatches;
+}
+
+impl Log {
+    fn add_stat(&mut self, id: i64, row: &NewStat) {
+        self.next_id = self.next_id.max(id + 1);
+        add_row(self.sums.entry(row.endpoint.clone()).or_default(), row);
+        // Buffered rows are appended after newer ones, the timestamps sort as text
+        if self.first_ts.as_ref().is_none_or(|first| row.ts < *first) {
+            self.first_ts = Some(row.ts.clone());
+        }
+        if self.last_ts.as_ref().is_none_or(|last| row.ts > *last) {
+            self.last_ts = Some(row.ts.clone());
+        }
+    }
+
+    fn add_rejection(&mut self, endpoint: String, reason: String) {
+        *self.rejections.entry((endpoint, reason)).or_default() += 1;
+    }
+
+    fn append<R: Serialize>(&mut self, entries: &[Entry<R>]) -> Result<(), String> {
+        if self.read_only {
+            return Err("the stats log is open read-only".to_string());
+        }
+        let mut lines = String::new();
+        for entry in entries {
+            lines.push_str(&serde_json::to_string(entry).map_err(|e| e.to_string())?);
+            lines.push('\n');
+        }
+        let result = self
+            .file
+            .write_all(lines.as_bytes())
+            .and_then(|()| self.file.sync_data());
+        if let Err(e) = result {
+            // Don't leave half a line for the next append to continue
+            let _ = self.file.set_len(self.len);
+            return Err(e.to_string());
+        }
+        self.len += lines.len() as u64;
+        Ok(())
+    }
+}
+
+impl LogStore {
+    // Open the log at --db-path and total up what it holds. Read-only logs have to exist.
+    pub fn open(db: &DbArgs, read_only: bool) -> Result<Self, String> {
+        if read_only && !std::path::Path::new(&db.db_path).is_file() {
+            return Err(format!("Database {} does not exist", db.db_path));
+        }
+        if !read_only {
+            crate::prepare_db_file(db)?;
+        }
+        let file = OpenOptions::new()
+            .read(true)
+            .append(!read_only)
+            .open(&db.db_path)
+            .map_err(|e| format!("Failed to open database {}: {}", db.db_path, e))?;
+        let mut log = Log {
+            file,
+            read_only,
+            len: 0,
+            next_id: 1,
+            sums: BTreeMap::new(),
+            rejections: RejectionCounts::new(),
+            first_ts: None,
+            last_ts: None,
+        };
+        let damaged = |e: &dyn std::fmt::Display, at: u64| {
+            format!("Database {} is damaged at byte {}: {}", db.db_path, at, e)
+        };
+        let mut reader = BufReader::new(
+            File::open(&db.db_path)
+                .map_err(|e| format!("Failed to open database {}: {}", db.db_path, e))?,
+        );
+        let mut line = String::new();
+        loop {
+            line.clear();
+            let read = reader
+                .read_line(&mut line)
+                .map_err(|e| damaged(&e, log.len))?;
+            // A line without its newline was cut off while being written
+            if read == 0 || !line.ends_with('\n') {
+                break;
+            }
+            match serde_json::from_str(&line).map_err(|e| damaged(&e, log.len))? {
+                Entry::Stat { id, row } => log.add_stat(id, &row),
+                Entry::Rejection {
+                    endpoint, reason, ..
+                } => log.add_rejection(endpoint, reason),
+            }
+            log.len += read as u64;
+        }
+        let size = log
+            .file
+            .metadata()
+            .map_err(|e| format!("Failed to read database {}: {}", db.db_path, e))?
+            .len();
+        if size > log.len && !read_only {
+            eprintln!(
+                "Dropping {} bytes of an unfinished line at the end of {}",
+                size - log.len,
+                db.db_path
+            );
+            log.file
+                .set_len(log.len)
+                .map_err(|e| format!("Failed to repair database {}: {}", db.db_path, e))?;
+        }
+        Ok(LogStore {
+            log: Mutex::new(log),
+        })
+    }
+}
+
+impl Store for LogStore {
+    fn writer(&self) -> Box<dyn Writer + '_> {
+        Box::new(self.log.lock().unwrap())
+    }
+
+    fn add_endpoint_sums(&self, sums: &mut BTreeMap<String, EndpointSums>) -> Result<(), String> {
+        for (endpoint, logged) in &self.log.lock().unwrap().sums {
+            add_sums(sums.entry(endpoint.clone()).or_default(), logged);
+        }
+        Ok(())
+    }
+
+    fn add_rejection_counts(&self, counts: &mut RejectionCounts) -> Result<(), String> {
+        for (key, count) in &self.log.lock().unwrap().rejections {
+            *counts.entry(key.clone()).or_default() += count;
+        }
+        Ok(())
+    }
+
+    fn summary(&self) -> Result<StatsSummary, String> {
+        let log = self.log.lock().unwrap();
+        let (requests, bytes, runtime_us) = log.sums.values().fold((0, 0, 0), |totals, sums| {
+            (
+                totals.0 + sums.count,
+                totals.1 + sums.bytes,
+                totals.2 + sums.runtime_us,
+            )
+        });
+        let elapsed_s = log.first_ts.as_deref().and_then(|first| {
+            chrono::NaiveDateTime::parse_from_str(first, "%Y-%m-%d %H:%M:%S")
+                .ok()
+                .map(|first| (chrono::Utc::now().naive_utc() - first).num_seconds())
+        });
+        Ok(StatsSummary::new(
+            requests,
+            bytes,
+            runtime_us,
+            log.first_ts.clone(),
+            log.last_ts.clone(),
+            elapsed_s,
+        ))
+    }
+}
+
+impl Writer for MutexGuard<'_, Log> {
+    fn insert(&mut self, rows: &[NewStat]) -> Result<Vec<i64>, String> {
+        let ids: Vec<i64> = (self.next_id..).take(rows.len()).collect();
+        let entries: Vec<_> = ids
+            .iter()
+            .zip(rows)
+            .map(|(&id, row)| Entry::Stat { id, row })
+            .collect();
+        self.append(&entries)?;
+        for (&id, row) in ids.iter().zip(rows) {
+            self.add_stat(id, row);
+        }
+        Ok(ids)
+    }
+
+    fn reject(&mut self, endpoint: &str, status: u16, reason: &str) -> Result<(), String> {
+        self.append(&[Entry::<&NewStat>::Rejection {
+            endpoint: endpoint.to_string(),
+            status,
+            reason: reason.to_string(),
+            ts: chrono::Utc::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+        }])?;
+        self.add_rejection(endpoint.to_string(), reason.to_string());
+        Ok(())
+    }
+}
@@ -0,0 +1,144 @@
+// --max-memory: refuse large uploads while the process uses too much memory
+//
+// The resident set size is sampled twice a second from /proc (Linux only). Above the
+// limit, destruction requests announcing more than LARGE_UPLOAD bytes, or no length at
+// all, get 503 until memory is back under the limit. Small requests keep working.
+use actix_web::body::{BoxBody, MessageBody};
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::header::CONTENT_LENGTH;
+use actix_web::web::Data;
+use actix_web::{Error, HttpResponse};
+use std::future::{ready, Future, Ready};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+const SAMPLE_INTERVAL: Duration = Duration::from_millis(500);
+// Uploads up to this size are still accepted above the limit
+const LARGE_UPLOAD: u64 = 1024 * 1024;
+
+pub struct MemoryGuard {
+    limit: u64,
+    over_limit: AtomicBool,
+}
+
+// Resident set size of this process, None where /proc isn't available
+fn rss_bytes() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmRSS:"))?;
+    let kb: u64 = line
+        .trim_start_matches("VmRSS:")
+        .trim()
+        .trim_end_matches("kB")
+        .trim()
+        .parse()
+        .ok()?;
+    Some(kb * 1024)
+}
+
+impl MemoryGuard {
+    // None if the memory usage can't be measured on this system
+    pub fn new(limit: u64) -> Option<Self> {
+        let rss = rss_bytes()?;
+        Some(MemoryGuard {
+            limit,
+            over_limit: AtomicBool::new(rss > limit),
+        })
+    }
+
+    fn sample(&self) {
+        let Some(rss) = rss_bytes() else {
+            return;
+        };
+        let over_limit = rss > self.limit;
+        if self.over_limit.swap(over_limit, Ordering::Relaxed) != over_limit {
+            match over_limit {
+                true => eprintln!(
+                    "Memory usage {} MB is above --max-memory {}, refusing large uploads",
+                    rss / (1024 * 1024),
+                    crate::format_size(self.limit as usize)
+                ),
+                false => eprintln!(
+                    "Memory usage {} MB is back under --max-memory, accepting large uploads again",
+                    rss / (1024 * 1024)
+                ),
+            }
+        }
+    }
+}
+
+pub fn spawn_monitor(guard: Data<MemoryGuard>) {
+    std::thread::spawn(move || loop {
+        std::thread::sleep(SAMPLE_INTERVAL);
+        guard.sample();
+    });
+}
+
+// Whether a request is refused for now, uploads of unknown length count as large
+fn is_refused(req: &ServiceRequest) -> bool {
+    let Some(guard) = req.app_data::<Data<MemoryGuard>>() else {
+        return false;
+    };
+    if !guard.over_limit.load(Ordering::Relaxed) || !crate::read_only::is_destruction(req) {
+        return false;
+    }
+    req.headers()
+        .get(CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<u64>().ok())
+        .is_none_or(|length| length > LARGE_UPLOAD)
+}
+
+// Middleware refusing large destruction requests while memory is above the limit
+pub struct RefuseLargeUploads;
+
+impl<S, B> Transform<S, ServiceRequest> for RefuseLargeUploads
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<BoxBody>;
+    type Error = Error;
+    type Transform = RefuseLargeUploadsMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RefuseLargeUploadsMiddleware { service }))
+    }
+}
+
+pub struct RefuseLargeUploadsMiddleware<S> {
+    service: S,
+}
+
+impl<S, B> Service<ServiceRequest> for RefuseLargeUploadsMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<BoxBody>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    fn poll_ready(&self, ctx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(ctx)
+    }
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        if !is_refused(&req) {
+            let fut = self.service.call(req);
+            return Box::pin(async move { Ok(fut.await?.map_into_boxed_body()) });
+        }
+        let response = HttpResponse::ServiceUnavailable()
+            .insert_header(("Retry-After", "5"))
+            .json(serde_json::json!({
+                "error": format!(
+                    "The pulverizer is low on memory and only accepts payloads up to {} right now. Please try again shortly.",
+                    crate::format_size(LARGE_UPLOAD as usize)
+                )
+            }));
+        Box::pin(async move { Ok(req.into_response(response)) })
+    }
+}
@@ -0,0 +1,160 @@
+// `merge` subcommand: combine the stats of several pulverizer databases into one
+//
+// Rows are copied into the --db-path database unless an identical row (same values
+// in every column but the id) is already there, so merging a file twice or merging
+// overlapping backups counts every request once. Ids are assigned anew.
+use crate::DbArgs;
+use rusqlite::{params, Connection};
+use std::path::Path;
+
+// Columns copied from every input, all of them make up a row's identity
+const COLUMNS: &[&str] = &[
+    "endpoint",
+    "payload_size",
+    "runtime_us",
+    "ts",
+    "passes",
+    "read_us",
+    "process_us",
+    "persist_us",
+    "client_ip",
+    "user_agent",
+    "country",
+    "city",
+    "chunked",
+    "tenant",
+    "work_us",
+    "checksum_mismatch",
+    "sample_weight",
+];
+
+// CLI arguments of the merge subcommand
+#[derive(clap::Args, Debug, Clone)]
+pub struct MergeArgs {
+    /// Databases to merge into --db-path, e.g. files of other pods or rotated files
+    #[arg(required = true)]
+    inputs: Vec<String>,
+}
+
+// Columns an input has, older databases may lack the newer ones
+fn input_columns(conn: &Connection) -> rusqlite::Result<Vec<String>> {
+    let mut stmt =
+        conn.prepare("SELECT name FROM pragma_table_info('endpoint_stats_raw', 'input')")?;
+    let names = stmt.query_map([], |row| row.get(0))?;
+    names.collect()
+}
+
+// Copy the rows of the attached input, returns (rows read, rows added)
+fn copy_rows(conn: &Connection, available: &[String]) -> rusqlite::Result<(i64, usize)> {
+    let select = COLUMNS
+        .iter()
+        .map(|column| match available.iter().any(|name| name == column) {
+            true => format!("s.{}", column),
+            false => format!("NULL AS {}", column),
+        })
+        .collect::<Vec<_>>()
+        .join(", ");
+    let same = COLUMNS
+        .iter()
+        .map(|column| format!("m.{0} IS c.{0}", column))
+        .collect::<Vec<_>>()
+        .join(" AND ");
+    let tx = conn.unchecked_transaction()?;
+    let read: i64 = tx.query_row("SELECT COUNT(*) FROM input.endpoint_stats_raw", [], |row| {
+        row.get(0)
+    })?;
+    let before: i64 = tx.query_row(
+        "SELECT COALESCE(MAX(id), 0) FROM main.endpoint_stats_raw",
+        [],
+        |row| row.get(0),
+    )?;
+    let added = tx.execute(
+        &format!(
+            "INSERT INTO main.endpoint_stats_raw ({columns})
+            SELECT * FROM (SELECT {select} FROM input.endpoint_stats_raw s ORDER BY s.id) c
+            WHERE NOT EXISTS (SELECT 1 FROM main.endpoint_stats_raw m WHERE {same})",
+            columns = COLUMNS.join(", "),
+        ),
+        [],
+    )?;
+    // Count the new rows in the monthly usage, the inputs' own rollups would double count.
+    // Sampled rows stand for `sample_weight` destructions each.
+    tx.execute(
+        "INSERT INTO main.usage_rollups (month, tenant, requests, bytes, runtime_us)
+        SELECT strftime('%Y-%m', ts), COALESCE(tenant, ''), SUM(COALESCE(sample_weight, 1)), SUM(payload_size * COALESCE(sample_weight, 1)), SUM(runtime_us * COALESCE(sample_weight, 1))
+        FROM main.endpoint_stats_raw WHERE id > ?1 GROUP BY 1, 2
+        ON CONFLICT (month, tenant) DO UPDATE SET requests = requests + excluded.requests, bytes = bytes + excluded.bytes, runtime_us = runtime_us + excluded.runtime_us",
+        params![before],
+    )?;
+    tx.commit()?;
+    Ok((read, added))
+}
+
+// Merge one input file, returns (rows read, rows added)
+fn merge_input(conn: &Connection, path: &str) -> Result<(i64, usize), String> {
+    conn.execute("ATTACH DATABASE ?1 AS input", [path])
+        .map_err(|e| format!("Failed to open {}: {}", path, e))?;
+    let result = input_columns(conn)
+        .and_then(|available| match available.is_empty() {
+            true => Ok(None),
+            false => copy_rows(conn, &available).map(Some),
+        })
+        .map_err(|e| format!("Failed to merge {}: {}", path, e));
+    let _ = conn.execute("DETACH DATABASE input", []);
+    result?.ok_or_else(|| format!("{} has no stats table", path))
+}
+
+fn is_same_file(a: &str, b: &str) -> bool {
+    match (Path::new(a).canonicalize(), Path::new(b).canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+pub fn run(db: &DbArgs, args: MergeArgs) -> std::io::Result<()> {
+    let fail = |message: String| -> ! {
+        eprintln!("{}", message);
+        std::process::exit(1);
+    };
+    for input in &args.inputs {
+        if !Path::new(input).is_file() {
+            fail(format!("Database {} does not exist", input));
+        }
+        if is_same_file(input, &db.db_path) {
+            fail(format!("Can't merge {} into itself", input));
+        }
+    }
+    let conn = crate::init_db_or_exit(db);
+    // Makes the duplicate check an index lookup instead of a scan per row
+    conn.execute_batch(
+        "CREATE INDEX IF NOT EXISTS merge_identity ON endpoint_stats_raw (ts, endpoint, payload_size, runtime_us)",
+    )
+    .unwrap_or_else(|e| fail(format!("Failed to prepare {}: {}", db.db_path, e)));
+    let mut total = 0;
+    let mut result = Ok(());
+    for input in &args.inputs {
+        match merge_input(&conn, input) {
+            Ok((read, added)) => {
+                println!(
+                    "Merged {} of {} rows from {} ({} duplicates)",
+                    added,
+                    read,
+                    input,
+                    read - added as i64
+                );
+                total += added;
+            }
+            Err(e) => {
+                result = Err(e);
+                break;
+            }
+        }
+    }
+    let _ = conn.execute_batch("DROP INDEX IF EXISTS merge_identity");
+    // Inputs merged before a failure stay merged, running again skips them as duplicates
+    if let Err(e) = result {
+        fail(e);
+    }
+    println!("Added {} stats rows to {}", total, db.db_path);
+    Ok(())
+}
@@ -0,0 +1,149 @@
+// Prometheus text exposition of the stats store, served on /metrics or pushed to a Pushgateway
+use crate::store::Store;
+use crate::EndpointSums;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use std::fmt::Write;
+use std::time::Duration;
+
+pub const CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+// (name, help, value) of every exported metric, labeled by endpoint. Rows recorded with
+// --stats-sample count as many destructions as they stand for.
+type Metric = (&'static str, &'static str, fn(&EndpointSums) -> f64);
+const METRICS: &[Metric] = &[
+    (
+        "payload_pulverizer_requests_total",
+        "Destruction requests per endpoint.",
+        |sums| sums.count as f64,
+    ),
+    (
+        "payload_pulverizer_bytes_total",
+        "Payload bytes destroyed per endpoint.",
+        |sums| sums.bytes as f64,
+    ),
+    (
+        "payload_pulverizer_runtime_seconds_total",
+        "Time spent handling destruction requests per endpoint.",
+        |sums| sums.runtime_us as f64 / 1e6,
+    ),
+];
+
+// Counters are totals over the stats table, so pruning it makes them drop (like a restart would)
+pub fn render(store: &dyn Store) -> Result<String, String> {
+    let mut sums = std::collections::BTreeMap::new();
+    store.add_endpoint_sums(&mut sums)?;
+    let mut out = String::new();
+    for (name, help, value) in METRICS {
+        let _ = writeln!(out, "# HELP {} {}", name, help);
+        let _ = writeln!(out, "# TYPE {} counter", name);
+        for (endpoint, sums) in &sums {
+            let _ = writeln!(
+                out,
+                "{}{{endpoint=\"{}\"}} {}",
+                name,
+                escape_label(endpoint),
+                value(sums)
+            );
+        }
+    }
+    let mut rejected = std::collections::BTreeMap::new();
+    store.add_rejection_counts(&mut rejected)?;
+    let name = "payload_pulverizer_rejections_total";
+    let _ = writeln!(
+        out,
+        "# HELP {} Requests rejected before destroying anything, per endpoint and reason.",
+        name
+    );
+    let _ = writeln!(out, "# TYPE {} counter", name);
+    for ((endpoint, reason), count) in rejected {
+        let _ = writeln!(
+            out,
+            "{}{{endpoint=\"{}\",reason=\"{}\"}} {}",
+            name,
+            escape_label(&endpoint),
+            escape_label(&reason),
+            count
+        );
+    }
+    Ok(out)
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+// Where and how often to push, from --pushgateway-url and friends
+pub struct PushConfig {
+    pub url: String,
+    pub job: String,
+    pub instance: Option<String>,
+    pub interval: Duration,
+}
+
+// A label of the grouping key as URL path segments. Values are percent-encoded, except
+// empty ones and ones with a slash, which the Pushgateway only takes base64url encoded.
+fn grouping_label(name: &str, value: &str) -> String {
+    if value.is_empty() {
+        return format!("/{}@base64/=", name);
+    }
+    if value.contains('/') {
+        return format!("/{}@base64/{}", name, URL_SAFE_NO_PAD.encode(value));
+    }
+    let mut encoded = format!("/{}/", name);
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                encoded.push(byte as char)
+            }
+            _ => {
+                let _ = write!(encoded, "%{:02X}", byte);
+            }
+        }
+    }
+    encoded
+}
+
+impl PushConfig {
+    // Pushgateway grouping key URL, e.g. http://gw:9091/metrics/job/pulverizer/instance/a
+    fn target(&self) -> String {
+        let mut target = format!(
+            "{}/metrics{}",
+            self.url.trim_end_matches('/'),
+            grouping_label("job", &self.job)
+        );
+        if let Some(instance) = &self.instance {
+            target.push_str(&grouping_label("instance", instance));
+        }
+        target
+    }
+}
+
+// Push the metrics on a background thread for as long as the process runs
+pub fn spawn_pusher(db: actix_web::web::Data<dyn Store>, config: PushConfig) {
+    std::thread::spawn(move || {
+        let agent = ureq::AgentBuilder::new().timeout(config.interval).build();
+        let target = config.target();
+        loop {
+            std::thread::sleep(config.interval);
+            let body = match render(db.get_ref()) {
+                Ok(body) => body,
+                Err(e) => {
+                    eprintln!("Failed to collect metrics for the Pushgateway: {}", e);
+                    continue;
+                }
+            };
+            // PUT replaces all metrics of our group, so endpoints pruned away disappear too
+            if let Err(e) = agent
+                .put(&target)
+                .set("Content-Type", CONTENT_TYPE)
+                .send_string(&body)
+            {
+                eprintln!("Failed to push metrics to {}: {}", target, e);
+            }
+        }
+    });
+}
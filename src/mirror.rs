@@ -0,0 +1,65 @@
+// /mirror: destroy the body, describe the request that carried it
+//
+// Answers any method with what actually arrived: method, path, query string, HTTP
+// version, every header, the declared and the actual body length and the client
+// address as the server sees it. Meant for debugging what proxies and SDKs send.
+// Nothing is recorded in the stats.
+use crate::{body, AppConfig};
+use actix_web::http::header::CONTENT_LENGTH;
+use actix_web::web::Data;
+use actix_web::{HttpRequest, HttpResponse, Result};
+use serde::Serialize;
+
+#[derive(Serialize)]
+struct MirrorResponse {
+    method: String,
+    path: String,
+    query_string: String,
+    http_version: String,
+    // [name, value] pairs, repeated headers appear repeatedly
+    headers: Vec<[String; 2]>,
+    // None without a Content-Length header (e.g. chunked)
+    declared_length: Option<u64>,
+    actual_length: usize,
+    chunked: bool,
+    peer_addr: Option<String>,
+    // Differs from peer_addr behind --trusted-proxies
+    client_ip: Option<String>,
+}
+
+/// Handler for /mirror (any method)
+/// Destroys the payload and echoes back how the request arrived.
+pub async fn mirror_handler(
+    req: HttpRequest,
+    body: body::Body,
+    config: Data<AppConfig>,
+) -> Result<HttpResponse> {
+    let headers = req
+        .headers()
+        .iter()
+        .map(|(name, value)| {
+            [
+                name.to_string(),
+                String::from_utf8_lossy(value.as_bytes()).into_owned(),
+            ]
+        })
+        .collect();
+    let declared_length = req
+        .headers()
+        .get(CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().parse().ok());
+    let response = MirrorResponse {
+        method: req.method().to_string(),
+        path: req.path().to_string(),
+        query_string: req.query_string().to_string(),
+        http_version: format!("{:?}", req.version()),
+        headers,
+        declared_length,
+        actual_length: body.len(),
+        chunked: body::is_chunked(&req),
+        peer_addr: req.peer_addr().map(|addr| addr.to_string()),
+        client_ip: crate::client_ip(&req, &config.trusted_proxies).map(|ip| ip.to_string()),
+    };
+    Ok(HttpResponse::Ok().json(response))
+}
@@ -0,0 +1,100 @@
+// NDJSON validation for /validate-before-destroy
+//
+// Payloads declared as application/x-ndjson (or application/jsonl) hold one JSON
+// document per line, so they are checked record by record instead of as one document,
+// which they never are once there's more than one record. Every line is a record, only
+// the terminator of the last one is optional; blank lines are invalid records.
+use serde::Serialize;
+
+// Invalid records listed per payload, the rest is counted
+const MAX_INVALID_RECORDS: usize = 100;
+
+pub const MEDIA_TYPES: &[&str] = &["application/x-ndjson", "application/jsonl"];
+
+// Record sizes in bytes, without the line terminator
+#[derive(Serialize)]
+pub struct RecordSizes {
+    min: usize,
+    max: usize,
+    avg: f64,
+    p50: usize,
+    p90: usize,
+    p99: usize,
+}
+
+#[derive(Serialize)]
+pub struct NdjsonReport {
+    records: usize,
+    // Indices of records that don't parse, counting from 0 with one record per line
+    invalid_records: Vec<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    more_invalid: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sizes: Option<RecordSizes>,
+    // Reason of the first invalid record
+    #[serde(skip_serializing_if = "Option::is_none")]
+    first_error: Option<String>,
+}
+
+// Value at the given percentile of an already sorted slice
+fn percentile(sorted: &[usize], pct: f64) -> usize {
+    let rank = ((pct / 100.0) * (sorted.len() - 1) as f64).round() as usize;
+    sorted[rank]
+}
+
+pub fn report(body_str: &str) -> NdjsonReport {
+    let text = body_str
+        .strip_suffix('\n')
+        .map(|text| text.strip_suffix('\r').unwrap_or(text))
+        .unwrap_or(body_str);
+    let mut sizes = Vec::new();
+    let mut invalid = 0;
+    let mut invalid_records = Vec::new();
+    let mut first_error = None;
+    if !text.is_empty() {
+        for (index, line) in text.split('\n').enumerate() {
+            let record = line.strip_suffix('\r').unwrap_or(line);
+            sizes.push(record.len());
+            if let Err(e) = serde_json::from_str::<serde::de::IgnoredAny>(record) {
+                invalid += 1;
+                if invalid_records.len() < MAX_INVALID_RECORDS {
+                    invalid_records.push(index);
+                }
+                first_error.get_or_insert_with(|| {
+                    let message = e.to_string();
+                    let reason = message.split(" at line ").next().unwrap_or(&message);
+                    format!("record {}, column {}: {}", index, e.column(), reason)
+                });
+            }
+        }
+    }
+    let records = sizes.len();
+    sizes.sort_unstable();
+    NdjsonReport {
+        records,
+        invalid_records,
+        more_invalid: (invalid > MAX_INVALID_RECORDS).then(|| invalid - MAX_INVALID_RECORDS),
+        sizes: (!sizes.is_empty()).then(|| RecordSizes {
+            min: sizes[0],
+            max: sizes[records - 1],
+            avg: sizes.iter().sum::<usize>() as f64 / records as f64,
+            p50: percentile(&sizes, 50.0),
+            p90: percentile(&sizes, 90.0),
+            p99: percentile(&sizes, 99.0),
+        }),
+        first_error,
+    }
+}
+
+impl NdjsonReport {
+    pub fn detail(&self) -> String {
+        let invalid = self.invalid_records.len() + self.more_invalid.unwrap_or(0);
+        match &self.first_error {
+            None => format!("Valid NDJSON with {} records.", self.records),
+            Some(error) => format!(
+                "NDJSON with {} records, {} invalid. First invalid {}.",
+                self.records, invalid, error
+            ),
+        }
+    }
+}
@@ -0,0 +1,164 @@
+// ?respond_bytes=N: pad destruction responses with N bytes of filler
+//
+// Uploads are large and answers tiny, which is the wrong way round for testing links
+// with asymmetric bandwidth. With `?respond_bytes=N` (up to --max-respond-bytes) the
+// response carries N extra bytes: JSON answers get them as a `padding` string so they
+// still parse, anything else gets them appended. The filler is streamed from a static
+// buffer, so large paddings cost no memory.
+use crate::AppConfig;
+use actix_web::body::{self, BoxBody, MessageBody, SizedStream};
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::header::{CONTENT_LENGTH, CONTENT_TYPE};
+use actix_web::http::StatusCode;
+use actix_web::web::{self, Bytes, Data};
+use actix_web::{Error, HttpResponse};
+use futures_util::stream;
+use serde::Deserialize;
+use std::future::{ready, Future, Ready};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+const CHUNK_SIZE: usize = 64 * 1024;
+static FILLER: [u8; CHUNK_SIZE] = [b'0'; CHUNK_SIZE];
+
+#[derive(Deserialize)]
+struct PaddingQuery {
+    respond_bytes: Option<String>,
+}
+
+// Requested padding, Err with the cap if it's not a number up to the cap
+fn requested(req: &ServiceRequest) -> Result<Option<usize>, usize> {
+    let max = req
+        .app_data::<Data<AppConfig>>()
+        .map_or(0, |config| config.max_respond_bytes);
+    let Ok(query) = web::Query::<PaddingQuery>::from_query(req.query_string()) else {
+        return Ok(None);
+    };
+    match query.respond_bytes.as_deref().map(str::parse::<usize>) {
+        None => Ok(None),
+        Some(Ok(bytes)) if bytes <= max => Ok(Some(bytes)),
+        Some(_) => Err(max),
+    }
+}
+
+// The filler in chunks borrowed from FILLER
+fn filler(mut left: usize) -> impl Iterator<Item = Bytes> {
+    std::iter::from_fn(move || {
+        let n = left.min(CHUNK_SIZE);
+        left -= n;
+        (n > 0).then(|| Bytes::from_static(&FILLER[..n]))
+    })
+}
+
+// The original body with `bytes` of filler, inside a JSON object where there is one
+fn pad(
+    original: Bytes,
+    is_json: bool,
+    bytes: usize,
+) -> SizedStream<impl futures_util::Stream<Item = Result<Bytes, Error>>> {
+    let closing = original.iter().rposition(|b| !b.is_ascii_whitespace());
+    let (head, tail): (Bytes, Bytes) = match closing {
+        Some(i) if is_json && original[i] == b'}' => {
+            let inner = &original[..i];
+            let empty = inner.iter().all(|b| b.is_ascii_whitespace() || *b == b'{');
+            let field = if empty {
+                "\"padding\":\""
+            } else {
+                ",\"padding\":\""
+            };
+            let mut head = inner.to_vec();
+            head.extend_from_slice(field.as_bytes());
+            (head.into(), Bytes::from_static(b"\"}"))
+        }
+        _ => (original, Bytes::new()),
+    };
+    let size = (head.len() + bytes + tail.len()) as u64;
+    let chunks = std::iter::once(head)
+        .chain(filler(bytes))
+        .chain(std::iter::once(tail))
+        .filter(|chunk| !chunk.is_empty())
+        .map(Ok);
+    SizedStream::new(size, stream::iter(chunks))
+}
+
+// Middleware padding destruction responses as asked for with ?respond_bytes=
+pub struct PadResponse;
+
+impl<S, B> Transform<S, ServiceRequest> for PadResponse
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<BoxBody>;
+    type Error = Error;
+    type Transform = PadResponseMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(PadResponseMiddleware { service }))
+    }
+}
+
+pub struct PadResponseMiddleware<S> {
+    service: S,
+}
+
+impl<S, B> Service<ServiceRequest> for PadResponseMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<BoxBody>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    fn poll_ready(&self, ctx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(ctx)
+    }
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let padding = match crate::read_only::is_destruction(&req) {
+            true => requested(&req),
+            false => Ok(None),
+        };
+        let bytes = match padding {
+            Ok(Some(bytes)) => bytes,
+            Ok(None) => {
+                let fut = self.service.call(req);
+                return Box::pin(async move { Ok(fut.await?.map_into_boxed_body()) });
+            }
+            Err(max) => {
+                let response = HttpResponse::BadRequest().json(serde_json::json!({
+                    "error": format!(
+                        "respond_bytes must be a number of bytes up to {} (--max-respond-bytes).",
+                        max
+                    )
+                }));
+                return Box::pin(async move { Ok(req.into_response(response)) });
+            }
+        };
+        let fut = self.service.call(req);
+        Box::pin(async move {
+            let (req, mut res) = fut.await?.into_parts();
+            let is_json = res
+                .headers()
+                .get(CONTENT_TYPE)
+                .and_then(|value| value.to_str().ok())
+                .is_some_and(|value| value.starts_with("application/json"));
+            res.headers_mut().remove(CONTENT_LENGTH);
+            // 204 can't carry a body, e.g. from /blackhole
+            if res.status() == StatusCode::NO_CONTENT && bytes > 0 {
+                *res.status_mut() = StatusCode::OK;
+            }
+            let (res, original) = res.into_parts();
+            let original = body::to_bytes(original)
+                .await
+                .map_err(|e| actix_web::error::ErrorInternalServerError(e.into()))?;
+            let res = res
+                .set_body(pad(original, is_json, bytes))
+                .map_into_boxed_body();
+            Ok(ServiceResponse::new(req, res))
+        })
+    }
+}
@@ -0,0 +1,187 @@
+// Parquet export of the raw stats for /stats/export?format=parquet (feature "parquet")
+//
+// The file is written on a background thread, one row group per page of rows, and
+// streamed out while it grows, so large histories are never held in memory at once.
+use crate::StatRow;
+use actix_web::web::{Bytes, Data};
+use arrow_array::builder::{
+    BooleanBuilder, Int64Builder, StringBuilder, TimestampMicrosecondBuilder,
+};
+use arrow_array::{ArrayRef, RecordBatch};
+use arrow_schema::{DataType, Field, Schema, TimeUnit};
+use futures_util::Stream;
+use parquet::arrow::ArrowWriter;
+use rusqlite::Connection;
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc;
+
+// Rows per database page and row group
+const PAGE_ROWS: usize = 10_000;
+// Bytes collected before they are handed to the response
+const CHUNK_SIZE: usize = 64 * 1024;
+// Chunks buffered ahead of a slow client
+const CHUNKS_AHEAD: usize = 4;
+
+fn schema() -> Arc<Schema> {
+    let int = |name| Field::new(name, DataType::Int64, true);
+    let text = |name| Field::new(name, DataType::Utf8, true);
+    Arc::new(Schema::new(vec![
+        Field::new("id", DataType::Int64, false),
+        Field::new("endpoint", DataType::Utf8, false),
+        Field::new("payload_size", DataType::Int64, false),
+        Field::new("runtime_us", DataType::Int64, false),
+        Field::new(
+            "ts",
+            DataType::Timestamp(TimeUnit::Microsecond, Some("UTC".into())),
+            true,
+        ),
+        int("passes"),
+        int("read_us"),
+        int("process_us"),
+        int("persist_us"),
+        text("client_ip"),
+        text("user_agent"),
+        text("country"),
+        text("city"),
+        Field::new("chunked", DataType::Boolean, true),
+        text("tenant"),
+        int("work_us"),
+        Field::new("checksum_mismatch", DataType::Boolean, true),
+        int("sample_weight"),
+    ]))
+}
+
+fn int_column(rows: &[StatRow], value: impl Fn(&StatRow) -> Option<i64>) -> ArrayRef {
+    let mut builder = Int64Builder::with_capacity(rows.len());
+    rows.iter()
+        .for_each(|row| builder.append_option(value(row)));
+    Arc::new(builder.finish())
+}
+
+fn text_column(rows: &[StatRow], value: impl Fn(&StatRow) -> Option<&str>) -> ArrayRef {
+    let mut builder = StringBuilder::new();
+    rows.iter()
+        .for_each(|row| builder.append_option(value(row)));
+    Arc::new(builder.finish())
+}
+
+fn to_batch(
+    schema: &Arc<Schema>,
+    rows: &[StatRow],
+) -> Result<RecordBatch, arrow_schema::ArrowError> {
+    // SQLite's CURRENT_TIMESTAMP is UTC without a zone
+    let mut ts = TimestampMicrosecondBuilder::with_capacity(rows.len()).with_timezone("UTC");
+    for row in rows {
+        ts.append_option(
+            chrono::NaiveDateTime::parse_from_str(&row.ts, "%Y-%m-%d %H:%M:%S")
+                .ok()
+                .map(|ts| ts.and_utc().timestamp_micros()),
+        );
+    }
+    let mut chunked = BooleanBuilder::with_capacity(rows.len());
+    rows.iter()
+        .for_each(|row| chunked.append_option(row.chunked));
+    let mut checksum_mismatch = BooleanBuilder::with_capacity(rows.len());
+    rows.iter()
+        .for_each(|row| checksum_mismatch.append_option(row.checksum_mismatch));
+    RecordBatch::try_new(
+        schema.clone(),
+        vec![
+            int_column(rows, |row| Some(row.id)),
+            text_column(rows, |row| Some(row.endpoint.as_str())),
+            int_column(rows, |row| Some(row.payload_size)),
+            int_column(rows, |row| Some(row.runtime_us)),
+            Arc::new(ts.finish()),
+            int_column(rows, |row| row.passes),
+            int_column(rows, |row| row.read_us),
+            int_column(rows, |row| row.process_us),
+            int_column(rows, |row| row.persist_us),
+            text_column(rows, |row| row.client_ip.as_deref()),
+            text_column(rows, |row| row.user_agent.as_deref()),
+            text_column(rows, |row| row.country.as_deref()),
+            text_column(rows, |row| row.city.as_deref()),
+            Arc::new(chunked.finish()),
+            text_column(rows, |row| row.tenant.as_deref()),
+            int_column(rows, |row| row.work_us),
+            Arc::new(checksum_mismatch.finish()),
+            int_column(rows, |row| row.sample_weight),
+        ],
+    )
+}
+
+// Hands everything written to it to the response in chunks. Blocks while the
+// client lags behind and fails once it went away.
+struct ChannelWriter {
+    sender: mpsc::Sender<io::Result<Bytes>>,
+    buffer: Vec<u8>,
+}
+
+impl ChannelWriter {
+    fn send(&mut self) -> io::Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        let chunk = Bytes::from(std::mem::take(&mut self.buffer));
+        self.sender
+            .blocking_send(Ok(chunk))
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "client went away"))
+    }
+}
+
+impl Write for ChannelWriter {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(data);
+        if self.buffer.len() >= CHUNK_SIZE {
+            self.send()?;
+        }
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.send()
+    }
+}
+
+fn write_all(
+    db: &Mutex<Connection>,
+    out: ChannelWriter,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let schema = schema();
+    let mut writer = ArrowWriter::try_new(out, schema.clone(), None)?;
+    let mut after = 0;
+    loop {
+        let rows = crate::stat_rows_after(&db.lock().unwrap(), after, PAGE_ROWS)?;
+        let Some(last) = rows.last() else {
+            break;
+        };
+        after = last.id;
+        writer.write(&to_batch(&schema, &rows)?)?;
+        // Close the row group so its bytes go out right away
+        writer.flush()?;
+    }
+    writer.finish()?;
+    writer.into_inner()?.flush()?;
+    Ok(())
+}
+
+// The Parquet file as a response body stream
+pub fn stream(db: Data<Mutex<Connection>>) -> impl Stream<Item = Result<Bytes, actix_web::Error>> {
+    let (sender, receiver) = mpsc::channel(CHUNKS_AHEAD);
+    std::thread::spawn(move || {
+        let errors = sender.clone();
+        let out = ChannelWriter {
+            sender,
+            buffer: Vec::new(),
+        };
+        if let Err(e) = write_all(&db, out) {
+            eprintln!("Parquet export failed: {}", e);
+            // Abort the response instead of ending it like a complete file
+            let _ = errors.blocking_send(Err(io::Error::other(e.to_string())));
+        }
+    });
+    futures_util::stream::unfold(receiver, |mut receiver| async move {
+        let chunk = receiver.recv().await?;
+        Some((chunk.map_err(actix_web::Error::from), receiver))
+    })
+}
@@ -0,0 +1,220 @@
+// WASM plugin hooks (feature "wasm-plugins").
+//
+// A plugin is a core WebAssembly module without imports that exports:
+//   memory                          its linear memory
+//   alloc(len: i32) -> i32          reserve `len` bytes for the input
+//   on_payload(ptr: i32, len: i32) -> i64
+//
+// `on_payload` receives the request metadata as JSON, e.g.
+//   {"method":"POST","path":"/shred","query":"","content_type":"text/plain","content_length":42}
+// and returns `(out_ptr << 32) | out_len` pointing at a JSON answer (or 0 for "no opinion"):
+//   {"veto": true, "reason": "…"}          refuse the destruction with 403
+//   {"fields": {…}}                        extra fields added to JSON responses under "plugins"
+use actix_web::body::{to_bytes, BoxBody, MessageBody};
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::Method;
+use actix_web::{web, Error, HttpResponse};
+use serde::{Deserialize, Serialize};
+use std::future::{ready, Future, Ready};
+use std::path::Path;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use wasmtime::{Config, Engine, Instance, Module, Store};
+
+// Instruction budget per hook call, so a buggy plugin can't hang a request
+const PLUGIN_FUEL: u64 = 10_000_000;
+
+#[derive(Serialize)]
+struct HookInput<'a> {
+    method: &'a str,
+    path: &'a str,
+    query: &'a str,
+    content_type: Option<&'a str>,
+    content_length: Option<u64>,
+}
+
+#[derive(Deserialize, Default)]
+struct HookOutput {
+    #[serde(default)]
+    veto: bool,
+    reason: Option<String>,
+    #[serde(default)]
+    fields: serde_json::Map<String, serde_json::Value>,
+}
+
+struct Plugin {
+    name: String,
+    module: Module,
+}
+
+pub struct PluginHost {
+    engine: Engine,
+    plugins: Vec<Plugin>,
+}
+
+impl PluginHost {
+    pub fn load(paths: &[String]) -> Result<Self, String> {
+        let mut config = Config::new();
+        config.consume_fuel(true);
+        let engine = Engine::new(&config).map_err(|e| e.to_string())?;
+        let plugins = paths
+            .iter()
+            .map(|path| {
+                let module = Module::from_file(&engine, path)
+                    .map_err(|e| format!("Failed to load plugin {}: {}", path, e))?;
+                let name = Path::new(path)
+                    .file_stem()
+                    .map(|stem| stem.to_string_lossy().into_owned())
+                    .unwrap_or_else(|| path.clone());
+                Ok(Plugin { name, module })
+            })
+            .collect::<Result<_, String>>()?;
+        Ok(PluginHost { engine, plugins })
+    }
+
+    // Run one plugin in a fresh instance, plugins can't keep state between requests
+    fn call(&self, plugin: &Plugin, input: &[u8]) -> wasmtime::Result<HookOutput> {
+        let mut store = Store::new(&self.engine, ());
+        store.set_fuel(PLUGIN_FUEL)?;
+        let instance = Instance::new(&mut store, &plugin.module, &[])?;
+        let memory = instance
+            .get_memory(&mut store, "memory")
+            .ok_or_else(|| wasmtime::Error::msg("plugin does not export memory"))?;
+        let alloc = instance.get_typed_func::<i32, i32>(&mut store, "alloc")?;
+        let hook = instance.get_typed_func::<(i32, i32), i64>(&mut store, "on_payload")?;
+
+        let ptr = alloc.call(&mut store, input.len() as i32)?;
+        memory.write(&mut store, ptr as u32 as usize, input)?;
+        let packed = hook.call(&mut store, (ptr, input.len() as i32))? as u64;
+        if packed == 0 {
+            return Ok(HookOutput::default());
+        }
+        let (out_ptr, out_len) = ((packed >> 32) as usize, (packed & 0xFFFF_FFFF) as usize);
+        let mut output = vec![0u8; out_len];
+        memory.read(&store, out_ptr, &mut output)?;
+        Ok(serde_json::from_slice(&output)?)
+    }
+
+    // Run all plugins. Err(reason) if one vetoes, otherwise the extra fields per plugin.
+    // Blocks for as long as the plugins take, up to their fuel.
+    fn run(&self, input: &[u8]) -> Result<serde_json::Map<String, serde_json::Value>, String> {
+        let mut fields = serde_json::Map::new();
+        for plugin in &self.plugins {
+            match self.call(plugin, input) {
+                Ok(output) if output.veto => {
+                    return Err(output
+                        .reason
+                        .unwrap_or_else(|| format!("Vetoed by plugin {}.", plugin.name)));
+                }
+                Ok(output) if !output.fields.is_empty() => {
+                    fields.insert(plugin.name.clone(), output.fields.into());
+                }
+                Ok(_) => {}
+                // A broken plugin must not take the whole service down, so fail open
+                Err(e) => eprintln!("Plugin {} failed: {}", plugin.name, e),
+            }
+        }
+        Ok(fields)
+    }
+}
+
+// Middleware invoking the plugins for every destruction request (POST/PATCH)
+pub struct PluginHooks(pub Arc<PluginHost>);
+
+impl<S, B> Transform<S, ServiceRequest> for PluginHooks
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<BoxBody>;
+    type Error = Error;
+    type Transform = PluginHooksMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(PluginHooksMiddleware {
+            service: Rc::new(service),
+            host: self.0.clone(),
+        }))
+    }
+}
+
+pub struct PluginHooksMiddleware<S> {
+    service: Rc<S>,
+    host: Arc<PluginHost>,
+}
+
+impl<S, B> Service<ServiceRequest> for PluginHooksMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<BoxBody>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    fn poll_ready(&self, ctx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(ctx)
+    }
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        if req.method() != Method::POST && req.method() != Method::PATCH {
+            return Box::pin(async move { Ok(service.call(req).await?.map_into_boxed_body()) });
+        }
+        let headers = req.headers();
+        let input = HookInput {
+            method: req.method().as_str(),
+            path: req.path(),
+            query: req.query_string(),
+            content_type: headers.get("Content-Type").and_then(|v| v.to_str().ok()),
+            content_length: headers
+                .get("Content-Length")
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.parse().ok()),
+        };
+        let input = serde_json::to_vec(&input).unwrap_or_default();
+        let host = self.host.clone();
+
+        Box::pin(async move {
+            // Plugins run on the blocking pool, a slow one mustn't stall the other requests
+            // of this worker
+            let fields = match web::block(move || host.run(&input)).await? {
+                Ok(fields) => fields,
+                Err(reason) => {
+                    let response =
+                        HttpResponse::Forbidden().json(serde_json::json!({ "error": reason }));
+                    return Ok(req.into_response(response));
+                }
+            };
+            let res = service.call(req).await?.map_into_boxed_body();
+            let is_json = res
+                .headers()
+                .get("Content-Type")
+                .is_some_and(|v| v.as_bytes().starts_with(b"application/json"));
+            if fields.is_empty() || !is_json {
+                return Ok(res);
+            }
+            // Splice the plugin fields into the JSON object the handler produced
+            let (http_req, http_res) = res.into_parts();
+            let (head, body) = http_res.into_parts();
+            let bytes = to_bytes(body)
+                .await
+                .map_err(actix_web::error::ErrorInternalServerError)?;
+            let body = match serde_json::from_slice::<serde_json::Value>(&bytes) {
+                Ok(serde_json::Value::Object(mut object)) => {
+                    object.insert("plugins".to_string(), fields.into());
+                    serde_json::to_vec(&object).unwrap_or_else(|_| bytes.to_vec())
+                }
+                _ => bytes.to_vec(),
+            };
+            Ok(ServiceResponse::new(
+                http_req,
+                head.set_body(body).map_into_boxed_body(),
+            ))
+        })
+    }
+}
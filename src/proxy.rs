@@ -0,0 +1,86 @@
+// Client addresses behind reverse proxies: forwarded headers are only believed
+// when the peer is inside one of the --trusted-proxies ranges.
+use actix_web::http::header::HeaderMap;
+use ipnet::IpNet;
+use std::net::{IpAddr, SocketAddr};
+
+// Parse a --trusted-proxies entry, a CIDR range or a single address
+pub fn parse_trusted_proxy(spec: &str) -> Result<IpNet, String> {
+    let spec = spec.trim();
+    spec.parse::<IpNet>()
+        .or_else(|_| spec.parse::<IpAddr>().map(IpNet::from))
+        .map_err(|_| {
+            format!(
+                "invalid proxy '{}', use an address or CIDR range like 10.0.0.0/8",
+                spec
+            )
+        })
+}
+
+fn is_trusted(ip: IpAddr, trusted: &[IpNet]) -> bool {
+    trusted.iter().any(|net| net.contains(&ip))
+}
+
+// An address as found in forwarded headers: "1.2.3.4", "1.2.3.4:5678", "[::1]:5678", "\"[::1]\""
+fn parse_node(node: &str) -> Option<IpAddr> {
+    let node = node.trim().trim_matches('"');
+    if let Ok(addr) = node.parse::<SocketAddr>() {
+        return Some(addr.ip());
+    }
+    node.trim_start_matches('[')
+        .trim_end_matches(']')
+        .parse()
+        .ok()
+}
+
+// The `for=` nodes of all Forwarded headers, nearest client first
+fn forwarded_for(headers: &HeaderMap) -> Vec<String> {
+    headers
+        .get_all("Forwarded")
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|element| {
+            element.split(';').find_map(|pair| {
+                let (key, value) = pair.split_once('=')?;
+                key.trim()
+                    .eq_ignore_ascii_case("for")
+                    .then(|| value.trim().to_string())
+            })
+        })
+        .collect()
+}
+
+fn x_forwarded_for(headers: &HeaderMap) -> Vec<String> {
+    headers
+        .get_all("X-Forwarded-For")
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|node| node.trim().to_string())
+        .filter(|node| !node.is_empty())
+        .collect()
+}
+
+// The address of the client that sent the request. Hops are walked from the peer
+// backwards, skipping trusted proxies, so clients can't spoof their address by
+// sending their own X-Forwarded-For. Forwarded wins over X-Forwarded-For.
+pub fn client_ip(peer: IpAddr, headers: &HeaderMap, trusted: &[IpNet]) -> IpAddr {
+    if !is_trusted(peer, trusted) {
+        return peer;
+    }
+    let mut hops = forwarded_for(headers);
+    if hops.is_empty() {
+        hops = x_forwarded_for(headers);
+    }
+    let mut client = peer;
+    for hop in hops.iter().rev() {
+        // Obfuscated or "unknown" nodes end the chain we can follow
+        let Some(ip) = parse_node(hop) else {
+            break;
+        };
+        client = ip;
+        if !is_trusted(ip, trusted) {
+            break;
+        }
+    }
+    client
+}
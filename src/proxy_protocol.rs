@@ -0,0 +1,221 @@
+// HAProxy PROXY protocol (v1 and v2) for --proxy-protocol.
+//
+// A TCP load balancer prepends a small header with the original client address to
+// every connection. It is read before any HTTP and the address becomes the peer
+// address of all requests on that connection, so --trusted-proxies and everything
+// else built on the client IP keeps working. Connections without a header are dropped.
+use crate::gauges::Gauges;
+use actix_http::body::MessageBody;
+use actix_http::{HttpService, Protocol, Request, Response};
+use actix_service::{
+    fn_service, map_config, IntoServiceFactory, ServiceFactory, ServiceFactoryExt,
+};
+use actix_web::dev::{AppConfig, Extensions, Server, Service};
+use actix_web::rt::net::TcpStream;
+use actix_web::web::Data;
+use std::fmt;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+// How long a new connection may take to send its header
+const HEADER_TIMEOUT: Duration = Duration::from_secs(5);
+// Longest v1 header including CRLF, as per the spec
+const V1_MAX_LENGTH: usize = 107;
+const V2_SIGNATURE: &[u8; 12] = b"\r\n\r\n\0\r\nQUIT\n";
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+// "PROXY TCP4 192.0.2.1 198.51.100.1 56324 443\r\n", after the first bytes were read
+async fn read_v1(
+    io: &mut (impl AsyncRead + Unpin),
+    mut line: Vec<u8>,
+) -> io::Result<Option<SocketAddr>> {
+    while !line.ends_with(b"\r\n") {
+        if line.len() >= V1_MAX_LENGTH {
+            return Err(invalid("PROXY v1 header too long"));
+        }
+        line.push(io.read_u8().await?);
+    }
+    let line = std::str::from_utf8(&line).map_err(|_| invalid("PROXY v1 header isn't ASCII"))?;
+    let fields: Vec<&str> = line.trim_end().split(' ').collect();
+    match fields.as_slice() {
+        ["PROXY", "UNKNOWN", ..] => Ok(None),
+        ["PROXY", "TCP4" | "TCP6", source, _, port, _] => {
+            let ip: IpAddr = source
+                .parse()
+                .map_err(|_| invalid("invalid PROXY v1 source address"))?;
+            let port: u16 = port
+                .parse()
+                .map_err(|_| invalid("invalid PROXY v1 source port"))?;
+            Ok(Some(SocketAddr::new(ip, port)))
+        }
+        _ => Err(invalid("malformed PROXY v1 header")),
+    }
+}
+
+// Binary header: signature, version/command, family, length, addresses
+async fn read_v2(
+    io: &mut (impl AsyncRead + Unpin),
+    start: &[u8],
+) -> io::Result<Option<SocketAddr>> {
+    let mut header = [0u8; 16];
+    header[..start.len()].copy_from_slice(start);
+    io.read_exact(&mut header[start.len()..]).await?;
+    if &header[..12] != V2_SIGNATURE || header[12] >> 4 != 2 {
+        return Err(invalid("malformed PROXY v2 header"));
+    }
+    let length = u16::from_be_bytes([header[14], header[15]]) as usize;
+    let mut addresses = vec![0u8; length];
+    io.read_exact(&mut addresses).await?;
+    // LOCAL connections come from the balancer itself, e.g. health checks
+    if header[12] & 0x0f == 0 {
+        return Ok(None);
+    }
+    let source = match header[13] >> 4 {
+        1 if length >= 12 => {
+            let ip: [u8; 4] = addresses[..4].try_into().unwrap();
+            let port = u16::from_be_bytes([addresses[8], addresses[9]]);
+            SocketAddr::new(IpAddr::V4(Ipv4Addr::from(ip)), port)
+        }
+        2 if length >= 36 => {
+            let ip: [u8; 16] = addresses[..16].try_into().unwrap();
+            let port = u16::from_be_bytes([addresses[32], addresses[33]]);
+            SocketAddr::new(IpAddr::V6(Ipv6Addr::from(ip)), port)
+        }
+        1 | 2 => return Err(invalid("PROXY v2 addresses too short for their family")),
+        // Unix sockets and unspecified families carry no usable client address
+        _ => return Ok(None),
+    };
+    Ok(Some(source))
+}
+
+// The client address announced by the header, None if the balancer didn't tell
+async fn read_header(io: &mut (impl AsyncRead + Unpin)) -> io::Result<Option<SocketAddr>> {
+    let mut start = [0u8; 5];
+    io.read_exact(&mut start).await?;
+    if &start == b"PROXY" {
+        read_v1(io, start.to_vec()).await
+    } else if start == V2_SIGNATURE[..5] {
+        read_v2(io, &start).await
+    } else {
+        Err(invalid("connection doesn't start with a PROXY header"))
+    }
+}
+
+// Serve the app like HttpServer does, but read a PROXY header off every connection first
+pub async fn serve<F, I, S, B>(
+    listener: std::net::TcpListener,
+    factory: F,
+    shutdown_timeout: u64,
+    gauges: Data<Gauges>,
+) -> io::Result<()>
+where
+    F: Fn() -> I + Send + Clone + 'static,
+    I: IntoServiceFactory<S, Request>,
+    S: ServiceFactory<Request, Config = AppConfig> + 'static,
+    S::Error: Into<actix_web::Error> + 'static,
+    S::InitError: fmt::Debug,
+    S::Response: Into<Response<B>> + 'static,
+    <S::Service as Service<Request>>::Future: 'static,
+    S::Service: 'static,
+    B: MessageBody + 'static,
+{
+    let server: Server = Server::build()
+        .shutdown_timeout(shutdown_timeout)
+        .listen("payload-pulverizer", listener, move || {
+            let app = factory()
+                .into_factory()
+                .map_err(|err| err.into().error_response());
+            fn_service(|mut io: TcpStream| async move {
+                let peer = io.peer_addr().ok();
+                let client = actix_web::rt::time::timeout(HEADER_TIMEOUT, read_header(&mut io))
+                    .await
+                    .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "no PROXY header"))??;
+                Ok((io, Protocol::Http1, client.or(peer)))
+            })
+            .map_err(actix_http::error::DispatchError::Io)
+            .and_then(
+                HttpService::build()
+                    .on_connect_ext({
+                        let gauges = gauges.clone();
+                        move |_: &TcpStream, extensions: &mut Extensions| {
+                            crate::gauges::on_connect(&gauges, extensions)
+                        }
+                    })
+                    .finish(map_config(app, |_| AppConfig::default())),
+            )
+        })?
+        .run();
+    server.await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn read(mut header: &[u8]) -> io::Result<Option<SocketAddr>> {
+        read_header(&mut header).await
+    }
+
+    fn v2(command: u8, family: u8, addresses: &[u8]) -> Vec<u8> {
+        let mut header = V2_SIGNATURE.to_vec();
+        header.extend([0x20 | command, family]);
+        header.extend((addresses.len() as u16).to_be_bytes());
+        header.extend(addresses);
+        header
+    }
+
+    fn tcp6_source() -> Vec<u8> {
+        let mut source = "2001:db8::1".parse::<Ipv6Addr>().unwrap().octets().to_vec();
+        source.extend(56324u16.to_be_bytes());
+        source
+    }
+
+    #[actix_web::test]
+    async fn v1_announces_the_client() {
+        let tcp4 = read(b"PROXY TCP4 192.0.2.1 198.51.100.1 56324 443\r\nGET /").await;
+        assert_eq!(tcp4.unwrap(), Some("192.0.2.1:56324".parse().unwrap()));
+        let tcp6 = read(b"PROXY TCP6 2001:db8::1 2001:db8::2 56324 443\r\n").await;
+        assert_eq!(tcp6.unwrap(), Some("[2001:db8::1]:56324".parse().unwrap()));
+        let unknown = read(b"PROXY UNKNOWN ffff::1 ffff::2 1 2\r\n").await;
+        assert_eq!(unknown.unwrap(), None);
+        assert!(read(b"PROXY TCP4 192.0.2.1\r\n").await.is_err());
+    }
+
+    #[actix_web::test]
+    async fn v1_rejects_long_headers() {
+        let mut line = b"PROXY UNKNOWN ".to_vec();
+        line.resize(V1_MAX_LENGTH, b'x');
+        line.extend(b"\r\n");
+        assert!(read(&line).await.is_err());
+    }
+
+    #[actix_web::test]
+    async fn v2_announces_the_client() {
+        let mut tcp4 = vec![192, 0, 2, 1, 198, 51, 100, 1];
+        tcp4.extend(56324u16.to_be_bytes());
+        tcp4.extend(443u16.to_be_bytes());
+        let header = v2(1, 0x11, &tcp4);
+        assert_eq!(
+            read(&header).await.unwrap(),
+            Some("192.0.2.1:56324".parse().unwrap())
+        );
+        // LOCAL skips the addresses and keeps the balancer as the peer
+        assert_eq!(read(&v2(0, 0x11, &tcp4)).await.unwrap(), None);
+    }
+
+    #[actix_web::test]
+    async fn v2_rejects_short_headers() {
+        let header = v2(1, 0x11, &[192, 0, 2, 1, 198, 51, 100, 1, 0, 1, 0, 2]);
+        // The length promises more address bytes than the connection sends
+        assert!(read(&header[..header.len() - 4]).await.is_err());
+        // A length too short for the announced family
+        assert!(read(&v2(1, 0x11, &[192, 0, 2, 1])).await.is_err());
+        assert!(read(&v2(1, 0x21, &tcp6_source())).await.is_err());
+        assert!(read(&header[..14]).await.is_err());
+    }
+}
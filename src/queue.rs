@@ -0,0 +1,553 @@
+// Background destruction jobs for clients that can't wait for the result on the upload
+//
+// POST /queue takes the payload, answers 202 with a job id right away and destroys the
+// payload in the background with the method from `?method=` (auto by default). The
+// destruction endpoints do the same with `?async=true`. GET /queue/{id} (or /jobs/{id})
+// with `?wait=30s` long-polls until the job is done and returns the response the
+// destruction endpoint would have given. Jobs are kept in memory for an hour.
+//
+// At most --queue-workers jobs are processed at a time. Waiting jobs start in the order
+// of their X-Priority header (high, normal, low), first come first served within each.
+// Once --queue-max-waiting jobs are waiting, new ones are refused with 429, and at most
+// MAX_JOBS are kept for polling.
+use crate::dedup::DedupCache;
+use crate::store::Store;
+use crate::{body, AppConfig, SharedRng, ShredQuery};
+use actix_web::body::{BoxBody, MessageBody};
+use actix_web::dev::{Payload, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::web::{self, Data};
+use actix_web::{Error, HttpMessage, HttpRequest, HttpResponse, Responder, Result};
+use futures_util::StreamExt;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::future::{ready, Future, Ready};
+use std::pin::Pin;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use tokio::sync::{oneshot, watch};
+
+// Finished and abandoned jobs are forgotten after this long
+const JOB_EXPIRY: Duration = Duration::from_secs(60 * 60);
+// Jobs kept for polling at most, the oldest finished one makes room for a new one
+const MAX_JOBS: usize = 10_000;
+// Longest a poll may wait
+const MAX_WAIT: Duration = Duration::from_secs(60);
+#[cfg(feature = "extras")]
+const METHODS: &[&str] = &["auto", "pulverize", "shred", "burn", "blackhole"];
+#[cfg(not(feature = "extras"))]
+const METHODS: &[&str] = &["auto", "pulverize", "blackhole"];
+// Destruction paths that have their own way of coming back later, or whose response
+// headers are the point
+const NOT_ASYNC: &[&str] = &[
+    "/queue",
+    "/tus",
+    "/cookies/destroy",
+    "/stage",
+    "/commit",
+    "/abort",
+];
+const PRIORITIES: &[&str] = &["high", "normal", "low"];
+
+// What the destruction endpoint answered
+#[derive(Serialize, Clone)]
+struct JobResult {
+    http_status: u16,
+    // The JSON response body, null for empty bodies (e.g. blackhole)
+    response: serde_json::Value,
+}
+
+struct Job {
+    method: &'static str,
+    priority: &'static str,
+    submitted_at: String,
+    result: watch::Receiver<Option<JobResult>>,
+    created: Instant,
+}
+
+// Jobs waiting for a worker, by priority
+struct Waiting {
+    running: usize,
+    queues: [VecDeque<oneshot::Sender<()>>; 3],
+}
+
+// Hands out the workers, highest priority first
+struct Workers {
+    count: usize,
+    // --queue-max-waiting
+    max_waiting: usize,
+    // Jobs accepted that don't have a worker yet
+    queued: AtomicUsize,
+    waiting: Mutex<Waiting>,
+    // How long recent jobs took, for Retry-After with --max-backlog
+    average_job_us: AtomicU64,
+}
+
+// A job's place in the queue, from being accepted until it gets a worker
+struct Ticket {
+    workers: Arc<Workers>,
+}
+
+// A busy worker, passed on to the next waiting job when dropped
+struct Worker {
+    workers: Arc<Workers>,
+    started: Instant,
+}
+
+pub struct QueueStore {
+    jobs: Mutex<HashMap<String, Job>>,
+    workers: Arc<Workers>,
+}
+
+#[derive(Deserialize)]
+pub struct SubmitQuery {
+    method: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct PollQuery {
+    // How long to wait for the job to finish, e.g. 30s
+    wait: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct AsyncQuery {
+    #[serde(rename = "async")]
+    run_async: Option<bool>,
+}
+
+#[derive(Serialize)]
+struct JobResponse {
+    // "queued", "pending" or "done"
+    status: &'static str,
+    job_id: String,
+    method: &'static str,
+    priority: &'static str,
+    submitted_at: String,
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    result: Option<JobResult>,
+}
+
+impl Workers {
+    // A place in the queue, None if --queue-max-waiting jobs are waiting already
+    fn reserve(self: &Arc<Self>) -> Option<Ticket> {
+        self.queued
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |queued| {
+                (queued < self.max_waiting).then_some(queued + 1)
+            })
+            .ok()?;
+        Some(Ticket {
+            workers: self.clone(),
+        })
+    }
+
+    // Wait until a worker is free for a job of the given priority (index in PRIORITIES)
+    async fn acquire(self: &Arc<Self>, ticket: Ticket, priority: usize) -> Worker {
+        let turn = {
+            let mut waiting = self.waiting.lock().unwrap();
+            if waiting.running < self.count {
+                waiting.running += 1;
+                None
+            } else {
+                let (sender, receiver) = oneshot::channel();
+                waiting.queues[priority].push_back(sender);
+                Some(receiver)
+            }
+        };
+        if let Some(turn) = turn {
+            // The worker is handed over by the job finishing before
+            let _ = turn.await;
+        }
+        drop(ticket);
+        Worker {
+            workers: self.clone(),
+            started: Instant::now(),
+        }
+    }
+}
+
+impl Drop for Ticket {
+    fn drop(&mut self) {
+        self.workers.queued.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl Drop for Worker {
+    fn drop(&mut self) {
+        crate::backpressure::update_average(&self.workers.average_job_us, self.started.elapsed());
+        let mut waiting = self.workers.waiting.lock().unwrap();
+        while let Some(next) = waiting.queues.iter_mut().find_map(VecDeque::pop_front) {
+            // Skip jobs that stopped waiting
+            if next.send(()).is_ok() {
+                return;
+            }
+        }
+        waiting.running -= 1;
+    }
+}
+
+// The X-Priority of a request as index in PRIORITIES, normal if not given. None for
+// unknown priorities.
+fn priority(req: &HttpRequest) -> Option<usize> {
+    let Some(value) = req.headers().get("X-Priority") else {
+        return Some(1);
+    };
+    let value = value.to_str().ok()?.trim();
+    PRIORITIES
+        .iter()
+        .position(|priority| value.eq_ignore_ascii_case(priority))
+}
+
+fn invalid_priority() -> HttpResponse {
+    HttpResponse::BadRequest().json(serde_json::json!({
+        "error": format!("Invalid X-Priority. Use one of: {}.", PRIORITIES.join(", "))
+    }))
+}
+
+impl QueueStore {
+    pub fn new(workers: usize, max_waiting: usize) -> Self {
+        QueueStore {
+            jobs: Mutex::new(HashMap::new()),
+            workers: Arc::new(Workers {
+                count: workers,
+                max_waiting,
+                queued: AtomicUsize::new(0),
+                waiting: Mutex::new(Waiting {
+                    running: 0,
+                    queues: Default::default(),
+                }),
+                average_job_us: AtomicU64::new(0),
+            }),
+        }
+    }
+
+    // How long a new job would wait for a worker, if max_depth or more are waiting
+    pub fn backlog(&self, max_depth: usize) -> Option<Duration> {
+        let waiting = self.workers.queued.load(Ordering::Relaxed);
+        (waiting >= max_depth).then(|| self.expected_wait(waiting))
+    }
+
+    // How long the last of `waiting` jobs waits, from how long recent jobs took
+    fn expected_wait(&self, waiting: usize) -> Duration {
+        let average = self.workers.average_job_us.load(Ordering::Relaxed);
+        let rounds = waiting.div_ceil(self.workers.count) as u32;
+        Duration::from_micros(average) * rounds
+    }
+
+    // The answer while no more jobs are taken
+    fn full(&self) -> HttpResponse {
+        let waiting = self.workers.queued.load(Ordering::Relaxed);
+        crate::backpressure::too_many(
+            "Too many jobs are waiting for a worker.",
+            self.expected_wait(waiting),
+        )
+    }
+
+    // A place in the queue for a job, None if it is full
+    fn reserve(&self) -> Option<Ticket> {
+        self.workers.reserve()
+    }
+
+    // Add a job, the sender takes its result. None if MAX_JOBS are kept and none of them
+    // is finished.
+    fn create(
+        &self,
+        method: &'static str,
+        priority: usize,
+    ) -> Option<(JobResponse, watch::Sender<Option<JobResult>>)> {
+        let id = format!("{:032x}", rand::rng().random::<u128>());
+        let (sender, receiver) = watch::channel(None);
+        let submitted_at = chrono::Utc::now().to_rfc3339();
+        let mut jobs = self.jobs.lock().unwrap();
+        jobs.retain(|_, job| job.created.elapsed() < JOB_EXPIRY);
+        if jobs.len() >= MAX_JOBS {
+            let oldest = jobs
+                .iter()
+                // Done, or dropped unfinished
+                .filter(|(_, job)| {
+                    job.result.borrow().is_some() || job.result.has_changed().is_err()
+                })
+                .min_by_key(|(_, job)| job.created)
+                .map(|(id, _)| id.clone())?;
+            jobs.remove(&oldest);
+        }
+        jobs.insert(
+            id.clone(),
+            Job {
+                method,
+                priority: PRIORITIES[priority],
+                submitted_at: submitted_at.clone(),
+                result: receiver,
+                created: Instant::now(),
+            },
+        );
+        let queued = JobResponse {
+            status: "queued",
+            job_id: id,
+            method,
+            priority: PRIORITIES[priority],
+            submitted_at,
+            result: None,
+        };
+        Some((queued, sender))
+    }
+}
+
+// Keep what the destruction endpoint answered
+async fn collect(status: u16, body: impl MessageBody) -> JobResult {
+    let bytes = actix_web::body::to_bytes(body).await.unwrap_or_default();
+    let response = match bytes.is_empty() {
+        true => serde_json::Value::Null,
+        false => serde_json::from_slice(&bytes).unwrap_or_else(|_| {
+            serde_json::Value::String(String::from_utf8_lossy(&bytes).into_owned())
+        }),
+    };
+    JobResult {
+        http_status: status,
+        response,
+    }
+}
+
+// Run the destruction endpoint picked by ?method=
+#[allow(clippy::too_many_arguments)]
+async fn run(
+    method: &'static str,
+    req: HttpRequest,
+    body: body::Body,
+    shred_query: web::Query<ShredQuery>,
+    db: Data<dyn Store>,
+    dedup: Data<DedupCache>,
+    rng: Data<SharedRng>,
+    config: Data<AppConfig>,
+) -> JobResult {
+    let response = match method {
+        "pulverize" => crate::pulverize_handler(req.clone(), body, db, dedup, config)
+            .await
+            .map(|response| response.respond_to(&req).map_into_boxed_body()),
+        #[cfg(feature = "extras")]
+        "shred" => crate::shred_handler(req.clone(), body, shred_query, db, dedup, rng, config)
+            .await
+            .map(|response| response.respond_to(&req).map_into_boxed_body()),
+        #[cfg(feature = "extras")]
+        "burn" => crate::burn_handler(req.clone(), body, db, dedup, config)
+            .await
+            .map(|response| response.respond_to(&req).map_into_boxed_body()),
+        "blackhole" => crate::blackhole_handler(req.clone(), body, db, dedup, config)
+            .await
+            .map(|response| response.respond_to(&req).map_into_boxed_body()),
+        _ => crate::auto_handler(req.clone(), body, shred_query, db, dedup, rng, config).await,
+    };
+    let response = match response {
+        Ok(response) => response,
+        Err(e) => e.error_response(),
+    };
+    collect(response.status().as_u16(), response.into_body()).await
+}
+
+/// Handler for POST /queue
+/// Accepts the payload and destroys it in the background, `?method=` picks the endpoint.
+#[allow(clippy::too_many_arguments)]
+pub async fn submit_handler(
+    req: HttpRequest,
+    body: body::Body,
+    query: web::Query<SubmitQuery>,
+    shred_query: web::Query<ShredQuery>,
+    store: Data<QueueStore>,
+    db: Data<dyn Store>,
+    dedup: Data<DedupCache>,
+    rng: Data<SharedRng>,
+    config: Data<AppConfig>,
+) -> Result<HttpResponse> {
+    let requested = query.method.as_deref().unwrap_or("auto");
+    let Some(method) = METHODS.iter().copied().find(|method| *method == requested) else {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": format!("Unknown method. Use one of: {}.", METHODS.join(", "))
+        })));
+    };
+    let Some(priority) = priority(&req) else {
+        return Ok(invalid_priority());
+    };
+    let Some(ticket) = store.reserve() else {
+        return Ok(store.full());
+    };
+    let Some((queued, sender)) = store.create(method, priority) else {
+        return Ok(store.full());
+    };
+    let workers = store.workers.clone();
+    let job_req = req.clone();
+    actix_web::rt::spawn(async move {
+        let _worker = workers.acquire(ticket, priority).await;
+        let result = run(method, job_req, body, shred_query, db, dedup, rng, config).await;
+        let _ = sender.send(Some(result));
+    });
+    Ok(HttpResponse::Accepted()
+        // Relative to the request path, so it also works under --base-path
+        .insert_header(("Location", format!("{}/{}", req.path(), queued.job_id)))
+        .json(queued))
+}
+
+/// Handler for GET /queue/{id} and GET /jobs/{id}
+/// Returns the job's result, `?wait=` holds the request until the job is done (up to 60s).
+pub async fn poll_handler(
+    path: web::Path<String>,
+    query: web::Query<PollQuery>,
+    store: Data<QueueStore>,
+) -> Result<HttpResponse> {
+    let wait = match query.wait.as_deref().map(crate::parse_duration_secs) {
+        None => Duration::ZERO,
+        Some(Some(secs)) => Duration::from_secs(secs as u64).min(MAX_WAIT),
+        Some(None) => {
+            return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+                "error": "Invalid wait, use a duration like 30s."
+            })))
+        }
+    };
+    let id = path.into_inner();
+    let (method, priority, submitted_at, mut result) = {
+        let jobs = store.jobs.lock().unwrap();
+        match jobs.get(&id) {
+            Some(job) if job.created.elapsed() < JOB_EXPIRY => {
+                let submitted_at = job.submitted_at.clone();
+                (job.method, job.priority, submitted_at, job.result.clone())
+            }
+            _ => {
+                return Ok(HttpResponse::NotFound().json(serde_json::json!({
+                    "error": "Unknown or expired job."
+                })))
+            }
+        }
+    };
+    // A closed channel means the job was dropped unfinished, stop waiting then too
+    let _ = actix_web::rt::time::timeout(wait, result.wait_for(Option::is_some)).await;
+    let finished = result.borrow().clone();
+    let response = JobResponse {
+        status: if finished.is_some() {
+            "done"
+        } else {
+            "pending"
+        },
+        job_id: id,
+        method,
+        priority,
+        submitted_at,
+        result: finished,
+    };
+    Ok(match response.result {
+        Some(_) => HttpResponse::Ok().json(response),
+        None => HttpResponse::Accepted().json(response),
+    })
+}
+
+// The endpoint of a destruction request asking for ?async=true
+fn async_endpoint(req: &ServiceRequest) -> Option<&'static str> {
+    let path = crate::read_only::destruction_path(req.request())?;
+    let query = web::Query::<AsyncQuery>::from_query(req.query_string()).ok()?;
+    (query.run_async == Some(true) && !NOT_ASYNC.contains(&path))
+        .then(|| path.trim_start_matches('/'))
+}
+
+// Whether a destruction request will become a job
+pub fn is_queued(req: &ServiceRequest) -> bool {
+    crate::read_only::destruction_path(req.request()) == Some("/queue")
+        || async_endpoint(req).is_some()
+}
+
+// Middleware running ?async=true destruction requests as background jobs. The body is
+// read before answering 202, past the --spill-dir threshold to disk, and the endpoint
+// then gets it as if it had just arrived.
+pub struct AsyncJobs;
+
+impl<S, B> Transform<S, ServiceRequest> for AsyncJobs
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<BoxBody>;
+    type Error = Error;
+    type Transform = AsyncJobsMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(AsyncJobsMiddleware {
+            service: Rc::new(service),
+        }))
+    }
+}
+
+pub struct AsyncJobsMiddleware<S> {
+    service: Rc<S>,
+}
+
+impl<S, B> Service<ServiceRequest> for AsyncJobsMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<BoxBody>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    fn poll_ready(&self, ctx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(ctx)
+    }
+
+    fn call(&self, mut req: ServiceRequest) -> Self::Future {
+        let endpoint = async_endpoint(&req);
+        let store = req.app_data::<Data<QueueStore>>().cloned();
+        let (Some(endpoint), Some(store)) = (endpoint, store) else {
+            let fut = self.service.call(req);
+            return Box::pin(async move { Ok(fut.await?.map_into_boxed_body()) });
+        };
+        let Some(priority) = priority(req.request()) else {
+            return Box::pin(async move { Ok(req.into_response(invalid_priority())) });
+        };
+        // Refused before the body is read
+        let Some(ticket) = store.reserve() else {
+            return Box::pin(async move { Ok(req.into_response(store.full())) });
+        };
+        let service = self.service.clone();
+        Box::pin(async move {
+            // Still compressed, the endpoint decodes it. Going over the limit here means
+            // the endpoint would refuse it anyway.
+            let limit = body::payload_limit(req.request());
+            let mut payload = req.take_payload();
+            let mut buffer = body::Buffer::new(req.request());
+            while let Some(chunk) = payload.next().await {
+                let chunk = chunk?;
+                if buffer.received() + chunk.len() > limit {
+                    return Err(body::too_large(limit));
+                }
+                buffer.push(&chunk)?;
+            }
+            req.set_payload(Payload::from(buffer.finish()?));
+            let Some((queued, sender)) = store.create(endpoint, priority) else {
+                return Ok(req.into_response(store.full()));
+            };
+            let base_path = req
+                .app_data::<Data<AppConfig>>()
+                .map_or_else(String::new, |config| config.base_path.clone());
+            let location = format!("{}/jobs/{}", base_path, queued.job_id);
+            let http_req = req.request().clone();
+            let workers = store.workers.clone();
+            actix_web::rt::spawn(async move {
+                let _worker = workers.acquire(ticket, priority).await;
+                let result = match service.call(req).await {
+                    Ok(res) => collect(res.status().as_u16(), res.into_body()).await,
+                    Err(e) => {
+                        let res = e.error_response();
+                        collect(res.status().as_u16(), res.into_body()).await
+                    }
+                };
+                let _ = sender.send(Some(result));
+            });
+            let response = HttpResponse::Accepted()
+                .insert_header(("Location", location))
+                .json(queued);
+            Ok(ServiceResponse::new(http_req, response))
+        })
+    }
+}
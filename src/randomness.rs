@@ -0,0 +1,122 @@
+// Randomness tests for /autopsy, to confirm that payloads claimed to be encrypted look it
+//
+// Ciphertext and well compressed data are indistinguishable from random bytes, so a
+// payload passing all three tests looks the part. Plain text, images with headers or
+// base64 fail at least one of them. Passing is no proof of encryption.
+use serde::Serialize;
+
+// Below this the statistics are too noisy to judge
+const MIN_SIZE: usize = 256;
+// Two-sided limit for the normal approximations, about p = 0.001
+const Z_LIMIT: f64 = 3.29;
+
+#[derive(Serialize)]
+pub struct TestResult {
+    statistic: f64,
+    passed: bool,
+}
+
+#[derive(Serialize)]
+pub struct Randomness {
+    // Byte distribution against a uniform one, 255 is expected for random data
+    #[serde(skip_serializing_if = "Option::is_none")]
+    chi_square: Option<TestResult>,
+    // Share of 1 bits, 0.5 is expected for random data
+    #[serde(skip_serializing_if = "Option::is_none")]
+    monobit: Option<TestResult>,
+    // Correlation of every byte with the next one, 0 is expected for random data
+    #[serde(skip_serializing_if = "Option::is_none")]
+    serial_correlation: Option<TestResult>,
+    verdict: String,
+}
+
+fn round(value: f64, decimals: i32) -> f64 {
+    let factor = 10f64.powi(decimals);
+    (value * factor).round() / factor
+}
+
+// Wilson–Hilferty approximation of the chi-square distribution by a normal one
+fn chi_square(histogram: &[u64], len: usize) -> TestResult {
+    let expected = len as f64 / 256.0;
+    let statistic: f64 = histogram
+        .iter()
+        .map(|count| (*count as f64 - expected).powi(2) / expected)
+        .sum();
+    let k = 255.0;
+    let z = ((statistic / k).cbrt() - (1.0 - 2.0 / (9.0 * k))) / (2.0 / (9.0 * k)).sqrt();
+    TestResult {
+        statistic: round(statistic, 2),
+        passed: z.abs() < Z_LIMIT,
+    }
+}
+
+fn monobit(histogram: &[u64], len: usize) -> TestResult {
+    let ones: u64 = histogram
+        .iter()
+        .enumerate()
+        .map(|(byte, count)| (byte as u8).count_ones() as u64 * count)
+        .sum();
+    let bits = len as f64 * 8.0;
+    let z = (2.0 * ones as f64 - bits).abs() / bits.sqrt();
+    TestResult {
+        statistic: round(ones as f64 / bits, 4),
+        passed: z < Z_LIMIT,
+    }
+}
+
+// The serial correlation coefficient as computed by `ent`, wrapping around at the end
+fn serial_correlation(body: &[u8]) -> TestResult {
+    let n = body.len() as f64;
+    let (mut sum, mut squares, mut products) = (0.0, 0.0, 0.0);
+    for (i, &byte) in body.iter().enumerate() {
+        let (u, next) = (byte as f64, body[(i + 1) % body.len()] as f64);
+        sum += u;
+        squares += u * u;
+        products += u * next;
+    }
+    let denominator = n * squares - sum * sum;
+    // Constant data is as correlated as it gets
+    let statistic = match denominator {
+        0.0 => 1.0,
+        _ => (n * products - sum * sum) / denominator,
+    };
+    TestResult {
+        statistic: round(statistic, 4),
+        passed: statistic.abs() * n.sqrt() < Z_LIMIT,
+    }
+}
+
+pub fn test(body: &[u8], histogram: &[u64]) -> Randomness {
+    if body.len() < MIN_SIZE {
+        return Randomness {
+            chi_square: None,
+            monobit: None,
+            serial_correlation: None,
+            verdict: format!("Too small to tell, needs at least {} bytes.", MIN_SIZE),
+        };
+    }
+    let chi_square = chi_square(histogram, body.len());
+    let monobit = monobit(histogram, body.len());
+    let serial_correlation = serial_correlation(body);
+    let failed: Vec<&str> = [
+        (chi_square.passed, "chi_square"),
+        (monobit.passed, "monobit"),
+        (serial_correlation.passed, "serial_correlation"),
+    ]
+    .into_iter()
+    .filter_map(|(passed, name)| (!passed).then_some(name))
+    .collect();
+    let verdict = match failed.is_empty() {
+        true => "Looks like ciphertext or compressed data.".to_string(),
+        false => format!(
+            "Does not look like ciphertext or compressed data (failed {}).",
+            failed.join(", ")
+        ),
+    };
+    Randomness {
+        chi_square: Some(chi_square),
+        monobit: Some(monobit),
+        serial_correlation: Some(serial_correlation),
+        verdict,
+    }
+}
@@ -0,0 +1,106 @@
+// --read-only: destruction endpoints answer 503 while stats and health checks keep working
+use actix_web::body::{BoxBody, MessageBody};
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::Method;
+use actix_web::{Error, HttpRequest, HttpResponse};
+use std::future::{ready, Future, Ready};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+// Paths whose POST/PATCH requests destroy payloads and write stats
+const DESTRUCTION_PATHS: &[&str] = &[
+    "/pulverize",
+    "/blackhole",
+    "/shred",
+    "/burn",
+    "/validate-before-destroy",
+    "/auto",
+    "/autopsy",
+    "/compare-then-destroy",
+    "/queue",
+    "/tus",
+    "/cookies/destroy",
+    "/fetch-and-destroy",
+    "/stage",
+    "/commit",
+    "/abort",
+];
+
+const READ_ONLY_MESSAGE: &str =
+    "The pulverizer is in read-only mode. The shredder is unplugged for maintenance, your payload survives for now. Please try again later.";
+
+// The destruction path a request goes to, without --base-path. Decided by the route it
+// is going to take, so percent-encoding the path doesn't get around it.
+pub fn destruction_path(req: &HttpRequest) -> Option<&'static str> {
+    if req.method() != Method::POST && req.method() != Method::PATCH {
+        return None;
+    }
+    let path = crate::index::resolve(req)?.path;
+    DESTRUCTION_PATHS.iter().copied().find(|prefix| {
+        path.strip_prefix(prefix)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+    })
+}
+
+pub fn is_destruction(req: &ServiceRequest) -> bool {
+    destruction_path(req.request()).is_some()
+}
+
+// Middleware rejecting destruction requests, `boring` picks the terse message
+pub struct ReadOnly {
+    pub boring: bool,
+}
+
+impl<S, B> Transform<S, ServiceRequest> for ReadOnly
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<BoxBody>;
+    type Error = Error;
+    type Transform = ReadOnlyMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(ReadOnlyMiddleware {
+            service,
+            boring: self.boring,
+        }))
+    }
+}
+
+pub struct ReadOnlyMiddleware<S> {
+    service: S,
+    boring: bool,
+}
+
+impl<S, B> Service<ServiceRequest> for ReadOnlyMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<BoxBody>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    fn poll_ready(&self, ctx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(ctx)
+    }
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        if !is_destruction(&req) {
+            let fut = self.service.call(req);
+            return Box::pin(async move { Ok(fut.await?.map_into_boxed_body()) });
+        }
+        let message = if self.boring {
+            "Read-only mode."
+        } else {
+            READ_ONLY_MESSAGE
+        };
+        let response = HttpResponse::ServiceUnavailable()
+            .insert_header(("Retry-After", "300"))
+            .json(serde_json::json!({ "error": message, "read_only": true }));
+        Box::pin(async move { Ok(req.into_response(response)) })
+    }
+}
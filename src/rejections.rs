@@ -0,0 +1,168 @@
+// Rejected requests, recorded in the `rejections` table (or the stats log)
+//
+// Payloads that are too large, have a content type that isn't allowed, run into
+// --max-backlog or come without valid credentials never reach a handler's stats row,
+// so without this they'd be invisible in capacity planning. Only the route, status
+// and reason are kept, never the payload or the client. /stats and /metrics report
+// the counts per endpoint and reason.
+use actix_web::body::MessageBody;
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::StatusCode;
+use actix_web::web::Data;
+use actix_web::Error;
+#[cfg(feature = "sqlite")]
+use rusqlite::Connection;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::future::{ready, Future, Ready};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+#[derive(Serialize, Clone)]
+pub struct RejectionCount {
+    pub endpoint: String,
+    pub reason: String,
+    pub count: i64,
+}
+
+// The reason recorded for a status, None if it isn't a rejection
+fn reason(status: StatusCode) -> Option<&'static str> {
+    match status {
+        StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => Some("auth"),
+        StatusCode::PAYLOAD_TOO_LARGE => Some("too_large"),
+        StatusCode::UNSUPPORTED_MEDIA_TYPE => Some("content_type"),
+        StatusCode::TOO_MANY_REQUESTS => Some("rate_limited"),
+        _ => None,
+    }
+}
+
+#[cfg(feature = "sqlite")]
+pub fn init_table(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS rejections (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            endpoint TEXT NOT NULL,
+            status INTEGER NOT NULL,
+            reason TEXT NOT NULL,
+            ts DATETIME DEFAULT CURRENT_TIMESTAMP
+        )",
+        [],
+    )
+    .map(|_| ())
+}
+
+// Rotated files from before rejections were recorded lack the table
+#[cfg(feature = "sqlite")]
+fn has_table(conn: &Connection) -> rusqlite::Result<bool> {
+    conn.query_row(
+        "SELECT COUNT(*) > 0 FROM sqlite_master WHERE type = 'table' AND name = 'rejections'",
+        [],
+        |row| row.get(0),
+    )
+}
+
+// Add the rejections of a database file to `counts`, by endpoint and reason
+#[cfg(feature = "sqlite")]
+pub fn add_counts(
+    conn: &Connection,
+    counts: &mut BTreeMap<(String, String), i64>,
+) -> rusqlite::Result<()> {
+    if !has_table(conn)? {
+        return Ok(());
+    }
+    let mut stmt = conn
+        .prepare("SELECT endpoint, reason, COUNT(*) FROM rejections GROUP BY endpoint, reason")?;
+    let mut rows = stmt.query([])?;
+    while let Some(row) = rows.next()? {
+        *counts.entry((row.get(0)?, row.get(1)?)).or_default() += row.get::<_, i64>(2)?;
+    }
+    Ok(())
+}
+
+pub fn into_list(counts: BTreeMap<(String, String), i64>) -> Vec<RejectionCount> {
+    counts
+        .into_iter()
+        .map(|((endpoint, reason), count)| RejectionCount {
+            endpoint,
+            reason,
+            count,
+        })
+        .collect()
+}
+
+// The route a rejected request was meant for, like stats endpoints without --base-path
+// and the leading slash, e.g. "pulverize" or "commit/{id}". Requests refused before
+// routing get the route they were headed for, "unmatched" if there is none. The path
+// itself is never stored, anyone could fill the table with made-up ones.
+fn endpoint(req: &actix_web::HttpRequest) -> String {
+    let base_path = req
+        .app_data::<Data<crate::AppConfig>>()
+        .map_or("", |config| config.base_path.as_str());
+    let Some(pattern) = req.match_pattern() else {
+        return match crate::index::resolve(req) {
+            Some(endpoint) => endpoint.path.trim_start_matches('/').to_string(),
+            None => "unmatched".to_string(),
+        };
+    };
+    let pattern = pattern.strip_prefix(base_path).unwrap_or(&pattern);
+    pattern.trim_start_matches('/').to_string()
+}
+
+fn record(req: &actix_web::HttpRequest, status: StatusCode) {
+    let Some(reason) = reason(status) else {
+        return;
+    };
+    let Some(db) = req.app_data::<Data<dyn crate::store::Store>>() else {
+        return;
+    };
+    let result = db.writer().reject(&endpoint(req), status.as_u16(), reason);
+    if let Err(e) = result {
+        eprintln!("Failed to record rejection: {}", e);
+    }
+}
+
+// Middleware recording rejected requests, whichever layer turned them away
+pub struct RecordRejections;
+
+impl<S, B> Transform<S, ServiceRequest> for RecordRejections
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = RecordRejectionsMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RecordRejectionsMiddleware { service }))
+    }
+}
+
+pub struct RecordRejectionsMiddleware<S> {
+    service: S,
+}
+
+impl<S, B> Service<ServiceRequest> for RecordRejectionsMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    fn poll_ready(&self, ctx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(ctx)
+    }
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let fut = self.service.call(req);
+        Box::pin(async move {
+            let res = fut.await?;
+            record(res.request(), res.status());
+            Ok(res)
+        })
+    }
+}
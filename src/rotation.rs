@@ -0,0 +1,269 @@
+// Rotation of the stats database for --rotate-monthly and --rotate-size
+//
+// The current file is closed, renamed to `<db-path>.<YYYY-MM>` after the month of its
+// oldest row (`.1`, `.2`, … when a month needs several files) and replaced by a fresh
+// one. Usage rollups move along to the new file, so /stats/usage keeps whole months, and
+// so do the tables that aren't per request: rejections (moved, so /stats counts them once
+// across files), the unique payload sketches and the --remember-hashes filter and receipts.
+use crate::{cardinality, hash_index, rejections, DbArgs};
+use actix_web::web::Data;
+use rusqlite::types::Value;
+use rusqlite::{params, params_from_iter, Connection, OpenFlags};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+pub struct RotationPolicy {
+    pub monthly: bool,
+    pub max_size: Option<u64>,
+    // Rotated files to keep, None keeps them all
+    pub keep: Option<usize>,
+}
+
+impl RotationPolicy {
+    pub fn is_enabled(&self) -> bool {
+        self.monthly || self.max_size.is_some()
+    }
+}
+
+// "2024-06" or "2024-06.2" → sort key
+fn rotation_key(suffix: &str) -> Option<(String, u32)> {
+    let (month, n) = match suffix.split_once('.') {
+        Some((month, n)) => (month, n.parse().ok()?),
+        None => (suffix, 0),
+    };
+    chrono::NaiveDate::parse_from_str(&format!("{}-01", month), "%Y-%m-%d").ok()?;
+    Some((month.to_string(), n))
+}
+
+// Rotated files of the database at `db_path`, oldest first
+pub fn rotated_files(db_path: &str) -> Vec<PathBuf> {
+    let path = Path::new(db_path);
+    let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+        return Vec::new();
+    };
+    let dir = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let prefix = format!("{}.", name);
+    let mut files: Vec<((String, u32), PathBuf)> = entries
+        .flatten()
+        .filter_map(|entry| {
+            let file_name = entry.file_name().into_string().ok()?;
+            let key = rotation_key(file_name.strip_prefix(&prefix)?)?;
+            Some((key, entry.path()))
+        })
+        .collect();
+    files.sort();
+    files.into_iter().map(|(_, path)| path).collect()
+}
+
+// A free name for the current file, after the month of its oldest row
+fn rotation_target(conn: &Connection, db_path: &str) -> rusqlite::Result<PathBuf> {
+    let month: String = conn.query_row(
+        "SELECT COALESCE(strftime('%Y-%m', MIN(ts)), strftime('%Y-%m', 'now')) FROM endpoint_stats_raw",
+        [],
+        |row| row.get(0),
+    )?;
+    let mut target = PathBuf::from(format!("{}.{}", db_path, month));
+    let mut n = 1;
+    while target.exists() {
+        target = PathBuf::from(format!("{}.{}.{}", db_path, month, n));
+        n += 1;
+    }
+    Ok(target)
+}
+
+fn is_due(conn: &Connection, db_path: &str, policy: &RotationPolicy) -> rusqlite::Result<bool> {
+    let (rows, older_month): (i64, bool) = conn.query_row(
+        "SELECT COUNT(*), COALESCE(strftime('%Y-%m', MIN(ts)) < strftime('%Y-%m', 'now'), 0) FROM endpoint_stats_raw",
+        [],
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    )?;
+    if rows == 0 {
+        return Ok(false);
+    }
+    let too_big = policy.max_size.is_some_and(|max_size| {
+        std::fs::metadata(db_path).is_ok_and(|meta| meta.len() >= max_size)
+    });
+    Ok(too_big || (policy.monthly && older_month))
+}
+
+type Rollup = (String, String, i64, i64, i64);
+
+fn read_rollups(conn: &Connection) -> rusqlite::Result<Vec<Rollup>> {
+    let mut stmt =
+        conn.prepare("SELECT month, tenant, requests, bytes, runtime_us FROM usage_rollups")?;
+    let rows = stmt.query_map([], |row| {
+        Ok((
+            row.get(0)?,
+            row.get(1)?,
+            row.get(2)?,
+            row.get(3)?,
+            row.get(4)?,
+        ))
+    })?;
+    rows.collect()
+}
+
+fn write_rollups(conn: &Connection, rollups: &[Rollup]) -> rusqlite::Result<()> {
+    let tx = conn.unchecked_transaction()?;
+    for (month, tenant, requests, bytes, runtime_us) in rollups {
+        tx.execute(
+            "INSERT OR REPLACE INTO usage_rollups (month, tenant, requests, bytes, runtime_us) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![month, tenant, requests, bytes, runtime_us],
+        )?;
+    }
+    tx.commit()
+}
+
+type CreateTable = fn(&Connection) -> rusqlite::Result<()>;
+
+// Tables carried into the new file as they are, with what creates them there
+const CARRIED: [(&str, CreateTable); 4] = [
+    ("rejections", rejections::init_table),
+    ("unique_payloads", cardinality::ensure_table),
+    ("hash_filter", hash_index::ensure_tables),
+    ("hash_receipts", hash_index::ensure_tables),
+];
+
+type Rows = Vec<Vec<Value>>;
+
+// The rows of the carried tables, tables created on the first write may not exist yet
+fn read_carried(conn: &Connection) -> rusqlite::Result<Vec<(usize, Rows)>> {
+    let mut carried = Vec::new();
+    for (i, (table, _)) in CARRIED.iter().enumerate() {
+        let exists: bool = conn.query_row(
+            "SELECT COUNT(*) > 0 FROM sqlite_master WHERE type = 'table' AND name = ?1",
+            [table],
+            |row| row.get(0),
+        )?;
+        if !exists {
+            continue;
+        }
+        let mut stmt = conn.prepare(&format!("SELECT * FROM {}", table))?;
+        let columns = stmt.column_count();
+        let rows = stmt.query_map([], |row| (0..columns).map(|i| row.get(i)).collect())?;
+        carried.push((i, rows.collect::<rusqlite::Result<Rows>>()?));
+    }
+    Ok(carried)
+}
+
+fn write_carried(conn: &Connection, carried: &[(usize, Rows)]) -> rusqlite::Result<()> {
+    let tx = conn.unchecked_transaction()?;
+    for (i, rows) in carried {
+        let (table, create) = CARRIED[*i];
+        create(&tx)?;
+        for row in rows {
+            let placeholders = vec!["?"; row.len()].join(", ");
+            tx.execute(
+                &format!("INSERT OR REPLACE INTO {} VALUES ({})", table, placeholders),
+                params_from_iter(row),
+            )?;
+        }
+    }
+    tx.commit()
+}
+
+// Rejections are moved rather than copied, /stats adds up the rotated files too
+fn drop_rejections(db_args: &DbArgs, target: &Path) -> Result<(), String> {
+    let rotated = DbArgs {
+        db_path: target.to_string_lossy().into_owned(),
+        ..db_args.clone()
+    };
+    let conn = crate::open_db(&rotated, OpenFlags::SQLITE_OPEN_READ_WRITE)?;
+    conn.execute("DELETE FROM rejections", [])
+        .map(|_| ())
+        .map_err(|e| {
+            format!(
+                "Failed to move the rejections out of {}: {}",
+                rotated.db_path, e
+            )
+        })
+}
+
+// Swap the current file for a fresh one while holding the connection, so no request
+// is recorded in between. On failure the old file is put back.
+fn rotate(db: &Mutex<Connection>, db_args: &DbArgs) -> Result<PathBuf, String> {
+    let mut conn = db.lock().unwrap();
+    let target = rotation_target(&conn, &db_args.db_path).map_err(|e| e.to_string())?;
+    let rollups = read_rollups(&conn).map_err(|e| e.to_string())?;
+    let carried = read_carried(&conn).map_err(|e| e.to_string())?;
+    let placeholder = Connection::open_in_memory().map_err(|e| e.to_string())?;
+    std::mem::replace(&mut *conn, placeholder)
+        .close()
+        .map_err(|(_, e)| format!("Failed to close {}: {}", db_args.db_path, e))?;
+    let reopen = |conn: &mut Connection| match crate::open_db(db_args, OpenFlags::default()) {
+        Ok(old) => *conn = old,
+        Err(e) => eprintln!("{}", e),
+    };
+    if let Err(e) = std::fs::rename(&db_args.db_path, &target) {
+        reopen(&mut conn);
+        return Err(format!("Failed to move {}: {}", db_args.db_path, e));
+    }
+    let fresh = crate::init_db(db_args).and_then(|fresh| {
+        write_rollups(&fresh, &rollups)
+            .map(|()| fresh)
+            .map_err(|e| format!("Failed to carry over usage rollups: {}", e))
+            .and_then(|fresh| {
+                write_carried(&fresh, &carried)
+                    .map(|()| fresh)
+                    .map_err(|e| format!("Failed to carry over tables: {}", e))
+            })
+    });
+    match fresh {
+        Ok(fresh) => {
+            *conn = fresh;
+            if let Err(e) = drop_rejections(db_args, &target) {
+                eprintln!("{}, they are counted twice", e);
+            }
+            Ok(target)
+        }
+        Err(e) => {
+            let _ = std::fs::remove_file(&db_args.db_path);
+            if let Err(e) = std::fs::rename(&target, &db_args.db_path) {
+                eprintln!("Failed to move {} back: {}", target.display(), e);
+            }
+            reopen(&mut conn);
+            Err(e)
+        }
+    }
+}
+
+// Delete the oldest rotated files beyond the --rotate-keep limit
+fn prune(db_path: &str, keep: usize) {
+    let files = rotated_files(db_path);
+    for old in &files[..files.len().saturating_sub(keep)] {
+        match std::fs::remove_file(old) {
+            Ok(()) => println!("Deleted rotated database {}", old.display()),
+            Err(e) => eprintln!("Failed to delete rotated database {}: {}", old.display(), e),
+        }
+    }
+}
+
+// Check the policy right away and then every minute for as long as the process runs
+pub fn spawn(db: Data<Mutex<Connection>>, db_args: DbArgs, policy: RotationPolicy) {
+    std::thread::spawn(move || loop {
+        let due = is_due(&db.lock().unwrap(), &db_args.db_path, &policy);
+        match due {
+            Ok(true) => match rotate(&db, &db_args) {
+                Ok(target) => {
+                    println!("Rotated database to {}", target.display());
+                    if let Some(keep) = policy.keep {
+                        prune(&db_args.db_path, keep);
+                    }
+                }
+                Err(e) => eprintln!("Failed to rotate database: {}", e),
+            },
+            Ok(false) => {}
+            Err(e) => eprintln!("Failed to check database rotation: {}", e),
+        }
+        std::thread::sleep(CHECK_INTERVAL);
+    });
+}
@@ -0,0 +1,101 @@
+// Operator-defined routing rules for /auto, loaded from the config file
+use actix_web::HttpRequest;
+use serde::Deserialize;
+use std::collections::HashMap;
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum RoutingAction {
+    Pulverize,
+    Shred,
+    Burn,
+    Blackhole,
+    Reject,
+}
+
+impl RoutingAction {
+    pub fn method(self) -> &'static str {
+        match self {
+            RoutingAction::Pulverize => "pulverize",
+            RoutingAction::Shred => "shred",
+            RoutingAction::Burn => "burn",
+            RoutingAction::Blackhole => "blackhole",
+            RoutingAction::Reject => "reject",
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct RoutingRule {
+    pub name: String,
+    // Case-insensitive prefix of the Content-Type header
+    pub content_type: Option<String>,
+    pub min_size: Option<usize>,
+    pub max_size: Option<usize>,
+    // All listed headers must be present with exactly these values ("*" matches any value)
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+    pub action: RoutingAction,
+    // Only used with action = "reject"
+    pub reject_status: Option<u16>,
+    pub reject_message: Option<String>,
+}
+
+impl RoutingRule {
+    pub fn validate(&self) -> Result<(), String> {
+        if let (Some(min), Some(max)) = (self.min_size, self.max_size) {
+            if min > max {
+                return Err(format!(
+                    "Rule '{}': min_size is larger than max_size",
+                    self.name
+                ));
+            }
+        }
+        if let Some(status) = self.reject_status {
+            if !(400..=599).contains(&status) {
+                return Err(format!(
+                    "Rule '{}': reject_status must be a 4xx or 5xx status code",
+                    self.name
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    fn matches(&self, req: &HttpRequest, body: &[u8]) -> bool {
+        if let Some(prefix) = &self.content_type {
+            let content_type = req
+                .headers()
+                .get("Content-Type")
+                .and_then(|v| v.to_str().ok())
+                .unwrap_or("");
+            if !content_type
+                .to_ascii_lowercase()
+                .starts_with(&prefix.to_ascii_lowercase())
+            {
+                return false;
+            }
+        }
+        if self.min_size.is_some_and(|min| body.len() < min)
+            || self.max_size.is_some_and(|max| body.len() > max)
+        {
+            return false;
+        }
+        self.headers.iter().all(|(name, expected)| {
+            match req.headers().get(name.as_str()).map(|v| v.to_str()) {
+                Some(Ok(value)) => expected == "*" || value == expected,
+                _ => false,
+            }
+        })
+    }
+}
+
+// First rule matching the request, if any
+pub fn evaluate<'a>(
+    rules: &'a [RoutingRule],
+    req: &HttpRequest,
+    body: &[u8],
+) -> Option<&'a RoutingRule> {
+    rules.iter().find(|rule| rule.matches(req, body))
+}
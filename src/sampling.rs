@@ -0,0 +1,43 @@
+// --stats-sample 1/N: record only every Nth destruction
+//
+// For deployments where writing a stats row per destruction is the bottleneck. The
+// sample is deterministic: a counter per endpoint picks the first of every N of its
+// destructions, which is recorded with `sample_weight` N. Separate counters keep
+// interleaved traffic from starving an endpoint of samples. The reports multiply by it, so their counts and sums
+// stay estimates of all destructions; rows recorded without sampling weigh 1. Skipped
+// destructions are still destroyed, their receipts just have no stat_id.
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+pub struct Sampler {
+    every: u64,
+    // Destructions seen per endpoint
+    seen: Mutex<HashMap<String, u64>>,
+}
+
+impl Sampler {
+    pub fn new(every: u64) -> Self {
+        Sampler {
+            every,
+            seen: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // The weight to record the endpoint's next destruction with, None if it is skipped
+    pub fn next(&self, endpoint: &str) -> Option<i64> {
+        let mut seen = self.seen.lock().unwrap();
+        let count = seen.entry(endpoint.to_string()).or_insert(0);
+        let sampled = count.is_multiple_of(self.every);
+        *count += 1;
+        sampled.then_some(self.every as i64)
+    }
+}
+
+// clap value parser for --stats-sample, e.g. 1/100
+pub fn parse_arg(spec: &str) -> Result<u64, String> {
+    spec.trim()
+        .strip_prefix("1/")
+        .and_then(|every| every.trim().parse::<u64>().ok())
+        .filter(|every| *every >= 1)
+        .ok_or_else(|| format!("invalid sample '{}', use e.g. 1/100", spec))
+}
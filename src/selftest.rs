@@ -0,0 +1,725 @@
+// `selftest` subcommand: a one-command smoke test after deployments
+//
+// Starts the server in-process on a free localhost port and a scratch database, sends
+// every endpoint a representative request, plus oversized and malformed ones that have
+// to be refused, and checks that the destructions and rejections ended up in the
+// database. The database given with --db-path is never touched. Exits non-zero if any
+// check fails.
+use crate::store::RejectionCounts;
+use crate::{DbArgs, ServeArgs};
+use clap::Parser;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+const BOUNDARY: &str = "payload-pulverizer-selftest";
+// How long a queued job may take before it counts as stuck
+const JOB_TIMEOUT: Duration = Duration::from_secs(10);
+
+// CLI arguments of the selftest subcommand
+#[derive(clap::Args, Debug, Clone)]
+pub struct SelftestArgs {
+    /// Keep the scratch database afterwards and print its path
+    #[arg(long)]
+    keep_db: bool,
+}
+
+// Destruction endpoints that have to leave a stats row
+fn recorded_endpoints() -> Vec<&'static str> {
+    let endpoints = [
+        "pulverize",
+        "blackhole",
+        "compare-then-destroy",
+        "cookies",
+        "tus",
+        "stage",
+    ]
+    .into_iter();
+    #[cfg(feature = "extras")]
+    let endpoints = endpoints.chain(["shred", "burn", "autopsy"]);
+    #[cfg(feature = "validators")]
+    let endpoints = endpoints.chain(["validate-before-destroy"]);
+    endpoints.collect()
+}
+
+// Runs the checks against the server and keeps score
+struct Client {
+    agent: ureq::Agent,
+    base: String,
+    admin_token: String,
+    passed: usize,
+    failed: Vec<String>,
+}
+
+impl Client {
+    fn request(&self, method: &str, path: &str) -> ureq::Request {
+        self.agent
+            .request(method, &format!("{}{}", self.base, path))
+    }
+
+    fn get(&self, path: &str) -> ureq::Request {
+        self.request("GET", path)
+    }
+
+    fn post(&self, path: &str) -> ureq::Request {
+        self.request("POST", path)
+    }
+
+    fn admin(&self, path: &str) -> ureq::Request {
+        self.post(path)
+            .set("Authorization", &format!("Bearer {}", self.admin_token))
+    }
+
+    // Report a check, the value is passed on for checks that build on it
+    fn check<T>(&mut self, name: &str, result: Result<T, String>) -> Option<T> {
+        match result {
+            Ok(value) => {
+                println!("ok    {}", name);
+                self.passed += 1;
+                Some(value)
+            }
+            Err(e) => {
+                println!("FAIL  {}: {}", name, e);
+                self.failed.push(name.to_string());
+                None
+            }
+        }
+    }
+}
+
+// The response if it has the expected status. ureq returns 4xx and 5xx as errors, but
+// here they are answers like any other.
+fn expect(
+    result: Result<ureq::Response, ureq::Error>,
+    status: u16,
+) -> Result<ureq::Response, String> {
+    let response = match result {
+        Ok(response) | Err(ureq::Error::Status(_, response)) => response,
+        Err(e) => return Err(e.to_string()),
+    };
+    match response.status() == status {
+        true => Ok(response),
+        false => Err(format!("expected {}, got {}", status, response.status())),
+    }
+}
+
+fn json(response: ureq::Response) -> Result<Value, String> {
+    let text = response.into_string().map_err(|e| e.to_string())?;
+    serde_json::from_str(&text).map_err(|e| format!("invalid JSON response: {}", e))
+}
+
+// A JSON response whose `key` is `expected`
+fn expect_field(
+    result: Result<ureq::Response, ureq::Error>,
+    status: u16,
+    key: &str,
+    expected: Value,
+) -> Result<Value, String> {
+    let body = json(expect(result, status)?)?;
+    match body.get(key) {
+        Some(value) if *value == expected => Ok(body),
+        value => Err(format!(
+            "expected {} to be {}, got {:?}",
+            key, expected, value
+        )),
+    }
+}
+
+fn expect_ok(result: Result<ureq::Response, ureq::Error>, status: u16) -> Result<(), String> {
+    expect(result, status).map(drop)
+}
+
+fn string_field(body: &Value, key: &str) -> Result<String, String> {
+    body.get(key)
+        .and_then(Value::as_str)
+        .map(str::to_string)
+        .ok_or_else(|| format!("no {} in the response", key))
+}
+
+// A multipart/form-data body with the given parts
+fn multipart(parts: &[(&str, &str)]) -> String {
+    let mut body = String::new();
+    for (name, content) in parts {
+        body.push_str(&format!(
+            "--{}\r\nContent-Disposition: form-data; name=\"{}\"\r\n\r\n{}\r\n",
+            BOUNDARY, name, content
+        ));
+    }
+    body.push_str(&format!("--{}--\r\n", BOUNDARY));
+    body
+}
+
+fn check_destruction(client: &mut Client) {
+    let payload = r#"{"order": 42, "items": ["anvil", "piano"]}"#;
+    client.check(
+        "POST /pulverize",
+        expect_field(
+            client
+                .post("/pulverize")
+                .set("Content-Type", "application/json")
+                .send_string(payload),
+            200,
+            "status",
+            "success".into(),
+        ),
+    );
+    client.check(
+        "POST /pulverize refuses a broken gzip body",
+        expect_ok(
+            client
+                .post("/pulverize")
+                .set("Content-Encoding", "gzip")
+                .send_string("not gzip at all"),
+            400,
+        ),
+    );
+    client.check(
+        "POST /blackhole",
+        expect_ok(client.post("/blackhole").send_string(payload), 204),
+    );
+    client.check(
+        "POST /auto",
+        expect_ok(
+            client
+                .post("/auto")
+                .set("Content-Type", "application/json")
+                .send_string(payload),
+            200,
+        ),
+    );
+    client.check(
+        "POST /compare-then-destroy",
+        expect_field(
+            client
+                .post("/compare-then-destroy")
+                .set(
+                    "Content-Type",
+                    &format!("multipart/form-data; boundary={}", BOUNDARY),
+                )
+                .send_string(&multipart(&[("a", "hello\nworld"), ("b", "hello\nthere")])),
+            200,
+            "status",
+            "compared_and_destroyed".into(),
+        ),
+    );
+    client.check(
+        "POST /compare-then-destroy refuses a body without parts",
+        expect_ok(
+            client.post("/compare-then-destroy").send_string(payload),
+            400,
+        ),
+    );
+    client.check(
+        "POST /cookies/destroy",
+        expect_field(
+            client
+                .post("/cookies/destroy")
+                .set("Cookie", "session=doomed; theme=dark")
+                .call(),
+            200,
+            "count",
+            2.into(),
+        ),
+    );
+    #[cfg(feature = "extras")]
+    {
+        client.check(
+            "POST /shred",
+            expect_field(
+                client.post("/shred").send_string(payload),
+                200,
+                "status",
+                "shredded".into(),
+            ),
+        );
+        client.check(
+            "GET /shred/styles",
+            expect_ok(client.get("/shred/styles").call(), 200),
+        );
+        client.check(
+            "POST /burn",
+            expect_field(
+                client.post("/burn").send_string(payload),
+                200,
+                "status",
+                "incinerated".into(),
+            ),
+        );
+        client.check(
+            "POST /autopsy",
+            expect_field(
+                client.post("/autopsy").send_string(payload),
+                200,
+                "status",
+                "examined".into(),
+            ),
+        );
+    }
+    #[cfg(feature = "validators")]
+    {
+        client.check(
+            "POST /validate-before-destroy",
+            expect_field(
+                client
+                    .post("/validate-before-destroy")
+                    .set("Content-Type", "application/json")
+                    .send_string(payload),
+                200,
+                "is_json",
+                true.into(),
+            ),
+        );
+        client.check(
+            "POST /validate-before-destroy reports malformed JSON",
+            expect_field(
+                client
+                    .post("/validate-before-destroy")
+                    .set("Content-Type", "application/json")
+                    .send_string(r#"{"order": 42, "items": ["anvil""#),
+                200,
+                "is_json",
+                false.into(),
+            ),
+        );
+        client.check(
+            "POST /validate-before-destroy refuses an oversized payload",
+            expect_ok(
+                client.post("/validate-before-destroy").send_bytes(&vec![
+                    b'x';
+                    crate::VALIDATE_MAX_SIZE
+                        + 1
+                ]),
+                413,
+            ),
+        );
+    }
+}
+
+fn check_queue(client: &mut Client) {
+    let submitted = expect(client.post("/queue").send_string("queued payload"), 202)
+        .and_then(json)
+        .and_then(|body| string_field(&body, "job_id"));
+    let Some(job_id) = client.check("POST /queue", submitted) else {
+        return;
+    };
+    let started = Instant::now();
+    let finished = loop {
+        match expect(client.get(&format!("/queue/{}", job_id)).call(), 200)
+            .and_then(json)
+            .and_then(|body| string_field(&body, "status"))
+        {
+            Ok(status) if status == "done" => break Ok(()),
+            Ok(status) if started.elapsed() > JOB_TIMEOUT => {
+                break Err(format!("job still {} after {:?}", status, JOB_TIMEOUT))
+            }
+            Ok(_) => std::thread::sleep(Duration::from_millis(50)),
+            Err(e) => break Err(e),
+        }
+    };
+    client.check("GET /queue/{id}", finished);
+    client.check(
+        "GET /jobs/{id}",
+        expect_ok(client.get(&format!("/jobs/{}", job_id)).call(), 200),
+    );
+}
+
+fn check_tus(client: &mut Client) {
+    client.check(
+        "OPTIONS /tus",
+        expect_ok(client.request("OPTIONS", "/tus").call(), 204),
+    );
+    let created = expect(
+        client
+            .post("/tus")
+            .set("Tus-Resumable", "1.0.0")
+            .set("Upload-Length", "11")
+            .call(),
+        201,
+    )
+    .and_then(|response| {
+        response
+            .header("Location")
+            .map(str::to_string)
+            .ok_or_else(|| "no Location header".to_string())
+    });
+    let Some(location) = client.check("POST /tus", created) else {
+        return;
+    };
+    let tus = |method: &str| {
+        client
+            .request(method, &location)
+            .set("Tus-Resumable", "1.0.0")
+    };
+    let head = expect(tus("HEAD").call(), 200).and_then(|response| {
+        match response.header("Upload-Offset") {
+            Some("0") => Ok(()),
+            offset => Err(format!("expected Upload-Offset 0, got {:?}", offset)),
+        }
+    });
+    let patch = |offset: &str, status: u16| {
+        let result = tus("PATCH")
+            .set("Upload-Offset", offset)
+            .set("Content-Type", "application/offset+octet-stream")
+            .send_string("hello world");
+        expect_ok(result, status)
+    };
+    let wrong_offset = patch("5", 409);
+    let upload = patch("0", 204);
+    let receipt = expect_field(tus("GET").call(), 200, "status", "destroyed".into());
+    let delete = expect_ok(tus("DELETE").call(), 204);
+    client.check("HEAD /tus/{id}", head);
+    client.check("PATCH /tus/{id} refuses a wrong offset", wrong_offset);
+    client.check("PATCH /tus/{id}", upload);
+    client.check("GET /tus/{id}", receipt);
+    client.check("DELETE /tus/{id}", delete);
+}
+
+fn check_stage(client: &mut Client) {
+    let stage = |client: &mut Client| {
+        let staged = expect(client.post("/stage").send_string("staged payload"), 201)
+            .and_then(json)
+            .and_then(|body| string_field(&body, "stage_id"));
+        client.check("POST /stage", staged)
+    };
+    if let Some(id) = stage(client) {
+        client.check(
+            "POST /commit/{id}",
+            expect_field(
+                client.post(&format!("/commit/{}", id)).call(),
+                200,
+                "status",
+                "destroyed".into(),
+            ),
+        );
+        client.check(
+            "POST /commit/{id} refuses a committed stage",
+            expect_ok(client.post(&format!("/commit/{}", id)).call(), 404),
+        );
+    }
+    if let Some(id) = stage(client) {
+        client.check(
+            "POST /abort/{id}",
+            expect_field(
+                client.post(&format!("/abort/{}", id)).call(),
+                200,
+                "status",
+                "aborted".into(),
+            ),
+        );
+    }
+}
+
+fn check_misc(client: &mut Client) {
+    client.check(
+        "GET /",
+        expect_field(
+            client.get("/").call(),
+            200,
+            "name",
+            env!("CARGO_PKG_NAME").into(),
+        ),
+    );
+    client.check(
+        "OPTIONS /pulverize",
+        expect(client.request("OPTIONS", "/pulverize").call(), 204).and_then(|response| {
+            match response.header("Allow") {
+                Some("POST, OPTIONS") => Ok(()),
+                allow => Err(format!("expected Allow: POST, OPTIONS, got {:?}", allow)),
+            }
+        }),
+    );
+    client.check(
+        "GET /pulverize refuses the wrong method",
+        expect_ok(client.get("/pulverize").call(), 405),
+    );
+    client.check(
+        "GET /ping",
+        expect_field(client.get("/ping").call(), 200, "status", "pong".into()),
+    );
+    client.check(
+        "GET /healthz",
+        expect_ok(client.get("/healthz").call(), 200),
+    );
+    client.check(
+        "GET /generate",
+        expect(client.get("/generate?bytes=1k").call(), 200).and_then(|response| {
+            let mut body = Vec::new();
+            std::io::Read::read_to_end(&mut response.into_reader(), &mut body)
+                .map_err(|e| e.to_string())?;
+            match body.len() {
+                1024 => Ok(()),
+                len => Err(format!("expected 1024 bytes, got {}", len)),
+            }
+        }),
+    );
+    client.check(
+        "POST /mirror",
+        expect_field(
+            client.post("/mirror").send_string("echo"),
+            200,
+            "method",
+            "POST".into(),
+        ),
+    );
+    client.check(
+        "POST /fetch-and-destroy refuses without --fetch-allowed-hosts",
+        expect_ok(
+            client
+                .post("/fetch-and-destroy")
+                .set("Content-Type", "application/json")
+                .send_string(r#"{"url": "https://example.com/"}"#),
+            403,
+        ),
+    );
+    #[cfg(feature = "sqlite")]
+    client.check(
+        "GET /was-it-destroyed refuses without --remember-hashes",
+        expect_ok(
+            client
+                .get(&format!("/was-it-destroyed?sha256={}", "0".repeat(64)))
+                .call(),
+            400,
+        ),
+    );
+    client.check(
+        "POST /admin/reload refuses without a token",
+        expect_ok(client.post("/admin/reload").call(), 401),
+    );
+    client.check(
+        "POST /admin/reload",
+        expect_ok(client.admin("/admin/reload").call(), 400),
+    );
+    #[cfg(feature = "sqlite")]
+    client.check(
+        "POST /admin/backup",
+        expect_ok(client.admin("/admin/backup").call(), 200),
+    );
+    #[cfg(feature = "sqlite")]
+    client.check(
+        "POST /admin/integrity-check",
+        expect_field(
+            client.admin("/admin/integrity-check?mode=quick").call(),
+            200,
+            "status",
+            "ok".into(),
+        ),
+    );
+    client.check(
+        "GET /bench",
+        expect_ok(
+            client
+                .get("/bench")
+                .set("Authorization", &format!("Bearer {}", client.admin_token))
+                .call(),
+            200,
+        ),
+    );
+}
+
+fn check_reports(client: &mut Client) {
+    client.check(
+        "GET /stats",
+        expect(client.get("/stats").call(), 200)
+            .and_then(json)
+            .and_then(|body| match body["stats"].as_array() {
+                Some(stats) if !stats.is_empty() => Ok(()),
+                _ => Err("no stats reported".to_string()),
+            }),
+    );
+    let paths = ["/stats/summary"].into_iter();
+    #[cfg(feature = "sqlite")]
+    let paths = paths.chain([
+        "/stats/by-client",
+        "/stats/export?format=jsonl",
+        "/stats/usage",
+        "/stats/tail",
+        "/stats/timeseries",
+        "/stats/compare",
+        "/grafana",
+    ]);
+    for path in paths {
+        client.check(
+            &format!("GET {}", path),
+            expect_ok(client.get(path).call(), 200),
+        );
+    }
+    // The checks run one after the other, so the only request in flight is this one
+    client.check(
+        "GET /stats/live-gauges",
+        expect_field(
+            client.get("/stats/live-gauges").call(),
+            200,
+            "in_flight_requests",
+            1.into(),
+        ),
+    );
+    client.check(
+        "GET /metrics",
+        expect(client.get("/metrics").call(), 200).and_then(|response| {
+            match response.into_string().map_err(|e| e.to_string())? {
+                text if text.contains("payload_pulverizer_requests_total") => Ok(()),
+                _ => Err("no payload_pulverizer_requests_total".to_string()),
+            }
+        }),
+    );
+    #[cfg(feature = "sqlite")]
+    check_grafana(client);
+    // Streams, only the status is checked
+    client.check("GET /events", expect_ok(client.get("/events").call(), 200));
+    client.check(
+        "GET /stats/live refuses a plain request",
+        expect_ok(client.get("/stats/live").call(), 400),
+    );
+}
+
+#[cfg(feature = "sqlite")]
+fn check_grafana(client: &mut Client) {
+    client.check(
+        "POST /grafana/search",
+        expect_ok(
+            client
+                .post("/grafana/search")
+                .set("Content-Type", "application/json")
+                .send_string("{}"),
+            200,
+        ),
+    );
+    let now = chrono::Utc::now();
+    let query = serde_json::json!({
+        "range": {
+            "from": (now - chrono::Duration::hours(1)).to_rfc3339(),
+            "to": (now + chrono::Duration::hours(1)).to_rfc3339(),
+        },
+        "targets": [{"target": "pulverize.count"}],
+    });
+    client.check(
+        "POST /grafana/query",
+        expect_ok(
+            client
+                .post("/grafana/query")
+                .set("Content-Type", "application/json")
+                .send_string(&query.to_string()),
+            200,
+        ),
+    );
+}
+
+// The rows the requests above left in the database
+fn check_rows(client: &mut Client, db_args: &DbArgs) {
+    let store = match crate::store::open_read_only(db_args) {
+        Ok(store) => store,
+        Err(e) => {
+            client.check::<()>("open the scratch database", Err(e));
+            return;
+        }
+    };
+    let mut sums = BTreeMap::new();
+    let sums = store.add_endpoint_sums(&mut sums).map(|()| sums);
+    for endpoint in recorded_endpoints() {
+        let rows = match &sums {
+            Ok(sums) if sums.get(endpoint).is_some_and(|sums| sums.count > 0) => Ok(()),
+            Ok(_) => Err("no stats row".to_string()),
+            Err(e) => Err(e.clone()),
+        };
+        client.check(&format!("stats rows for {}", endpoint), rows);
+    }
+    let mut counts = RejectionCounts::new();
+    let counts = store.add_rejection_counts(&mut counts).map(|()| counts);
+    let reasons = ["auth"].into_iter();
+    #[cfg(feature = "validators")]
+    let reasons = reasons.chain(["too_large"]);
+    for reason in reasons {
+        let rows = match &counts {
+            Ok(counts)
+                if counts
+                    .iter()
+                    .any(|((_, logged), &n)| logged == reason && n > 0) =>
+            {
+                Ok(())
+            }
+            Ok(_) => Err("no rejection recorded".to_string()),
+            Err(e) => Err(e.clone()),
+        };
+        client.check(&format!("rejections recorded as {}", reason), rows);
+    }
+}
+
+// Run every check, returns whether all of them passed
+fn run_checks(base: String, admin_token: String, db_args: DbArgs) -> bool {
+    let mut client = Client {
+        agent: ureq::AgentBuilder::new()
+            .timeout(Duration::from_secs(30))
+            .build(),
+        base,
+        admin_token,
+        passed: 0,
+        failed: Vec::new(),
+    };
+    check_misc(&mut client);
+    check_destruction(&mut client);
+    check_queue(&mut client);
+    check_tus(&mut client);
+    check_stage(&mut client);
+    check_reports(&mut client);
+    check_rows(&mut client, &db_args);
+    match client.failed.len() {
+        0 => println!("Selftest passed: {} checks", client.passed),
+        failed => println!(
+            "Selftest failed: {} of {} checks ({})",
+            failed,
+            failed + client.passed,
+            client.failed.join(", ")
+        ),
+    }
+    client.failed.is_empty()
+}
+
+// A database file and the journals SQLite keeps next to it
+fn remove_db(path: &str) {
+    for suffix in ["", "-wal", "-shm", "-journal"] {
+        let _ = std::fs::remove_file(format!("{}{}", path, suffix));
+    }
+}
+
+// Exits with 0 if every check passed and 1 otherwise
+pub async fn run(db_args: &DbArgs, args: SelftestArgs) -> std::io::Result<()> {
+    let db_path = std::env::temp_dir().join(format!(
+        "payload-pulverizer-selftest-{}.db",
+        std::process::id()
+    ));
+    let db_args = DbArgs {
+        db_path: db_path.to_string_lossy().into_owned(),
+        ..db_args.clone()
+    };
+    remove_db(&db_args.db_path);
+    // The defaults, whatever the environment sets, with admin endpoints behind a
+    // throwaway token
+    let admin_token = format!("{:032x}", rand::random::<u128>());
+    let serve_args = ServeArgs {
+        admin_token: Some(admin_token.clone()),
+        stats_token: None,
+        ..crate::Args::parse_from(["payload-pulverizer"]).serve
+    };
+    // Not listener::listen, a socket handed over by a supervisor belongs to the real server
+    let listener = std::net::TcpListener::bind(("127.0.0.1", 0))?;
+    let base = format!("http://{}", listener.local_addr()?);
+
+    let checks = {
+        let db_args = db_args.clone();
+        actix_web::rt::task::spawn_blocking(move || run_checks(base, admin_token, db_args))
+    };
+    let passed = tokio::select! {
+        result = crate::serve_on(&db_args, serve_args, listener) => {
+            result?;
+            println!("Selftest failed: the server stopped before the checks finished");
+            false
+        }
+        passed = checks => passed.map_err(std::io::Error::other)?,
+    };
+
+    match args.keep_db {
+        true => println!("Kept the scratch database at {}", db_args.db_path),
+        false => remove_db(&db_args.db_path),
+    }
+    std::process::exit(if passed { 0 } else { 1 });
+}
@@ -0,0 +1,197 @@
+// Named log packs for /shred, selected via the X-Shred-Style header or ?style=.
+// The config file can add more (or replace these) under [[shred_styles]].
+use rand::prelude::IndexedRandom;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+// A named pack of log sequences, one is picked at random per request
+pub struct ShredStyle {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub logs: &'static [&'static [&'static str]],
+}
+
+// Listing entry for GET /shred/styles
+#[derive(Serialize)]
+pub struct ShredStyleInfo {
+    pub name: String,
+    description: String,
+    sequences: usize,
+    // Defined in the config file
+    custom: bool,
+}
+
+pub const SHRED_STYLES: &[ShredStyle] = &[
+    ShredStyle {
+        name: "corporate",
+        description: "Synergy-driven payload lifecycle management.",
+        logs: &[
+            &[
+                "Payload onboarded. Aligning stakeholders...",
+                "Circling back on payload deliverables.",
+                "Payload has been right-sized to zero.",
+                "Action item closed.",
+            ],
+            &[
+                "Kicking off payload decommissioning workstream.",
+                "Leveraging best-in-class shredding synergies.",
+                "Payload sunset completed ahead of Q4.",
+            ],
+            &[
+                "Received your request. Filing a ticket.",
+                "Ticket escalated to payload disposal team.",
+                "Team in meeting. Scheduling follow-up.",
+                "Payload auto-deleted due to inactivity.",
+                "Synergy achieved. Payload gone.",
+            ],
+            &[
+                "Per my last email, the payload is being shredded.",
+                "Looping in Legal. Legal says shred it.",
+                "Payload offboarded. Please update your records.",
+            ],
+        ],
+    },
+    ShredStyle {
+        name: "drama",
+        description: "Every payload deserves a tragic farewell.",
+        logs: &[
+            &[
+                "The payload steps into the spotlight, trembling.",
+                "A hush falls. The blades begin to turn.",
+                "'Remember me!' it cries. We won't.",
+                "Curtain.",
+            ],
+            &[
+                "Act I: The payload arrives, young and full of bytes.",
+                "Act II: Betrayal. The shredder was never its friend.",
+                "Act III: Confetti. Applause. Tears.",
+            ],
+            &[
+                "Payload acquired. This is what we've trained for.",
+                "Initiating countdown... 3... 2... 1...",
+                "BOOM 💥",
+                "Payload disintegrated in a flash of glory.",
+                "Tell my variables... I loved them.",
+            ],
+            &[
+                "To shred, or not to shred: that is the question.",
+                "The answer, as always, is shred.",
+                "Exit payload, pursued by a blade.",
+            ],
+        ],
+    },
+    ShredStyle {
+        name: "noir",
+        description: "It was a dark and stormy request.",
+        logs: &[
+            &[
+                "It was a Tuesday. The payload walked in like it owned the place.",
+                "I knew its kind. Too many bytes, not enough sense.",
+                "The shredder doesn't ask questions. Neither do I.",
+                "In this town, everything ends up as dust.",
+            ],
+            &[
+                "Rain on the window. A payload on my desk.",
+                "Somebody wanted it gone. Somebody always does.",
+                "I fed it to the machine and lit a cigarette.",
+            ],
+            &[
+                "The payload had a past. They all do.",
+                "Now it has no future.",
+                "Case closed.",
+            ],
+            &[
+                "She said the payload was clean. She was lying.",
+                "The blades told the truth, like they always do.",
+                "I poured a drink. The city kept humming.",
+            ],
+        ],
+    },
+    ShredStyle {
+        name: "minimal",
+        description: "Just the facts.",
+        logs: &[
+            &["Received.", "Shredded."],
+            &["Payload in.", "Payload gone."],
+            &["Shredding.", "Done."],
+        ],
+    },
+];
+
+// Operator-defined log pack from the config file
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct CustomShredStyle {
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    pub logs: Vec<Vec<String>>,
+}
+
+impl CustomShredStyle {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.logs.is_empty() || self.logs.iter().any(Vec::is_empty) {
+            return Err(format!(
+                "Shred style '{}': logs must contain at least one non-empty sequence",
+                self.name
+            ));
+        }
+        Ok(())
+    }
+}
+
+// A style resolved by name, either compiled in or from the config file
+pub enum Style<'a> {
+    BuiltIn(&'static ShredStyle),
+    Custom(&'a CustomShredStyle),
+}
+
+impl Style<'_> {
+    pub fn choose_log(&self, rng: &mut impl Rng) -> Vec<String> {
+        match self {
+            Style::BuiltIn(style) => to_owned_log(style.logs.choose(rng).unwrap()),
+            Style::Custom(style) => style.logs.choose(rng).unwrap().clone(),
+        }
+    }
+}
+
+pub fn to_owned_log(log: &[&str]) -> Vec<String> {
+    log.iter().map(|line| line.to_string()).collect()
+}
+
+// Style by name, case-insensitive. Custom styles win over built-in ones.
+pub fn find<'a>(name: &str, custom: &'a [CustomShredStyle]) -> Option<Style<'a>> {
+    if let Some(style) = custom
+        .iter()
+        .find(|style| style.name.eq_ignore_ascii_case(name))
+    {
+        return Some(Style::Custom(style));
+    }
+    SHRED_STYLES
+        .iter()
+        .find(|style| style.name.eq_ignore_ascii_case(name))
+        .map(Style::BuiltIn)
+}
+
+pub fn list(custom: &[CustomShredStyle]) -> Vec<ShredStyleInfo> {
+    let built_in = SHRED_STYLES
+        .iter()
+        .filter(|style| {
+            !custom
+                .iter()
+                .any(|c| c.name.eq_ignore_ascii_case(style.name))
+        })
+        .map(|style| ShredStyleInfo {
+            name: style.name.to_string(),
+            description: style.description.to_string(),
+            sequences: style.logs.len(),
+            custom: false,
+        });
+    let custom = custom.iter().map(|style| ShredStyleInfo {
+        name: style.name.clone(),
+        description: style.description.clone(),
+        sequences: style.logs.len(),
+        custom: true,
+    });
+    built_in.chain(custom).collect()
+}
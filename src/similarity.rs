@@ -0,0 +1,192 @@
+// Near-duplicate detection across recent destructions (--similarity-window)
+//
+// Every destroyed payload leaves a 64-bit SimHash of its first megabyte, never the
+// content. Payloads whose signature differs from a recent one in only a few bits are
+// near-duplicates: the same document with a changed timestamp, id or a few words.
+// The closest match is reported in the X-Similar-To response header.
+use actix_web::body::MessageBody;
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::header::{HeaderName, HeaderValue};
+use actix_web::web::Data;
+use actix_web::{Error, HttpMessage, HttpRequest};
+use std::collections::VecDeque;
+use std::future::{ready, Future, Ready};
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::pin::Pin;
+use std::sync::Mutex;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+// Bytes of a payload that make up its signature
+const SAMPLE_SIZE: usize = 1024 * 1024;
+// Payloads with fewer word pairs are too short for a meaningful signature
+const MIN_FEATURES: usize = 8;
+// Signatures kept at most, the oldest go first
+const MAX_SIGNATURES: usize = 10_000;
+
+struct Signature {
+    simhash: u64,
+    endpoint: &'static str,
+    stat_id: Option<i64>,
+    destroyed_at: String,
+    seen: Instant,
+}
+
+pub struct SimilarityIndex {
+    // None disables the detection entirely (and skips hashing)
+    window: Option<Duration>,
+    // Differing bits up to which two signatures count as similar
+    max_distance: u32,
+    signatures: Mutex<VecDeque<Signature>>,
+}
+
+// The X-Similar-To value of a request, picked up by TagSimilar
+struct SimilarTo(HeaderValue);
+
+fn feature_hash(first: &[u8], second: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    (first, second).hash(&mut hasher);
+    hasher.finish()
+}
+
+// SimHash over pairs of consecutive words, None for payloads too short to judge
+fn simhash(body: &[u8]) -> Option<u64> {
+    let sample = &body[..body.len().min(SAMPLE_SIZE)];
+    let words: Vec<&[u8]> = sample
+        .split(|byte| byte.is_ascii_whitespace() || byte.is_ascii_punctuation())
+        .filter(|word| !word.is_empty())
+        .collect();
+    if words.len() <= MIN_FEATURES {
+        return None;
+    }
+    let mut weights = [0i64; 64];
+    for pair in words.windows(2) {
+        let hash = feature_hash(pair[0], pair[1]);
+        for (bit, weight) in weights.iter_mut().enumerate() {
+            *weight += if hash >> bit & 1 == 1 { 1 } else { -1 };
+        }
+    }
+    Some(
+        weights
+            .iter()
+            .enumerate()
+            .filter(|(_, weight)| **weight > 0)
+            .fold(0, |simhash, (bit, _)| simhash | 1 << bit),
+    )
+}
+
+impl SimilarityIndex {
+    pub fn new(window: Option<Duration>, max_distance: u32) -> Self {
+        SimilarityIndex {
+            window,
+            max_distance,
+            signatures: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    // Remember the payload and return the X-Similar-To value of the closest recent
+    // destruction, if any is similar enough
+    fn observe(
+        &self,
+        endpoint: &'static str,
+        body: &[u8],
+        stat_id: Option<i64>,
+    ) -> Option<HeaderValue> {
+        let window = self.window?;
+        let simhash = simhash(body)?;
+        let mut signatures = self.signatures.lock().unwrap();
+        while signatures
+            .front()
+            .is_some_and(|signature| signature.seen.elapsed() >= window)
+        {
+            signatures.pop_front();
+        }
+        // Newest first, so ties go to the latest destruction
+        let closest = signatures
+            .iter()
+            .rev()
+            .map(|signature| ((signature.simhash ^ simhash).count_ones(), signature))
+            .filter(|(distance, _)| *distance <= self.max_distance)
+            .min_by_key(|(distance, _)| *distance)
+            .map(|(distance, signature)| {
+                let stat_id = signature
+                    .stat_id
+                    .map_or_else(String::new, |id| format!("stat_id={}; ", id));
+                format!(
+                    "{}endpoint={}; distance={}; destroyed_at={}",
+                    stat_id, signature.endpoint, distance, signature.destroyed_at
+                )
+            });
+        if signatures.len() == MAX_SIGNATURES {
+            signatures.pop_front();
+        }
+        signatures.push_back(Signature {
+            simhash,
+            endpoint,
+            stat_id,
+            destroyed_at: chrono::Utc::now().to_rfc3339(),
+            seen: Instant::now(),
+        });
+        closest.and_then(|value| HeaderValue::from_str(&value).ok())
+    }
+}
+
+// Check a destroyed payload against recent ones, the result ends up in the response
+pub fn observe(req: &HttpRequest, endpoint: &'static str, body: &[u8], stat_id: Option<i64>) {
+    let Some(index) = req.app_data::<Data<SimilarityIndex>>() else {
+        return;
+    };
+    if let Some(value) = index.observe(endpoint, body, stat_id) {
+        req.extensions_mut().insert(SimilarTo(value));
+    }
+}
+
+// Middleware adding the X-Similar-To header to responses of near-duplicate payloads
+pub struct TagSimilar;
+
+impl<S, B> Transform<S, ServiceRequest> for TagSimilar
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = TagSimilarMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(TagSimilarMiddleware { service }))
+    }
+}
+
+pub struct TagSimilarMiddleware<S> {
+    service: S,
+}
+
+impl<S, B> Service<ServiceRequest> for TagSimilarMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    fn poll_ready(&self, ctx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(ctx)
+    }
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let fut = self.service.call(req);
+        Box::pin(async move {
+            let mut res = fut.await?;
+            let similar = res.request().extensions_mut().remove::<SimilarTo>();
+            if let Some(SimilarTo(value)) = similar {
+                res.headers_mut()
+                    .insert(HeaderName::from_static("x-similar-to"), value);
+            }
+            Ok(res)
+        })
+    }
+}
@@ -0,0 +1,185 @@
+// --spill-dir: keep large request bodies on disk instead of in memory
+//
+// Bodies growing past the threshold are written to a file in the scratch directory and
+// memory-mapped from there, so handlers see them like any other body while the kernel
+// pages them in and out. Once the last reference is gone, the file is overwritten with
+// zeros, synced and deleted. Files left behind by a crash get the same treatment on the
+// next start.
+use actix_web::web::Bytes;
+use std::fs::{File, OpenOptions};
+use std::io::{Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+const FILE_PREFIX: &str = "pulverizer-spill-";
+const WIPE_CHUNK: usize = 1024 * 1024;
+
+pub struct Scratch {
+    dir: PathBuf,
+    // Bodies larger than this go to disk
+    threshold: usize,
+}
+
+// A body being written to the scratch directory
+pub struct SpillFile {
+    file: File,
+    path: PathBuf,
+    len: usize,
+}
+
+// The memory map of a finished spill file, wiped when dropped
+#[cfg(unix)]
+struct Mapped {
+    ptr: *mut libc::c_void,
+    spill: Option<SpillFile>,
+}
+
+// The map is only read after creation
+#[cfg(unix)]
+unsafe impl Send for Mapped {}
+#[cfg(unix)]
+unsafe impl Sync for Mapped {}
+
+// Overwrite a spill file with zeros, then delete it
+fn wipe(file: &mut File, path: &Path, len: usize) {
+    let zeros = vec![0u8; WIPE_CHUNK];
+    let overwritten = file.seek(SeekFrom::Start(0)).and_then(|_| {
+        let mut left = len;
+        while left > 0 {
+            let n = left.min(WIPE_CHUNK);
+            file.write_all(&zeros[..n])?;
+            left -= n;
+        }
+        file.sync_all()
+    });
+    if let Err(e) = overwritten {
+        eprintln!("Failed to overwrite spill file {}: {}", path.display(), e);
+    }
+    if let Err(e) = std::fs::remove_file(path) {
+        eprintln!("Failed to delete spill file {}: {}", path.display(), e);
+    }
+}
+
+impl Scratch {
+    pub fn new(dir: &str, threshold: usize) -> Result<Self, String> {
+        let dir = PathBuf::from(dir);
+        std::fs::create_dir_all(&dir)
+            .map_err(|e| format!("Failed to create spill directory {}: {}", dir.display(), e))?;
+        Ok(Scratch { dir, threshold })
+    }
+
+    pub fn threshold(&self) -> usize {
+        self.threshold
+    }
+
+    // Wipe spill files of an earlier run that didn't get to it
+    pub fn clean(&self) {
+        let Ok(entries) = std::fs::read_dir(&self.dir) else {
+            return;
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let is_spill = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.starts_with(FILE_PREFIX));
+            let file = OpenOptions::new().write(true).open(&path);
+            if let (true, Ok(mut file), Ok(meta)) = (is_spill, file, entry.metadata()) {
+                println!("Wiping leftover spill file {}", path.display());
+                wipe(&mut file, &path, meta.len() as usize);
+            }
+        }
+    }
+
+    // Start a spill file holding what was received so far
+    pub fn create(&self, received: &[u8]) -> std::io::Result<SpillFile> {
+        let path = self
+            .dir
+            .join(format!("{}{:032x}", FILE_PREFIX, rand::random::<u128>()));
+        let mut options = OpenOptions::new();
+        options.read(true).write(true).create_new(true);
+        // Payloads are nobody else's business
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let file = options.open(&path)?;
+        let mut spill = SpillFile { file, path, len: 0 };
+        spill.write(received)?;
+        Ok(spill)
+    }
+}
+
+impl SpillFile {
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn write(&mut self, chunk: &[u8]) -> std::io::Result<()> {
+        self.file.write_all(chunk)?;
+        self.len += chunk.len();
+        Ok(())
+    }
+
+    // Map the written body into memory
+    #[cfg(unix)]
+    pub fn finish(self) -> std::io::Result<Bytes> {
+        if self.len == 0 {
+            return Ok(Bytes::new());
+        }
+        let ptr = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                self.len,
+                libc::PROT_READ,
+                libc::MAP_SHARED,
+                std::os::unix::io::AsRawFd::as_raw_fd(&self.file),
+                0,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(Bytes::from_owner(Mapped {
+            ptr,
+            spill: Some(self),
+        }))
+    }
+
+    // Without mmap the body is read back, the file is wiped right away
+    #[cfg(not(unix))]
+    pub fn finish(mut self) -> std::io::Result<Bytes> {
+        use std::io::Read;
+        let mut body = Vec::with_capacity(self.len);
+        self.file.seek(SeekFrom::Start(0))?;
+        (&self.file).read_to_end(&mut body)?;
+        Ok(Bytes::from(body))
+    }
+}
+
+impl Drop for SpillFile {
+    // Bodies that were never finished (e.g. too large) are wiped right away
+    fn drop(&mut self) {
+        wipe(&mut self.file, &self.path, self.len);
+    }
+}
+
+#[cfg(unix)]
+impl AsRef<[u8]> for Mapped {
+    fn as_ref(&self) -> &[u8] {
+        let len = self.spill.as_ref().map_or(0, SpillFile::len);
+        unsafe { std::slice::from_raw_parts(self.ptr as *const u8, len) }
+    }
+}
+
+#[cfg(unix)]
+impl Drop for Mapped {
+    fn drop(&mut self) {
+        let Some(spill) = self.spill.take() else {
+            return;
+        };
+        unsafe {
+            libc::munmap(self.ptr, spill.len);
+        }
+        // Overwriting a large file takes a while, the last holder of the body may be a
+        // request handler
+        std::thread::spawn(move || drop(spill));
+    }
+}
@@ -0,0 +1,251 @@
+// Two-phase destruction: POST /stage holds a payload, POST /commit/{id} destroys it and
+// POST /abort/{id} lets it go undestroyed
+//
+// For workflow engines that want an explicit confirmation step. Staged payloads are
+// kept in memory (or in --spill-dir), up to --stage-max-bytes in total, and destroyed anyway once
+// --stage-timeout (or a shorter `?timeout=`) passes without a decision. Commits and
+// timeouts are recorded as "stage", with the details of the staging request; aborts
+// aren't recorded at all.
+use crate::store::Store;
+use crate::{
+    body, check_payload, get_start_time, record_stat_with, remember_payload, AppConfig, StatExtras,
+};
+use actix_web::web::{self, Bytes, Data};
+use actix_web::{HttpRequest, HttpResponse, Result};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+const SWEEP_INTERVAL: Duration = Duration::from_secs(1);
+
+struct Staged {
+    body: Bytes,
+    sha256: String,
+    staged_at: String,
+    deadline: Instant,
+    // Of the staging request, recorded whenever the payload is destroyed
+    extras: StatExtras,
+}
+
+pub struct StageStore {
+    staged: Mutex<HashMap<String, Staged>>,
+    timeout: Duration,
+    max_bytes: usize,
+}
+
+#[derive(Deserialize)]
+pub struct StageQuery {
+    // e.g. 30s, at most --stage-timeout
+    timeout: Option<String>,
+}
+
+#[derive(Serialize)]
+struct StageResponse {
+    status: &'static str,
+    stage_id: String,
+    bytes: usize,
+    sha256: String,
+    expires_at: String,
+    // Where to confirm or cancel, relative like tus locations
+    commit: String,
+    abort: String,
+}
+
+#[derive(Serialize)]
+struct CommitResponse {
+    status: &'static str,
+    stage_id: String,
+    bytes: usize,
+    sha256: String,
+    stat_id: Option<i64>,
+    staged_at: String,
+    destroyed_at: String,
+    runtime_us: u128,
+}
+
+#[derive(Serialize)]
+struct AbortResponse {
+    status: &'static str,
+    stage_id: String,
+    bytes: usize,
+    runtime_us: u128,
+}
+
+fn error(response: &mut actix_web::HttpResponseBuilder, message: String) -> HttpResponse {
+    response.json(serde_json::json!({ "error": message }))
+}
+
+fn not_found(id: &str) -> HttpResponse {
+    error(
+        &mut HttpResponse::NotFound(),
+        format!(
+            "No payload staged as {}. It was committed, aborted or timed out.",
+            id
+        ),
+    )
+}
+
+impl StageStore {
+    pub fn new(timeout_secs: i64, max_bytes: usize) -> Self {
+        StageStore {
+            staged: Mutex::new(HashMap::new()),
+            timeout: Duration::from_secs(timeout_secs.max(1) as u64),
+            max_bytes,
+        }
+    }
+
+    pub fn max_bytes(&self) -> usize {
+        self.max_bytes
+    }
+
+    fn take(&self, id: &str) -> Option<Staged> {
+        self.staged.lock().unwrap().remove(id)
+    }
+}
+
+// Destroy and record a staged payload, returns the stat id
+fn destroy(db: &dyn Store, staged: Staged, runtime_us: u128) -> Option<i64> {
+    let size = staged.body.len();
+    // The bytes go here, the rest is bookkeeping
+    drop(staged.body);
+    record_stat_with(db, "stage", size, runtime_us, staged.extras)
+}
+
+// Destroy staged payloads whose time is up, once a second
+pub fn spawn_sweeper(store: Data<StageStore>, db: Data<dyn Store>) {
+    std::thread::spawn(move || loop {
+        std::thread::sleep(SWEEP_INTERVAL);
+        let now = Instant::now();
+        let expired: Vec<Staged> = {
+            let mut staged = store.staged.lock().unwrap();
+            let ids: Vec<String> = staged
+                .iter()
+                .filter(|(_, staged)| staged.deadline <= now)
+                .map(|(id, _)| id.clone())
+                .collect();
+            ids.iter().filter_map(|id| staged.remove(id)).collect()
+        };
+        for staged in expired {
+            destroy(db.get_ref(), staged, 0);
+        }
+    });
+}
+
+/// Handler for POST /stage
+/// Holds the payload until it is committed, aborted or times out.
+pub async fn stage_handler(
+    req: HttpRequest,
+    body: body::Body,
+    query: web::Query<StageQuery>,
+    store: Data<StageStore>,
+    config: Data<AppConfig>,
+) -> Result<HttpResponse> {
+    if let Some(response) = check_payload(&req, &config.settings()) {
+        return Ok(response);
+    }
+    let timeout = match &query.timeout {
+        None => store.timeout,
+        Some(spec) => match crate::parse_duration_secs(spec) {
+            Some(secs) if secs > 0 => store.timeout.min(Duration::from_secs(secs as u64)),
+            _ => {
+                return Ok(error(
+                    &mut HttpResponse::BadRequest(),
+                    format!("Invalid timeout '{}', use e.g. 30s or 5m.", spec),
+                ))
+            }
+        },
+    };
+    // The id is all it takes to commit or abort, never from the seeded rng
+    let id = format!("{:032x}", rand::rng().random::<u128>());
+    let sha256 = format!("{:x}", Sha256::digest(&body[..]));
+    let bytes = body.len();
+    {
+        let mut staged = store.staged.lock().unwrap();
+        let held: usize = staged.values().map(|staged| staged.body.len()).sum();
+        if held + bytes > store.max_bytes {
+            return Ok(error(
+                &mut HttpResponse::InsufficientStorage(),
+                format!(
+                    "The staging area is full, {} bytes of {} held (--stage-max-bytes).",
+                    held,
+                    crate::format_size(store.max_bytes)
+                ),
+            ));
+        }
+        staged.insert(
+            id.clone(),
+            Staged {
+                body: Bytes::clone(&body),
+                sha256: sha256.clone(),
+                staged_at: chrono::Utc::now().to_rfc3339(),
+                deadline: Instant::now() + timeout,
+                extras: StatExtras::for_request(&req, &config),
+            },
+        );
+    }
+    // Relative to the request path, so it also works under --base-path
+    let base = req.path().strip_suffix("/stage").unwrap_or_default();
+    let expires_at = chrono::Utc::now() + timeout;
+    Ok(HttpResponse::Created().json(StageResponse {
+        status: "staged",
+        bytes,
+        sha256,
+        expires_at: expires_at.to_rfc3339(),
+        commit: format!("{}/commit/{}", base, id),
+        abort: format!("{}/abort/{}", base, id),
+        stage_id: id,
+    }))
+}
+
+/// Handler for POST /commit/{id}
+/// Destroys a staged payload and returns the receipt.
+pub async fn commit_handler(
+    req: HttpRequest,
+    path: web::Path<String>,
+    store: Data<StageStore>,
+    db: Data<dyn Store>,
+) -> Result<HttpResponse> {
+    let start = get_start_time(&req);
+    let id = path.into_inner();
+    let Some(staged) = store.take(&id) else {
+        return Ok(not_found(&id));
+    };
+    let bytes = staged.body.len();
+    let sha256 = staged.sha256.clone();
+    let staged_at = staged.staged_at.clone();
+    remember_payload(&req, "stage", &staged.body);
+    let stat_id = destroy(db.get_ref(), staged, start.elapsed().as_micros());
+    Ok(HttpResponse::Ok().json(CommitResponse {
+        status: "destroyed",
+        stage_id: id,
+        bytes,
+        sha256,
+        stat_id,
+        staged_at,
+        destroyed_at: chrono::Utc::now().to_rfc3339(),
+        runtime_us: start.elapsed().as_micros(),
+    }))
+}
+
+/// Handler for POST /abort/{id}
+/// Cancels the destruction of a staged payload, it is dropped without being recorded.
+pub async fn abort_handler(
+    req: HttpRequest,
+    path: web::Path<String>,
+    store: Data<StageStore>,
+) -> Result<HttpResponse> {
+    let start = get_start_time(&req);
+    let id = path.into_inner();
+    let Some(staged) = store.take(&id) else {
+        return Ok(not_found(&id));
+    };
+    Ok(HttpResponse::Ok().json(AbortResponse {
+        status: "aborted",
+        stage_id: id,
+        bytes: staged.body.len(),
+        runtime_us: start.elapsed().as_micros(),
+    }))
+}
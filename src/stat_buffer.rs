@@ -0,0 +1,180 @@
+// Stats rows the database didn't take, kept in memory and retried
+//
+// A locked, full or broken database used to lose stats without a trace, which makes every
+// number untrustworthy. Rows whose insert fails wait here instead, up to --stat-buffer-rows
+// of them, and a background thread retries with exponential backoff until the database
+// takes them again. Buffered rows keep the time they were recorded. While rows are waiting,
+// new ones queue up behind them rather than each request running into the broken database.
+// Rows that don't fit are dropped. Failures, waiting and dropped rows are logged once per
+// outage and exported on /metrics.
+use crate::store::Store;
+use crate::NewStat;
+use actix_web::web::Data;
+use std::collections::VecDeque;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+pub struct StatBuffer {
+    capacity: usize,
+    rows: Mutex<VecDeque<NewStat>>,
+    failed_writes: AtomicU64,
+    dropped: AtomicU64,
+    // Only the first dropped row of an outage is logged
+    overflowing: AtomicBool,
+    // The retrier's current backoff, in seconds
+    backoff_secs: AtomicU64,
+}
+
+impl StatBuffer {
+    pub fn new(capacity: usize) -> Self {
+        StatBuffer {
+            capacity,
+            rows: Mutex::new(VecDeque::new()),
+            failed_writes: AtomicU64::new(0),
+            dropped: AtomicU64::new(0),
+            overflowing: AtomicBool::new(false),
+            backoff_secs: AtomicU64::new(MIN_BACKOFF.as_secs()),
+        }
+    }
+
+    pub fn pending_rows(&self) -> usize {
+        self.rows.lock().unwrap().len()
+    }
+
+    // The longest the waiting rows wait for the next retry
+    pub fn retry_in(&self) -> Duration {
+        Duration::from_secs(self.backoff_secs.load(Ordering::Relaxed))
+    }
+
+    // Whether rows are waiting, new ones have to queue up behind them then
+    pub fn is_pending(&self) -> bool {
+        !self.rows.lock().unwrap().is_empty()
+    }
+
+    // Count a failed insert and keep its row for later
+    pub fn failed(&self, row: NewStat, error: &str) {
+        self.failed_writes.fetch_add(1, Ordering::Relaxed);
+        if self.capacity == 0 {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+            eprintln!("Failed to write a stats row: {}", error);
+            return;
+        }
+        let rows = self.rows.lock().unwrap();
+        if rows.is_empty() {
+            eprintln!(
+                "Failed to write stats ({}), keeping up to {} rows in memory and retrying",
+                error, self.capacity
+            );
+        }
+        self.push(rows, row);
+    }
+
+    // Queue a row while others are waiting
+    pub fn defer(&self, row: NewStat) {
+        self.push(self.rows.lock().unwrap(), row);
+    }
+
+    fn push(&self, mut rows: std::sync::MutexGuard<VecDeque<NewStat>>, row: NewStat) {
+        if rows.len() < self.capacity {
+            rows.push_back(row);
+            return;
+        }
+        self.dropped.fetch_add(1, Ordering::Relaxed);
+        if !self.overflowing.swap(true, Ordering::Relaxed) {
+            eprintln!(
+                "Stats buffer full ({} rows), dropping new stats until the database recovers",
+                self.capacity
+            );
+        }
+    }
+
+    // Write all waiting rows in one transaction, they stay buffered if that fails
+    fn flush(&self, store: &dyn Store) -> Result<usize, String> {
+        let mut writer = store.writer();
+        let mut rows = self.rows.lock().unwrap();
+        if rows.is_empty() {
+            return Ok(0);
+        }
+        if let Err(e) = writer.insert(rows.make_contiguous()) {
+            self.failed_writes.fetch_add(1, Ordering::Relaxed);
+            return Err(e);
+        }
+        let written = rows.len();
+        rows.clear();
+        self.overflowing.store(false, Ordering::Relaxed);
+        Ok(written)
+    }
+
+    pub fn render_metrics(&self) -> String {
+        let mut out = String::new();
+        let mut metric = |name: &str, help: &str, kind: &str, value: u64| {
+            let _ = writeln!(out, "# HELP {} {}", name, help);
+            let _ = writeln!(out, "# TYPE {} {}", name, kind);
+            let _ = writeln!(out, "{} {}", name, value);
+        };
+        metric(
+            "payload_pulverizer_stat_write_failures_total",
+            "Failed attempts to write stats rows, retries included.",
+            "counter",
+            self.failed_writes.load(Ordering::Relaxed),
+        );
+        metric(
+            "payload_pulverizer_stat_buffer_rows",
+            "Stats rows waiting in memory for the database.",
+            "gauge",
+            self.rows.lock().unwrap().len() as u64,
+        );
+        metric(
+            "payload_pulverizer_stat_rows_dropped_total",
+            "Stats rows lost because the buffer was full.",
+            "counter",
+            self.dropped.load(Ordering::Relaxed),
+        );
+        out
+    }
+}
+
+// Retry the waiting rows on a background thread for as long as the process runs
+pub fn spawn_retrier(buffer: Data<StatBuffer>, db: Data<dyn Store>) {
+    std::thread::spawn(move || {
+        let mut backoff = MIN_BACKOFF;
+        loop {
+            std::thread::sleep(backoff);
+            backoff = match buffer.flush(db.get_ref()) {
+                Ok(0) => MIN_BACKOFF,
+                Ok(written) => {
+                    println!("Wrote {} buffered stats rows", written);
+                    MIN_BACKOFF
+                }
+                Err(e) => {
+                    let backoff = (backoff * 2).min(MAX_BACKOFF);
+                    eprintln!(
+                        "Still failing to write stats ({}), retrying in {}s",
+                        e,
+                        backoff.as_secs()
+                    );
+                    backoff
+                }
+            };
+            buffer
+                .backoff_secs
+                .store(backoff.as_secs(), Ordering::Relaxed);
+        }
+    });
+}
+
+// Last attempt once the server has stopped, whatever is still waiting is lost then
+pub fn flush_on_exit(buffer: &StatBuffer, db: &dyn Store) {
+    if let Err(e) = buffer.flush(db) {
+        eprintln!(
+            "Failed to write stats ({}), {} buffered rows are lost",
+            e,
+            buffer.rows.lock().unwrap().len()
+        );
+    }
+}
@@ -0,0 +1,38 @@
+// --stats-cache-ttl: answer /stats from memory for a while
+//
+// Dashboards polling /stats every second would otherwise run the GROUP BY over the
+// stats table, and with ?span=all over every rotated file, on each poll. The responses are
+// kept per span for the TTL; destructions recorded meanwhile show up once it expires.
+use crate::StatsResponse;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+pub struct StatsCache {
+    ttl: Duration,
+    // (all spans, computed at, response)
+    entries: Mutex<Vec<(bool, Instant, StatsResponse)>>,
+}
+
+impl StatsCache {
+    pub fn new(ttl_secs: i64) -> Self {
+        StatsCache {
+            ttl: Duration::from_secs(ttl_secs.max(0) as u64),
+            entries: Mutex::new(Vec::new()),
+        }
+    }
+
+    // A response still within the TTL and its age
+    pub fn get(&self, all: bool) -> Option<(StatsResponse, Duration)> {
+        let entries = self.entries.lock().unwrap();
+        entries
+            .iter()
+            .find(|(span, at, _)| *span == all && at.elapsed() < self.ttl)
+            .map(|(_, at, response)| (response.clone(), at.elapsed()))
+    }
+
+    pub fn put(&self, all: bool, response: &StatsResponse) {
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|(span, _, _)| *span != all);
+        entries.push((all, Instant::now(), response.clone()));
+    }
+}
@@ -0,0 +1,218 @@
+// GET /stats/compare: per-endpoint deltas between two time windows
+//
+// A window is `FROM..TO`, each bound `now`, a duration ago (`7d`), a date (`2024-06-01`,
+// midnight UTC) or an RFC 3339 timestamp. `window_a` defaults to the last 7 days and
+// `window_b` to the window of the same length right before `window_a`, so a bare
+// request answers "this week vs last week". Covers the current database file only.
+use crate::parse_duration_secs;
+use actix_web::web::{self, Data};
+use actix_web::{HttpResponse, Responder, Result};
+use chrono::{DateTime, NaiveDate, Timelike, Utc};
+use rusqlite::{params, Connection};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Mutex;
+
+const DEFAULT_WINDOW: &str = "7d..now";
+// How stats rows store ts
+const TS_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+#[derive(Deserialize)]
+pub struct CompareQuery {
+    window_a: Option<String>,
+    window_b: Option<String>,
+}
+
+#[derive(Serialize)]
+struct Window {
+    from: String,
+    to: String,
+}
+
+#[derive(Serialize, Default)]
+struct WindowStats {
+    count: i64,
+    bytes: i64,
+    // None without requests in the window
+    p95_runtime_us: Option<i64>,
+}
+
+// window_a minus window_b
+#[derive(Serialize)]
+struct Delta {
+    count: i64,
+    bytes: i64,
+    p95_runtime_us: Option<i64>,
+    // Relative to window_b, None if it had none
+    count_pct: Option<f64>,
+    bytes_pct: Option<f64>,
+}
+
+#[derive(Serialize)]
+struct EndpointComparison {
+    endpoint: String,
+    a: WindowStats,
+    b: WindowStats,
+    delta: Delta,
+}
+
+#[derive(Serialize)]
+struct CompareResponse {
+    window_a: Window,
+    window_b: Window,
+    endpoints: Vec<EndpointComparison>,
+}
+
+fn parse_bound(spec: &str, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+    let spec = spec.trim();
+    if spec == "now" {
+        return Some(now);
+    }
+    if let Ok(date) = NaiveDate::parse_from_str(spec, "%Y-%m-%d") {
+        return Some(date.and_hms_opt(0, 0, 0)?.and_utc());
+    }
+    if let Ok(ts) = DateTime::parse_from_rfc3339(spec) {
+        return Some(ts.with_timezone(&Utc));
+    }
+    let secs = parse_duration_secs(spec)?;
+    now.checked_sub_signed(chrono::Duration::try_seconds(secs)?)
+}
+
+fn parse_window(spec: &str, now: DateTime<Utc>) -> Result<(DateTime<Utc>, DateTime<Utc>), String> {
+    let invalid = || {
+        format!(
+            "Invalid window '{}'. Use FROM..TO with now, a duration ago (7d), a date or an RFC 3339 timestamp, e.g. 14d..7d.",
+            spec
+        )
+    };
+    let (from, to) = spec.split_once("..").ok_or_else(invalid)?;
+    let from = parse_bound(from, now).ok_or_else(invalid)?;
+    let to = parse_bound(to, now).ok_or_else(invalid)?;
+    if from >= to {
+        return Err(format!("Window '{}' ends before it starts.", spec));
+    }
+    Ok((from, to))
+}
+
+// ts only has whole seconds, a row at 12:00:00.3 is stored as 12:00:00. Bounds are rounded
+// up so it still falls into a window starting or ending at 12:00:00.5 on the right side.
+fn ts_bound(bound: DateTime<Utc>) -> String {
+    let whole = bound.with_nanosecond(0).unwrap_or(bound);
+    let whole = match whole < bound {
+        true => whole + chrono::Duration::seconds(1),
+        false => whole,
+    };
+    whole.format(TS_FORMAT).to_string()
+}
+
+fn window_stats(
+    conn: &Connection,
+    (from, to): (DateTime<Utc>, DateTime<Utc>),
+) -> rusqlite::Result<BTreeMap<String, WindowStats>> {
+    let (from, to) = (ts_bound(from), ts_bound(to));
+    let mut stmt = conn.prepare(
+        "SELECT endpoint, COUNT(*), SUM(COALESCE(sample_weight, 1)), COALESCE(SUM(payload_size * COALESCE(sample_weight, 1)), 0) FROM endpoint_stats_raw WHERE ts >= ?1 AND ts < ?2 GROUP BY endpoint",
+    )?;
+    // Rows per endpoint next to the stats, sampled rows count as several destructions
+    let mut stats: BTreeMap<String, (i64, WindowStats)> = stmt
+        .query_map(params![from, to], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                (
+                    row.get(1)?,
+                    WindowStats {
+                        count: row.get(2)?,
+                        bytes: row.get(3)?,
+                        p95_runtime_us: None,
+                    },
+                ),
+            ))
+        })?
+        .collect::<rusqlite::Result<_>>()?;
+    // Nearest rank, SQLite has no percentile function
+    let mut p95 = conn.prepare(
+        "SELECT runtime_us FROM endpoint_stats_raw WHERE endpoint = ?1 AND ts >= ?2 AND ts < ?3 ORDER BY runtime_us LIMIT 1 OFFSET ?4",
+    )?;
+    for (endpoint, (rows, entry)) in &mut stats {
+        let rank = (*rows as f64 * 0.95).ceil() as i64 - 1;
+        entry.p95_runtime_us =
+            Some(p95.query_row(params![endpoint, from, to, rank], |row| row.get(0))?);
+    }
+    Ok(stats
+        .into_iter()
+        .map(|(endpoint, (_, entry))| (endpoint, entry))
+        .collect())
+}
+
+fn pct(a: i64, b: i64) -> Option<f64> {
+    (b != 0).then(|| (a - b) as f64 * 100.0 / b as f64)
+}
+
+fn window(from: DateTime<Utc>, to: DateTime<Utc>) -> Window {
+    Window {
+        from: from.to_rfc3339(),
+        to: to.to_rfc3339(),
+    }
+}
+
+/// Handler for GET /stats/compare
+/// Returns count, bytes and p95 runtime per endpoint for two time windows and their deltas,
+/// e.g. `?window_a=7d..now&window_b=14d..7d`.
+pub async fn compare_handler(
+    query: web::Query<CompareQuery>,
+    db: Data<Mutex<Connection>>,
+) -> Result<impl Responder> {
+    let now = Utc::now();
+    let bad_request =
+        |message: String| HttpResponse::BadRequest().json(serde_json::json!({ "error": message }));
+    let window_a = query.window_a.as_deref().unwrap_or(DEFAULT_WINDOW);
+    let (a_from, a_to) = match parse_window(window_a, now) {
+        Ok(window) => window,
+        Err(message) => return Ok(bad_request(message)),
+    };
+    let (b_from, b_to) = match &query.window_b {
+        Some(window_b) => match parse_window(window_b, now) {
+            Ok(window) => window,
+            Err(message) => return Ok(bad_request(message)),
+        },
+        None => (a_from - (a_to - a_from), a_from),
+    };
+    let conn = db.lock().unwrap();
+    let (mut a, mut b) = match (
+        window_stats(&conn, (a_from, a_to)),
+        window_stats(&conn, (b_from, b_to)),
+    ) {
+        (Ok(a), Ok(b)) => (a, b),
+        (Err(e), _) | (_, Err(e)) => {
+            return Ok(HttpResponse::InternalServerError()
+                .json(serde_json::json!({ "error": format!("Failed to read stats: {}", e) })))
+        }
+    };
+    drop(conn);
+    let names: BTreeSet<String> = a.keys().chain(b.keys()).cloned().collect();
+    let endpoints = names
+        .into_iter()
+        .map(|endpoint| {
+            let a = a.remove(&endpoint).unwrap_or_default();
+            let b = b.remove(&endpoint).unwrap_or_default();
+            let delta = Delta {
+                count: a.count - b.count,
+                bytes: a.bytes - b.bytes,
+                p95_runtime_us: a.p95_runtime_us.zip(b.p95_runtime_us).map(|(a, b)| a - b),
+                count_pct: pct(a.count, b.count),
+                bytes_pct: pct(a.bytes, b.bytes),
+            };
+            EndpointComparison {
+                endpoint,
+                a,
+                b,
+                delta,
+            }
+        })
+        .collect();
+    Ok(HttpResponse::Ok().json(CompareResponse {
+        window_a: window(a_from, a_to),
+        window_b: window(b_from, b_to),
+        endpoints,
+    }))
+}
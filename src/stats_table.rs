@@ -0,0 +1,145 @@
+// Plain-text rendering of /stats for terminals, picked with `Accept: text/plain`
+use crate::StatsEntry;
+use actix_web::http::header::{Accept, Header};
+use actix_web::{mime, HttpRequest};
+
+pub const CONTENT_TYPE: &str = "text/plain; charset=utf-8";
+
+const HEADERS: [&str; 10] = [
+    "ENDPOINT",
+    "REQUESTS",
+    "BYTES",
+    "AVG SIZE",
+    "RUNTIME",
+    "AVG RUNTIME",
+    "AVG READ",
+    "AVG PROCESS",
+    "AVG PERSIST",
+    "CHUNKED",
+];
+
+// Whether the client ranks text/plain above JSON, */* and a missing header mean JSON
+pub fn is_preferred(req: &HttpRequest) -> bool {
+    let Ok(accept) = Accept::parse(req) else {
+        return false;
+    };
+    accept
+        .ranked()
+        .into_iter()
+        .find_map(|mime| match (mime.type_(), mime.subtype()) {
+            (mime::TEXT, mime::PLAIN) => Some(true),
+            (mime::APPLICATION, mime::JSON) | (mime::STAR, mime::STAR) => Some(false),
+            _ => None,
+        })
+        .unwrap_or(false)
+}
+
+// 512 B, 1.5 KiB, 12.0 MiB, …
+fn human_size(bytes: f64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = bytes;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    match unit {
+        0 => format!("{:.0} {}", value, UNITS[0]),
+        _ => format!("{:.1} {}", value, UNITS[unit]),
+    }
+}
+
+// 850 µs, 12.3 ms, 4.20 s, 2m 05s, 1h 02m
+fn human_duration(us: f64) -> String {
+    let secs = us / 1e6;
+    if us < 1e3 {
+        format!("{:.0} µs", us)
+    } else if us < 1e6 {
+        format!("{:.1} ms", us / 1e3)
+    } else if secs < 60.0 {
+        format!("{:.2} s", secs)
+    } else if secs < 3600.0 {
+        format!("{}m {:02}s", secs as u64 / 60, secs as u64 % 60)
+    } else {
+        format!("{}h {:02}m", secs as u64 / 3600, secs as u64 / 60 % 60)
+    }
+}
+
+fn optional_duration(us: Option<f64>) -> String {
+    us.map_or_else(|| "-".to_string(), human_duration)
+}
+
+fn row(entry: &StatsEntry) -> [String; 10] {
+    [
+        entry.endpoint.clone(),
+        entry.count.to_string(),
+        human_size(entry.total_bytes as f64),
+        human_size(entry.avg_payload_size),
+        human_duration(entry.total_runtime_us as f64),
+        human_duration(entry.avg_runtime_us),
+        optional_duration(entry.avg_read_us),
+        optional_duration(entry.avg_process_us),
+        optional_duration(entry.avg_persist_us),
+        entry.chunked_count.to_string(),
+    ]
+}
+
+// Aligned table with one line per endpoint and a total line
+pub fn render(stats: &[StatsEntry]) -> String {
+    let mut rows: Vec<[String; 10]> = vec![HEADERS.map(str::to_string)];
+    rows.extend(stats.iter().map(row));
+    let (count, bytes, runtime_us, chunked) = stats.iter().fold((0, 0, 0, 0), |acc, entry| {
+        (
+            acc.0 + entry.count,
+            acc.1 + entry.total_bytes,
+            acc.2 + entry.total_runtime_us,
+            acc.3 + entry.chunked_count,
+        )
+    });
+    let average = |total: i64| match count {
+        0 => 0.0,
+        _ => total as f64 / count as f64,
+    };
+    rows.push([
+        "TOTAL".to_string(),
+        count.to_string(),
+        human_size(bytes as f64),
+        human_size(average(bytes)),
+        human_duration(runtime_us as f64),
+        human_duration(average(runtime_us)),
+        String::new(),
+        String::new(),
+        String::new(),
+        chunked.to_string(),
+    ]);
+    let mut widths = [0; 10];
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    let mut out = String::new();
+    for (i, row) in rows.iter().enumerate() {
+        if i == rows.len() - 1 {
+            let rule: Vec<String> = widths.iter().map(|width| "-".repeat(*width)).collect();
+            out.push_str(&rule.join("  "));
+            out.push('\n');
+        }
+        let cells: Vec<String> = row
+            .iter()
+            .zip(widths)
+            .enumerate()
+            .map(|(column, (cell, width))| {
+                let pad = " ".repeat(width - cell.chars().count());
+                // Names left, numbers right
+                match column {
+                    0 => format!("{}{}", cell, pad),
+                    _ => format!("{}{}", pad, cell),
+                }
+            })
+            .collect();
+        out.push_str(cells.join("  ").trim_end());
+        out.push('\n');
+    }
+    out
+}
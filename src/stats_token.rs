@@ -0,0 +1,90 @@
+// --stats-token: a bearer token of its own for the stats, metrics and exports
+//
+// The monitoring system only gets to read, it can't submit payloads or use admin
+// endpoints with its token, and --admin-token isn't accepted here either.
+// Destruction endpoints, /healthz and /ping stay open.
+use actix_web::body::{BoxBody, MessageBody};
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::{Error, HttpResponse};
+use std::future::{ready, Future, Ready};
+use std::pin::Pin;
+use std::rc::Rc;
+use std::task::{Context, Poll};
+
+// /stats covers the reports below it, including /stats/export and /stats/live
+const STATS_PATHS: &[&str] = &["/stats", "/metrics", "/events", "/grafana"];
+
+// Decided by the route the request is going to take, not the raw path, which may be
+// percent-encoded
+fn is_stats(req: &ServiceRequest) -> bool {
+    let Some(endpoint) = crate::index::resolve(req.request()) else {
+        return false;
+    };
+    let path = endpoint.path;
+    STATS_PATHS.iter().any(|prefix| {
+        path.strip_prefix(prefix)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+    })
+}
+
+// Middleware rejecting stats requests without the token, lets everything through
+// when none is configured
+pub struct StatsToken(pub Option<String>);
+
+impl<S, B> Transform<S, ServiceRequest> for StatsToken
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<BoxBody>;
+    type Error = Error;
+    type Transform = StatsTokenMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(StatsTokenMiddleware {
+            service,
+            token: self.0.clone().map(Rc::from),
+        }))
+    }
+}
+
+pub struct StatsTokenMiddleware<S> {
+    service: S,
+    token: Option<Rc<str>>,
+}
+
+impl<S, B> Service<ServiceRequest> for StatsTokenMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<BoxBody>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    fn poll_ready(&self, ctx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(ctx)
+    }
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let presented = req
+            .headers()
+            .get("Authorization")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "));
+        let allowed = match &self.token {
+            Some(token) if is_stats(&req) => crate::token_matches(presented, token),
+            _ => true,
+        };
+        if allowed {
+            let fut = self.service.call(req);
+            return Box::pin(async move { Ok(fut.await?.map_into_boxed_body()) });
+        }
+        let response = HttpResponse::Unauthorized()
+            .insert_header(("WWW-Authenticate", "Bearer"))
+            .json(serde_json::json!({ "error": "Missing or invalid stats token." }));
+        Box::pin(async move { Ok(req.into_response(response)) })
+    }
+}
@@ -0,0 +1,102 @@
+// Where the destruction endpoints, /stats and /metrics keep and read the stats
+//
+// SQLite by default. Builds without the `sqlite` feature use the append-only log of
+// log_store.rs instead, which needs no C toolchain. Everything that queries the rows
+// itself (exports, rotation, merging, the hash index, …) talks to SQLite directly and is
+// left out of those builds.
+use crate::{DbArgs, EndpointSums, NewStat, StatsSummary};
+#[cfg(feature = "sqlite")]
+use rusqlite::{params, Connection};
+use std::collections::BTreeMap;
+#[cfg(feature = "sqlite")]
+use std::sync::{Mutex, MutexGuard};
+
+// Rejected requests by (endpoint, reason)
+pub type RejectionCounts = BTreeMap<(String, String), i64>;
+
+pub trait Store: Send + Sync {
+    // Wait for the store to be free for writing. Time spent here is what --max-backlog
+    // and the persist_us of a row look at.
+    fn writer(&self) -> Box<dyn Writer + '_>;
+    // Add the totals of every endpoint to `sums`
+    fn add_endpoint_sums(&self, sums: &mut BTreeMap<String, EndpointSums>) -> Result<(), String>;
+    // Add the rejected requests to `counts`
+    fn add_rejection_counts(&self, counts: &mut RejectionCounts) -> Result<(), String>;
+    fn summary(&self) -> Result<StatsSummary, String>;
+}
+
+pub trait Writer {
+    // Write the rows, all of them or none, returns their ids
+    fn insert(&mut self, rows: &[NewStat]) -> Result<Vec<i64>, String>;
+    fn reject(&mut self, endpoint: &str, status: u16, reason: &str) -> Result<(), String>;
+}
+
+#[cfg(feature = "sqlite")]
+impl Store for Mutex<Connection> {
+    fn writer(&self) -> Box<dyn Writer + '_> {
+        Box::new(self.lock().unwrap())
+    }
+
+    fn add_endpoint_sums(&self, sums: &mut BTreeMap<String, EndpointSums>) -> Result<(), String> {
+        crate::add_endpoint_sums(&self.lock().unwrap(), sums).map_err(|e| e.to_string())
+    }
+
+    fn add_rejection_counts(&self, counts: &mut RejectionCounts) -> Result<(), String> {
+        crate::rejections::add_counts(&self.lock().unwrap(), counts).map_err(|e| e.to_string())
+    }
+
+    fn summary(&self) -> Result<StatsSummary, String> {
+        self.lock()
+            .unwrap()
+            .query_row(
+                "SELECT COALESCE(SUM(COALESCE(sample_weight, 1)), 0), COALESCE(SUM(payload_size * COALESCE(sample_weight, 1)), 0), COALESCE(SUM(runtime_us * COALESCE(sample_weight, 1)), 0), MIN(ts), MAX(ts), strftime('%s', 'now') - strftime('%s', MIN(ts)) FROM endpoint_stats_raw",
+                [],
+                |row| {
+                    Ok(StatsSummary::new(
+                        row.get(0)?,
+                        row.get(1)?,
+                        row.get(2)?,
+                        row.get(3)?,
+                        row.get(4)?,
+                        row.get(5)?,
+                    ))
+                },
+            )
+            .map_err(|e| e.to_string())
+    }
+}
+
+#[cfg(feature = "sqlite")]
+impl Writer for MutexGuard<'_, Connection> {
+    fn insert(&mut self, rows: &[NewStat]) -> Result<Vec<i64>, String> {
+        self.transaction()
+            .and_then(|tx| {
+                let ids = rows
+                    .iter()
+                    .map(|row| row.insert(&tx))
+                    .collect::<rusqlite::Result<Vec<_>>>()?;
+                tx.commit().map(|_| ids)
+            })
+            .map_err(|e| e.to_string())
+    }
+
+    fn reject(&mut self, endpoint: &str, status: u16, reason: &str) -> Result<(), String> {
+        self.execute(
+            "INSERT INTO rejections (endpoint, status, reason) VALUES (?1, ?2, ?3)",
+            params![endpoint, status, reason],
+        )
+        .map(|_| ())
+        .map_err(|e| e.to_string())
+    }
+}
+
+// The store at --db-path opened read-only, to look at what a server wrote
+#[cfg(feature = "sqlite")]
+pub fn open_read_only(db: &DbArgs) -> Result<Box<dyn Store>, String> {
+    crate::open_db_read_only(db).map(|conn| Box::new(Mutex::new(conn)) as Box<dyn Store>)
+}
+
+#[cfg(not(feature = "sqlite"))]
+pub fn open_read_only(db: &DbArgs) -> Result<Box<dyn Store>, String> {
+    crate::log_store::LogStore::open(db, true).map(|log| Box::new(log) as Box<dyn Store>)
+}
@@ -0,0 +1,138 @@
+// RFC 3161 trusted timestamps for destruction receipts (--tsa-url)
+//
+// The SHA-256 a receipt reports is sent to a Time-Stamp Authority, whose signed token
+// proves the hash existed at the time it states. The token is added to the receipt as
+// base64 DER and can be checked independently, e.g. with `openssl ts -verify`.
+use base64::Engine;
+use std::io::Read;
+use std::time::Duration;
+
+const TSA_TIMEOUT: Duration = Duration::from_secs(10);
+// Tokens are a few KB, anything much larger isn't one
+const MAX_REPLY_SIZE: u64 = 256 * 1024;
+// 2.16.840.1.101.3.4.2.1
+const SHA256_OID: &[u8] = &[0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02, 0x01];
+
+// A DER element with the given tag
+fn der(tag: u8, content: &[u8]) -> Vec<u8> {
+    let mut out = vec![tag];
+    match content.len() {
+        len @ 0..=127 => out.push(len as u8),
+        len => {
+            let bytes: Vec<u8> = len
+                .to_be_bytes()
+                .into_iter()
+                .skip_while(|byte| *byte == 0)
+                .collect();
+            out.push(0x80 | bytes.len() as u8);
+            out.extend(bytes);
+        }
+    }
+    out.extend_from_slice(content);
+    out
+}
+
+fn der_sequence(elements: &[Vec<u8>]) -> Vec<u8> {
+    der(0x30, &elements.concat())
+}
+
+// A non-negative INTEGER, with a leading zero where the sign bit would be set
+fn der_unsigned(value: u64) -> Vec<u8> {
+    let mut bytes: Vec<u8> = value
+        .to_be_bytes()
+        .into_iter()
+        .skip_while(|byte| *byte == 0)
+        .collect();
+    if bytes.first().is_none_or(|byte| byte & 0x80 != 0) {
+        bytes.insert(0, 0);
+    }
+    der(0x02, &bytes)
+}
+
+// TimeStampReq for a SHA-256 digest, asking for the TSA certificate in the token
+fn timestamp_request(digest: &[u8; 32], nonce: u64) -> Vec<u8> {
+    let algorithm = der_sequence(&[der(0x06, SHA256_OID), der(0x05, &[])]);
+    let imprint = der_sequence(&[algorithm, der(0x04, digest)]);
+    der_sequence(&[
+        der_unsigned(1),
+        imprint,
+        der_unsigned(nonce),
+        der(0x01, &[0xff]),
+    ])
+}
+
+// Tag, content and total length of the DER element at the start of `data`
+fn read_element(data: &[u8]) -> Option<(u8, &[u8], usize)> {
+    let (&tag, rest) = data.split_first()?;
+    let (&first, rest) = rest.split_first()?;
+    let (len, header) = match first {
+        0..=127 => (first as usize, 2),
+        0x81..=0x84 => {
+            let count = (first & 0x7f) as usize;
+            let len = rest
+                .get(..count)?
+                .iter()
+                .fold(0, |len, byte| len << 8 | *byte as usize);
+            (len, 2 + count)
+        }
+        _ => return None,
+    };
+    let content = data.get(header..header.checked_add(len)?)?;
+    Some((tag, content, header + len))
+}
+
+// The TimeStampToken of a TimeStampResp, if the TSA granted the request
+fn parse_reply(reply: &[u8]) -> Result<&[u8], String> {
+    let malformed = || "Malformed reply from the TSA".to_string();
+    let (0x30, body, _) = read_element(reply).ok_or_else(malformed)? else {
+        return Err(malformed());
+    };
+    let (0x30, status_info, status_len) = read_element(body).ok_or_else(malformed)? else {
+        return Err(malformed());
+    };
+    let (0x02, status, _) = read_element(status_info).ok_or_else(malformed)? else {
+        return Err(malformed());
+    };
+    // 0 granted, 1 granted with modifications, everything else is a rejection
+    match status {
+        [0] | [1] => {}
+        [status] => return Err(format!("The TSA rejected the request (status {})", status)),
+        _ => return Err(malformed()),
+    }
+    let token = &body[status_len..];
+    let (_, _, token_len) = read_element(token).ok_or_else(malformed)?;
+    Ok(&token[..token_len])
+}
+
+// Ask the TSA for a token on a SHA-256 digest
+fn fetch_token(tsa_url: &str, digest: &[u8; 32]) -> Result<Vec<u8>, String> {
+    let query = timestamp_request(digest, rand::random());
+    let response = ureq::AgentBuilder::new()
+        .timeout(TSA_TIMEOUT)
+        .build()
+        .post(tsa_url)
+        .set("Content-Type", "application/timestamp-query")
+        .send_bytes(&query)
+        .map_err(|e| format!("Failed to reach the TSA: {}", e))?;
+    let mut reply = Vec::new();
+    response
+        .into_reader()
+        .take(MAX_REPLY_SIZE)
+        .read_to_end(&mut reply)
+        .map_err(|e| format!("Failed to read the TSA reply: {}", e))?;
+    parse_reply(&reply).map(<[u8]>::to_vec)
+}
+
+// Timestamp the hex SHA-256 of a receipt, returns the token as base64 DER. Blocks
+// while asking.
+pub fn stamp(tsa_url: &str, sha256: &str) -> Result<String, String> {
+    let mut digest = [0; 32];
+    for (i, byte) in digest.iter_mut().enumerate() {
+        *byte = sha256
+            .get(2 * i..2 * i + 2)
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+            .ok_or_else(|| format!("Not a SHA-256 digest: {}", sha256))?;
+    }
+    let token = fetch_token(tsa_url, &digest)?;
+    Ok(base64::engine::general_purpose::STANDARD.encode(token))
+}
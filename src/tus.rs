@@ -0,0 +1,470 @@
+// Resumable uploads following the tus.io 1.0.0 protocol (core, creation and termination).
+// Upload bytes are hashed as they arrive and discarded right away, so only the
+// offset and the running digest survive between PATCH requests. At most
+// --tus-max-uploads unfinished uploads are kept, empty ones are finished on creation.
+// Receipts of destroyed uploads are kept apart, the newest MAX_RECEIPTS of them. Expired
+// uploads and receipts are swept once a minute, not on the request path.
+use crate::store::Store;
+use actix_web::http::StatusCode;
+use actix_web::web::{self, Data};
+use actix_web::{HttpRequest, HttpResponse, HttpResponseBuilder, Result};
+use futures_util::StreamExt;
+use rand::Rng;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+const TUS_VERSION: &str = "1.0.0";
+// Bytes are never stored, so uploads may be far larger than the in-memory payload limit
+pub const TUS_MAX_SIZE: u64 = 10 * 1024 * 1024 * 1024;
+// Unfinished uploads and receipts are forgotten after this long without activity
+const TUS_EXPIRY: Duration = Duration::from_secs(24 * 60 * 60);
+// Receipts kept at most, the oldest go first
+const MAX_RECEIPTS: usize = 10_000;
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Serialize, Clone)]
+struct TusReceipt {
+    status: &'static str,
+    upload_id: String,
+    size: u64,
+    sha256: String,
+    destroyed_at: String,
+    runtime_us: u128,
+    // RFC 3161 token on sha256 (base64 DER), with --tsa-url
+    #[serde(skip_serializing_if = "Option::is_none")]
+    timestamp_token: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    timestamp_error: Option<String>,
+}
+
+// An upload still receiving bytes
+struct TusUpload {
+    length: u64,
+    offset: u64,
+    // Taken out while a PATCH is in flight
+    hasher: Option<Sha256>,
+    runtime_us: u128,
+    last_activity: Instant,
+}
+
+// Receipts of destroyed uploads, in the order they were destroyed
+#[derive(Default)]
+struct Receipts {
+    by_id: HashMap<String, TusReceipt>,
+    order: VecDeque<(Instant, String)>,
+}
+
+// Locked in this order when both are needed
+pub struct TusStore {
+    uploads: Mutex<HashMap<String, TusUpload>>,
+    receipts: Mutex<Receipts>,
+    max_unfinished: usize,
+}
+
+impl Receipts {
+    fn insert(&mut self, receipt: TusReceipt) {
+        while self.order.len() >= MAX_RECEIPTS {
+            let Some((_, oldest)) = self.order.pop_front() else {
+                break;
+            };
+            self.by_id.remove(&oldest);
+        }
+        self.order
+            .push_back((Instant::now(), receipt.upload_id.clone()));
+        self.by_id.insert(receipt.upload_id.clone(), receipt);
+    }
+
+    fn sweep(&mut self) {
+        while let Some((destroyed, id)) = self.order.front() {
+            if destroyed.elapsed() < TUS_EXPIRY {
+                break;
+            }
+            self.by_id.remove(id);
+            self.order.pop_front();
+        }
+    }
+}
+
+impl TusStore {
+    pub fn new(max_unfinished: usize) -> Self {
+        TusStore {
+            uploads: Mutex::new(HashMap::new()),
+            receipts: Mutex::new(Receipts::default()),
+            max_unfinished,
+        }
+    }
+}
+
+// Forget uploads left unfinished and receipts past TUS_EXPIRY, once a minute
+pub fn spawn_sweeper(store: Data<TusStore>) {
+    std::thread::spawn(move || loop {
+        std::thread::sleep(SWEEP_INTERVAL);
+        store
+            .uploads
+            .lock()
+            .unwrap()
+            .retain(|_, upload| upload.last_activity.elapsed() < TUS_EXPIRY);
+        store.receipts.lock().unwrap().sweep();
+    });
+}
+
+// Start a response with the headers every tus response carries
+fn tus_response(status: StatusCode) -> HttpResponseBuilder {
+    let mut builder = HttpResponse::build(status);
+    builder
+        .insert_header(("Tus-Resumable", TUS_VERSION))
+        .insert_header(("Cache-Control", "no-store"));
+    builder
+}
+
+fn tus_error(status: StatusCode, message: &str) -> HttpResponse {
+    tus_response(status).json(serde_json::json!({ "error": message }))
+}
+
+// All requests except OPTIONS must announce the protocol version we speak
+fn check_version(req: &HttpRequest) -> Option<HttpResponse> {
+    let version = req
+        .headers()
+        .get("Tus-Resumable")
+        .and_then(|v| v.to_str().ok());
+    if version == Some(TUS_VERSION) {
+        None
+    } else {
+        let mut response = tus_error(
+            StatusCode::PRECONDITION_FAILED,
+            "Unsupported or missing Tus-Resumable version.",
+        );
+        response
+            .headers_mut()
+            .insert("Tus-Version".parse().unwrap(), TUS_VERSION.parse().unwrap());
+        Some(response)
+    }
+}
+
+fn header_u64(req: &HttpRequest, name: &str) -> Option<u64> {
+    req.headers().get(name)?.to_str().ok()?.parse().ok()
+}
+
+/// Handler for OPTIONS /tus
+/// Advertises the supported tus version, extensions and maximum size.
+pub async fn options_handler() -> Result<HttpResponse> {
+    Ok(tus_response(StatusCode::NO_CONTENT)
+        .insert_header(("Tus-Version", TUS_VERSION))
+        .insert_header(("Tus-Extension", "creation,termination"))
+        .insert_header(("Tus-Max-Size", TUS_MAX_SIZE.to_string()))
+        .finish())
+}
+
+/// Handler for POST /tus
+/// Creates a new upload of `Upload-Length` bytes and returns its URL in `Location`.
+pub async fn create_handler(
+    req: HttpRequest,
+    store: Data<TusStore>,
+    db: Data<dyn Store>,
+    config: Data<crate::AppConfig>,
+) -> Result<HttpResponse> {
+    if let Some(response) = check_version(&req) {
+        return Ok(response);
+    }
+    let start = crate::get_start_time(&req);
+    let Some(length) = header_u64(&req, "Upload-Length") else {
+        return Ok(tus_error(
+            StatusCode::BAD_REQUEST,
+            "Missing or invalid Upload-Length header.",
+        ));
+    };
+    if length > TUS_MAX_SIZE {
+        return Ok(tus_error(
+            StatusCode::PAYLOAD_TOO_LARGE,
+            "Upload-Length exceeds Tus-Max-Size.",
+        ));
+    }
+    // The id is all it takes to write to or read the upload, never from the seeded rng
+    let id = format!("{:032x}", rand::rng().random::<u128>());
+    // Nothing to PATCH, the upload is complete right away
+    if length == 0 {
+        let sha256 = format!("{:x}", Sha256::digest(b""));
+        let runtime_us = start.elapsed().as_micros();
+        finish(&req, &store, &db, &config, &id, 0, sha256, runtime_us).await?;
+    } else {
+        let mut uploads = store.uploads.lock().unwrap();
+        if uploads.len() >= store.max_unfinished {
+            return Ok(tus_error(
+                StatusCode::SERVICE_UNAVAILABLE,
+                "Too many unfinished uploads, please try again later.",
+            ));
+        }
+        uploads.insert(
+            id.clone(),
+            TusUpload {
+                length,
+                offset: 0,
+                hasher: Some(Sha256::new()),
+                runtime_us: 0,
+                last_activity: Instant::now(),
+            },
+        );
+    }
+    Ok(tus_response(StatusCode::CREATED)
+        // Relative to the request path, so it also works under --base-path
+        .insert_header(("Location", format!("{}/{}", req.path(), id)))
+        .finish())
+}
+
+/// Handler for HEAD /tus/{id}
+/// Reports how many bytes of the upload have been received so far.
+pub async fn head_handler(
+    req: HttpRequest,
+    path: web::Path<String>,
+    store: Data<TusStore>,
+) -> Result<HttpResponse> {
+    if let Some(response) = check_version(&req) {
+        return Ok(response);
+    }
+    let uploads = store.uploads.lock().unwrap();
+    let (offset, length) = match uploads.get(path.as_str()) {
+        Some(upload) => (upload.offset, upload.length),
+        None => match store.receipts.lock().unwrap().by_id.get(path.as_str()) {
+            Some(receipt) => (receipt.size, receipt.size),
+            None => return Ok(tus_response(StatusCode::NOT_FOUND).finish()),
+        },
+    };
+    Ok(tus_response(StatusCode::OK)
+        .insert_header(("Upload-Offset", offset.to_string()))
+        .insert_header(("Upload-Length", length.to_string()))
+        .finish())
+}
+
+/// Handler for PATCH /tus/{id}
+/// Appends bytes at `Upload-Offset`. Once the upload is complete it is destroyed and receipted.
+pub async fn patch_handler(
+    req: HttpRequest,
+    path: web::Path<String>,
+    mut payload: web::Payload,
+    store: Data<TusStore>,
+    db: Data<dyn Store>,
+    config: Data<crate::AppConfig>,
+) -> Result<HttpResponse> {
+    if let Some(response) = check_version(&req) {
+        return Ok(response);
+    }
+    let start = crate::get_start_time(&req);
+    let content_type = req
+        .headers()
+        .get("Content-Type")
+        .and_then(|v| v.to_str().ok());
+    if content_type != Some("application/offset+octet-stream") {
+        return Ok(tus_error(
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            "PATCH requests must use Content-Type application/offset+octet-stream.",
+        ));
+    }
+    let Some(client_offset) = header_u64(&req, "Upload-Offset") else {
+        return Ok(tus_error(
+            StatusCode::BAD_REQUEST,
+            "Missing or invalid Upload-Offset header.",
+        ));
+    };
+
+    // Take the hasher out so the lock isn't held while the body streams in
+    let id = path.into_inner();
+    let mut guard = {
+        let mut uploads = store.uploads.lock().unwrap();
+        let Some(upload) = uploads.get_mut(&id) else {
+            if store.receipts.lock().unwrap().by_id.contains_key(&id) {
+                return Ok(tus_error(
+                    StatusCode::CONFLICT,
+                    "Upload is already complete and destroyed.",
+                ));
+            }
+            return Ok(tus_response(StatusCode::NOT_FOUND).finish());
+        };
+        if upload.offset != client_offset {
+            return Ok(tus_error(
+                StatusCode::CONFLICT,
+                "Upload-Offset does not match the current offset.",
+            ));
+        }
+        match upload.hasher.take() {
+            Some(taken) => PatchGuard {
+                store: store.clone(),
+                id: id.clone(),
+                length: upload.length,
+                offset: upload.offset,
+                hasher: Some(taken),
+                runtime_us: upload.runtime_us,
+            },
+            None => {
+                return Ok(tus_error(
+                    StatusCode::LOCKED,
+                    "Another PATCH for this upload is in progress.",
+                ))
+            }
+        }
+    };
+
+    // Hash and forget every chunk. On a broken connection we keep what arrived so far.
+    let mut too_long = false;
+    while let Some(chunk) = payload.next().await {
+        let Ok(chunk) = chunk else {
+            break;
+        };
+        if guard.offset + chunk.len() as u64 > guard.length {
+            too_long = true;
+            break;
+        }
+        if let Some(hasher) = guard.hasher.as_mut() {
+            hasher.update(&chunk);
+        }
+        guard.offset += chunk.len() as u64;
+    }
+    guard.runtime_us += start.elapsed().as_micros();
+    let offset = guard.offset;
+
+    if offset == guard.length {
+        let sha256 = format!("{:x}", guard.hasher.take().unwrap_or_default().finalize());
+        let runtime_us = guard.runtime_us;
+        finish(&req, &store, &db, &config, &id, offset, sha256, runtime_us).await?;
+    }
+    // Dropping the guard hands an unfinished upload back for the next PATCH
+    drop(guard);
+
+    if too_long {
+        return Ok(tus_error(
+            StatusCode::PAYLOAD_TOO_LARGE,
+            "Body exceeds the declared Upload-Length.",
+        ));
+    }
+    Ok(tus_response(StatusCode::NO_CONTENT)
+        .insert_header(("Upload-Offset", offset.to_string()))
+        .finish())
+}
+
+// Destroy a complete upload: keep its receipt and record the stat. Done before the TSA
+// is asked, a client going away meanwhile can't leave the upload stuck half-done.
+#[allow(clippy::too_many_arguments)]
+async fn finish(
+    req: &HttpRequest,
+    store: &Data<TusStore>,
+    db: &Data<dyn Store>,
+    config: &crate::AppConfig,
+    id: &str,
+    size: u64,
+    sha256: String,
+    runtime_us: u128,
+) -> Result<()> {
+    let receipt = TusReceipt {
+        status: "destroyed",
+        upload_id: id.to_string(),
+        size,
+        sha256: sha256.clone(),
+        destroyed_at: chrono::Utc::now().to_rfc3339(),
+        runtime_us,
+        timestamp_token: None,
+        timestamp_error: None,
+    };
+    #[cfg(feature = "sqlite")]
+    crate::hash_index::remember_receipt(req, &sha256, "tus", &receipt.destroyed_at);
+    {
+        let mut uploads = store.uploads.lock().unwrap();
+        uploads.remove(id);
+        store.receipts.lock().unwrap().insert(receipt);
+    }
+    crate::record_stat_with(
+        db.get_ref(),
+        "tus",
+        size as usize,
+        runtime_us,
+        crate::StatExtras::for_request(req, config),
+    );
+    // The token is added to the stored receipt when it arrives, on the blocking pool
+    // so that happens even if this request is cancelled
+    if let Some(tsa_url) = config.tsa_url.clone() {
+        let store = store.clone();
+        let id = id.to_string();
+        web::block(move || {
+            let stamped = crate::timestamp::stamp(&tsa_url, &sha256);
+            if let Some(receipt) = store.receipts.lock().unwrap().by_id.get_mut(&id) {
+                match stamped {
+                    Ok(token) => receipt.timestamp_token = Some(token),
+                    Err(e) => receipt.timestamp_error = Some(e),
+                }
+            }
+        })
+        .await?;
+    }
+    Ok(())
+}
+
+// Holds the state of an upload while a PATCH streams in and puts it back when
+// dropped, even if the request future is cancelled halfway through.
+struct PatchGuard {
+    store: Data<TusStore>,
+    id: String,
+    length: u64,
+    offset: u64,
+    hasher: Option<Sha256>,
+    runtime_us: u128,
+}
+
+impl Drop for PatchGuard {
+    fn drop(&mut self) {
+        let Some(hasher) = self.hasher.take() else {
+            return;
+        };
+        // The upload may have been terminated meanwhile, then there is nothing to restore
+        if let Some(upload) = self.store.uploads.lock().unwrap().get_mut(&self.id) {
+            upload.offset = self.offset;
+            upload.hasher = Some(hasher);
+            upload.runtime_us = self.runtime_us;
+            upload.last_activity = Instant::now();
+        }
+    }
+}
+
+/// Handler for GET /tus/{id}
+/// Returns the destruction receipt of a completed upload.
+pub async fn receipt_handler(
+    path: web::Path<String>,
+    store: Data<TusStore>,
+) -> Result<HttpResponse> {
+    let uploads = store.uploads.lock().unwrap();
+    if uploads.contains_key(path.as_str()) {
+        return Ok(tus_error(
+            StatusCode::CONFLICT,
+            "Upload is not complete yet.",
+        ));
+    }
+    match store.receipts.lock().unwrap().by_id.get(path.as_str()) {
+        Some(receipt) => Ok(tus_response(StatusCode::OK).json(receipt)),
+        None => Ok(tus_response(StatusCode::NOT_FOUND).finish()),
+    }
+}
+
+/// Handler for DELETE /tus/{id}
+/// Terminates an upload, forgetting everything received so far.
+pub async fn delete_handler(
+    req: HttpRequest,
+    path: web::Path<String>,
+    store: Data<TusStore>,
+) -> Result<HttpResponse> {
+    if let Some(response) = check_version(&req) {
+        return Ok(response);
+    }
+    let mut uploads = store.uploads.lock().unwrap();
+    let removed = uploads.remove(path.as_str()).is_some()
+        || store
+            .receipts
+            .lock()
+            .unwrap()
+            .by_id
+            .remove(path.as_str())
+            .is_some();
+    match removed {
+        true => Ok(tus_response(StatusCode::NO_CONTENT).finish()),
+        false => Ok(tus_response(StatusCode::NOT_FOUND).finish()),
+    }
+}
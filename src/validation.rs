@@ -0,0 +1,233 @@
+// The format checks of /validate-before-destroy, without anything HTTP
+//
+// Everything here works on the payload text alone, so it can be fuzzed directly (see
+// fuzz/). The handler adds what needs the server: size limits, link checks over the
+// network, stats and timings.
+use crate::{calendar, email, graphql, inspect, jcs, links, ndjson};
+use pulldown_cmark::Parser as MdParser;
+use quick_xml::events::Event as XmlEvent;
+use quick_xml::Reader as XmlReader;
+use serde::Serialize;
+
+// Duplicate keys reported per validation, the rest is summed up
+const MAX_DUPLICATE_KEY_DETAILS: usize = 20;
+
+// What the request tells about the payload besides its bytes
+#[derive(Default)]
+pub struct Options {
+    // Declared as application/x-www-form-urlencoded
+    pub form: bool,
+    // Declared as one of the NDJSON media types
+    pub ndjson: bool,
+    // ?reveal_headers=true for emails
+    pub reveal_headers: bool,
+    // Privacy mode, keeps email headers redacted anyway
+    pub privacy: bool,
+}
+
+#[derive(Serialize, Default)]
+pub struct Validation {
+    pub is_json: bool,
+    pub is_xml: bool,
+    pub is_markdown: bool,
+    pub details: Vec<String>,
+    // Shape of the document, only for valid JSON
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub json_structure: Option<inspect::JsonStructure>,
+    // RFC 8785 check, only for valid JSON
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub canonical_json: Option<jcs::CanonicalReport>,
+    // Shape of the document, only for valid XML
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub xml_structure: Option<inspect::XmlStructure>,
+    // Operations of a GraphQL document, raw or in a JSON envelope
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub graphql: Option<graphql::GraphqlReport>,
+    // Headers, parts and attachments of an RFC 5322 message
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub email: Option<email::EmailReport>,
+    // Entries and dates of an iCalendar or vCard payload
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub calendar: Option<calendar::CalendarReport>,
+    // Fields of an application/x-www-form-urlencoded payload
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub form: Option<inspect::FormReport>,
+    // Records of an application/x-ndjson payload
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ndjson: Option<ndjson::NdjsonReport>,
+    // http(s) links found in the payload, for the handler to report or check
+    #[serde(skip)]
+    pub urls: Vec<String>,
+}
+
+// 1-based line and column of a byte offset, columns count characters
+fn line_column(text: &str, offset: usize) -> (usize, usize) {
+    let before = &text[..text.floor_char_boundary(offset)];
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    (
+        before.matches('\n').count() + 1,
+        before[line_start..].chars().count() + 1,
+    )
+}
+
+// Why a payload isn't JSON, serde_json puts the location at the end of its message
+fn json_error_detail(e: &serde_json::Error) -> String {
+    let message = e.to_string();
+    let reason = message.split(" at line ").next().unwrap_or(&message);
+    format!(
+        "Invalid JSON at line {}, column {}: {}.",
+        e.line(),
+        e.column(),
+        reason
+    )
+}
+
+// One line per key that occurs more than once in the same object
+fn duplicate_key_details(body_str: &str) -> Vec<String> {
+    let duplicates = inspect::duplicate_keys(body_str);
+    let mut details: Vec<String> = duplicates
+        .iter()
+        .take(MAX_DUPLICATE_KEY_DETAILS)
+        .map(|duplicate| {
+            let place = match duplicate.path.as_str() {
+                "" => "the top-level object".to_string(),
+                path => path.to_string(),
+            };
+            format!(
+                "Duplicate key \"{}\" in {} ({} times).",
+                duplicate.key, place, duplicate.count
+            )
+        })
+        .collect();
+    if duplicates.len() > MAX_DUPLICATE_KEY_DETAILS {
+        details.push(format!(
+            "{} more duplicate keys not listed.",
+            duplicates.len() - MAX_DUPLICATE_KEY_DETAILS
+        ));
+    }
+    details
+}
+
+// Well-formed XML with at least one root element, or why not
+pub fn check_xml(body_str: &str) -> Result<(), String> {
+    let mut xml_reader = XmlReader::from_str(body_str);
+    xml_reader.trim_text(true);
+    let mut buf = Vec::new();
+    let mut has_root_element = false;
+
+    loop {
+        match xml_reader.read_event_into(&mut buf) {
+            Ok(XmlEvent::Start(_)) => {
+                has_root_element = true;
+            }
+            Ok(XmlEvent::Eof) => {
+                // Only consider it valid XML if we found a root element and reached EOF without errors
+                return match has_root_element {
+                    true => Ok(()),
+                    false => Err("Invalid XML: no root element.".to_string()),
+                };
+            }
+            Ok(_) => {
+                // Continue parsing other events
+            }
+            Err(e) => {
+                let (line, column) = line_column(body_str, xml_reader.buffer_position());
+                return Err(format!(
+                    "Invalid XML at line {}, column {}: {}.",
+                    line, column, e
+                ));
+            }
+        }
+        buf.clear();
+    }
+}
+
+// Check a payload against every known format
+pub fn validate(body_str: &str, options: &Options) -> Validation {
+    let mut details = Vec::new();
+
+    // Parse errors are only explained for payloads that look like they tried, plain
+    // text would otherwise always come with two
+    let first_char = body_str.trim_start().chars().next();
+
+    // JSON check
+    let json = serde_json::from_str::<serde_json::Value>(body_str);
+    let is_json = json.is_ok();
+    let json_structure = json.as_ref().ok().map(inspect::json_structure);
+    let canonical_json = is_json.then(|| jcs::check(body_str));
+    // GraphQL shorthand queries start with "{" too, they aren't broken JSON
+    let graphql = graphql::detect(body_str, json.as_ref().ok());
+    match &json {
+        Ok(_) => {
+            details.push("Valid JSON detected.".to_string());
+            details.extend(duplicate_key_details(body_str));
+            details.extend(canonical_json.as_ref().map(jcs::CanonicalReport::detail));
+        }
+        // NDJSON with more than one record is never a single document
+        Err(e) if matches!(first_char, Some('{' | '[')) && graphql.is_none() && !options.ndjson => {
+            details.push(json_error_detail(e))
+        }
+        Err(_) => {}
+    }
+    details.extend(graphql.as_ref().map(graphql::GraphqlReport::detail));
+
+    // Documents hiding inside the payload or its fields
+    details.extend(inspect::double_encodings(body_str, json.as_ref().ok()));
+
+    // XML check
+    let xml = check_xml(body_str);
+    let is_xml = xml.is_ok();
+    let xml_structure = is_xml.then(|| inspect::xml_structure(body_str));
+    match xml {
+        Ok(()) => details.push("Valid XML detected.".to_string()),
+        Err(e) if first_char == Some('<') => details.push(e),
+        Err(_) => {}
+    }
+
+    // Markdown check (very basic: parses without error and has at least one event)
+    let mut is_markdown = false;
+    let mut md_parser = MdParser::new(body_str);
+    if md_parser.next().is_some() {
+        is_markdown = true;
+        details.push("Markdown content detected (parsed successfully).".to_string());
+    }
+
+    // Form and NDJSON checks, only when declared: "a=b" is also perfectly fine text
+    let form = options.form.then(|| inspect::form_report(body_str));
+    details.extend(form.as_ref().map(inspect::FormReport::detail));
+    let ndjson = options.ndjson.then(|| ndjson::report(body_str));
+    details.extend(ndjson.as_ref().map(ndjson::NdjsonReport::detail));
+
+    // Email check, the headers stay redacted unless asked for
+    let email = email::detect(body_str, options.reveal_headers && !options.privacy);
+    details.extend(email.as_ref().map(email::EmailReport::detail));
+    if email.is_some() && options.reveal_headers && options.privacy {
+        details.push("Email headers stay redacted in privacy mode.".to_string());
+    }
+
+    // iCalendar and vCard check
+    let calendar = calendar::detect(body_str);
+    details.extend(calendar.as_ref().map(calendar::CalendarReport::detail));
+
+    let is_other_format = graphql.is_some() || email.is_some() || calendar.is_some();
+    let is_declared_format = form.is_some() || ndjson.is_some();
+    if !is_json && !is_xml && !is_markdown && !is_other_format && !is_declared_format {
+        details.push("No known markup detected (JSON, XML, Markdown).".to_string());
+    }
+
+    Validation {
+        urls: links::extract(body_str, json.as_ref().ok()),
+        is_json,
+        is_xml,
+        is_markdown,
+        details,
+        json_structure,
+        canonical_json,
+        xml_structure,
+        graphql,
+        email,
+        calendar,
+        form,
+        ndjson,
+    }
+}
@@ -0,0 +1,95 @@
+// ?work=: genuine CPU work before responding, for modelling downstream services
+//
+// `?work=250ms` keeps a core busy hashing for that long, `?work=5000` does a fixed
+// amount of hashing (one unit is 1000 chained SHA-256 rounds), so the cost scales with
+// the hardware like real processing would. The time spent is reported as `work_us`.
+use actix_web::error::InternalError;
+use actix_web::web;
+use actix_web::{HttpMessage, HttpRequest, HttpResponse};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::time::{Duration, Instant};
+
+const MAX_DURATION: Duration = Duration::from_secs(10);
+const MAX_UNITS: u64 = 100_000;
+const ROUNDS_PER_UNIT: u64 = 1000;
+
+#[derive(Deserialize)]
+struct WorkQuery {
+    work: Option<String>,
+}
+
+#[derive(Clone, Copy)]
+enum Work {
+    For(Duration),
+    Units(u64),
+}
+
+// How long the work took, picked up by StatExtras::for_request
+#[derive(Clone, Copy)]
+pub struct WorkDone(pub u128);
+
+fn parse(spec: &str) -> Option<Work> {
+    let spec = spec.trim();
+    let work = if let Some(ms) = spec.strip_suffix("ms") {
+        Work::For(Duration::from_millis(ms.parse().ok()?))
+    } else if let Some(secs) = spec.strip_suffix('s') {
+        Work::For(Duration::try_from_secs_f64(secs.parse().ok()?).ok()?)
+    } else {
+        Work::Units(spec.parse().ok()?)
+    };
+    match work {
+        Work::For(duration) if duration > MAX_DURATION => None,
+        Work::Units(units) if units > MAX_UNITS => None,
+        work => Some(work),
+    }
+}
+
+fn invalid(spec: &str) -> actix_web::Error {
+    let response = HttpResponse::BadRequest().json(serde_json::json!({
+        "error": format!(
+            "Invalid work '{}', use a duration up to {}s (e.g. 250ms) or up to {} units.",
+            spec,
+            MAX_DURATION.as_secs(),
+            MAX_UNITS
+        )
+    }));
+    InternalError::from_response("invalid work", response).into()
+}
+
+// Hash in a chain, so nothing can be skipped or parallelized
+fn hash_rounds(state: &mut [u8; 32], rounds: u64) {
+    for _ in 0..rounds {
+        *state = Sha256::digest(*state).into();
+    }
+}
+
+fn burn(work: Work) {
+    let mut state = [0u8; 32];
+    match work {
+        Work::Units(units) => hash_rounds(&mut state, units * ROUNDS_PER_UNIT),
+        Work::For(duration) => {
+            let start = Instant::now();
+            while start.elapsed() < duration {
+                hash_rounds(&mut state, ROUNDS_PER_UNIT);
+            }
+        }
+    }
+    std::hint::black_box(state);
+}
+
+// Do the work asked for with ?work=, if any, on the blocking thread pool
+pub async fn simulate(req: &HttpRequest) -> actix_web::Result<()> {
+    let Ok(query) = web::Query::<WorkQuery>::from_query(req.query_string()) else {
+        return Ok(());
+    };
+    let Some(spec) = &query.work else {
+        return Ok(());
+    };
+    let work = parse(spec).ok_or_else(|| invalid(spec))?;
+    let start = Instant::now();
+    web::block(move || burn(work)).await?;
+    req.extensions_mut()
+        .insert(WorkDone(start.elapsed().as_micros()));
+    Ok(())
+}
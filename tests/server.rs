@@ -0,0 +1,159 @@
+// In-process tests against the real app wiring, see "Integration Tests" in the README
+#![cfg(feature = "sqlite")]
+use actix_web::test;
+use payload_pulverizer::{configure_app, AppState};
+use rusqlite::Connection;
+use serde_json::Value;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// A database path no other test uses, removed when dropped
+struct ScratchDb(PathBuf);
+
+impl ScratchDb {
+    fn new() -> Self {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        ScratchDb(std::env::temp_dir().join(format!(
+            "payload-pulverizer-test-{}-{}.db",
+            std::process::id(),
+            NEXT.fetch_add(1, Ordering::Relaxed)
+        )))
+    }
+
+    fn path(&self) -> &str {
+        self.0.to_str().unwrap()
+    }
+
+    fn state(&self, flags: &[&str]) -> AppState {
+        let args = ["payload-pulverizer", "--db-path", self.path(), "serve"];
+        AppState::from_args(args.iter().chain(flags)).unwrap()
+    }
+
+    // (endpoint, payload_size, tenant, client_ip) of every stored row
+    fn rows(&self) -> Vec<(String, i64, Option<String>, Option<String>)> {
+        let conn = Connection::open(&self.0).unwrap();
+        let mut stmt = conn
+            .prepare("SELECT endpoint, payload_size, tenant, client_ip FROM endpoint_stats_raw ORDER BY id")
+            .unwrap();
+        let rows = stmt
+            .query_map([], |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+            })
+            .unwrap();
+        rows.map(Result::unwrap).collect()
+    }
+}
+
+impl Drop for ScratchDb {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+#[actix_web::test]
+async fn pulverize_records_a_stat() {
+    let db = ScratchDb::new();
+    let state = db.state(&[]);
+    let app = test::init_service(configure_app(&state)).await;
+
+    let request = test::TestRequest::post()
+        .uri("/pulverize")
+        .set_payload("doomed")
+        .to_request();
+    let response: Value = test::call_and_read_body_json(&app, request).await;
+    assert_eq!(response["status"], "success");
+    assert!(response["runtime_us"].is_u64());
+
+    assert_eq!(db.rows(), vec![("pulverize".to_string(), 6, None, None)]);
+    let request = test::TestRequest::get().uri("/stats").to_request();
+    let stats: Value = test::call_and_read_body_json(&app, request).await;
+    let entry = &stats["stats"][0];
+    assert_eq!(entry["endpoint"], "pulverize");
+    assert_eq!(entry["count"], 1);
+    assert_eq!(entry["total_bytes"], 6);
+}
+
+#[actix_web::test]
+async fn privacy_stores_nothing_identifying() {
+    let db = ScratchDb::new();
+    let state = db.state(&[
+        "--privacy",
+        "--record-clients",
+        "--tenant-header",
+        "X-Tenant",
+    ]);
+    let app = test::init_service(configure_app(&state)).await;
+
+    let request = test::TestRequest::post()
+        .uri("/blackhole")
+        .peer_addr("192.0.2.7:4711".parse().unwrap())
+        .insert_header(("X-Tenant", "acme"))
+        .set_payload("secret")
+        .to_request();
+    let response = test::call_service(&app, request).await;
+    assert_eq!(response.status(), 204);
+    assert_eq!(response.headers().get("X-Privacy-Mode").unwrap(), "on");
+
+    assert_eq!(db.rows(), vec![("blackhole".to_string(), 6, None, None)]);
+}
+
+#[actix_web::test]
+async fn stats_token_covers_percent_encoded_paths() {
+    let db = ScratchDb::new();
+    let state = db.state(&["--stats-token", "sekrit"]);
+    let app = test::init_service(configure_app(&state)).await;
+
+    for path in ["/stats", "/%73tats", "/%73tats/tail", "/metric%73"] {
+        let request = test::TestRequest::get().uri(path).to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), 401, "{}", path);
+    }
+    let request = test::TestRequest::get()
+        .uri("/%73tats")
+        .insert_header(("Authorization", "Bearer sekrit"))
+        .to_request();
+    let response = test::call_service(&app, request).await;
+    assert_eq!(response.status(), 200);
+}
+
+#[actix_web::test]
+async fn read_only_covers_percent_encoded_paths() {
+    let db = ScratchDb::new();
+    // A read-only server needs an existing database
+    drop(db.state(&[]));
+    let state = db.state(&["--read-only"]);
+    let app = test::init_service(configure_app(&state)).await;
+
+    for path in ["/pulverize", "/%70ulverize", "/blackhol%65"] {
+        let request = test::TestRequest::post()
+            .uri(path)
+            .set_payload("doomed")
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), 503, "{}", path);
+    }
+    assert!(db.rows().is_empty());
+}
+
+#[actix_web::test]
+async fn rejections_never_store_the_raw_path() {
+    let db = ScratchDb::new();
+    let state = db.state(&["--stats-token", "sekrit"]);
+    let app = test::init_service(configure_app(&state)).await;
+
+    for path in ["/stats/made-up-by-a-scanner", "/%73tats/tail"] {
+        let request = test::TestRequest::get().uri(path).to_request();
+        test::call_service(&app, request).await;
+    }
+    let conn = Connection::open(db.path()).unwrap();
+    let mut stmt = conn
+        .prepare("SELECT endpoint FROM rejections ORDER BY id")
+        .unwrap();
+    let endpoints: Vec<String> = stmt
+        .query_map([], |row| row.get(0))
+        .unwrap()
+        .map(Result::unwrap)
+        .collect();
+    // Unknown paths aren't stats routes, they get 404 and aren't recorded at all
+    assert_eq!(endpoints, vec!["stats/tail"]);
+}